@@ -0,0 +1,66 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kun_peng::compact_hash::{CHTable, HashConfig, Page, TableLayout};
+
+/// 64 MiB of cells, well past the caches
+const CAPACITY: usize = 1 << 24;
+const LOOKUPS: usize = 1 << 16;
+
+fn random_keys(len: usize, seed: u64) -> Vec<u64> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+        .collect()
+}
+
+fn table(layout: TableLayout, keys: &[u64]) -> CHTable {
+    let config = HashConfig {
+        layout,
+        ..HashConfig::new(1, CAPACITY, 16, keys.len(), 1, CAPACITY)
+    };
+    let mut page = Page::with_capacity(0, CAPACITY);
+    for (i, &hash_key) in keys.iter().enumerate() {
+        let slot = config.slot(hash_key, i as u32 % 60000 + 1);
+        let idx = (slot.idx..)
+            .find(|&i| page.data[i] == 0 || page.data[i] == slot.value)
+            .unwrap();
+        page.data[idx] = slot.value;
+    }
+    page.size = page.data.len();
+    CHTable {
+        config,
+        pages: vec![page],
+    }
+}
+
+/// Lookups of stored keys (hits) and of absent keys (misses), by layout and load factor
+fn lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chtable_lookup");
+    group.throughput(Throughput::Elements(LOOKUPS as u64));
+    for load_factor in [0.7, 0.9] {
+        let keys = random_keys((CAPACITY as f64 * load_factor) as usize, 0x9e3779b97f4a7c15);
+        let hits: Vec<u64> = keys.iter().step_by(keys.len() / LOOKUPS).copied().collect();
+        let misses = random_keys(LOOKUPS, 0x2545f4914f6cdd1d);
+        for layout in [TableLayout::Linear, TableLayout::Bucketed] {
+            let chtable = table(layout, &keys);
+            for (name, lookups) in [("hits", &hits), ("misses", &misses)] {
+                let id = format!("{:?}/{}", layout, name);
+                group.bench_with_input(BenchmarkId::new(id, load_factor), lookups, |b, keys| {
+                    b.iter(|| {
+                        keys.iter()
+                            .filter_map(|&hash_key| chtable.get(black_box(hash_key)))
+                            .fold(0u64, |sum, taxid| sum.wrapping_add(taxid as u64))
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lookup);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use kun_peng::mmscanner::{reverse_complement, scan_minimizers, RollingLmer};
+use seqkmer::Meros;
+
+fn random_bases(len: usize) -> Vec<u64> {
+    let mut state = 0x9e3779b97f4a7c15u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % 4
+        })
+        .collect()
+}
+
+/// Canonical l-mers of a sequence, with a full and an incremental reverse complement
+fn canonical_lmers(c: &mut Criterion) {
+    let n = 31;
+    let bases = random_bases(1 << 20);
+    let mask = (1u64 << (n * 2)) - 1;
+    let mut group = c.benchmark_group("canonical_lmers");
+    group.throughput(Throughput::Elements(bases.len() as u64));
+    group.bench_function("reverse_complement", |b| {
+        b.iter(|| {
+            let mut lmer = 0u64;
+            let mut sum = 0u64;
+            for (i, &code) in bases.iter().enumerate() {
+                lmer = ((lmer << 2) | code) & mask;
+                if i + 1 >= n {
+                    sum = sum.wrapping_add(lmer.min(reverse_complement(lmer, n)));
+                }
+            }
+            black_box(sum)
+        })
+    });
+    group.bench_function("rolling", |b| {
+        b.iter(|| {
+            let mut lmer = RollingLmer::new(n);
+            let mut sum = 0u64;
+            for &code in &bases {
+                if lmer.push(code) {
+                    sum = sum.wrapping_add(lmer.canonical());
+                }
+            }
+            black_box(sum)
+        })
+    });
+    group.finish();
+}
+
+fn scan(c: &mut Criterion) {
+    let seq: Vec<u8> = random_bases(1 << 20)
+        .into_iter()
+        .map(|code| b"ACGT"[code as usize])
+        .collect();
+    let meros = Meros::new(35, 31, None, None, None);
+    let mut group = c.benchmark_group("scan_minimizers");
+    group.throughput(Throughput::Bytes(seq.len() as u64));
+    group.bench_function("1Mb", |b| {
+        b.iter(|| {
+            let mut minimizers = Vec::new();
+            scan_minimizers(black_box(&seq), &meros, &mut minimizers);
+            minimizers.len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, canonical_lmers, scan);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+use std::process::Command;
+
+// Records the git commit of the build for the provenance of the produced files.
+// A `KUN_PENG_GIT_COMMIT` set in the build environment takes precedence.
+fn main() {
+    println!("cargo:rerun-if-env-changed=KUN_PENG_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if std::env::var_os("KUN_PENG_GIT_COMMIT").is_some() {
+        return;
+    }
+    let output = Command::new("git").args(["rev-parse", "HEAD"]).output();
+    if let Ok(output) = output {
+        if output.status.success() {
+            let commit = String::from_utf8_lossy(&output.stdout);
+            println!("cargo:rustc-env=KUN_PENG_GIT_COMMIT={}", commit.trim());
+        }
+    }
+}
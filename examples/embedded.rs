@@ -0,0 +1,86 @@
+//! Embedding the classifier in an async service.
+//!
+//! One database is loaded once and shared by several tokio tasks through an `Arc`, each
+//! task classifying its own batch of reads. Classification is CPU bound, so the batches run
+//! on the blocking pool and the async workers stay free.
+//!
+//! The database is the one built by `build_and_classify`:
+//!
+//! ```sh
+//! cargo run --release --example build_and_classify
+//! cargo run --release --example embedded -- test_database data/COVID_19.fa
+//! ```
+use kun_peng::classifier::{ClassifierOptions, Database};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Number of tasks classifying at the same time
+const TASKS: usize = 4;
+/// Length of the reads cut from the genome
+const READ_LEN: usize = 150;
+
+#[tokio::main]
+async fn main() {
+    let workspace_root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut args = std::env::args().skip(1);
+    let db_dir = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| workspace_root.join("test_database"));
+    let fasta = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| workspace_root.join("data/COVID_19.fa"));
+
+    let db = Arc::new(Database::load(&db_dir).expect("Failed to load the database"));
+    let options = Arc::new(ClassifierOptions::default());
+    let classified = Arc::new(AtomicUsize::new(0));
+
+    // Cut the genome into reads and deal them out to the tasks
+    let content = fs::read_to_string(&fasta).expect("Failed to read the fasta file");
+    let genome: String = content.lines().filter(|l| !l.starts_with('>')).collect();
+    let reads: Vec<Vec<u8>> = genome
+        .as_bytes()
+        .chunks(READ_LEN)
+        .filter(|read| read.len() == READ_LEN)
+        .map(|read| read.to_vec())
+        .collect();
+    let total = reads.len();
+    let mut batches: Vec<Vec<(String, Vec<u8>)>> = vec![Vec::new(); TASKS];
+    for (i, read) in reads.into_iter().enumerate() {
+        batches[i % TASKS].push((format!("read_{}", i), read));
+    }
+
+    let mut tasks = Vec::with_capacity(TASKS);
+    for (task, batch) in batches.into_iter().enumerate() {
+        let (db, options, classified) = (db.clone(), options.clone(), classified.clone());
+        tasks.push(tokio::task::spawn_blocking(move || {
+            let results: Vec<_> = batch
+                .iter()
+                .map(|(id, read)| db.classify_mates(id, &[read], &options, &classified))
+                .collect();
+            (task, results)
+        }));
+    }
+
+    for handle in tasks {
+        let (task, results) = handle.await.expect("Classification task panicked");
+        let calls = results.iter().filter(|result| result.classified).count();
+        println!(
+            "task {}: {} of {} reads classified",
+            task,
+            calls,
+            results.len()
+        );
+        if let Some(result) = results.first() {
+            println!("{}", result);
+        }
+    }
+    println!(
+        "{} of {} reads classified",
+        classified.load(Ordering::Relaxed),
+        total
+    );
+}
@@ -0,0 +1,307 @@
+//! Cells of the compact hash table and rows of the hits of a read
+use core::cmp::Ordering as CmpOrdering;
+use core::fmt::Debug;
+
+/// Trait for compact hash operations
+pub trait Compact: Default + PartialEq + Clone + Copy + Eq + Sized + Send + Sync + Debug {
+    /// Creates a compacted value from a hash key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let compacted = u32::compacted(0x1234567890ABCDEF, 16);
+    /// assert_eq!(compacted, 0x1234);
+    /// ```
+    fn compacted(hash_key: u64, value_bits: usize) -> Self;
+
+    /// Creates a hash value from a hash key and a value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let hash_value = u32::hash_value(0x1234567890ABCDEF, 16, 0xABCD);
+    /// assert_eq!(hash_value, 0x1234ABCD);
+    /// ```
+    fn hash_value(hash_key: u64, value_bits: usize, value: Self) -> Self;
+
+    /// Returns the left part of the value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let value: u32 = 0x1234ABCD;
+    /// assert_eq!(value.left(16), 0x1234);
+    /// ```
+    fn left(&self, value_bits: usize) -> Self;
+
+    /// Returns the right part of the value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let value: u32 = 0x1234ABCD;
+    /// assert_eq!(value.right(0xFFFF), 0xABCD);
+    /// ```
+    fn right(&self, value_mask: usize) -> Self;
+
+    /// Combines left and right parts into a single value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let combined = u32::combined(0x1234, 0xABCD, 16);
+    /// assert_eq!(combined, 0x1234ABCD);
+    /// ```
+    fn combined(left: Self, right: Self, value_bits: usize) -> Self;
+
+    /// Converts the value to u32
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let value: u32 = 0x1234ABCD;
+    /// assert_eq!(value.to_u32(), 0x1234ABCD);
+    /// ```
+    fn to_u32(&self) -> u32;
+
+    /// Creates a value from u32
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Compact;
+    ///
+    /// let value = u32::from_u32(0x1234ABCD);
+    /// assert_eq!(value, 0x1234ABCD);
+    /// ```
+    fn from_u32(value: u32) -> Self;
+}
+
+impl Compact for u32 {
+    fn hash_value(hash_key: u64, value_bits: usize, value: u32) -> u32 {
+        Self::compacted(hash_key, value_bits) << value_bits | value
+    }
+    fn compacted(value: u64, value_bits: usize) -> u32 {
+        (value >> (32 + value_bits)) as u32
+    }
+
+    fn left(&self, value_bits: usize) -> u32 {
+        *self >> value_bits
+    }
+
+    fn right(&self, value_mask: usize) -> u32 {
+        *self & value_mask as u32
+    }
+    fn combined(left: Self, right: Self, value_bits: usize) -> Self {
+        left << value_bits | right
+    }
+
+    fn to_u32(&self) -> u32 {
+        *self
+    }
+    fn from_u32(value: u32) -> Self {
+        value
+    }
+}
+
+impl Compact for u64 {
+    fn hash_value(hash_key: u64, value_bits: usize, value: u64) -> u64 {
+        Self::compacted(hash_key, value_bits) << (32 + value_bits) | value
+    }
+    fn compacted(value: u64, value_bits: usize) -> u64 {
+        value >> (32 + value_bits)
+    }
+
+    fn left(&self, value_bits: usize) -> u64 {
+        *self >> (32 + value_bits)
+    }
+
+    fn right(&self, value_mask: usize) -> u64 {
+        let mask: u64 = ((value_mask as u64) << 32) | 0xFFFFFFFF;
+        mask & *self
+    }
+
+    fn combined(left: Self, right: Self, value_bits: usize) -> Self {
+        left << (32 + value_bits) | right
+    }
+
+    fn to_u32(&self) -> u32 {
+        *self as u32
+    }
+    fn from_u32(value: u32) -> Self {
+        value as u64
+    }
+}
+
+#[repr(C)]
+#[derive(PartialEq, Clone, Copy, Eq, Debug)]
+pub struct Row {
+    pub value: u32,
+    pub seq_id: u32,
+    /// 1-based position of the minimizer in the read, over all its mates.
+    /// Positions past `u32::MAX` are clamped, see [`Row::kmer_id_of`].
+    pub kmer_id: u32,
+}
+
+impl Row {
+    /// Creates a new Row
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Row;
+    ///
+    /// let row = Row::new(0x1234, 1, 2);
+    /// assert_eq!(row.value, 0x1234);
+    /// assert_eq!(row.seq_id, 1);
+    /// assert_eq!(row.kmer_id, 2);
+    /// ```
+    pub fn new(value: u32, seq_id: u32, kmer_id: u32) -> Self {
+        Self {
+            value,
+            seq_id,
+            kmer_id,
+        }
+    }
+
+    /// Converts a minimizer position to a `kmer_id`
+    ///
+    /// A read with more than `u32::MAX` minimizers would wrap around and corrupt its hit
+    /// string, its positions are clamped instead and, with the `std` feature, a warning is
+    /// printed once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::Row;
+    ///
+    /// assert_eq!(Row::kmer_id_of(100_000), 100_000);
+    /// assert_eq!(Row::kmer_id_of(u32::MAX as usize), u32::MAX);
+    /// assert_eq!(Row::kmer_id_of((1 << 32) + 5), u32::MAX);
+    /// ```
+    #[inline]
+    pub fn kmer_id_of(pos: usize) -> u32 {
+        u32::try_from(pos).unwrap_or_else(|_| {
+            warn_clamped();
+            u32::MAX
+        })
+    }
+
+    #[inline]
+    pub fn as_slice(&self, row_size: usize) -> &[u8] {
+        let slot_ptr = self as *const Self as *const u8;
+        unsafe { core::slice::from_raw_parts(slot_ptr, row_size) }
+    }
+}
+
+#[cfg(feature = "std")]
+fn warn_clamped() {
+    static WARN: std::sync::Once = std::sync::Once::new();
+    WARN.call_once(|| {
+        eprintln!(
+            "WARNING: a read has more than {} minimizers, the positions past it are clamped",
+            u32::MAX
+        )
+    });
+}
+
+#[cfg(not(feature = "std"))]
+fn warn_clamped() {}
+
+// Implement PartialOrd, comparing only the kmer_id field
+impl PartialOrd for Row {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Implement Ord, comparing only the kmer_id field
+impl Ord for Row {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.kmer_id.cmp(&other.kmer_id)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Slot<B>
+where
+    B: Compact,
+{
+    pub idx: usize,
+    pub value: B,
+}
+
+impl<B> Slot<B>
+where
+    B: Compact,
+{
+    /// Creates a new Slot
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::{Slot, Compact};
+    ///
+    /// let slot = Slot::<u32>::new(1, 0x1234);
+    /// assert_eq!(slot.idx, 1);
+    /// assert_eq!(slot.value, 0x1234);
+    /// ```
+    pub fn new(idx: usize, value: B) -> Self {
+        Self { idx, value }
+    }
+
+    #[inline]
+    pub fn as_slice(&self, slot_size: usize) -> &[u8] {
+        let slot_ptr = self as *const Self as *const u8;
+        unsafe { core::slice::from_raw_parts(slot_ptr, slot_size) }
+    }
+}
+
+impl Slot<u64> {
+    /// Returns the sequence ID (lower 32 bits) for a u64 Slot
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng_core::compact::{Slot, Compact};
+    ///
+    /// let slot = Slot::<u64>::new(1, 0x1234567890ABCDEF);
+    /// assert_eq!(slot.get_seq_id(), 0x90ABCDEF);
+    /// ```
+    pub fn get_seq_id(&self) -> u32 {
+        self.value.right(0) as u32
+    }
+}
+
+// Implement PartialOrd, comparing only the idx field
+impl<B> PartialOrd for Slot<B>
+where
+    B: Compact,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Implement Ord, comparing only the idx field
+impl<B> Ord for Slot<B>
+where
+    B: Compact,
+{
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.idx.cmp(&other.idx)
+    }
+}
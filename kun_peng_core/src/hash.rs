@@ -1,6 +1,6 @@
+use core::hash::{BuildHasher, Hasher};
 use seahash::SeaHasher;
 use serde::{Deserialize, Serialize};
-use std::hash::{BuildHasher, Hasher};
 
 // 声明常量
 const M1: u64 = 0xff51afd7ed558ccd;
@@ -10,7 +10,7 @@ const M2: u64 = 0xc4ceb9fe1a85ec53;
 /// # Examples
 ///
 /// ```
-/// # use kun_peng::fmix64;
+/// # use kun_peng_core::fmix64;
 /// let key: u64 = 123;
 /// let hash = fmix64(key);
 /// assert_eq!(hash, 9208534749291869864);
@@ -36,7 +36,7 @@ const C2: u64 = 0x4cf5ad432745937f;
 /// # Examples
 ///
 /// ```
-/// use kun_peng::murmur_hash3;
+/// use kun_peng_core::murmur_hash3;
 ///
 /// let key: u64 = 123;
 /// let hash = murmur_hash3(key);
@@ -62,7 +62,7 @@ pub fn murmur_hash3(key: u64) -> u64 {
 /// # Examples
 ///
 /// ```
-/// use kun_peng::sea_hash;
+/// use kun_peng_core::sea_hash;
 ///
 /// let key: u64 = 123;
 /// let hash = sea_hash(key);
@@ -81,7 +81,7 @@ pub fn sea_hash(key: u64) -> u64 {
 ///
 /// ```
 /// use std::hash::{Hasher, BuildHasher};
-/// use kun_peng::{KHasher, KBuildHasher};
+/// use kun_peng_core::{KHasher, KBuildHasher};
 ///
 /// let mut hasher = KBuildHasher.build_hasher();
 /// hasher.write_u64(123);
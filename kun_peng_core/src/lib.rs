@@ -0,0 +1,17 @@
+//! The parts of Kun-peng that do no file IO and start no threads
+//!
+//! The k-mer hash and rank helpers extracted from `kun_peng`, which re-exports them: the
+//! k-mer hashes, the cells of the compact hash table and the taxonomic ranks. Classifying
+//! is not here. The minimizer scanner builds on the `Meros` and readers of `seqkmer`, and
+//! the hash table, the taxonomy and `resolve`/`process_hitgroup` stay in `kun_peng` with
+//! them. Without the default `std` feature the crate only needs `alloc` and builds for
+//! `wasm32-unknown-unknown`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod compact;
+mod hash;
+pub mod rank;
+
+pub use hash::*;
@@ -0,0 +1,79 @@
+//! Standard taxonomic ranks and their report codes
+use alloc::format;
+use alloc::string::String;
+
+/// Codes of the standard ranks used in the reports, from the root down
+pub const RANK_CODES: [char; 8] = ['D', 'K', 'P', 'C', 'O', 'F', 'G', 'S'];
+
+/// Weight of the hits of a species and below, one less for every standard rank above
+pub const MAX_RANK_WEIGHT: u64 = RANK_CODES.len() as u64;
+
+/// Smallest taxid of the custom taxa, added to the NCBI taxonomy of a database
+pub const EXTRA_TAXID_MIN: u64 = 2_000_000_000;
+
+/// Largest taxid of the custom taxa, the reports write taxids as 32 bit numbers
+pub const EXTRA_TAXID_MAX: u64 = u32::MAX as u64;
+
+/// Get the report code of a standard rank, `None` for the other ranks
+pub fn rank_code(rank: &str) -> Option<char> {
+    match rank {
+        "superkingdom" => Some('D'),
+        "kingdom" => Some('K'),
+        "phylum" => Some('P'),
+        "class" => Some('C'),
+        "order" => Some('O'),
+        "family" => Some('F'),
+        "genus" => Some('G'),
+        "species" => Some('S'),
+        _ => None,
+    }
+}
+
+/// The rank name of a report code, the inverse of [`rank_code`]
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng_core::rank::{rank_code, rank_name, RANK_CODES};
+///
+/// assert_eq!(rank_name('G'), "genus");
+/// assert!(RANK_CODES.iter().all(|&code| rank_code(rank_name(code)) == Some(code)));
+/// ```
+pub fn rank_name(code: char) -> &'static str {
+    match code {
+        'D' => "superkingdom",
+        'K' => "kingdom",
+        'P' => "phylum",
+        'C' => "class",
+        'O' => "order",
+        'F' => "family",
+        'G' => "genus",
+        'S' => "species",
+        _ => "no rank",
+    }
+}
+
+/// Parse a rank given as a report code (`G`) or a rank name (`genus`)
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng_core::rank::parse_rank;
+///
+/// assert_eq!(parse_rank("G"), Ok('G'));
+/// assert_eq!(parse_rank("s"), Ok('S'));
+/// assert_eq!(parse_rank("phylum"), Ok('P'));
+/// assert!(parse_rank("strain").is_err());
+/// ```
+pub fn parse_rank(s: &str) -> Result<char, String> {
+    let upper = s.to_ascii_uppercase();
+    match upper.chars().next() {
+        Some(code) if upper.len() == 1 && RANK_CODES.contains(&code) => Ok(code),
+        _ => rank_code(&s.to_ascii_lowercase()).ok_or_else(|| {
+            format!(
+                "unknown rank `{}`, expected one of D, K, P, C, O, F, G, S or a rank name",
+                s
+            )
+        }),
+    }
+}
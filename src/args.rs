@@ -1,12 +1,18 @@
+use crate::classify::ScoreMode;
+use crate::compact_hash::TableLayout;
+use crate::mmscanner::IupacPolicy;
+use crate::output::ConfidenceBands;
+use crate::taxonomy::parse_rank;
 use crate::utils::expand_spaced_seed_mask;
 use crate::{construct_seed_template, parse_binary};
-use clap::Parser;
+use clap::{Command, Parser};
 use seqkmer::Meros;
 use seqkmer::{
     BITS_PER_CHAR, DEFAULT_KMER_LENGTH, DEFAULT_MINIMIZER_LENGTH, DEFAULT_MINIMIZER_SPACES,
     DEFAULT_TOGGLE_MASK,
 };
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 
 pub const U32MAXPLUS: u64 = u32::MAX as u64;
 pub const ONEGB: u64 = 1073741824;
@@ -29,8 +35,34 @@ pub struct Build {
     #[clap(short, long, value_parser = clap::value_parser!(u8).range(0..31), default_value_t = 0)]
     pub requested_bits_for_taxid: u8,
 
+    /// How to scan the IUPAC ambiguity codes (R, Y, ...) of the genomes: `break` the k-mers
+    /// like an N, read them as their `first-base` in ACGT order, or `skip` them.
+    #[clap(long = "iupac", value_name = "POLICY", default_value = "break")]
+    pub iupac: IupacPolicy,
+
+    /// Skip the spaces and tabs inside the sequences, like the line breaks, instead of
+    /// ending the k-mers at them.
+    #[clap(long = "skip-whitespace")]
+    pub skip_whitespace: bool,
+
+    /// Index the minimizers of the forward strand only, for strand-specific data such as
+    /// RNA. `direct` and `classify` refuse such an index, they scan reads canonically.
+    #[clap(long = "forward-only")]
+    pub forward_only: bool,
+
+    /// Layout of the hash table: `linear` probing from the cell of each minimizer, like
+    /// Kraken 2, or `bucketed`, probing 64 byte buckets of 16 cells at a time, which stalls
+    /// less on memory at high load. Older builds can't read a bucketed index.
+    #[clap(long = "table-layout", value_name = "LAYOUT", default_value = "linear")]
+    pub table_layout: TableLayout,
+
+    /// Leave out the minimizers whose LCA sits above this rank (e.g. `G` or `genus`), such
+    /// as those of repeats shared by many genomes.
+    #[clap(long = "max-minimizer-rank", value_parser = parse_rank)]
+    pub max_minimizer_rank: Option<char>,
+
     /// Number of threads
-    #[clap(short = 'p', long, default_value_t = num_cpus::get())]
+    #[clap(short = 'p', long, value_parser = parse_threads, default_value_t = default_threads())]
     pub threads: usize,
 }
 
@@ -43,13 +75,48 @@ const BUFFER_SIZE: usize = 16 * 1024 * 1024;
 /// combines the functionality of the 'splitr', 'annotate', and 'resolve' commands into a single workflow.
 /// This command streamlines the process of splitting fast(q/a) files, annotating sequences, and resolving the taxonomy tree,
 /// providing a comprehensive solution for sequence classification.
+///
+/// # Examples
+///
+/// Invalid option combinations are rejected by the parser:
+///
+/// ```
+/// use clap::{error::ErrorKind, Parser};
+/// use kun_peng::args::ClassifyArgs;
+///
+/// let base = ["classify", "--db", "db", "--chunk-dir", "chunks"];
+/// let cases: &[(&[&str], ErrorKind, &str)] = &[
+///     (&["-S", "r.fq"], ErrorKind::MissingRequiredArgument, "--paired-end-processing"),
+///     (&["--report-zero-counts", "r.fq"], ErrorKind::MissingRequiredArgument, "--output-dir"),
+///     (&["--report-kmer-data", "r.fq"], ErrorKind::MissingRequiredArgument, "--output-dir"),
+///     (&["-T", "1.5", "r.fq"], ErrorKind::ValueValidation, "must be between 0 and 1"),
+///     (&["-T=-0.1", "r.fq"], ErrorKind::ValueValidation, "must be between 0 and 1"),
+///     (&["--min-classified-warn", "2", "r.fq"], ErrorKind::ValueValidation, "must be between 0 and 1"),
+/// ];
+///
+/// for (extra, kind, message) in cases {
+///     let argv: Vec<&str> = base.iter().chain(extra.iter()).copied().collect();
+///     let err = ClassifyArgs::try_parse_from(&argv).unwrap_err();
+///     assert_eq!(err.kind(), *kind, "{:?}", argv);
+///     assert!(err.to_string().contains(message), "{:?}: {}", argv, err);
+/// }
+///
+/// let argv = ["-P", "-S", "-z", "--output-dir", "out", "-T", "0.1", "r.fq"];
+/// assert!(ClassifyArgs::try_parse_from(base.iter().chain(argv.iter())).is_ok());
+/// ```
 #[derive(Parser, Debug, Clone)]
 #[clap(
     version,
     about = "Integrates 'splitr', 'annotate', and 'resolve' into a unified workflow for sequence classification. classify a set of sequences",
-    long_about = "classify a set of sequences"
+    long_about = "classify a set of sequences",
+    args_override_self = true
 )]
 pub struct ClassifyArgs {
+    /// Read options from a TOML file of `option = value` lines, named like the long flags
+    /// (e.g. `confidence-threshold = 0.1`). Flags on the command line override the file.
+    #[clap(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
     /// database hash chunk directory and other files
     #[arg(long = "db", required = true)]
     pub database: PathBuf,
@@ -62,14 +129,35 @@ pub struct ClassifyArgs {
     #[clap(long = "output-dir", value_parser)]
     pub output_dir: Option<PathBuf>,
 
+    /// Also split the Kraken output lines into `<prefix>.classified.kraken` and `<prefix>.unclassified.kraken`.
+    #[clap(long = "split-output", value_parser)]
+    pub split_output: Option<PathBuf>,
+
+    /// Write the IDs of the classified reads to this file, one per line (gzip if it ends with `.gz`).
+    #[clap(long = "classified-ids-output", value_parser)]
+    pub classified_ids_output: Option<PathBuf>,
+
+    /// Write the IDs of the unclassified reads to this file, one per line (gzip if it ends with `.gz`).
+    #[clap(long = "unclassified-ids-output", value_parser)]
+    pub unclassified_ids_output: Option<PathBuf>,
+
     /// Enable paired-end processing.
     #[clap(short = 'P', long = "paired-end-processing", action)]
     pub paired_end_processing: bool,
 
     /// Process pairs with mates in the same file.
-    #[clap(short = 'S', long = "single-file-pairs", action)]
+    #[clap(
+        short = 'S',
+        long = "single-file-pairs",
+        action,
+        requires = "paired_end_processing"
+    )]
     pub single_file_pairs: bool,
 
+    /// Treat all input files as consecutive parts of one sample, with a global read index.
+    #[clap(long = "merge-parts", action)]
+    pub merge_parts: bool,
+
     /// Minimum quality score for FASTQ data.
     #[clap(
         short = 'Q',
@@ -79,10 +167,43 @@ pub struct ClassifyArgs {
     )]
     pub minimum_quality_score: i32,
 
+    /// Don't sample the read lengths of the first input to warn about reads short for the
+    /// k-mer length of the database.
+    #[clap(long)]
+    pub no_length_check: bool,
+
+    /// Don't sample the characters of the first input to refuse protein reads for a DNA
+    /// database, or nucleotide reads for a protein one.
+    #[clap(long)]
+    pub ignore_alphabet_check: bool,
+
+    /// How to scan the IUPAC ambiguity codes (R, Y, ...) of the reads: `break` the k-mers like
+    /// an N, read them as their `first-base` in ACGT order, or `skip` them.
+    #[clap(long = "iupac", value_name = "POLICY", default_value = "break")]
+    pub iupac: IupacPolicy,
+
+    /// Skip the spaces and tabs inside the sequences, like the line breaks, instead of
+    /// ending the k-mers at them.
+    #[clap(long = "skip-whitespace")]
+    pub skip_whitespace: bool,
+
+    /// Cut this many bases from the start of every mate before scanning, e.g. adapter or
+    /// low-quality bases. Mates trimmed to nothing count as too short.
+    #[clap(long = "trim-head", value_name = "N", default_value_t = 0)]
+    pub trim_head: usize,
+
+    /// Cut this many bases from the end of every mate before scanning.
+    #[clap(long = "trim-tail", value_name = "N", default_value_t = 0)]
+    pub trim_tail: usize,
+
     /// The number of threads to use.
-    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    #[clap(short = 'p', long = "num-threads", value_parser = parse_threads, default_value_t = default_threads())]
     pub num_threads: usize,
 
+    /// The number of threads compressing the gzip outputs, on top of --num-threads.
+    #[clap(long = "io-threads", value_parser, default_value_t = 2)]
+    pub io_threads: usize,
+
     #[clap(long, default_value_t = BUFFER_SIZE)]
     pub buffer_size: usize,
 
@@ -90,15 +211,58 @@ pub struct ClassifyArgs {
     #[clap(long, value_parser = clap::value_parser!(u32).range(1..=32), default_value_t = 4)]
     pub batch_size: u32,
 
+    /// Deflate compress the hit intermediate written by annotate, smaller and slower
+    #[clap(long)]
+    pub compress_spill: bool,
+
     /// Confidence score threshold
     #[clap(
         short = 'T',
         long = "confidence-threshold",
-        value_parser,
+        value_parser = parse_fraction,
         default_value_t = 0.0
     )]
     pub confidence_threshold: f64,
 
+    /// The minimum number of minimizers supporting a call, whatever the read length. Short
+    /// reads passing --confidence-threshold with a couple of hits stay unclassified.
+    #[clap(long = "min-supporting-minimizers", value_parser, default_value_t = 0)]
+    pub min_supporting_minimizers: u64,
+
+    /// Append a High/Medium/Low confidence column, from the `medium,high` thresholds
+    /// on the fraction of minimizers supporting the call (e.g. `0.5,0.8`).
+    #[clap(long = "confidence-bands", value_name = "MEDIUM,HIGH")]
+    pub confidence_bands: Option<ConfidenceBands>,
+
+    /// Append a column with the coverage breadth of the call, the fraction of the read
+    /// segments with a hit in the called clade.
+    #[clap(long = "report-read-coverage", action)]
+    pub report_read_coverage: bool,
+
+    /// Append a column with the calls of the mates of a pair classified on their own,
+    /// `taxid1|taxid2` (`-` for single reads), to spot pairs whose mates disagree.
+    #[clap(long = "report-mate-calls", action)]
+    pub report_mate_calls: bool,
+
+    /// Append this tag as the last column of every output line and record it in the reports,
+    /// to tell runs apart after merging.
+    #[clap(long = "read-group", value_parser = parse_read_group)]
+    pub read_group: Option<String>,
+
+    /// Record the version, the parameters and the inputs of the run in a
+    /// `# provenance:` header line of the reports.
+    #[clap(long = "provenance", action)]
+    pub provenance: bool,
+
+    /// With --provenance, also record the xxHash64 checksums of the input files.
+    #[clap(long = "hash-inputs", action, requires = "provenance")]
+    pub hash_inputs: bool,
+
+    /// Record the k-mer and minimizer parameters of the database (k, l, spaced seed mask,
+    /// toggle mask, type) in a `# index:` header line of the reports.
+    #[clap(long = "index-params", action)]
+    pub index_params: bool,
+
     /// The minimum number of hit groups needed for a call.
     #[clap(
         short = 'g',
@@ -108,14 +272,115 @@ pub struct ClassifyArgs {
     )]
     pub minimum_hit_groups: usize,
 
-    /// In comb. w/ -R, provide minimizer information in report
-    #[clap(short = 'K', long, value_parser, default_value_t = false)]
+    /// The number of non-matching minimizers allowed between two hits of the same hit group.
+    /// If not set, every minimizer of the read counts as a hit group.
+    #[clap(long = "hit-group-gap", value_parser)]
+    pub hit_group_gap: Option<usize>,
+
+    /// Leave the reads with hits in fewer than --minimum-hit-groups hit groups out of the
+    /// outputs, counted as filtered rather than unclassified.
+    #[clap(long = "filter-hit-groups", action)]
+    pub filter_hit_groups: bool,
+
+    /// Report calls above this rank (e.g. `S` or `genus`) as unclassified.
+    #[clap(long = "max-rank", value_parser = parse_rank)]
+    pub max_rank: Option<char>,
+
+    /// Weigh every hit by the rank of its taxon, hits of general taxa count less.
+    #[clap(long = "weight-by-rank", action)]
+    pub weight_by_rank: bool,
+
+    /// Call the LCA of every taxon hit by a minimizer of the read, whatever the counts,
+    /// the confidence threshold and the hit groups.
+    #[clap(long = "lca-of-all", action, conflicts_with = "weight_by_rank")]
+    pub lca_of_all: bool,
+
+    /// How the hits of a read add up: `positions` counts a minimizer at every position it
+    /// hits, as Kraken 2 does, `distinct` once per read, so repeats weigh less.
+    #[clap(long = "score-mode", value_name = "MODE", default_value = "positions")]
+    pub score_mode: ScoreMode,
+
+    /// Report calls with hits in less than this fraction of the read segments as
+    /// unclassified, e.g. `0.2` against hits clustered in a repeat.
+    #[clap(long = "min-read-coverage", value_parser = parse_fraction, default_value_t = 0.0)]
+    pub min_read_coverage: f64,
+
+    /// Warn when the fraction of classified sequences is below this value.
+    #[clap(long = "min-classified-warn", value_parser = parse_fraction, default_value_t = 0.05)]
+    pub min_classified_warn: f64,
+
+    /// Exit with an error instead of a warning when the classified fraction is below `--min-classified-warn`.
+    #[clap(long = "min-classified-error", action)]
+    pub min_classified_error: bool,
+
+    /// Periodically overwrite this file with the running counts as JSON
+    /// (`{"processed":N,"classified":M,"rate":R}`), for monitoring.
+    #[clap(long = "status-file", value_parser)]
+    pub status_file: Option<PathBuf>,
+
+    /// Write the totals, the per file counts, the stage timings and the warnings of the run
+    /// to this file as JSON, see `kun_peng::summary::RunSummary` for the schema.
+    #[clap(long = "summary-output", value_parser)]
+    pub summary_output: Option<PathBuf>,
+
+    /// In comb. w/ --output-dir, provide minimizer information in report
+    #[clap(
+        short = 'K',
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "output_dir"
+    )]
     pub report_kmer_data: bool,
 
-    /// In comb. w/ -R, report taxa w/ 0 count
-    #[clap(short = 'z', long, value_parser, default_value_t = false)]
+    /// In comb. w/ --output-dir, report taxa w/ 0 count
+    #[clap(
+        short = 'z',
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "output_dir"
+    )]
     pub report_zero_counts: bool,
 
+    /// Report unclassified sequences as a top-level `U` row.
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
+    pub report_include_unclassified: bool,
+
+    /// Deepest level indented in the Kraken report, deeper taxa keep their counts and
+    /// the indentation of this level.
+    #[clap(
+        long = "report-max-indent",
+        value_name = "DEPTH",
+        default_value_t = 100
+    )]
+    pub report_max_indent: usize,
+
+    /// In comb. w/ --output-dir, write `output_<N>.sparse.tsv`, a flat table of the taxa
+    /// with reads, their counts and their full lineage, without the tree structure.
+    #[clap(long = "report-sparse", action, requires = "output_dir")]
+    pub report_sparse: bool,
+
+    /// In comb. w/ --output-dir, write `output_<N>.<rank>.tsv` with the reads of every taxon
+    /// of a rank (e.g. `G` or `genus`), the reads above it on an `unassigned at <rank>` row.
+    #[clap(long = "report-rank", value_name = "RANK", value_parser = parse_rank, requires = "output_dir")]
+    pub report_rank: Option<char>,
+
+    /// In comb. w/ --output-dir, write `output_<N>.tree.json`, the taxa with reads as a
+    /// nested tree, for sunburst and other hierarchical charts.
+    #[clap(long = "report-tree-json", action, requires = "output_dir")]
+    pub report_tree_json: bool,
+
+    /// In comb. w/ --output-dir, sum the reads of custom taxon groups into
+    /// `output_<N>.groups.tsv`, from a `group_name<TAB>taxid,taxid,...` file.
+    #[clap(long = "groups", requires = "output_dir")]
+    pub groups: Option<PathBuf>,
+
+    /// In comb. w/ --output-dir, write `output_<N>.abundance.tsv` with the reads of each
+    /// taxon normalized by its genome size, from a `taxid<TAB>bp` file.
+    #[clap(long = "genome-sizes", requires = "output_dir")]
+    pub genome_sizes: Option<PathBuf>,
+
     // /// output file contains all unclassified sequence
     // #[clap(long, value_parser, default_value_t = false)]
     // pub full_output: bool,
@@ -126,6 +391,130 @@ pub struct ClassifyArgs {
     pub input_files: Vec<PathBuf>,
 }
 
+/// Inserts the options of the `--config` file of `command` into its command line
+///
+/// `argv` is the full command line, with `name` as the program or the subcommand name.
+/// The options of the file go right after the name, so with `args_override_self` the flags
+/// given on the command line win. Without `--config` the command line is returned as is.
+///
+/// # Examples
+///
+/// ```
+/// use clap::{CommandFactory, Parser};
+/// use kun_peng::args::{expand_config_args, ClassifyArgs};
+///
+/// let config = std::env::temp_dir().join("kun_peng_classify_config.toml");
+/// std::fs::write(
+///     &config,
+///     "db = \"/data/db\"\nchunk_dir = \"chunks\"\nconfidence-threshold = 0.1\n\
+///      num-threads = 4\noutput-dir = \"out\"\nreport-zero-counts = true\n\
+///      report-read-coverage = false\nreport-include-unclassified = false\n",
+/// )
+/// .unwrap();
+/// let config = config.to_str().unwrap();
+///
+/// let argv = ["classify", "--config", config, "-p", "16", "r.fq"];
+/// let argv: Vec<_> = argv.iter().map(Into::into).collect();
+/// let argv = expand_config_args(argv, "classify", &ClassifyArgs::command()).unwrap();
+/// let args = ClassifyArgs::try_parse_from(argv).unwrap();
+/// assert_eq!(args.database.to_str(), Some("/data/db"));
+/// assert_eq!(args.confidence_threshold, 0.1);
+/// assert!(args.report_zero_counts && !args.report_read_coverage);
+/// assert!(!args.report_include_unclassified);
+/// // The command line overrides the file
+/// assert_eq!(args.num_threads, 16);
+/// assert_eq!(args.input_files.len(), 1);
+///
+/// std::fs::write(std::env::temp_dir().join("kun_peng_bad.toml"), "confidence = 0.1\n").unwrap();
+/// let bad = std::env::temp_dir().join("kun_peng_bad.toml");
+/// let argv = vec!["classify".into(), "--config".into(), bad.into_os_string()];
+/// let err = expand_config_args(argv, "classify", &ClassifyArgs::command()).unwrap_err();
+/// assert!(err.contains("unknown option `confidence`"), "{}", err);
+/// ```
+pub fn expand_config_args(
+    argv: Vec<OsString>,
+    name: &str,
+    command: &Command,
+) -> Result<Vec<OsString>, String> {
+    let Some(name_pos) = argv.iter().take(2).position(|arg| arg == name) else {
+        return Ok(argv);
+    };
+    let mut config = None;
+    for (i, arg) in argv.iter().enumerate().skip(name_pos + 1) {
+        if arg == "--" {
+            break;
+        } else if arg == "--config" {
+            config = argv.get(i + 1).map(PathBuf::from);
+        } else if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            config = Some(PathBuf::from(path));
+        }
+    }
+    let Some(config) = config else {
+        return Ok(argv);
+    };
+
+    let options = read_config_file(&config, command)?;
+    let mut expanded = argv;
+    expanded.splice(
+        name_pos + 1..name_pos + 1,
+        options.into_iter().map(OsString::from),
+    );
+    Ok(expanded)
+}
+
+/// The options of a TOML config file of `command` as command line arguments
+fn read_config_file(path: &Path, command: &Command) -> Result<Vec<String>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read config file {}: {}", path.display(), e))?;
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e| format!("invalid config file {}: {}", path.display(), e))?;
+
+    let mut options = Vec::new();
+    for (key, value) in &table {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) || arg.get_id() == key.as_str())
+            .filter(|arg| arg.get_long().is_some() && arg.get_id() != "config")
+            .ok_or_else(|| format!("unknown option `{}` in {}", key, path.display()))?;
+        let flag = format!("--{}", arg.get_long().unwrap());
+        let value = match value {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Float(f) => f.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            _ => {
+                return Err(format!(
+                    "option `{}` in {} must be a string, a number or a boolean",
+                    key,
+                    path.display()
+                ))
+            }
+        };
+        if arg.get_action().takes_values() {
+            options.push(flag);
+            options.push(value);
+        } else {
+            match value.as_str() {
+                "true" => options.push(flag),
+                "false" => {}
+                _ => {
+                    return Err(format!(
+                        "option `{}` in {} must be true or false",
+                        key,
+                        path.display()
+                    ))
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// The longest minimizer whose mask still fits a u64 shift
+const MAX_MINIMIZER_LENGTH: i64 = (64 / BITS_PER_CHAR - 1) as i64;
+
 #[derive(Parser, Debug, Clone, Copy)]
 #[clap(version, about = "k-mer")]
 pub struct KLMTArgs {
@@ -134,7 +523,7 @@ pub struct KLMTArgs {
     pub k_mer: u64,
 
     /// Set length of minimizers, 1 <= l <= 31
-    #[clap(short, long, value_parser = clap::value_parser!(u8).range(1..=31), default_value_t = DEFAULT_MINIMIZER_LENGTH)]
+    #[clap(short, long, value_parser = clap::value_parser!(u8).range(1..=MAX_MINIMIZER_LENGTH), default_value_t = DEFAULT_MINIMIZER_LENGTH)]
     pub l_mer: u8,
 
     // /// Spaced seed mask
@@ -168,6 +557,78 @@ impl KLMTArgs {
     }
 }
 
+/// Parse a fraction between 0 and 1
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::args::parse_fraction;
+///
+/// assert_eq!(parse_fraction("0.25"), Ok(0.25));
+/// assert!(parse_fraction("1.5").is_err());
+/// assert!(parse_fraction("abc").is_err());
+/// ```
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let value: f64 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("`{}` must be between 0 and 1", s))
+    }
+}
+
+/// Parse a read group tag, it must fit in one tab separated column
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::args::parse_read_group;
+///
+/// assert_eq!(parse_read_group("run7_lane1"), Ok("run7_lane1".to_string()));
+/// assert!(parse_read_group("").is_err());
+/// assert!(parse_read_group("run\t7").is_err());
+/// assert!(parse_read_group("run 7").is_err());
+/// ```
+pub fn parse_read_group(s: &str) -> Result<String, String> {
+    if s.is_empty() || s.contains(char::is_whitespace) {
+        Err(format!(
+            "`{}` must be a non-empty tag without whitespace",
+            s
+        ))
+    } else {
+        Ok(s.to_string())
+    }
+}
+
+/// The fewest threads the parallel readers run with: one reads, one collects and at least
+/// one works.
+pub const MIN_THREADS: usize = 3;
+
+/// The default thread count, one per CPU but no fewer than [`MIN_THREADS`]
+pub fn default_threads() -> usize {
+    num_cpus::get().max(MIN_THREADS)
+}
+
+/// Parse a thread count, raised to [`MIN_THREADS`] on small machines
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::args::parse_threads;
+///
+/// assert_eq!(parse_threads("8"), Ok(8));
+/// assert_eq!(parse_threads("1"), Ok(3));
+/// assert!(parse_threads("0").is_err());
+/// assert!(parse_threads("many").is_err());
+/// ```
+pub fn parse_threads(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("the thread count must be at least 1".to_string()),
+        Ok(threads) => Ok(threads.max(MIN_THREADS)),
+        Err(_) => Err(format!("`{}` is not a thread count", s)),
+    }
+}
+
 /// Parse size string to usize
 ///
 /// # Examples
@@ -0,0 +1,364 @@
+//! Sizes of the read batches of a classification, from a memory budget
+//!
+//! The reads of the batches in flight, read ahead, being classified or waiting to be
+//! written, take `queue_len × batch_reads × bases per read × BATCH_SAFETY_FACTOR` bytes at
+//! most. [`BatchPlan`] keeps that under `--max-batch-memory`: short reads get full batches
+//! and two batches per thread, long reads smaller batches, down to one read, and then
+//! fewer batches in flight.
+use crate::reader::{sample_read_lengths, LENGTH_SAMPLE_READS};
+use seqkmer::{Base, Reader};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::Result;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Bytes of memory per base of a batch in flight: the record as read, its prepared copy,
+/// the minimizers and hits of its k-mers and its output line
+pub const BATCH_SAFETY_FACTOR: u64 = 4;
+
+/// Most reads in a batch, the batches of short reads
+pub const MAX_BATCH_READS: usize = 4096;
+
+/// Batches in flight per classifying thread, one being classified and one waiting
+pub const BATCHES_PER_THREAD: usize = 2;
+
+/// Bases per read assumed for inputs that can't be sampled
+pub const DEFAULT_READ_BASES: u64 = 150;
+
+/// Number of reads per batch and of batches in flight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchPlan {
+    /// Batches read ahead, classified or waiting to be written at once
+    pub queue_len: usize,
+    /// Reads per batch
+    pub batch_reads: usize,
+    /// Mean bases of a read, over its mates
+    pub read_bases: u64,
+}
+
+impl BatchPlan {
+    /// The plan fitting `max_memory`, unless `queue_len` or `batch_reads` are given
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::batching::{BatchPlan, BATCH_SAFETY_FACTOR, MAX_BATCH_READS};
+    ///
+    /// let gib = 1 << 30;
+    /// let fits = |plan: BatchPlan, max_memory: u64| {
+    ///     plan.memory() <= max_memory
+    ///         || plan.batch_reads == 1 && plan.queue_len == 1
+    /// };
+    ///
+    /// // Short reads, full batches and two per thread
+    /// let plan = BatchPlan::new(gib, 16, 150, None, None);
+    /// assert_eq!((plan.queue_len, plan.batch_reads), (32, MAX_BATCH_READS));
+    /// // Nanopore reads, smaller batches
+    /// let plan = BatchPlan::new(gib, 16, 20_000, None, None);
+    /// assert_eq!((plan.queue_len, plan.batch_reads), (32, 419));
+    /// // Long reads on many threads, one read a batch and fewer batches
+    /// let plan = BatchPlan::new(gib, 128, 2_000_000, None, None);
+    /// assert_eq!((plan.queue_len, plan.batch_reads), (134, 1));
+    /// // A read above the budget still goes through, alone
+    /// let plan = BatchPlan::new(gib, 8, 1 << 30, None, None);
+    /// assert_eq!((plan.queue_len, plan.batch_reads), (1, 1));
+    ///
+    /// for threads in [1, 2, 4, 16, 64, 256] {
+    ///     for read_bases in [100, 150, 300, 1_000, 15_000, 100_000, 1_000_000, 50_000_000] {
+    ///         for max_memory in [64 << 20, gib, 16 * gib] {
+    ///             let plan = BatchPlan::new(max_memory, threads, read_bases, None, None);
+    ///             assert!(fits(plan, max_memory), "{:?}", plan);
+    ///             assert!((1..=MAX_BATCH_READS).contains(&plan.batch_reads));
+    ///             assert!((1..=2 * threads).contains(&plan.queue_len));
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// // The overrides are kept, the other knob still fits the budget
+    /// let plan = BatchPlan::new(gib, 16, 20_000, Some(4), None);
+    /// assert_eq!((plan.queue_len, plan.batch_reads), (4, 3355));
+    /// let plan = BatchPlan::new(gib, 16, 20_000, None, Some(1000));
+    /// assert_eq!((plan.queue_len, plan.batch_reads), (13, 1000));
+    /// assert_eq!(BatchPlan::new(gib, 16, 150, Some(3), Some(7)).batch_reads, 7);
+    /// assert_eq!(plan.memory(), 13 * 1000 * 20_000 * BATCH_SAFETY_FACTOR);
+    /// ```
+    pub fn new(
+        max_memory: u64,
+        num_threads: usize,
+        read_bases: u64,
+        queue_len: Option<usize>,
+        batch_reads: Option<usize>,
+    ) -> Self {
+        let read_bytes = read_bases.max(1) * BATCH_SAFETY_FACTOR;
+        let reads_fitting = |batches: usize| (max_memory / (batches as u64 * read_bytes)) as usize;
+        let threads_queue = BATCHES_PER_THREAD * num_threads.max(1);
+        let (queue_len, batch_reads) = match (queue_len, batch_reads) {
+            (Some(queue_len), Some(batch_reads)) => (queue_len, batch_reads),
+            (Some(queue_len), None) => {
+                let queue_len = queue_len.max(1);
+                (
+                    queue_len,
+                    reads_fitting(queue_len).clamp(1, MAX_BATCH_READS),
+                )
+            }
+            (None, Some(batch_reads)) => {
+                let batches = reads_fitting(1) / batch_reads.max(1);
+                (batches.clamp(1, threads_queue), batch_reads)
+            }
+            (None, None) => {
+                let batch_reads = reads_fitting(threads_queue).clamp(1, MAX_BATCH_READS);
+                let batches = reads_fitting(1) / batch_reads;
+                (batches.clamp(1, threads_queue), batch_reads)
+            }
+        };
+        Self {
+            queue_len: queue_len.max(1),
+            batch_reads: batch_reads.max(1),
+            read_bases: read_bases.max(1),
+        }
+    }
+
+    /// Bytes taken by the batches in flight at once
+    pub fn memory(&self) -> u64 {
+        self.queue_len as u64 * self.batch_reads as u64 * self.read_bases * BATCH_SAFETY_FACTOR
+    }
+}
+
+impl fmt::Display for BatchPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "batches: {} reads of {} bases on average, {} in flight, about {} MiB",
+            self.batch_reads,
+            self.read_bases,
+            self.queue_len,
+            self.memory() >> 20
+        )
+    }
+}
+
+/// Mean bases of a read over its mates, from the first reads of the file of every mate
+///
+/// [`DEFAULT_READ_BASES`] a mate for the files that can't be sampled.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::batching::sample_read_bases;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_read_bases");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let (r1, r2) = (dir.join("r1.fa"), dir.join("r2.fa"));
+/// std::fs::write(&r1, ">a/1\nACGTACGTAC\n>b/1\nACGTAC\n").unwrap();
+/// std::fs::write(&r2, ">a/2\nACGT\n>b/2\nACGTAC\n").unwrap();
+/// assert_eq!(sample_read_bases(&[&r1]), 8);
+/// assert_eq!(sample_read_bases(&[&r1, &r2]), 13);
+/// ```
+pub fn sample_read_bases<P: AsRef<Path>>(mates: &[P]) -> u64 {
+    mates
+        .iter()
+        .map(
+            |path| match sample_read_lengths(path, LENGTH_SAMPLE_READS) {
+                Ok(lengths) if !lengths.is_empty() => {
+                    lengths.iter().sum::<u64>() / lengths.len() as u64
+                }
+                _ => DEFAULT_READ_BASES,
+            },
+        )
+        .sum()
+}
+
+/// Number of batches in flight, shared by the reader handing them out and the writer
+/// taking their results
+///
+/// It also keeps the first read of each batch in flight, in the order the batches were
+/// handed out, for [`InOrder`].
+#[derive(Clone)]
+pub struct InFlight {
+    state: Arc<(Mutex<usize>, Condvar)>,
+    limit: usize,
+    order: Arc<Mutex<VecDeque<usize>>>,
+}
+
+impl InFlight {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Arc::new((Mutex::new(0), Condvar::new())),
+            limit: limit.max(1),
+            order: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Waits until a batch may be handed out and counts it, `first_read` being the
+    /// `reads_index` of its first read
+    fn acquire(&self, first_read: usize) {
+        let (count, freed) = &*self.state;
+        let mut count = count.lock().unwrap();
+        while *count >= self.limit {
+            count = freed.wait(count).unwrap();
+        }
+        *count += 1;
+        self.order.lock().unwrap().push_back(first_read);
+    }
+
+    /// Counts the result of a batch as taken
+    pub fn release(&self) {
+        let (count, freed) = &*self.state;
+        let mut count = count.lock().unwrap();
+        *count = count.saturating_sub(1);
+        freed.notify_one();
+    }
+}
+
+/// Puts the results of the batches of a [`BatchReader`] back in the order of their reads
+///
+/// The threads finish their batches in any order. A result waits here until the results
+/// of the batches handed out before it are taken, and only counts as taken, freeing its
+/// place in flight, once it is returned.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::batching::{BatchPlan, BatchReader, InFlight, InOrder};
+/// use seqkmer::{Base, OptionPair, Reader, SeqFormat, SeqHeader};
+///
+/// struct Reads(bool);
+/// impl Reader for Reads {
+///     fn next(&mut self) -> std::io::Result<Option<Vec<Base<Vec<u8>>>>> {
+///         if std::mem::replace(&mut self.0, true) {
+///             return Ok(None);
+///         }
+///         let read = |i: usize| {
+///             let header = SeqHeader {
+///                 id: format!("r{}", i),
+///                 file_index: 1,
+///                 reads_index: i,
+///                 format: SeqFormat::Fasta,
+///             };
+///             Base::new(header, OptionPair::Single(b"ACGT".to_vec()))
+///         };
+///         Ok(Some((1..=6).map(read).collect()))
+///     }
+/// }
+///
+/// let plan = BatchPlan::new(1 << 30, 1, 4, Some(3), Some(2));
+/// let in_flight = InFlight::new(plan.queue_len);
+/// let mut inner = Reads(false);
+/// let mut reader = BatchReader::new(&mut inner, plan, in_flight.clone());
+/// let mut in_order = InOrder::new(in_flight);
+/// let batches: Vec<usize> = (0..3)
+///     .map(|_| reader.next().unwrap().unwrap()[0].header.reads_index)
+///     .collect();
+/// assert_eq!(batches, [1, 3, 5]);
+///
+/// // The third and second batches finish first and wait for the first one
+/// assert!(in_order.push(5, "r5 r6").is_empty());
+/// assert!(in_order.push(3, "r3 r4").is_empty());
+/// assert_eq!(in_order.push(1, "r1 r2"), ["r1 r2", "r3 r4", "r5 r6"]);
+/// ```
+pub struct InOrder<T> {
+    in_flight: InFlight,
+    waiting: HashMap<usize, T>,
+}
+
+impl<T> InOrder<T> {
+    pub fn new(in_flight: InFlight) -> Self {
+        Self {
+            in_flight,
+            waiting: HashMap::new(),
+        }
+    }
+
+    /// Takes the result of the batch whose first read is `first_read`, and returns the
+    /// results that are next in read order, each counted as taken
+    pub fn push(&mut self, first_read: usize, result: T) -> Vec<T> {
+        self.waiting.insert(first_read, result);
+        let mut ready = Vec::new();
+        let mut order = self.in_flight.order.lock().unwrap();
+        while let Some(result) = order.front().and_then(|read| self.waiting.remove(read)) {
+            order.pop_front();
+            self.in_flight.release();
+            ready.push(result);
+        }
+        ready
+    }
+}
+
+/// Hands out the reads of another reader in batches of a [`BatchPlan`]
+///
+/// The batches of the inner reader are cut to `batch_reads` reads, and a batch is only
+/// handed out while fewer than `queue_len` are in flight: the writer calls
+/// [`InFlight::release`] for every result it takes, or takes them through [`InOrder`].
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::batching::{BatchPlan, BatchReader, InFlight};
+/// use seqkmer::{Base, OptionPair, Reader, SeqFormat, SeqHeader};
+///
+/// struct Batches(Vec<usize>, usize);
+/// impl Reader for Batches {
+///     fn next(&mut self) -> std::io::Result<Option<Vec<Base<Vec<u8>>>>> {
+///         if self.0.is_empty() {
+///             return Ok(None);
+///         }
+///         let reads = (0..self.0.remove(0))
+///             .map(|_| {
+///                 self.1 += 1;
+///                 let header = SeqHeader {
+///                     id: format!("r{}", self.1),
+///                     file_index: 1,
+///                     reads_index: self.1,
+///                     format: SeqFormat::Fasta,
+///                 };
+///                 Base::new(header, OptionPair::Single(b"ACGT".to_vec()))
+///             })
+///             .collect();
+///         Ok(Some(reads))
+///     }
+/// }
+///
+/// let plan = BatchPlan::new(1 << 30, 1, 4, Some(2), Some(3));
+/// let in_flight = InFlight::new(plan.queue_len);
+/// let mut inner = Batches(vec![7, 2], 0);
+/// let mut reader = BatchReader::new(&mut inner, plan, in_flight.clone());
+/// let mut sizes = Vec::new();
+/// while let Some(batch) = reader.next().unwrap() {
+///     sizes.push(batch.len());
+///     assert_eq!(batch.last().unwrap().header.reads_index, sizes.iter().sum::<usize>());
+///     in_flight.release();
+/// }
+/// assert_eq!(sizes, [3, 3, 1, 2]);
+/// ```
+pub struct BatchReader<'a, R: Reader> {
+    inner: &'a mut R,
+    batch_reads: usize,
+    in_flight: InFlight,
+    pending: VecDeque<Base<Vec<u8>>>,
+}
+
+impl<'a, R: Reader> BatchReader<'a, R> {
+    pub fn new(inner: &'a mut R, plan: BatchPlan, in_flight: InFlight) -> Self {
+        Self {
+            inner,
+            batch_reads: plan.batch_reads,
+            in_flight,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: Reader> Reader for BatchReader<'_, R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        while self.pending.is_empty() {
+            match self.inner.next()? {
+                Some(seqs) => self.pending.extend(seqs),
+                None => return Ok(None),
+            }
+        }
+        self.in_flight.acquire(self.pending[0].header.reads_index);
+        let len = self.batch_reads.min(self.pending.len());
+        Ok(Some(self.pending.drain(..len).collect()))
+    }
+}
@@ -0,0 +1,235 @@
+use clap::Parser;
+use kun_peng::args::{default_threads, parse_fraction, parse_threads};
+use kun_peng::compact_hash::HashConfig;
+use kun_peng::db::{
+    add_k2file_to_page, convert_fna_to_k2_format, extend_taxonomy, get_bits_for_taxid,
+};
+use kun_peng::lock::DbLock;
+use kun_peng::mmscanner::{IupacPolicy, ScanOptions};
+use kun_peng::provenance::Provenance;
+use kun_peng::taxonomy::Taxonomy;
+use kun_peng::utils::{
+    create_partition_files, create_partition_writers, get_file_limit, read_id_to_taxon_map,
+    set_fd_limit,
+};
+use kun_peng::IndexOptions;
+use std::fs::{self, remove_file};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(version, about = "add genomes to an existing database", long_about = None)]
+pub struct Args {
+    /// database directory, with the hash_*.k2d pages, hash_config.k2d, opts.k2d and taxo.k2d
+    #[arg(long = "db", required = true)]
+    pub database: PathBuf,
+
+    /// Sequence ID to taxon map of the new genomes, one `seqid<TAB>taxid` per line
+    #[arg(short = 'm', long = "seqid2taxid", required = true)]
+    pub id_to_taxon_map: PathBuf,
+
+    /// NCBI taxonomy directory (nodes.dmp, names.dmp), required when the new genomes
+    /// have taxids missing from the database taxonomy
+    #[arg(long = "taxdump")]
+    pub taxonomy_dir: Option<PathBuf>,
+
+    /// Refuse to add the genomes if a hash page would be filled beyond this fraction
+    #[arg(long, value_parser = parse_fraction, default_value_t = 0.9)]
+    pub max_load_factor: f64,
+
+    /// How to scan the IUPAC ambiguity codes (R, Y, ...) of the genomes: `break` the k-mers
+    /// like an N, read them as their `first-base` in ACGT order, or `skip` them.
+    #[arg(long = "iupac", value_name = "POLICY", default_value = "break")]
+    pub iupac: IupacPolicy,
+
+    /// Skip the spaces and tabs inside the sequences, like the line breaks, instead of
+    /// ending the k-mers at them.
+    #[arg(long = "skip-whitespace")]
+    pub skip_whitespace: bool,
+
+    /// Number of threads
+    #[clap(short = 'p', long, value_parser = parse_threads, default_value_t = default_threads())]
+    pub threads: usize,
+
+    /// Record the xxHash64 checksums of the new genomes in the database provenance
+    #[arg(long)]
+    pub hash_inputs: bool,
+
+    /// Remove the lock of an earlier run on the database that is no longer running, e.g.
+    /// killed, instead of refusing to start
+    #[arg(long)]
+    pub force_unlock: bool,
+
+    /// FASTA files of the new genomes
+    #[arg(required = true)]
+    pub input_files: Vec<PathBuf>,
+}
+
+/// Adds the minimizers of new genomes to the database
+///
+/// The updated pages, taxonomy and config are written to a temporary directory first. The
+/// database files are only replaced, one rename each, once every page is known to stay
+/// under `--max-load-factor`.
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    if args.max_load_factor >= 1.0 {
+        return Err("--max-load-factor must be below 1, a full page drops minimizers".into());
+    }
+    let k2d_dir = &args.database;
+    let _lock = DbLock::acquire(k2d_dir, "add-to-db", args.force_unlock)?;
+    let idx_opts = IndexOptions::read_index_options(k2d_dir.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros()?;
+    let hash_filename = k2d_dir.join("hash_config.k2d");
+    let mut hash_config = HashConfig::from_hash_header(&hash_filename)?;
+    let taxonomy = Taxonomy::from_file(k2d_dir.join("taxo.k2d"))?;
+
+    let page_files: Vec<PathBuf> = (1..=hash_config.partition)
+        .map(|i| k2d_dir.join(format!("hash_{}.k2d", i)))
+        .collect();
+    if let Some(missing) = page_files.iter().find(|path| !path.exists()) {
+        return Err(format!("missing hash page {:?}", missing).into());
+    }
+
+    let id_to_taxon_map = read_id_to_taxon_map(&args.id_to_taxon_map)?;
+    let mut new_taxids: Vec<u64> = id_to_taxon_map
+        .values()
+        .copied()
+        .filter(|&taxid| taxonomy.get_internal_id(taxid) == 0)
+        .collect();
+    new_taxids.sort_unstable();
+    new_taxids.dedup();
+    let (taxonomy, remap) = if new_taxids.is_empty() {
+        (taxonomy, None)
+    } else {
+        let taxonomy_dir = args.taxonomy_dir.as_ref().ok_or_else(|| {
+            format!(
+                "{} taxids are not in the database taxonomy (e.g. {}), --taxdump is required",
+                new_taxids.len(),
+                new_taxids[0]
+            )
+        })?;
+        let (extended, remap) = extend_taxonomy(taxonomy_dir, &taxonomy, &new_taxids)?;
+        get_bits_for_taxid(hash_config.value_bits, extended.node_count() as f64).map_err(|_| {
+            format!(
+                "{} taxa don't fit in the {} taxid bits of the index, rebuild the database",
+                extended.node_count(),
+                hash_config.value_bits
+            )
+        })?;
+        println!("{} new taxids added to the taxonomy", new_taxids.len());
+        (extended, Some(remap))
+    };
+
+    // 开始计时
+    let start = Instant::now();
+
+    let tmp_dir = k2d_dir.join("add_to_db.tmp");
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+    }
+    let partition = hash_config.partition;
+    if partition >= get_file_limit() {
+        set_fd_limit(partition as u64 + 1).expect("Failed to set file descriptor limit");
+    }
+    let chunk_files = create_partition_files(partition, &tmp_dir, "chunk");
+    let mut writers = create_partition_writers(&chunk_files);
+    let scan_options = ScanOptions {
+        iupac: args.iupac,
+        skip_whitespace: args.skip_whitespace,
+        forward_only: idx_opts.forward_only,
+        ..Default::default()
+    };
+    for fna_file in &args.input_files {
+        println!("convert fna file {:?}", fna_file);
+        convert_fna_to_k2_format(
+            fna_file,
+            meros,
+            scan_options,
+            &taxonomy,
+            &id_to_taxon_map,
+            hash_config,
+            &mut writers,
+            hash_config.hash_capacity,
+            args.threads,
+        )?;
+    }
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+    drop(writers);
+
+    let mut size = 0;
+    let mut new_page_files = Vec::with_capacity(partition);
+    for (i, (page_file, chunk_file)) in page_files.iter().zip(&chunk_files).enumerate() {
+        let new_page_file = tmp_dir.join(format!("hash_{}.k2d", i + 1));
+        let (count, capacity) = add_k2file_to_page(
+            hash_config,
+            page_file,
+            chunk_file,
+            &new_page_file,
+            &taxonomy,
+            remap.as_deref(),
+        )?;
+        remove_file(chunk_file)?;
+        if count as f64 > args.max_load_factor * capacity as f64 {
+            fs::remove_dir_all(&tmp_dir)?;
+            return Err(format!(
+                "hash page {} would hold {} of {} cells, above --max-load-factor {}, \
+                 the database is unchanged, rebuild it with more capacity",
+                i + 1,
+                count,
+                capacity,
+                args.max_load_factor
+            )
+            .into());
+        }
+        size += count;
+        new_page_files.push(new_page_file);
+        println!(
+            "process page {}/{}: duration: {:?}",
+            i + 1,
+            partition,
+            start.elapsed()
+        );
+    }
+    println!(
+        "{} minimizers added to the index",
+        size.saturating_sub(hash_config.size)
+    );
+
+    let provenance =
+        Provenance::new("add-to-db", &args).with_inputs(&args.input_files, args.hash_inputs)?;
+    hash_config.size = size;
+    let new_hash_filename = tmp_dir.join("hash_config.k2d");
+    hash_config.write_to_file(&new_hash_filename)?;
+    provenance.append_to(&new_hash_filename)?;
+    let new_opts_filename = tmp_dir.join("opts.k2d");
+    fs::copy(k2d_dir.join("opts.k2d"), &new_opts_filename)?;
+    provenance.append_to(&new_opts_filename)?;
+    let new_taxo_filename = tmp_dir.join("taxo.k2d");
+    if remap.is_some() {
+        taxonomy.write_to_disk(&new_taxo_filename)?;
+    }
+
+    // The config goes last, its size only matches the updated pages.
+    for (new_page_file, page_file) in new_page_files.iter().zip(&page_files) {
+        fs::rename(new_page_file, page_file)?;
+    }
+    if remap.is_some() {
+        fs::rename(&new_taxo_filename, k2d_dir.join("taxo.k2d"))?;
+    }
+    fs::rename(&new_opts_filename, k2d_dir.join("opts.k2d"))?;
+    fs::rename(&new_hash_filename, &hash_filename)?;
+    fs::remove_dir_all(&tmp_dir)?;
+
+    println!("add to db took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
@@ -1,7 +1,10 @@
 use clap::Parser;
+use kun_peng::args::{default_threads, parse_threads};
 use kun_peng::compact_hash::{read_next_page, Compact, HashConfig, Page, Row, Slot};
+use kun_peng::spill::{SpillHeader, SpillWriter};
 use kun_peng::utils::{find_and_sort_files, open_file};
 use seqkmer::buffer_read_parallel;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io::{self, BufReader, BufWriter, Read, Result, Write};
@@ -38,8 +41,12 @@ pub struct Args {
     #[clap(long, value_parser = clap::value_parser!(u32).range(1..=32), default_value_t = 4)]
     pub batch_size: u32,
 
+    /// Deflate compress the hit intermediate, smaller and slower
+    #[clap(long)]
+    pub compress_spill: bool,
+
     /// The number of threads to use.
-    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    #[clap(short = 'p', long = "num-threads", value_parser = parse_threads, default_value_t = default_threads())]
     pub num_threads: usize,
 }
 
@@ -95,29 +102,25 @@ fn _write_to_file(
 fn write_to_file(
     file_index: u64,
     seq_id_mod: u32,
-    bytes: &[u8],
-    writers: &mut HashMap<(u64, u32), BufWriter<File>>,
+    rows: &mut [Row],
+    header: SpillHeader,
+    writers: &mut HashMap<(u64, u32), SpillWriter<BufWriter<File>>>,
     chunk_dir: &PathBuf,
 ) -> io::Result<()> {
     // 检查是否已经有该文件的 writer，没有则创建一个新的
-    let writer = writers.entry((file_index, seq_id_mod)).or_insert_with(|| {
-        let file_name = format!("sample_file_{}_{}.bin", file_index, seq_id_mod);
-        let file_path = chunk_dir.join(file_name);
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&file_path)
-            .expect("failed to open file");
-        BufWriter::new(file)
-    });
-
-    writer.write_all(bytes)?;
+    let writer = match writers.entry((file_index, seq_id_mod)) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let file_name = format!("sample_file_{}_{}.bin", file_index, seq_id_mod);
+            entry.insert(SpillWriter::append(chunk_dir.join(file_name), header)?)
+        }
+    };
 
-    Ok(())
+    writer.write_block(rows)
 }
 
 fn clean_up_writers(
-    writers: &mut HashMap<(u64, u32), BufWriter<File>>,
+    writers: &mut HashMap<(u64, u32), SpillWriter<BufWriter<File>>>,
     current_file_index: u64,
 ) -> io::Result<()> {
     let keys_to_remove: Vec<(u64, u32)> = writers
@@ -135,6 +138,7 @@ fn clean_up_writers(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_batch<R>(
     reader: &mut R,
     hash_config: &HashConfig,
@@ -144,30 +148,31 @@ fn process_batch<R>(
     bin_threads: u32,
     // page_index: usize,
     num_threads: usize,
+    compress_spill: bool,
 ) -> std::io::Result<()>
 where
     R: Read + Send,
 {
-    let row_size = std::mem::size_of::<Row>();
-    let mut writers: HashMap<(u64, u32), BufWriter<File>> = HashMap::new();
+    let mut writers: HashMap<(u64, u32), SpillWriter<BufWriter<File>>> = HashMap::new();
     let mut current_file_index: Option<u64> = None;
 
     let value_mask = hash_config.get_value_mask();
     let value_bits = hash_config.get_value_bits();
     let idx_mask = hash_config.get_idx_mask();
     let idx_bits = hash_config.get_idx_bits();
+    let header = SpillHeader::new(value_bits, compress_spill);
 
     buffer_read_parallel(
         reader,
         num_threads,
         buffer_size,
         |dataset: Vec<Slot<u64>>| {
-            let mut results: HashMap<(u64, u32), Vec<u8>> = HashMap::new();
+            let mut results: HashMap<(u64, u32), Vec<Row>> = HashMap::new();
             for slot in dataset {
                 let indx = slot.idx & idx_mask;
                 let compacted = slot.value.left(value_bits) as u32;
                 // let taxid = chtm.get_from_page(indx, compacted, page_index);
-                let taxid = page.find_index(indx, compacted, value_bits, value_mask);
+                let taxid = page.find(indx, compacted, hash_config);
 
                 if taxid > 0 {
                     let kmer_id = slot.idx >> idx_bits;
@@ -175,34 +180,40 @@ where
                     let seq_id = slot.get_seq_id() as u32;
                     let left = slot.value.left(value_bits) as u32;
                     let high = u32::combined(left, taxid, value_bits);
-                    let row = Row::new(high, seq_id, kmer_id as u32);
-                    let value_bytes = row.as_slice(row_size);
+                    let row = Row::new(high, seq_id, Row::kmer_id_of(kmer_id));
                     let seq_id_mod = seq_id % bin_threads;
 
                     results
                         .entry((file_index, seq_id_mod))
-                        .or_insert_with(Vec::new)
-                        .extend(value_bytes);
+                        .or_default()
+                        .push(row);
                 }
             }
             results
         },
         |result| {
             while let Some(data) = result.next() {
-                let res = data.unwrap();
+                let mut res = data.unwrap();
                 let mut file_keys: Vec<_> = res.keys().cloned().collect();
                 file_keys.sort_unstable(); // 对 (file_index, seq_id_mod) 进行排序
 
                 for (file_index, seq_id_mod) in file_keys {
-                    if let Some(bytes) = res.get(&(file_index, seq_id_mod)) {
+                    if let Some(rows) = res.get_mut(&(file_index, seq_id_mod)) {
                         // 如果当前处理的 file_index 改变了，清理非当前的 writers
                         if current_file_index != Some(file_index) {
                             clean_up_writers(&mut writers, file_index).expect("clean writer");
                             current_file_index = Some(file_index);
                         }
 
-                        write_to_file(file_index, seq_id_mod, bytes, &mut writers, &chunk_dir)
-                            .expect("write to file error");
+                        write_to_file(
+                            file_index,
+                            seq_id_mod,
+                            rows,
+                            header,
+                            &mut writers,
+                            &chunk_dir,
+                        )
+                        .expect("write to file error");
                     }
                 }
             }
@@ -248,6 +259,7 @@ fn process_chunk_file<P: AsRef<Path>>(
         args.batch_size,
         // page_index,
         args.num_threads,
+        args.compress_spill,
     )?;
 
     Ok(())
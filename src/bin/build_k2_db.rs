@@ -2,7 +2,7 @@
 use clap::Parser;
 use kun_peng::compact_hash::HashConfig;
 use kun_peng::db::process_k2file;
-use kun_peng::taxonomy::Taxonomy;
+use kun_peng::taxonomy::{parse_rank, Taxonomy};
 use kun_peng::utils::find_and_trans_files;
 use std::fs::remove_file;
 use std::path::PathBuf;
@@ -14,9 +14,16 @@ pub struct Args {
     /// database hash chunk directory and other files
     #[arg(long = "db", required = true)]
     pub database: PathBuf,
+
+    /// Leave out the minimizers whose LCA sits above this rank (e.g. `G` or `genus`)
+    #[arg(long = "max-minimizer-rank", value_parser = parse_rank)]
+    pub max_minimizer_rank: Option<char>,
 }
 
-pub fn run(database: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+pub fn run(
+    database: &PathBuf,
+    max_minimizer_rank: Option<char>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let k2d_dir = database;
     let taxonomy_filename = k2d_dir.join("taxo.k2d");
     let taxonomy = Taxonomy::from_file(taxonomy_filename)?;
@@ -30,19 +37,22 @@ pub fn run(database: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
     let chunk_files = find_and_trans_files(&k2d_dir, "chunk", ".k2", true)?;
 
     let mut size: usize = 0;
+    let mut removed: usize = 0;
 
     println!("start process k2 files...");
     for (i, chunk_file) in &chunk_files {
         // 计算持续时间
-        let count = process_k2file(
+        let (count, page_removed) = process_k2file(
             hash_config,
             &k2d_dir,
             &chunk_file,
             &taxonomy,
             hash_config.hash_capacity,
             *i,
+            max_minimizer_rank,
         )?;
         size += count;
+        removed += page_removed;
         let duration = start.elapsed();
         println!(
             "process chunk file {:?}/{:}: duration: {:?}",
@@ -50,6 +60,12 @@ pub fn run(database: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
         );
     }
 
+    if let Some(rank) = max_minimizer_rank {
+        println!(
+            "{} minimizers with an LCA above rank {} left out",
+            removed, rank
+        );
+    }
     hash_config.size = size;
     hash_config.write_to_file(&hash_filename)?;
 
@@ -68,7 +84,7 @@ pub fn run(database: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
 #[allow(dead_code)]
 fn main() {
     let args = Args::parse();
-    if let Err(e) = run(&args.database) {
+    if let Err(e) = run(&args.database, args.max_minimizer_rank) {
         eprintln!("Application error: {}", e);
     }
 }
@@ -0,0 +1,137 @@
+use clap::Parser;
+use kun_peng::args::parse_read_group;
+use kun_peng::classifier::{read_minimizer_file, ClassifierOptions, Database};
+use kun_peng::classify::ScoreMode;
+use kun_peng::taxonomy::parse_rank;
+use std::fs::File;
+use std::io::{self, BufWriter, Result, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Classify pre-extracted minimizers",
+    long_about = "Classify reads from minimizer files, bypassing sequence scanning.
+Each line holds the read ID, the sequence length, the space separated minimizer hashes
+of the first mate and, for pairs, those of the second mate, separated by tabs."
+)]
+pub struct Args {
+    /// database hash chunk directory and other files
+    #[arg(long = "db", required = true)]
+    pub database: PathBuf,
+
+    /// File path for outputting normal Kraken output.
+    #[clap(long = "output-file", value_parser)]
+    pub output_file: Option<PathBuf>,
+
+    /// Append this tag as the last column of every output line, to tell runs apart after merging.
+    #[clap(long = "read-group", value_parser = parse_read_group)]
+    pub read_group: Option<String>,
+
+    /// Confidence score threshold.
+    #[clap(
+        short = 'T',
+        long = "confidence-threshold",
+        value_parser,
+        default_value_t = 0.0
+    )]
+    pub confidence_threshold: f64,
+
+    /// The minimum number of minimizers supporting a call, whatever the read length. Short
+    /// reads passing --confidence-threshold with a couple of hits stay unclassified.
+    #[clap(long = "min-supporting-minimizers", value_parser, default_value_t = 0)]
+    pub min_supporting_minimizers: u64,
+
+    /// The minimum number of hit groups needed for a call.
+    #[clap(
+        short = 'g',
+        long = "minimum-hit-groups",
+        value_parser,
+        default_value_t = 2
+    )]
+    pub minimum_hit_groups: usize,
+
+    /// The number of non-matching minimizers allowed between two hits of the same hit group.
+    /// If not set, every minimizer of the read counts as a hit group.
+    #[clap(long = "hit-group-gap", value_parser)]
+    pub hit_group_gap: Option<usize>,
+
+    /// Report calls above this rank (e.g. `S` or `genus`) as unclassified.
+    #[clap(long = "max-rank", value_parser = parse_rank)]
+    pub max_rank: Option<char>,
+
+    /// Weigh every hit by the rank of its taxon, hits of general taxa count less.
+    #[clap(long = "weight-by-rank", action)]
+    pub weight_by_rank: bool,
+
+    /// Call the LCA of every taxon hit by a minimizer of the read, whatever the counts,
+    /// the confidence threshold and the hit groups.
+    #[clap(long = "lca-of-all", action, conflicts_with = "weight_by_rank")]
+    pub lca_of_all: bool,
+
+    /// How the hits of a read add up: `positions` counts a minimizer at every position it
+    /// hits, as Kraken 2 does, `distinct` once per read, so repeats weigh less.
+    #[clap(long = "score-mode", value_name = "MODE", default_value = "positions")]
+    pub score_mode: ScoreMode,
+
+    /// A list of minimizer files to be classified.
+    #[clap(required = true)]
+    pub input_files: Vec<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let db = Database::load(&args.database)?;
+    let options = ClassifierOptions {
+        confidence_threshold: args.confidence_threshold,
+        min_supporting_minimizers: args.min_supporting_minimizers,
+        minimum_hit_groups: args.minimum_hit_groups,
+        hit_group_gap: args.hit_group_gap,
+        max_rank: args.max_rank,
+        weight_by_rank: args.weight_by_rank,
+        lca_of_all: args.lca_of_all,
+        score_mode: args.score_mode,
+        ..Default::default()
+    };
+
+    let mut writer: Box<dyn Write> = match &args.output_file {
+        Some(file_path) => Box::new(BufWriter::new(File::create(file_path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+
+    println!("bulk classify start...");
+    let start = Instant::now();
+    let classify_counter = AtomicUsize::new(0);
+    let mut total_seqs = 0;
+    for input_file in &args.input_files {
+        let records = read_minimizer_file(input_file)?;
+        for record in &records {
+            let result = db.classify_minimizers(record, &options, &classify_counter);
+            let mut line = result.kraken_line();
+            if let Some(read_group) = &args.read_group {
+                line = line.column(read_group);
+            }
+            writeln!(writer, "{}", line)?;
+        }
+        total_seqs += records.len();
+    }
+    writer.flush()?;
+
+    let duration = start.elapsed();
+    println!(
+        "{} sequences classified, {} unclassified",
+        classify_counter.load(Ordering::SeqCst),
+        total_seqs - classify_counter.load(Ordering::SeqCst)
+    );
+    println!("bulk classify took: {:?}", duration);
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
@@ -1,11 +1,12 @@
 // 使用时需要引用模块路径
 use clap::Parser;
 use kun_peng::args::{parse_size, Build};
-use kun_peng::compact_hash::HashConfig;
+use kun_peng::compact_hash::{HashConfig, TableLayout, BUCKET_CELLS};
 use kun_peng::db::{convert_fna_to_k2_format, get_bits_for_taxid};
+use kun_peng::mmscanner::ScanOptions;
 use kun_peng::taxonomy::Taxonomy;
 use kun_peng::utils::{
-    create_partition_files, create_partition_writers, find_files, get_file_limit,
+    check_meros, create_partition_files, create_partition_writers, find_files, get_file_limit,
     read_id_to_taxon_map, set_fd_limit,
 };
 use kun_peng::IndexOptions;
@@ -25,6 +26,7 @@ pub struct Args {
 pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::error::Error>> {
     let file_num_limit = get_file_limit();
     let meros = args.build.klmt.as_meros();
+    check_meros(&meros)?;
     let k2d_dir = &args.build.database;
 
     let id_to_taxon_map_filename = args.build.database.join("seqid2taxid.map");
@@ -39,9 +41,21 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
     )
     .expect("more bits required for storing taxid");
 
+    let layout = args.build.table_layout;
+    if layout == TableLayout::Bucketed && !args.hash_capacity.is_multiple_of(BUCKET_CELLS) {
+        return Err(format!(
+            "--table-layout bucketed needs a --hash-capacity multiple of {}, got {}",
+            BUCKET_CELLS, args.hash_capacity
+        )
+        .into());
+    }
+
     let capacity = required_capacity;
     let partition = (capacity + args.hash_capacity - 1) / args.hash_capacity;
-    let hash_config = HashConfig::new(1, capacity, value_bits, 0, partition, args.hash_capacity);
+    let hash_config = HashConfig {
+        layout,
+        ..HashConfig::new(1, capacity, value_bits, 0, partition, args.hash_capacity)
+    };
 
     // 开始计时
     let start = Instant::now();
@@ -58,19 +72,26 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
 
     let library_dir = &args.build.database.join("library");
     let fna_files = find_files(&library_dir, "library", ".fna");
+    let scan_options = ScanOptions {
+        iupac: args.build.iupac,
+        skip_whitespace: args.build.skip_whitespace,
+        forward_only: args.build.forward_only,
+        ..Default::default()
+    };
 
     for fna_file in fna_files {
         println!("convert fna file {:?}", fna_file);
         convert_fna_to_k2_format(
             fna_file,
             meros,
+            scan_options,
             &taxonomy,
             &id_to_taxon_map,
             hash_config,
             &mut writers,
             chunk_size,
             args.build.threads,
-        );
+        )?;
     }
 
     let hash_filename = k2d_dir.join("hash_config.k2d");
@@ -81,7 +102,10 @@ pub fn run(args: Args, required_capacity: usize) -> Result<(), Box<dyn std::erro
     println!("chunk db took: {:?}", duration);
 
     let options_filename = k2d_dir.join("opts.k2d");
-    let idx_opts = IndexOptions::from_meros(meros);
+    let idx_opts = IndexOptions {
+        forward_only: args.build.forward_only,
+        ..IndexOptions::from_meros(meros)
+    };
     idx_opts.write_to_file(options_filename)?;
 
     Ok(())
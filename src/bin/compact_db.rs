@@ -0,0 +1,263 @@
+use clap::Parser;
+use kun_peng::args::{default_threads, parse_fraction, parse_threads};
+use kun_peng::compact_hash::{CHTable, HashConfig};
+use kun_peng::db::{convert_fna_to_k2_format, process_k2file, unexplained_cells};
+use kun_peng::lock::DbLock;
+use kun_peng::mmscanner::{IupacPolicy, ScanOptions};
+use kun_peng::provenance::Provenance;
+use kun_peng::taxonomy::{parse_rank, Taxonomy};
+use kun_peng::utils::{
+    create_partition_files, create_partition_writers, find_files, get_file_limit,
+    read_id_to_taxon_map, set_fd_limit,
+};
+use kun_peng::IndexOptions;
+use std::fs::{self, remove_file};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(version, about = "shrink the index of a database to a target load factor", long_about = None)]
+pub struct Args {
+    /// database directory, with the hash_*.k2d pages and the library/ and seqid2taxid.map
+    /// the index was built from
+    #[arg(long = "db", required = true)]
+    pub database: PathBuf,
+
+    /// Proportion of the compacted hash table to be populated
+    #[arg(long, value_parser = parse_fraction, default_value_t = 0.7)]
+    pub load_factor: f64,
+
+    /// The `--iupac` policy the database was built with
+    #[arg(long = "iupac", value_name = "POLICY", default_value = "break")]
+    pub iupac: IupacPolicy,
+
+    /// Set if the database was built with `--skip-whitespace`
+    #[arg(long = "skip-whitespace")]
+    pub skip_whitespace: bool,
+
+    /// The `--max-minimizer-rank` the database was built with
+    #[arg(long = "max-minimizer-rank", value_parser = parse_rank)]
+    pub max_minimizer_rank: Option<char>,
+
+    /// Replace the index even if some of its cells are not accounted for by the genomes of
+    /// library/, e.g. ones added with `add-to-db`, which the compacted index drops
+    #[arg(long)]
+    pub force: bool,
+
+    /// Remove the lock of an earlier run on the database that is no longer running, e.g.
+    /// killed, instead of refusing to start
+    #[arg(long)]
+    pub force_unlock: bool,
+
+    /// Number of threads
+    #[clap(short = 'p', long, value_parser = parse_threads, default_value_t = default_threads())]
+    pub threads: usize,
+}
+
+fn page_files(dir: &Path, partition: usize) -> Vec<PathBuf> {
+    (1..=partition)
+        .map(|i| dir.join(format!("hash_{}.k2d", i)))
+        .collect()
+}
+
+/// Size on disk of the pages of an index
+fn index_bytes(config: &HashConfig) -> usize {
+    config.capacity * std::mem::size_of::<u32>() + config.partition * 16
+}
+
+/// Rebuilds the index of a database with the capacity its cells need
+///
+/// A cell only keeps the high bits of the minimizer hash, the slot of a cell in a table
+/// of another capacity can't be computed from it. The minimizers are scanned again from
+/// the library of the database into a table sized for `--load-factor`. The database is
+/// only changed if the library accounts for every cell of the old table, so genomes
+/// added with `add-to-db` after the build, missing from the library, are never dropped.
+/// The cells themselves differ: keys sharing a compacted key meet in one cell, with
+/// their LCA, in some capacities and not in others.
+pub fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    if args.load_factor <= 0.0 || args.load_factor >= 1.0 {
+        return Err("--load-factor must be between 0 and 1".into());
+    }
+    let k2d_dir = &args.database;
+    let _lock = DbLock::acquire(k2d_dir, "compact-db", args.force_unlock)?;
+    let idx_opts = IndexOptions::read_index_options(k2d_dir.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros()?;
+    let hash_filename = k2d_dir.join("hash_config.k2d");
+    let hash_config = HashConfig::from_hash_header(&hash_filename)?;
+    let taxonomy = Taxonomy::from_file(k2d_dir.join("taxo.k2d"))?;
+
+    let old_pages = page_files(k2d_dir, hash_config.partition);
+    if let Some(missing) = old_pages.iter().find(|path| !path.exists()) {
+        return Err(format!("missing hash page {:?}", missing).into());
+    }
+    let fna_files = find_files(k2d_dir.join("library"), "library", ".fna");
+    if fna_files.is_empty() {
+        return Err(format!(
+            "no library/library*.fna in {:?}, the index can't be rebuilt",
+            k2d_dir
+        )
+        .into());
+    }
+
+    let start = Instant::now();
+    let old_table = CHTable::from_hash_files(hash_config, &old_pages)?;
+    let old_size = old_table
+        .pages
+        .iter()
+        .enumerate()
+        .flat_map(|(i, page)| {
+            let start = i * hash_config.hash_capacity;
+            let end = hash_config.capacity.min(start + hash_config.hash_capacity);
+            &page.data[..end - start]
+        })
+        .filter(|&&cell| cell != 0)
+        .count();
+    let capacity = (old_size as f64 / args.load_factor).ceil() as usize;
+    println!(
+        "index: {} of {} cells occupied, load factor {:.3}",
+        old_size,
+        hash_config.capacity,
+        old_size as f64 / hash_config.capacity as f64
+    );
+    if capacity >= hash_config.capacity {
+        println!(
+            "the index is already at or above load factor {}, nothing to compact",
+            args.load_factor
+        );
+        return Ok(());
+    }
+
+    let hash_capacity = hash_config.hash_capacity;
+    let partition = capacity.div_ceil(hash_capacity);
+    let mut new_config = HashConfig::new(
+        hash_config.version,
+        capacity,
+        hash_config.value_bits,
+        0,
+        partition,
+        hash_capacity,
+    );
+    new_config.layout = hash_config.layout;
+
+    let tmp_dir = k2d_dir.join("compact_db.tmp");
+    if tmp_dir.exists() {
+        fs::remove_dir_all(&tmp_dir)?;
+    }
+    if partition >= get_file_limit() {
+        set_fd_limit(partition as u64 + 1).expect("Failed to set file descriptor limit");
+    }
+    let id_to_taxon_map = read_id_to_taxon_map(k2d_dir.join("seqid2taxid.map"))?;
+    let chunk_files = create_partition_files(partition, &tmp_dir, "chunk");
+    let mut writers = create_partition_writers(&chunk_files);
+    let scan_options = ScanOptions {
+        iupac: args.iupac,
+        skip_whitespace: args.skip_whitespace,
+        forward_only: idx_opts.forward_only,
+        ..Default::default()
+    };
+    for fna_file in &fna_files {
+        println!("convert fna file {:?}", fna_file);
+        convert_fna_to_k2_format(
+            fna_file,
+            meros,
+            scan_options,
+            &taxonomy,
+            &id_to_taxon_map,
+            new_config,
+            &mut writers,
+            hash_capacity,
+            args.threads,
+        )?;
+    }
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+    drop(writers);
+
+    let mut size = 0;
+    for (i, chunk_file) in chunk_files.iter().enumerate() {
+        let (count, _) = process_k2file(
+            new_config,
+            &tmp_dir,
+            chunk_file,
+            &taxonomy,
+            hash_capacity,
+            i + 1,
+            args.max_minimizer_rank,
+        )?;
+        remove_file(chunk_file)?;
+        size += count;
+    }
+    new_config.size = size;
+
+    let new_pages = page_files(&tmp_dir, partition);
+    let unexplained = unexplained_cells(
+        &fna_files,
+        meros,
+        scan_options,
+        &taxonomy,
+        &id_to_taxon_map,
+        &old_table,
+        args.threads,
+    )?;
+    drop(old_table);
+    if unexplained > 0 {
+        if !args.force {
+            fs::remove_dir_all(&tmp_dir)?;
+            return Err(format!(
+                "{} cells of the index are not accounted for by library/, \
+                 the database is unchanged (is the library the one the index was built from?), \
+                 --force replaces it anyway",
+                unexplained
+            )
+            .into());
+        }
+        eprintln!(
+            "WARNING: {} cells of the index are not accounted for by library/ and are dropped",
+            unexplained
+        );
+    }
+
+    let provenance = Provenance::new("compact-db", &args);
+    let new_hash_filename = tmp_dir.join("hash_config.k2d");
+    new_config.write_to_file(&new_hash_filename)?;
+    provenance.append_to(&new_hash_filename)?;
+    let new_opts_filename = tmp_dir.join("opts.k2d");
+    fs::copy(k2d_dir.join("opts.k2d"), &new_opts_filename)?;
+    provenance.append_to(&new_opts_filename)?;
+
+    // The config goes last, its capacity only matches the new pages.
+    for (new_page, page) in new_pages.iter().zip(page_files(k2d_dir, partition)) {
+        fs::rename(new_page, page)?;
+    }
+    for page in &old_pages[partition..] {
+        remove_file(page)?;
+    }
+    fs::rename(&new_opts_filename, k2d_dir.join("opts.k2d"))?;
+    fs::rename(&new_hash_filename, &hash_filename)?;
+    fs::remove_dir_all(&tmp_dir)?;
+
+    let (old_bytes, new_bytes) = (index_bytes(&hash_config), index_bytes(&new_config));
+    println!(
+        "index: {} -> {} cells in {} -> {} pages, {} -> {} bytes ({:.1}% smaller), load factor {:.3}",
+        hash_config.capacity,
+        capacity,
+        hash_config.partition,
+        partition,
+        old_bytes,
+        new_bytes,
+        (1.0 - new_bytes as f64 / old_bytes as f64) * 100.0,
+        size as f64 / capacity as f64
+    );
+    println!("compact db took: {:?}", start.elapsed());
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
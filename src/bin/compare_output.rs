@@ -0,0 +1,95 @@
+use clap::Parser;
+use kun_peng::compare::{compare_outputs, Agreement};
+use kun_peng::output::open_output;
+use kun_peng::taxonomy::Taxonomy;
+use std::io::{BufWriter, Result, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "compare the per-read calls of two Kraken-style outputs",
+    long_about = "Compare the per-read calls of two Kraken-style outputs, e.g. of this tool and of
+kraken2 for the same reads.
+
+Reads are matched by ID. Each pair of calls is `same`, `ancestor` (the first call is an
+ancestor of the second), `descendant`, `disagree` (unrelated taxa) or `one-unclassified`.
+The summary table gives the reads of each category and the Pearson correlation of the
+clade counts of both outputs. The correlation is `NA` when it is undefined: when an output
+has no classified reads, or all its clades have the same count, as when every read is of
+one taxon."
+)]
+pub struct Args {
+    /// taxo.k2d of the database, of this tool or of Kraken 2
+    #[arg(long, required = true)]
+    pub taxonomy: PathBuf,
+
+    /// Write the reads whose calls are not the same to this TSV file
+    #[arg(long, value_name = "FILE")]
+    pub disagreements: Option<PathBuf>,
+
+    /// First Kraken output, plain or gzip compressed
+    pub first: PathBuf,
+
+    /// Second Kraken output, plain or gzip compressed
+    pub second: PathBuf,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let taxonomy = Taxonomy::from_file(&args.taxonomy)?;
+    let mut disagreements = match &args.disagreements {
+        Some(path) => {
+            let mut writer = BufWriter::new(open_output(path, false)?);
+            writeln!(writer, "read_id\tcategory\tfirst_taxid\tsecond_taxid")?;
+            Some(writer)
+        }
+        None => None,
+    };
+
+    let comparison = compare_outputs(
+        &taxonomy,
+        &args.first,
+        &args.second,
+        |id, agreement, first, second| match disagreements.as_mut() {
+            Some(writer) if agreement != Agreement::Same => writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                id,
+                agreement.label(),
+                first,
+                second
+            ),
+            _ => Ok(()),
+        },
+    )?;
+    if let Some(writer) = disagreements.as_mut() {
+        writer.flush()?;
+    }
+
+    let paired = comparison.paired_reads();
+    println!("category\treads\tpercentage");
+    for agreement in Agreement::ALL {
+        let reads = comparison.count(agreement);
+        let percentage = if paired > 0 {
+            100.0 * reads as f64 / paired as f64
+        } else {
+            0.0
+        };
+        println!("{}\t{}\t{:.2}", agreement.label(), reads, percentage);
+    }
+    println!("only_first\t{}\t-", comparison.only_first);
+    println!("only_second\t{}\t-", comparison.only_second);
+    match comparison.correlation() {
+        Some(r) => println!("clade_count_correlation\t{:.6}\t-", r),
+        None => println!("clade_count_correlation\tNA\t-"),
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
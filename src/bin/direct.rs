@@ -1,19 +1,47 @@
 use clap::Parser;
-use kun_peng::classify::process_hitgroup;
-use kun_peng::compact_hash::{CHTable, Compact, HashConfig, Row};
-use kun_peng::readcounts::{TaxonCounters, TaxonCountersDash};
-use kun_peng::report::report_kraken_style;
-use kun_peng::taxonomy::Taxonomy;
-use kun_peng::utils::{create_sample_file, find_and_sort_files, get_lastest_file_index};
+use kun_peng::args::{
+    default_threads, parse_fraction, parse_read_group, parse_size, parse_threads,
+};
+use kun_peng::batching::{sample_read_bases, BatchPlan, BatchReader, InFlight, InOrder};
+use kun_peng::bins::{bin_reads, BinWriters, MAX_OPEN_BINS};
+use kun_peng::checkpoint::{Checkpointer, SampleProgress, SeekableFastxReader};
+use kun_peng::classify::{
+    hit_string, hits_gff3, mate_calls, paint_bases, process_hitgroup, process_seq, HitGroupOptions,
+    ScoreMode,
+};
+use kun_peng::compact_hash::{CHTable, HashConfig, Row};
+use kun_peng::mmscanner::IupacPolicy;
+use kun_peng::output::{open_output, tee_all, ConfidenceBands, IoPool, KrakenLine, SplitOutput};
+use kun_peng::preprocess::{PreprocessReader, ReadPreprocessor};
+use kun_peng::provenance::Provenance;
+use kun_peng::readcounts::TaxonCounters;
+use kun_peng::reader::{
+    format_label, order_mate_files_warn, quality_score_note, sample_alphabet, sample_read_lengths,
+    verify_record_count, CheckedReader, LongReadPolicy, MultiFileReader, ReadSources,
+    ALPHABET_SAMPLE_READS, LENGTH_SAMPLE_READS,
+};
+use kun_peng::report::{
+    read_genome_sizes, read_report_groups, report_abundance, report_genome_coverage, report_groups,
+    report_kraken_style, report_rank, report_sparse, report_tree_json, AbundanceEvents,
+};
+use kun_peng::summary::{
+    alphabet_mismatch_message, check_classified_rate, file_summary_table,
+    low_classification_message, short_read_message, ReadStats, RunSummary, StatusFile,
+    STATUS_FILE_INTERVAL,
+};
+use kun_peng::taxonomy::{parse_rank, rank_name, Taxonomy};
+use kun_peng::utils::{
+    create_sample_file, find_and_sort_files, get_lastest_file_index, write_atomically,
+};
+use kun_peng::watch::{WatchLedger, WATCH_LEDGER};
 use kun_peng::{HitGroup, IndexOptions};
 use seqkmer::{read_parallel, Base, FastxReader, Meros, MinimizerIterator, OptionPair, Reader};
-use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufWriter, IsTerminal, Write};
 use std::io::{Error, ErrorKind, Result};
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug, Clone)]
 #[clap(
@@ -30,14 +58,91 @@ pub struct Args {
     #[clap(long = "output-dir", value_parser)]
     pub output_dir: Option<PathBuf>,
 
+    /// Also split the Kraken output lines into `<prefix>.classified.kraken` and `<prefix>.unclassified.kraken`.
+    #[clap(long = "split-output", value_parser)]
+    pub split_output: Option<PathBuf>,
+
+    /// Write the IDs of the classified reads to this file, one per line (gzip if it ends with `.gz`).
+    #[clap(long = "classified-ids-output", value_parser)]
+    pub classified_ids_output: Option<PathBuf>,
+
+    /// Write the IDs of the unclassified reads to this file, one per line (gzip if it ends with `.gz`).
+    #[clap(long = "unclassified-ids-output", value_parser)]
+    pub unclassified_ids_output: Option<PathBuf>,
+
     /// Enable paired-end processing.
     #[clap(short = 'P', long = "paired-end-processing", action)]
     pub paired_end_processing: bool,
 
     /// Process pairs with mates in the same file.
-    #[clap(short = 'S', long = "single-file-pairs", action)]
+    #[clap(
+        short = 'S',
+        long = "single-file-pairs",
+        action,
+        requires = "paired_end_processing"
+    )]
     pub single_file_pairs: bool,
 
+    /// Treat all input files as consecutive parts of one sample, with a global read index.
+    #[clap(long = "merge-parts", action)]
+    pub merge_parts: bool,
+
+    /// Copy every read into a file of its taxon in this directory, `<taxid>.fastq`, or
+    /// `unclassified.fastq`, once its sample is classified. Pairs go to `<taxid>_1.fastq`
+    /// and `<taxid>_2.fastq`, FASTA reads to `.fasta` files. The records are copied from
+    /// the inputs, qualities included, which must be plain or gzipped.
+    #[clap(
+        long = "bin-dir",
+        value_name = "DIR",
+        requires = "output_dir",
+        conflicts_with = "watch"
+    )]
+    pub bin_dir: Option<PathBuf>,
+
+    /// Count the records of every input again once its sample is classified, and fail if
+    /// they differ from the reads classified, e.g. a truncated file. A cheap second pass
+    /// over the lines, skipped for zstd and bzip2 inputs.
+    #[clap(long = "verify-counts", action)]
+    pub verify_counts: bool,
+
+    /// How to scan the IUPAC ambiguity codes (R, Y, ...) of the reads: `break` the k-mers like
+    /// an N, read them as their `first-base` in ACGT order, or `skip` them.
+    #[clap(long = "iupac", value_name = "POLICY", default_value = "break")]
+    pub iupac: IupacPolicy,
+
+    /// Skip the spaces and tabs inside the sequences, like the line breaks, instead of
+    /// ending the k-mers at them.
+    #[clap(long = "skip-whitespace")]
+    pub skip_whitespace: bool,
+
+    /// Cut this many bases from the start of every mate before scanning, e.g. adapter or
+    /// low-quality bases. Mates trimmed to nothing count as too short.
+    #[clap(long = "trim-head", value_name = "N", default_value_t = 0)]
+    pub trim_head: usize,
+
+    /// Cut this many bases from the end of every mate before scanning.
+    #[clap(long = "trim-tail", value_name = "N", default_value_t = 0)]
+    pub trim_tail: usize,
+
+    /// Truncate or skip the reads longer than this many bases, e.g. concatenation
+    /// artifacts, see --long-reads. They are counted in the summary.
+    #[clap(
+        long = "max-read-length",
+        value_name = "L",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_read_length: Option<u64>,
+
+    /// What --max-read-length does with the longer reads: `truncate` them to its length,
+    /// or `skip` them, leaving them unclassified.
+    #[clap(
+        long = "long-reads",
+        value_name = "POLICY",
+        default_value = "truncate",
+        requires = "max_read_length"
+    )]
+    pub long_reads: LongReadPolicy,
+
     /// Minimum quality score for FASTQ data.
     #[clap(
         short = 'Q',
@@ -47,23 +152,142 @@ pub struct Args {
     )]
     pub minimum_quality_score: i32,
 
+    /// Don't sample the read lengths of the first input to warn about reads short for the
+    /// k-mer length of the database.
+    #[clap(long)]
+    pub no_length_check: bool,
+
+    /// Don't sample the characters of the first input to refuse protein reads for a DNA
+    /// database, or nucleotide reads for a protein one.
+    #[clap(long)]
+    pub ignore_alphabet_check: bool,
+
     /// Confidence score threshold.
     #[clap(
         short = 'T',
         long = "confidence-threshold",
-        value_parser,
+        value_parser = parse_fraction,
         default_value_t = 0.0
     )]
     pub confidence_threshold: f64,
 
-    /// In comb. w/ -R, provide minimizer information in report
-    #[clap(short = 'K', long, value_parser, default_value_t = false)]
+    /// The minimum number of minimizers supporting a call, whatever the read length. Short
+    /// reads passing --confidence-threshold with a couple of hits stay unclassified.
+    #[clap(long = "min-supporting-minimizers", value_parser, default_value_t = 0)]
+    pub min_supporting_minimizers: u64,
+
+    /// In comb. w/ --output-dir, provide minimizer information in report
+    #[clap(
+        short = 'K',
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "output_dir"
+    )]
     pub report_kmer_data: bool,
 
-    /// In comb. w/ -R, report taxa w/ 0 count
-    #[clap(short = 'z', long, value_parser, default_value_t = false)]
+    /// In comb. w/ --output-dir, report taxa w/ 0 count
+    #[clap(
+        short = 'z',
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "output_dir"
+    )]
     pub report_zero_counts: bool,
 
+    /// Report unclassified sequences as a top-level `U` row.
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
+    pub report_include_unclassified: bool,
+
+    /// Deepest level indented in the Kraken report, deeper taxa keep their counts and
+    /// the indentation of this level.
+    #[clap(
+        long = "report-max-indent",
+        value_name = "DEPTH",
+        default_value_t = 100
+    )]
+    pub report_max_indent: usize,
+
+    /// In comb. w/ --output-dir, write `output_<N>.coverage.tsv` with the fraction of the
+    /// index minimizers of each detected taxon that were observed.
+    #[clap(long = "report-coverage", action, requires = "output_dir")]
+    pub report_coverage: bool,
+
+    /// In comb. w/ --output-dir, write `output_<N>.sparse.tsv`, a flat table of the taxa
+    /// with reads, their counts and their full lineage, without the tree structure.
+    #[clap(long = "report-sparse", action, requires = "output_dir")]
+    pub report_sparse: bool,
+
+    /// In comb. w/ --output-dir, write `output_<N>.<rank>.tsv` with the reads of every taxon
+    /// of a rank (e.g. `G` or `genus`), the reads above it on an `unassigned at <rank>` row.
+    #[clap(long = "report-rank", value_name = "RANK", value_parser = parse_rank, requires = "output_dir")]
+    pub report_rank: Option<char>,
+
+    /// In comb. w/ --output-dir, write `output_<N>.tree.json`, the taxa with reads as a
+    /// nested tree, for sunburst and other hierarchical charts.
+    #[clap(long = "report-tree-json", action, requires = "output_dir")]
+    pub report_tree_json: bool,
+
+    /// In comb. w/ --output-dir, sum the reads of custom taxon groups into
+    /// `output_<N>.groups.tsv`, from a `group_name<TAB>taxid,taxid,...` file.
+    #[clap(long = "groups", requires = "output_dir")]
+    pub groups: Option<PathBuf>,
+
+    /// In comb. w/ --output-dir, write `output_<N>.abundance.tsv` with the reads of each
+    /// taxon normalized by its genome size, from a `taxid<TAB>bp` file.
+    #[clap(long = "genome-sizes", requires = "output_dir")]
+    pub genome_sizes: Option<PathBuf>,
+
+    /// In comb. w/ --output-dir, write the minimizer hits of every read to `output_<N>.gff3`,
+    /// a GFF3 track with the taxid of each hit for genome browsers such as IGV.
+    #[clap(long = "gff3", action, requires = "output_dir")]
+    pub gff3: bool,
+
+    /// In comb. w/ --output-dir, paint every base of the reads with the taxon of the
+    /// minimizers covering it, as `taxid:bases` runs in `output_<N>.paint.txt`.
+    #[clap(long = "paint", action, requires = "output_dir")]
+    pub paint: bool,
+
+    /// Append a High/Medium/Low confidence column, from the `medium,high` thresholds
+    /// on the fraction of minimizers supporting the call (e.g. `0.5,0.8`).
+    #[clap(long = "confidence-bands", value_name = "MEDIUM,HIGH")]
+    pub confidence_bands: Option<ConfidenceBands>,
+
+    /// Append a column with the coverage breadth of the call, the fraction of the read
+    /// segments with a hit in the called clade.
+    #[clap(long = "report-read-coverage", action)]
+    pub report_read_coverage: bool,
+
+    /// Append a column with the calls of the mates of a pair classified on their own,
+    /// `taxid1|taxid2` (`-` for single reads), to spot pairs whose mates disagree.
+    #[clap(long = "report-mate-calls", action)]
+    pub report_mate_calls: bool,
+
+    /// Append this tag as the last column of every output line and record it in the reports,
+    /// to tell runs apart after merging.
+    #[clap(long = "read-group", value_parser = parse_read_group)]
+    pub read_group: Option<String>,
+
+    /// Append the input file of every read as the last column of the output lines, the
+    /// mates of a pair joined with a comma, to trace the reads of --merge-parts samples.
+    #[clap(long = "tag-source", action)]
+    pub tag_source: bool,
+
+    /// Record the version, the parameters and the inputs of the run in a
+    /// `# provenance:` header line of the reports.
+    #[clap(long = "provenance", action)]
+    pub provenance: bool,
+
+    /// With --provenance, also record the xxHash64 checksums of the input files.
+    #[clap(long = "hash-inputs", action, requires = "provenance")]
+    pub hash_inputs: bool,
+
+    /// Record the k-mer and minimizer parameters of the database (k, l, spaced seed mask,
+    /// toggle mask, type) in a `# index:` header line of the reports.
+    #[clap(long = "index-params", action)]
+    pub index_params: bool,
+
     /// The minimum number of hit groups needed for a call.
     #[clap(
         short = 'g',
@@ -73,184 +297,500 @@ pub struct Args {
     )]
     pub minimum_hit_groups: usize,
 
+    /// The number of non-matching minimizers allowed between two hits of the same hit group.
+    /// If not set, every minimizer of the read counts as a hit group.
+    #[clap(long = "hit-group-gap", value_parser)]
+    pub hit_group_gap: Option<usize>,
+
+    /// Leave the reads with hits in fewer than --minimum-hit-groups hit groups out of the
+    /// outputs, counted as filtered rather than unclassified.
+    #[clap(long = "filter-hit-groups", action)]
+    pub filter_hit_groups: bool,
+
+    /// Report calls above this rank (e.g. `S` or `genus`) as unclassified.
+    #[clap(long = "max-rank", value_parser = parse_rank)]
+    pub max_rank: Option<char>,
+
+    /// Weigh every hit by the rank of its taxon, hits of general taxa count less.
+    #[clap(long = "weight-by-rank", action)]
+    pub weight_by_rank: bool,
+
+    /// Call the LCA of every taxon hit by a minimizer of the read, whatever the counts,
+    /// the confidence threshold and the hit groups.
+    #[clap(long = "lca-of-all", action, conflicts_with = "weight_by_rank")]
+    pub lca_of_all: bool,
+
+    /// How the hits of a read add up: `positions` counts a minimizer at every position it
+    /// hits, as Kraken 2 does, `distinct` once per read, so repeats weigh less.
+    #[clap(long = "score-mode", value_name = "MODE", default_value = "positions")]
+    pub score_mode: ScoreMode,
+
+    /// Report calls with hits in less than this fraction of the read segments as
+    /// unclassified, e.g. `0.2` against hits clustered in a repeat.
+    #[clap(long = "min-read-coverage", value_parser = parse_fraction, default_value_t = 0.0)]
+    pub min_read_coverage: f64,
+
+    /// Warn when the fraction of classified sequences is below this value.
+    #[clap(long = "min-classified-warn", value_parser = parse_fraction, default_value_t = 0.05)]
+    pub min_classified_warn: f64,
+
+    /// Exit with an error instead of a warning when the classified fraction is below `--min-classified-warn`.
+    #[clap(long = "min-classified-error", action)]
+    pub min_classified_error: bool,
+
+    /// Periodically overwrite this file with the running counts as JSON
+    /// (`{"processed":N,"classified":M,"rate":R}`), for monitoring.
+    #[clap(long = "status-file", value_parser)]
+    pub status_file: Option<PathBuf>,
+
+    /// Write a line to this file (`-` for stderr) whenever the reads called at a taxon
+    /// first reach one of the --event-thresholds during the run, for surveillance.
+    #[clap(long = "abundance-events", value_name = "FILE")]
+    pub abundance_events: Option<PathBuf>,
+
+    /// Comma separated read counts firing the --abundance-events lines of a taxon.
+    #[clap(
+        long = "event-thresholds",
+        value_name = "N,N,...",
+        value_delimiter = ',',
+        default_value = "10,100,1000",
+        requires = "abundance_events"
+    )]
+    pub event_thresholds: Vec<u64>,
+
+    /// Write the totals, the per file counts, the stage timings and the warnings of the run
+    /// to this file as JSON, see `kun_peng::summary::RunSummary` for the schema.
+    #[clap(long = "summary-output", value_parser)]
+    pub summary_output: Option<PathBuf>,
+
+    /// Check the index for taxids missing from the taxonomy before classifying.
+    /// An optional N only checks every Nth index cell.
+    #[clap(long = "validate", value_name = "N", num_args = 0..=1, default_missing_value = "1")]
+    pub validate: Option<usize>,
+
     /// The number of threads to use.
-    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    #[clap(short = 'p', long = "num-threads", value_parser = parse_threads, default_value_t = default_threads())]
     pub num_threads: usize,
 
+    /// The number of threads compressing the gzip outputs, on top of --num-threads.
+    #[clap(long = "io-threads", value_parser, default_value_t = 2)]
+    pub io_threads: usize,
+
+    /// Memory for the reads in flight, read ahead, being classified or waiting to be written.
+    /// The batch size and the number of batches in flight are chosen from it and the mean read
+    /// length of each sample, e.g. '512M', '4G'.
+    #[clap(long = "max-batch-memory", value_parser = parse_size, default_value = "1G")]
+    pub max_batch_memory: usize,
+
+    /// Reads per batch, instead of the size fitting --max-batch-memory.
+    #[clap(long = "batch-reads", value_parser = clap::value_parser!(u64).range(1..))]
+    pub batch_reads: Option<u64>,
+
+    /// Batches in flight at once, instead of the number fitting --max-batch-memory.
+    #[clap(long = "queue-len", value_parser = clap::value_parser!(u64).range(1..))]
+    pub queue_len: Option<u64>,
+
+    /// Save a checkpoint to `<output-dir>/checkpoint.json` every N reads, at a batch boundary.
+    /// Needs a single sample (one file or one pair) that is uncompressed or BGZF compressed,
+    /// plain gzip and zstd inputs can't be sought.
+    #[clap(
+        long = "checkpoint-every",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "output_dir"
+    )]
+    pub checkpoint_every: Option<u64>,
+
+    /// Continue the sample of a checkpoint, appending to its outputs.
+    #[clap(
+        long = "resume-from",
+        value_name = "CHECKPOINT",
+        requires = "output_dir"
+    )]
+    pub resume_from: Option<PathBuf>,
+
+    /// Classify the FASTA/FASTQ files appearing in a directory, waking up every
+    /// --watch-interval seconds, and regenerate the `output.*` reports after each batch.
+    /// The classified files are kept in `<output-dir>/watch.ledger`, a restarted run skips
+    /// them. Files should be moved into the directory once complete.
+    #[clap(
+        long = "watch",
+        value_name = "DIR",
+        requires = "output_dir",
+        conflicts_with_all = ["paired_end_processing", "checkpoint_every", "resume_from"]
+    )]
+    pub watch: Option<PathBuf>,
+
+    /// Seconds between two scans of the --watch directory.
+    #[clap(
+        long = "watch-interval",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        default_value_t = 30
+    )]
+    pub watch_interval: u64,
+
+    /// Stop watching once this file exists, after classifying the files already there.
+    /// A relative path is taken in the --watch directory.
+    #[clap(long = "stop-file", value_name = "FILE", requires = "watch")]
+    pub stop_file: Option<PathBuf>,
+
+    /// Stop watching once this many files are classified, those of a restarted run's
+    /// ledger included.
+    #[clap(
+        long = "stop-after-files",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "watch"
+    )]
+    pub stop_after_files: Option<u64>,
+
     /// A list of input file paths (FASTA/FASTQ) to be processed by the classify program.
     /// Supports fasta or fastq format files (e.g., .fasta, .fastq) and gzip compressed files (e.g., .fasta.gz, .fastq.gz).
     // #[clap(short = 'F', long = "files")]
     pub input_files: Vec<String>,
 }
 
-fn process_seq(
-    rows: &mut Vec<Row>,
-    m_iter: &mut MinimizerIterator,
-    hash_config: &HashConfig,
-    chtable: &CHTable,
-    offset: usize,
-) -> usize {
-    let chunk_size = hash_config.hash_capacity;
-    let value_bits = hash_config.value_bits;
-    let data: Vec<(usize, u64)> = m_iter.collect();
-    for (sort, hash_key) in data {
-        let (idx, compacted) = hash_config.compact(hash_key);
-        let partition_index = idx / chunk_size;
-        let index = idx % chunk_size;
-
-        let taxid = chtable.get_from_page(index, compacted, partition_index);
-        if taxid > 0 {
-            let high = u32::combined(compacted, taxid, value_bits);
-            let row = Row::new(high, 0, sort as u32 + 1 + offset as u32);
-            rows.push(row);
+impl Args {
+    /// The options of [`process_hitgroup`] for a database with `value_mask`
+    fn hit_group_options(&self, value_mask: usize) -> HitGroupOptions {
+        HitGroupOptions {
+            minimum_hit_groups: self.minimum_hit_groups,
+            hit_group_gap: self.hit_group_gap,
+            max_rank: self.max_rank,
+            weight_by_rank: self.weight_by_rank,
+            lca_of_all: self.lca_of_all,
+            score_mode: self.score_mode,
+            min_read_coverage: self.min_read_coverage,
+            value_mask,
         }
     }
-    m_iter.size + offset
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_record(
     marker: &mut Base<MinimizerIterator>,
     args: &Args,
     taxonomy: &Taxonomy,
     chtable: &CHTable,
-    hash_config: &HashConfig,
-    cur_taxon_counts: &TaxonCountersDash,
+    meros: &Meros,
+    cur_taxon_counts: &mut TaxonCounters,
     classify_counter: &AtomicUsize,
+    read_stats: &mut ReadStats,
+    tracks: &mut ReadTracks,
+    source: Option<&str>,
 ) -> String {
+    let hash_config = &chtable.config;
     let id = &marker.header.id.clone();
-    let rows: Vec<Row> = marker
-        .fold(|rows, m_iter, offset| process_seq(rows, m_iter, &hash_config, chtable, offset));
+    let min_clear = meros.min_clear_hash_value;
+    let rows: Vec<Row> = marker.fold(|rows, m_iter, offset| {
+        process_seq(rows, m_iter, hash_config, chtable, min_clear, offset)
+    });
 
-    let hits = HitGroup::new(rows, marker.range());
+    let seq_lens = marker.body.apply(|m_iter| m_iter.seq_size());
+    seq_lens.apply(|&len| read_stats.add_mate_len(len as u64));
+    let hits = HitGroup::new(rows, marker.range()).with_seq_lens(seq_lens, meros.k_mer);
+    if hits.capacity() == 0 {
+        read_stats.add_no_kmers();
+    }
+    if args.filter_hit_groups && hits.below_hit_groups(args.minimum_hit_groups, args.hit_group_gap)
+    {
+        read_stats.add_filtered();
+        return String::new();
+    }
 
     let seq_len_str = marker.fmt_seq_size();
 
-    let required_score = hits.required_score(args.confidence_threshold);
-    let hit_data = process_hitgroup(
-        &hits,
-        taxonomy,
-        classify_counter,
-        required_score,
-        args.minimum_hit_groups,
-        hash_config.value_mask,
-    );
+    let required_score =
+        hits.required_score_with_floor(args.confidence_threshold, args.min_supporting_minimizers);
+    let options = args.hit_group_options(hash_config.value_mask);
+    let call = process_hitgroup(&hits, taxonomy, classify_counter, required_score, &options);
+    read_stats.zero_taxon_hits += call.zero_taxon_hits;
 
-    hit_data.3.iter().for_each(|(key, value)| {
+    call.taxon_counts.iter().for_each(|(key, value)| {
         cur_taxon_counts
             .entry(*key)
             .or_default()
             .merge(value)
             .unwrap();
     });
-    format!(
-        "{}\t{}\t{}\t{}\t{}\n",
-        hit_data.0, id, hit_data.1, seq_len_str, hit_data.2
-    )
+    if args.gff3 {
+        tracks.gff3.push_str(&hits_gff3(
+            id,
+            &hits,
+            call.taxid,
+            meros.k_mer,
+            hash_config.value_mask,
+            taxonomy,
+        ));
+    }
+    if args.paint {
+        let painting = paint_bases(&hits, meros.k_mer, hash_config.value_mask, taxonomy);
+        tracks.paint.push_str(&format!("{}\t{}\n", id, painting));
+    }
+    let hit_string = hit_string(&hits, hash_config.value_mask, taxonomy);
+    let mut line = KrakenLine::new(call.classified, id, call.taxid)
+        .seq_size(&seq_len_str)
+        .hit_string(&hit_string);
+    if let Some(bands) = &args.confidence_bands {
+        line = line.column(bands.label(call.confidence));
+    }
+    if args.report_read_coverage {
+        line = line.column(format!("{:.3}", call.read_coverage));
+    }
+    if args.report_mate_calls {
+        let mate_counter = AtomicUsize::new(0);
+        let calls = mate_calls(&hits, |mate| {
+            let required_score = mate.required_score_with_floor(
+                args.confidence_threshold,
+                args.min_supporting_minimizers,
+            );
+            process_hitgroup(mate, taxonomy, &mate_counter, required_score, &options).taxid
+        });
+        line = line.mate_calls(calls);
+    }
+    if let Some(read_group) = &args.read_group {
+        line = line.column(read_group);
+    }
+    if let Some(source) = source {
+        line = line.column(source);
+    }
+    format!("{}\n", line)
+}
+
+/// Path of the Kraken output of a sample
+fn kraken_output_path(output_dir: &Path, file_index: usize) -> PathBuf {
+    output_dir.join(format!("output_{}.txt", file_index))
+}
+
+/// Per-read outputs of a batch, written next to the Kraken output
+#[derive(Default)]
+struct ReadTracks {
+    gff3: String,
+    paint: String,
+}
+
+/// The files of the per-read outputs of a sample, those that are enabled
+struct TrackWriters {
+    gff3: Option<BufWriter<File>>,
+    paint: Option<BufWriter<File>>,
 }
 
+impl TrackWriters {
+    /// Paths of the enabled per-read outputs of a sample, see `--gff3` and `--paint`
+    fn paths(
+        args: &Args,
+        output_dir: &Path,
+        file_index: usize,
+    ) -> (Option<PathBuf>, Option<PathBuf>) {
+        let path = |enabled: bool, suffix: &str| {
+            enabled.then(|| output_dir.join(format!("output_{}.{}", file_index, suffix)))
+        };
+        (path(args.gff3, "gff3"), path(args.paint, "paint.txt"))
+    }
+
+    fn open(args: &Args, file_index: usize, append: bool) -> Result<Self> {
+        let (gff3, paint) = match &args.output_dir {
+            Some(output_dir) => Self::paths(args, output_dir, file_index),
+            None => (None, None),
+        };
+        let open = |path: Option<PathBuf>| -> Result<Option<BufWriter<File>>> {
+            path.map(|path| Ok(BufWriter::new(open_output(path, append)?)))
+                .transpose()
+        };
+        let mut writers = Self {
+            gff3: open(gff3)?,
+            paint: open(paint)?,
+        };
+        if !append {
+            if let Some(gff3) = writers.gff3.as_mut() {
+                writeln!(gff3, "##gff-version 3")?;
+            }
+        }
+        Ok(writers)
+    }
+
+    fn write(&mut self, tracks: &ReadTracks) -> Result<()> {
+        for (writer, track) in [
+            (&mut self.gff3, &tracks.gff3),
+            (&mut self.paint, &tracks.paint),
+        ] {
+            if let Some(writer) = writer {
+                writer.write_all(track.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for writer in [&mut self.gff3, &mut self.paint].into_iter().flatten() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn process_fastx_file<R>(
     args: &Args,
     meros: Meros,
-    hash_config: HashConfig,
-    file_index: usize,
     reader: &mut R,
     chtable: &CHTable,
     taxonomy: &Taxonomy,
-    total_taxon_counts: &mut TaxonCounters,
-) -> io::Result<(usize, usize)>
+    progress: &mut SampleProgress,
+    plan: BatchPlan,
+    split_outputs: &[SplitOutput],
+    sources: Option<&ReadSources>,
+    mut checkpointer: Option<Checkpointer>,
+    mut status_file: Option<&mut StatusFile>,
+    mut abundance_events: Option<&mut AbundanceEvents>,
+) -> io::Result<()>
 where
     R: Reader,
 {
-    let mut writer: Box<dyn Write + Send> = match &args.output_dir {
+    let file_index = progress.file_index;
+    let writer: Box<dyn Write + Send> = match &args.output_dir {
         Some(ref file_path) => {
-            let filename = file_path.join(format!("output_{}.txt", file_index));
-            let file = File::create(filename)?;
+            let filename = kraken_output_path(file_path, file_index);
+            let file = open_output(filename, progress.is_restored())?;
             Box::new(BufWriter::new(file)) as Box<dyn Write + Send>
         }
         None => Box::new(BufWriter::new(io::stdout())) as Box<dyn Write + Send>,
     };
+    let mut writer = tee_all(split_outputs, writer);
+    let mut track_writers = TrackWriters::open(args, file_index, progress.is_restored())?;
+    let steps = ReadPreprocessor {
+        skip_whitespace: args.skip_whitespace,
+        trim_head: args.trim_head,
+        trim_tail: args.trim_tail,
+        iupac: args.iupac,
+        max_len: args.max_read_length.map(|len| len as usize),
+        long_reads: args.long_reads,
+    };
+    let mut checked = CheckedReader::new(reader);
+    let mut reader = PreprocessReader::new(&mut checked, steps);
+    let in_flight = InFlight::new(plan.queue_len);
+    let mut batches = BatchReader::new(&mut reader, plan, in_flight.clone());
 
-    let cur_taxon_counts = TaxonCountersDash::new();
-
-    let seq_counter = AtomicUsize::new(0);
-    let classify_counter = AtomicUsize::new(0);
-
+    // Batches are written and counted in read order, so that the output follows the input
+    // and a checkpoint holds exactly the written reads.
+    let mut in_order = InOrder::new(in_flight.clone());
     let _ = read_parallel(
-        reader,
+        &mut batches,
         args.num_threads,
         &meros,
         |seqs| {
             let mut buffer = String::new();
+            let mut tracks = ReadTracks::default();
+            let mut chunk_stats = ReadStats::default();
+            let mut chunk_taxon_counts = TaxonCounters::new();
+            let classify_counter = AtomicUsize::new(0);
+            let first_read = seqs.first().map_or(0, |record| record.header.reads_index);
+            let mut reads = 0;
             for record in seqs {
-                seq_counter.fetch_add(1, Ordering::SeqCst);
+                reads += 1;
+                let source = sources.and_then(|sources| sources.source(record.header.reads_index));
                 let output_line = process_record(
                     record,
                     args,
                     taxonomy,
                     chtable,
-                    &hash_config,
-                    &cur_taxon_counts,
+                    &meros,
+                    &mut chunk_taxon_counts,
                     &classify_counter,
+                    &mut chunk_stats,
+                    &mut tracks,
+                    source.as_deref(),
                 );
                 buffer.push_str(&output_line);
             }
 
-            buffer
+            (
+                first_read,
+                (
+                    buffer,
+                    tracks,
+                    chunk_stats,
+                    chunk_taxon_counts,
+                    reads,
+                    classify_counter.into_inner(),
+                ),
+            )
         },
         |dataset| {
             while let Some(data) = dataset.next() {
-                let res = data.unwrap();
-                writer
-                    .write_all(res.as_bytes())
-                    .expect("Failed to write date to file");
+                let (first_read, result) = data.unwrap();
+                for (res, tracks, chunk_stats, chunk_taxon_counts, reads, classified) in
+                    in_order.push(first_read, result)
+                {
+                    writer
+                        .write_all(res.as_bytes())
+                        .expect("Failed to write date to file");
+                    track_writers
+                        .write(&tracks)
+                        .expect("Failed to write the per-read outputs");
+                    progress.add_batch(reads, classified, &chunk_stats, &chunk_taxon_counts);
+                    if let Some(events) = abundance_events.as_mut() {
+                        events
+                            .add(taxonomy, reads as u64, &chunk_taxon_counts)
+                            .expect("Failed to write abundance events");
+                    }
+                    if let Some(status) = status_file.as_mut() {
+                        status
+                            .add(reads as u64, classified as u64)
+                            .expect("Failed to write status file");
+                    }
+                    if let Some(checkpointer) = checkpointer.as_mut() {
+                        checkpointer
+                            .update(progress, || {
+                                track_writers.flush()?;
+                                writer.flush()
+                            })
+                            .expect("Failed to write checkpoint");
+                    }
+                }
             }
         },
     );
+    progress.read_stats.too_long += reader.long_reads();
+    writer.flush()?;
+    track_writers.flush()?;
+    checked.finish()?;
 
-    let mut sample_taxon_counts: HashMap<
-        u64,
-        kun_peng::readcounts::ReadCounts<
-            hyperloglogplus::HyperLogLogPlus<u64, kun_peng::KBuildHasher>,
-        >,
-    > = HashMap::new();
-    cur_taxon_counts.iter().for_each(|entry| {
-        total_taxon_counts
-            .entry(*entry.key())
-            .or_default()
-            .merge(&entry.value())
-            .unwrap();
-        sample_taxon_counts
-            .entry(*entry.key())
-            .or_default()
-            .merge(&entry.value())
-            .unwrap();
-    });
+    Ok(())
+}
 
-    let thread_sequences = seq_counter.load(Ordering::SeqCst);
-    let thread_classified = classify_counter.load(Ordering::SeqCst);
-    if let Some(output) = &args.output_dir {
-        let filename = output.join(format!("output_{}.kreport2", file_index));
-        report_kraken_style(
-            filename,
-            args.report_zero_counts,
-            args.report_kmer_data,
-            &taxonomy,
-            &sample_taxon_counts,
-            thread_sequences as u64,
-            (thread_sequences - thread_classified) as u64,
-        )?;
+/// Header lines of the reports, the read group, the provenance of the run and the index
+/// parameters
+fn report_header(args: &Args, idx_opts: &IndexOptions) -> Result<Vec<String>> {
+    let mut header: Vec<String> = args
+        .read_group
+        .iter()
+        .map(|read_group| format!("read_group: {}", read_group))
+        .collect();
+    if args.provenance {
+        let provenance =
+            Provenance::new("direct", args).with_inputs(&args.input_files, args.hash_inputs)?;
+        header.push(provenance.report_header()?);
     }
-
-    Ok((thread_sequences, thread_sequences - thread_classified))
+    if args.index_params {
+        header.push(idx_opts.report_header());
+    }
+    Ok(header)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_files(
     args: Args,
+    idx_opts: &IndexOptions,
     meros: Meros,
     hash_config: HashConfig,
     chtable: &CHTable,
     taxonomy: &Taxonomy,
+    summary: &RunSummary,
+    mut ledger: Option<&mut WatchLedger>,
 ) -> Result<()> {
+    let start = Instant::now();
     let (mut file_index, mut file_writer) = if let Some(out_dir) = &args.output_dir {
         let file_path = out_dir.join("sample_file.map");
         let file_writer = create_sample_file(&file_path);
@@ -266,51 +806,442 @@ fn process_files(
         )
     };
 
+    let index_counts = args
+        .report_coverage
+        .then(|| chtable.taxon_minimizer_counts());
+    let report_header = report_header(&args, idx_opts)?;
+    let groups = args
+        .groups
+        .as_ref()
+        .map(|path| read_report_groups(path, taxonomy))
+        .transpose()?;
+    let genome_sizes = args
+        .genome_sizes
+        .as_ref()
+        .map(|path| read_genome_sizes(path, taxonomy))
+        .transpose()?;
+    let io_pool = IoPool::new(args.io_threads)?;
+
     let mut process_funcs = |files: Vec<&[String]>| -> Result<()> {
-        let file_bits = (((files.len() + file_index) as f64).log2().ceil() as usize).max(1);
+        let samples: Vec<Vec<&[String]>> = if args.merge_parts {
+            vec![files]
+        } else {
+            files.into_iter().map(|part| vec![part]).collect()
+        };
+        let file_bits = (((samples.len() + file_index) as f64).log2().ceil() as usize).max(1);
         if file_bits > hash_config.value_bits {
             panic!("The number of files is too large to process.");
         }
 
+        let checkpointing = args.checkpoint_every.is_some() || args.resume_from.is_some();
+        if checkpointing && (samples.len() != 1 || samples[0].len() != 1) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "--checkpoint-every and --resume-from need a single sample (one file or one pair)",
+            ));
+        }
+        // The outputs are cut back to the checkpoint before they are opened for appending.
+        let mut resume = args
+            .resume_from
+            .as_ref()
+            .map(SampleProgress::load)
+            .transpose()?;
+        if let Some(progress) = &resume {
+            let inputs: Vec<PathBuf> = samples[0].concat().iter().map(PathBuf::from).collect();
+            if progress.inputs != inputs {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("the checkpoint belongs to the inputs {:?}", progress.inputs),
+                ));
+            }
+            progress.truncate_outputs()?;
+        }
+        // A watch run continues the outputs of its earlier batches, like a resumed sample
+        let watched = ledger
+            .as_deref()
+            .map(|ledger| &ledger.progress)
+            .filter(|progress| !progress.inputs.is_empty());
+        let append = resume.is_some() || watched.is_some();
+
         let mut total_taxon_counts = TaxonCounters::new();
         let mut total_seqs: usize = 0;
         let mut total_unclassified: usize = 0;
-        for file_pair in files {
-            file_index += 1;
-
-            writeln!(file_writer, "{}\t{}", file_index, file_pair.join(","))?;
-            file_writer.flush().unwrap();
+        let mut read_stats = ReadStats::default();
+        let mut split_outputs: Vec<SplitOutput> = args
+            .split_output
+            .as_ref()
+            .map(|prefix| SplitOutput::create(prefix, append))
+            .transpose()?
+            .into_iter()
+            .collect();
+        split_outputs.extend(SplitOutput::ids(
+            args.classified_ids_output.as_ref(),
+            args.unclassified_ids_output.as_ref(),
+            append,
+            &io_pool,
+        )?);
+        let mut status_file = args
+            .status_file
+            .as_ref()
+            .map(|path| StatusFile::new(path, STATUS_FILE_INTERVAL));
+        if let (Some(status), Some(progress)) = (status_file.as_mut(), watched) {
+            status.add(progress.reads_index as u64, progress.classified as u64)?;
+        }
+        let mut abundance_events = args
+            .abundance_events
+            .as_ref()
+            .map(|path| match watched {
+                Some(progress) => AbundanceEvents::resume(
+                    path,
+                    &args.event_thresholds,
+                    progress.reads_index as u64,
+                    &progress.taxon_counts,
+                ),
+                None => AbundanceEvents::create(path, &args.event_thresholds),
+            })
+            .transpose()?;
+        let mut bins = args
+            .bin_dir
+            .as_ref()
+            .map(|dir| BinWriters::new(dir, MAX_OPEN_BINS))
+            .transpose()?;
+        let mut file_stats = Vec::new();
+        for parts in samples {
+            let mut progress = match resume.take() {
+                Some(progress) => {
+                    file_index = progress.file_index;
+                    if let Some(status) = status_file.as_mut() {
+                        status.add(progress.reads_index as u64, progress.classified as u64)?;
+                    }
+                    progress
+                }
+                None => {
+                    file_index += 1;
+                    writeln!(file_writer, "{}\t{}", file_index, parts.concat().join(","))?;
+                    file_writer.flush().unwrap();
+                    let inputs = parts.concat().iter().map(PathBuf::from).collect();
+                    SampleProgress::new(file_index, inputs)
+                }
+            };
 
             let score = args.minimum_quality_score;
-            let paths = OptionPair::from_slice(file_pair);
-            let mut reader = FastxReader::from_paths(paths, file_index, score)?;
-            // let mut reader = create_reader(file_pair, file_index, score)?;
-            let (thread_sequences, thread_unclassified) = process_fastx_file(
-                &args,
-                meros,
-                hash_config,
-                file_index,
-                &mut reader,
-                chtable,
-                taxonomy,
-                &mut total_taxon_counts,
-            )?;
+            let plan = BatchPlan::new(
+                args.max_batch_memory as u64,
+                args.num_threads,
+                sample_read_bases(parts[0]),
+                args.queue_len.map(|len| len as usize),
+                args.batch_reads.map(|reads| reads as usize),
+            );
+            eprintln!("{}", plan);
+            // The source of a single file or pair is known, the parts are named as they are opened
+            let sources = args.tag_source.then(|| match &parts[..] {
+                [part] => ReadSources::single(&part.join(",")),
+                _ => ReadSources::default(),
+            });
+            if checkpointing {
+                let mut reader =
+                    SeekableFastxReader::from_paths(&progress.inputs, file_index, score)?;
+                if progress.is_restored() {
+                    reader.seek(&progress.offsets, progress.reads_index)?;
+                }
+                let checkpointer = match (args.checkpoint_every, &args.output_dir) {
+                    (Some(every), Some(output)) => {
+                        let mut outputs = vec![kraken_output_path(output, file_index)];
+                        let (gff3, paint) = TrackWriters::paths(&args, output, file_index);
+                        outputs.extend(gff3.into_iter().chain(paint));
+                        for split in &split_outputs {
+                            outputs.extend_from_slice(split.paths());
+                        }
+                        Some(Checkpointer::new(
+                            output.join("checkpoint.json"),
+                            every as usize,
+                            reader.marks(),
+                            outputs,
+                            &progress,
+                        ))
+                    }
+                    _ => None,
+                };
+                process_fastx_file(
+                    &args,
+                    meros,
+                    &mut reader,
+                    chtable,
+                    taxonomy,
+                    &mut progress,
+                    plan,
+                    &split_outputs,
+                    sources.as_ref(),
+                    checkpointer,
+                    status_file.as_mut(),
+                    abundance_events.as_mut(),
+                )?;
+            } else if let [file_pair @ [_]] = parts[..] {
+                let paths = OptionPair::from_slice(file_pair);
+                let mut reader = FastxReader::from_paths(paths, file_index, score)?;
+                // let mut reader = create_reader(file_pair, file_index, score)?;
+                process_fastx_file(
+                    &args,
+                    meros,
+                    &mut reader,
+                    chtable,
+                    taxonomy,
+                    &mut progress,
+                    plan,
+                    &split_outputs,
+                    sources.as_ref(),
+                    None,
+                    status_file.as_mut(),
+                    abundance_events.as_mut(),
+                )?;
+            } else {
+                // The mates of a pair are opened one by one, each with its own compression
+                let several = parts.len() > 1;
+                let parts = parts
+                    .iter()
+                    .map(|file_pair| {
+                        OptionPair::from_slice(file_pair).apply(|path| PathBuf::from(path))
+                    })
+                    .collect();
+                let mut reader = MultiFileReader::new(parts, file_index, score)?;
+                if let (Some(sources), true) = (&sources, several) {
+                    reader = reader.with_sources(sources.clone());
+                }
+                process_fastx_file(
+                    &args,
+                    meros,
+                    &mut reader,
+                    chtable,
+                    taxonomy,
+                    &mut progress,
+                    plan,
+                    &split_outputs,
+                    sources.as_ref(),
+                    None,
+                    status_file.as_mut(),
+                    abundance_events.as_mut(),
+                )?;
+                if several {
+                    println!("{}", reader.part_summary());
+                }
+            };
+            if args.verify_counts {
+                for mate in 0..parts[0].len() {
+                    let files: Vec<&String> = parts.iter().map(|part| &part[mate]).collect();
+                    verify_record_count(&files, progress.reads_index as u64)?;
+                }
+            }
+            if let (Some(bins), Some(output)) = (bins.as_mut(), &args.output_dir) {
+                bin_reads(&parts, &kraken_output_path(output, file_index), bins)?;
+            }
+            for (taxid, counts) in &progress.taxon_counts {
+                total_taxon_counts
+                    .entry(*taxid)
+                    .or_default()
+                    .merge(counts)
+                    .unwrap();
+            }
+            let thread_sequences = progress.reads_index;
+            let thread_unclassified =
+                progress.reads_index - progress.classified - progress.read_stats.filtered as usize;
+            if let Some(output) = &args.output_dir {
+                let filename = output.join(format!("output_{}.kreport2", file_index));
+                report_kraken_style(
+                    filename,
+                    args.report_zero_counts,
+                    args.report_kmer_data,
+                    taxonomy,
+                    &progress.taxon_counts,
+                    progress.reads_index as u64,
+                    args.report_include_unclassified
+                        .then_some(thread_unclassified as u64),
+                    args.report_max_indent,
+                    &report_header,
+                )?;
+            }
+            if let Some(output) = args.output_dir.as_ref().filter(|_| args.report_sparse) {
+                report_sparse(
+                    output.join(format!("output_{}.sparse.tsv", file_index)),
+                    taxonomy,
+                    &progress.taxon_counts,
+                    progress.reads_index as u64,
+                )?;
+            }
+            if let (Some(output), Some(rank)) = (&args.output_dir, args.report_rank) {
+                report_rank(
+                    output.join(format!("output_{}.{}.tsv", file_index, rank_name(rank))),
+                    taxonomy,
+                    &progress.taxon_counts,
+                    rank,
+                    progress.reads_index as u64,
+                )?;
+            }
+            if let Some(output) = args.output_dir.as_ref().filter(|_| args.report_tree_json) {
+                report_tree_json(
+                    output.join(format!("output_{}.tree.json", file_index)),
+                    taxonomy,
+                    &progress.taxon_counts,
+                )?;
+            }
+            if let (Some(output), Some(groups)) = (&args.output_dir, &groups) {
+                report_groups(
+                    output.join(format!("output_{}.groups.tsv", file_index)),
+                    taxonomy,
+                    &progress.taxon_counts,
+                    groups,
+                    progress.reads_index as u64,
+                )?;
+            }
+            if let (Some(output), Some(index_counts)) = (&args.output_dir, &index_counts) {
+                report_genome_coverage(
+                    output.join(format!("output_{}.coverage.tsv", file_index)),
+                    taxonomy,
+                    &progress.taxon_counts,
+                    index_counts,
+                )?;
+            }
+            if let (Some(output), Some(genome_sizes)) = (&args.output_dir, &genome_sizes) {
+                report_abundance(
+                    output.join(format!("output_{}.abundance.tsv", file_index)),
+                    taxonomy,
+                    &progress.taxon_counts,
+                    genome_sizes,
+                )?;
+            }
             total_seqs += thread_sequences;
             total_unclassified += thread_unclassified;
+            let mut sample_stats = progress.read_stats;
+            sample_stats.total_seqs = thread_sequences as u64;
+            sample_stats.classified = progress.classified as u64;
+            read_stats.merge(&sample_stats);
+            let inputs = parts.concat();
+            file_stats.push((
+                file_index,
+                inputs.join(","),
+                format_label(&inputs),
+                sample_stats,
+            ));
         }
-        if let Some(output) = &args.output_dir {
-            let filename = output.join("output.kreport2");
-            report_kraken_style(
-                filename,
-                args.report_zero_counts,
-                args.report_kmer_data,
-                &taxonomy,
+        if let Some(bins) = bins.as_mut() {
+            bins.flush()?;
+            println!("{} bin files", bins.files().len());
+        }
+        // A watch run reports all the files classified so far, the ledger is saved first
+        let mut report_counts = &total_taxon_counts;
+        let mut total_stats = read_stats.clone();
+        if let Some(ledger) = ledger.as_deref_mut() {
+            let files: Vec<PathBuf> = args.input_files.iter().map(PathBuf::from).collect();
+            let classified = read_stats.classified as usize;
+            ledger.add(
+                &files,
+                total_seqs,
+                classified,
+                &read_stats,
                 &total_taxon_counts,
-                total_seqs as u64,
-                total_unclassified as u64,
-            )?;
+            );
+            ledger.save()?;
+            let progress = &ledger.progress;
+            report_counts = &progress.taxon_counts;
+            total_stats = progress.read_stats.clone();
+            total_seqs = progress.reads_index;
+            total_unclassified =
+                progress.reads_index - progress.classified - progress.read_stats.filtered as usize;
+        }
+        if let Some(output) = &args.output_dir {
+            write_atomically(output.join("output.kreport2"), |tmp| {
+                report_kraken_style(
+                    tmp,
+                    args.report_zero_counts,
+                    args.report_kmer_data,
+                    &taxonomy,
+                    report_counts,
+                    total_seqs as u64,
+                    args.report_include_unclassified
+                        .then_some(total_unclassified as u64),
+                    args.report_max_indent,
+                    &report_header,
+                )
+            })?;
+            if args.report_sparse {
+                write_atomically(output.join("output.sparse.tsv"), |tmp| {
+                    report_sparse(tmp, taxonomy, report_counts, total_seqs as u64)
+                })?;
+            }
+            if let Some(rank) = args.report_rank {
+                write_atomically(
+                    output.join(format!("output.{}.tsv", rank_name(rank))),
+                    |tmp| report_rank(tmp, taxonomy, report_counts, rank, total_seqs as u64),
+                )?;
+            }
+            if args.report_tree_json {
+                write_atomically(output.join("output.tree.json"), |tmp| {
+                    report_tree_json(tmp, taxonomy, report_counts)
+                })?;
+            }
+            if let Some(groups) = &groups {
+                write_atomically(output.join("output.groups.tsv"), |tmp| {
+                    report_groups(tmp, taxonomy, report_counts, groups, total_seqs as u64)
+                })?;
+            }
+            if let Some(index_counts) = &index_counts {
+                write_atomically(output.join("output.coverage.tsv"), |tmp| {
+                    report_genome_coverage(tmp, taxonomy, report_counts, index_counts)
+                })?;
+            }
+            if let Some(genome_sizes) = &genome_sizes {
+                write_atomically(output.join("output.abundance.tsv"), |tmp| {
+                    report_abundance(tmp, taxonomy, report_counts, genome_sizes)
+                })?;
+            }
         }
 
+        if let Some(status) = status_file.as_mut() {
+            status.finish()?;
+        }
+        if file_stats.len() > 1 {
+            print!("{}", file_summary_table(&file_stats));
+        }
+        if read_stats.no_kmers > 0 {
+            println!("{} sequences without valid k-mers", read_stats.no_kmers);
+        }
+        if read_stats.filtered > 0 {
+            println!(
+                "{} sequences filtered below {} hit groups",
+                read_stats.filtered, args.minimum_hit_groups
+            );
+        }
+        if let Some(max_read_length) = args.max_read_length.filter(|_| read_stats.too_long > 0) {
+            let action = match args.long_reads {
+                LongReadPolicy::Truncate => "truncated",
+                LongReadPolicy::Skip => "skipped",
+            };
+            println!(
+                "{} sequences longer than {} bases {}",
+                read_stats.too_long, max_read_length, action
+            );
+        }
+        if let Some(message) = read_stats.zero_taxon_message() {
+            eprintln!("{}", message);
+        }
+        if let Some(path) = &args.summary_output {
+            // Every batch of a watch run writes its summary over the same base
+            let mut summary = summary.clone();
+            summary.set_counts(&total_stats, &file_stats);
+            summary.add_stage("classify", start.elapsed());
+            summary.warnings.extend(low_classification_message(
+                &total_stats,
+                &meros,
+                args.min_classified_warn,
+            ));
+            summary.warnings.extend(total_stats.zero_taxon_message());
+            summary.write(path)?;
+        }
+        check_classified_rate(
+            &total_stats,
+            &meros,
+            args.min_classified_warn,
+            args.min_classified_error,
+        )?;
         Ok(())
     };
 
@@ -322,13 +1253,122 @@ fn process_files(
         let files = args.input_files.chunks(1).collect();
         process_funcs(files)?;
     }
+    if io_pool.busy() > Duration::ZERO {
+        println!(
+            "gzip compression took: {:?} over {} io threads",
+            io_pool.busy(),
+            io_pool.num_threads()
+        );
+    }
 
     Ok(())
 }
 
-pub fn run(args: Args) -> Result<()> {
+/// Reports the index taxids outside the taxonomy, they would crash the classification
+fn validate_index(chtable: &CHTable, taxonomy: &Taxonomy, stride: usize) -> Result<()> {
+    let unknown = chtable.unknown_taxids(taxonomy.node_count(), stride);
+    if unknown.is_empty() {
+        println!("index validated: all taxids are in the taxonomy");
+        return Ok(());
+    }
+    for (taxid, count) in &unknown {
+        eprintln!(
+            "WARNING: taxid {} is referenced by {} index cells but missing from the taxonomy",
+            taxid, count
+        );
+    }
+    Err(Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "{} taxids of the index are missing from the taxonomy, \
+             the index and taxo.k2d do not belong together",
+            unknown.len()
+        ),
+    ))
+}
+
+/// Classifies the files appearing in the `--watch` directory until the stop file exists
+/// or `--stop-after-files` files are classified
+fn watch_files(
+    args: Args,
+    idx_opts: &IndexOptions,
+    meros: Meros,
+    hash_config: HashConfig,
+    chtable: &CHTable,
+    taxonomy: &Taxonomy,
+    summary: &RunSummary,
+) -> Result<()> {
+    let (Some(dir), Some(output)) = (&args.watch, &args.output_dir) else {
+        return Ok(());
+    };
+    let stop_file = args.stop_file.as_ref().map(|path| dir.join(path));
+    let mut ledger = WatchLedger::open(output.join(WATCH_LEDGER))?;
+    if !ledger.progress.inputs.is_empty() {
+        println!(
+            "watch: {} files already classified, {} reads",
+            ledger.progress.inputs.len(),
+            ledger.progress.reads_index
+        );
+    }
+    loop {
+        // Checked before the scan, the files there when the stop file appears still count
+        let stop = stop_file.as_ref().is_some_and(|path| path.exists());
+        let files = ledger.new_files(dir)?;
+        if !files.is_empty() {
+            println!("watch: classifying {} new files", files.len());
+            let batch = Args {
+                input_files: files
+                    .iter()
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect(),
+                ..args.clone()
+            };
+            process_files(
+                batch,
+                idx_opts,
+                meros,
+                hash_config,
+                chtable,
+                taxonomy,
+                summary,
+                Some(&mut ledger),
+            )?;
+        }
+        let classified = ledger.progress.inputs.len() as u64;
+        if stop
+            || args
+                .stop_after_files
+                .is_some_and(|files| classified >= files)
+        {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_secs(args.watch_interval));
+    }
+}
+
+/// The error of a run without input files, with a hint when standard input is not a terminal
+fn no_input_message() -> String {
+    let message = "No input files provided, direct reads FASTA/FASTQ files and not standard input.";
+    if io::stdin().is_terminal() {
+        message.to_string()
+    } else {
+        format!(
+            "{} Reads piped in must be written to a file first.",
+            message
+        )
+    }
+}
+
+pub fn run(mut args: Args) -> Result<()> {
+    if args.input_files.is_empty() && args.watch.is_none() {
+        return Err(Error::new(ErrorKind::InvalidInput, no_input_message()));
+    }
+
+    let mut summary = RunSummary::new("direct");
     let options_filename = &args.database.join("opts.k2d");
     let idx_opts = IndexOptions::read_index_options(options_filename)?;
+    idx_opts.require_canonical()?;
+    let meros = idx_opts.as_meros()?;
 
     if args.paired_end_processing && !args.single_file_pairs && args.input_files.len() % 2 != 0 {
         // 验证文件列表是否为偶数个
@@ -337,6 +1377,40 @@ pub fn run(args: Args) -> Result<()> {
             "Paired-end processing requires an even number of input files.",
         ));
     }
+    if args.paired_end_processing && !args.single_file_pairs {
+        order_mate_files_warn(&mut args.input_files)?;
+    }
+    if let Some(note) = quality_score_note(&args.input_files, args.minimum_quality_score) {
+        eprintln!("{}", note);
+    }
+    if let Some(first) = args
+        .input_files
+        .first()
+        .filter(|_| !args.ignore_alphabet_check)
+    {
+        let counts = sample_alphabet(first, ALPHABET_SAMPLE_READS)?;
+        if let Some(msg) = alphabet_mismatch_message(&counts, idx_opts.dna_db) {
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+    }
+    if let Some(first) = args.input_files.first().filter(|_| !args.no_length_check) {
+        let lengths = sample_read_lengths(first, LENGTH_SAMPLE_READS)?;
+        if let Some(msg) = short_read_message(&lengths, meros.k_mer) {
+            eprintln!("{}", msg);
+            summary.warnings.push(msg);
+        }
+    }
+
+    let gzip_ids = [&args.classified_ids_output, &args.unclassified_ids_output]
+        .into_iter()
+        .flatten()
+        .any(|path| path.extension().is_some_and(|ext| ext == "gz"));
+    if args.checkpoint_every.is_some() && gzip_ids {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--checkpoint-every can't resume gzip compressed ID outputs",
+        ));
+    }
 
     let taxonomy_filename = args.database.join("taxo.k2d");
     let taxo = Taxonomy::from_file(taxonomy_filename)?;
@@ -349,11 +1423,35 @@ pub fn run(args: Args) -> Result<()> {
     }
     println!("classify start...");
     let start = Instant::now();
-    let meros = idx_opts.as_meros();
     let hash_files = find_and_sort_files(&args.database, "hash", ".k2d", true)?;
     let chtable = CHTable::from_hash_files(hash_config, &hash_files)?;
+    if let Some(stride) = args.validate {
+        validate_index(&chtable, &taxo, stride)?;
+    }
+    summary.add_stage("load database", start.elapsed());
 
-    process_files(args, meros, hash_config, &chtable, &taxo)?;
+    if args.watch.is_some() {
+        watch_files(
+            args,
+            &idx_opts,
+            meros,
+            hash_config,
+            &chtable,
+            &taxo,
+            &summary,
+        )?;
+    } else {
+        process_files(
+            args,
+            &idx_opts,
+            meros,
+            hash_config,
+            &chtable,
+            &taxo,
+            &summary,
+            None,
+        )?;
+    }
     let duration = start.elapsed();
     println!("classify took: {:?}", duration);
     Ok(())
@@ -364,5 +1462,6 @@ fn main() {
     let args = Args::parse();
     if let Err(e) = run(args) {
         eprintln!("Application error: {}", e);
+        std::process::exit(1);
     }
 }
@@ -1,7 +1,7 @@
 use clap::{error::ErrorKind, Error, Parser};
 use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
-use kun_peng::args::KLMTArgs;
-use kun_peng::utils::{find_files, format_bytes, open_file};
+use kun_peng::args::{parse_threads, KLMTArgs};
+use kun_peng::utils::{check_meros, find_files, format_bytes, open_file};
 use kun_peng::KBuildHasher;
 
 use seqkmer::{read_parallel, BufferFastaReader};
@@ -41,7 +41,7 @@ pub struct Args {
     pub load_factor: f64,
 
     /// Number of threads
-    #[clap(short = 'p', long, default_value_t = 10)]
+    #[clap(short = 'p', long, value_parser = parse_threads, default_value_t = 10)]
     pub threads: usize,
 }
 
@@ -134,8 +134,8 @@ fn process_sequence<P: AsRef<Path>>(
 pub fn run(args: Args) -> usize {
     let meros = args.klmt.as_meros();
 
-    if meros.k_mer < meros.l_mer {
-        let err = Error::raw(ErrorKind::ValueValidation, "k cannot be less than l");
+    if let Err(e) = check_meros(&meros) {
+        let err = Error::raw(ErrorKind::ValueValidation, e.to_string());
         err.exit();
     }
 
@@ -1,17 +1,26 @@
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+mod add_to_db;
 mod annotate;
 mod build_k2_db;
+mod bulk_classify;
 mod chunk_db;
+mod compact_db;
+mod compare_output;
 mod direct;
 mod estimate_capacity;
 mod hashshard;
+mod markers;
 mod merge_fna;
 mod resolve;
+mod show_provenance;
 // mod seqid2taxid;
 mod splitr;
 
-use kun_peng::args::ClassifyArgs;
+use kun_peng::args::{expand_config_args, ClassifyArgs};
 use kun_peng::args::{parse_size, Build};
+use kun_peng::lock::DbLock;
+use kun_peng::provenance::Provenance;
+use kun_peng::summary::RunSummary;
 use kun_peng::utils::find_files;
 // use std::io::Result;
 use std::path::PathBuf;
@@ -56,6 +65,20 @@ struct BuildArgs {
     /// library fna temp file max size
     #[arg(long = "max-file-size", value_parser = parse_size, default_value = "2G")]
     pub max_file_size: usize,
+
+    /// Custom taxa, such as spike-in controls, one `taxid<TAB>parent_taxid<TAB>rank<TAB>name`
+    /// line each, with taxids from 2000000000 on
+    #[arg(long = "extra-taxa")]
+    pub extra_taxa: Option<PathBuf>,
+
+    /// Record the xxHash64 checksums of the library files in the database provenance
+    #[arg(long)]
+    hash_inputs: bool,
+
+    /// Remove the lock of an earlier build on the database that is no longer running,
+    /// e.g. killed, instead of refusing to start
+    #[arg(long)]
+    force_unlock: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -71,7 +94,14 @@ impl From<ClassifyArgs> for splitr::Args {
             database: item.database,
             paired_end_processing: item.paired_end_processing,
             single_file_pairs: item.single_file_pairs,
+            merge_parts: item.merge_parts,
             minimum_quality_score: item.minimum_quality_score,
+            no_length_check: item.no_length_check,
+            ignore_alphabet_check: item.ignore_alphabet_check,
+            iupac: item.iupac,
+            skip_whitespace: item.skip_whitespace,
+            trim_head: item.trim_head,
+            trim_tail: item.trim_tail,
             num_threads: item.num_threads,
             chunk_dir: item.chunk_dir,
             input_files: item.input_files,
@@ -87,6 +117,7 @@ impl From<ClassifyArgs> for annotate::Args {
             batch_size: item.batch_size,
             buffer_size: item.buffer_size,
             num_threads: item.num_threads,
+            compress_spill: item.compress_spill,
         }
     }
 }
@@ -97,11 +128,41 @@ impl From<ClassifyArgs> for resolve::Args {
             database: item.database,
             chunk_dir: item.chunk_dir,
             num_threads: item.num_threads,
+            io_threads: item.io_threads,
             confidence_threshold: item.confidence_threshold,
+            min_supporting_minimizers: item.min_supporting_minimizers,
+            confidence_bands: item.confidence_bands,
+            report_read_coverage: item.report_read_coverage,
+            report_mate_calls: item.report_mate_calls,
+            read_group: item.read_group,
+            provenance: item.provenance,
+            hash_inputs: item.hash_inputs,
+            index_params: item.index_params,
             minimum_hit_groups: item.minimum_hit_groups,
+            hit_group_gap: item.hit_group_gap,
+            filter_hit_groups: item.filter_hit_groups,
+            max_rank: item.max_rank,
+            weight_by_rank: item.weight_by_rank,
+            lca_of_all: item.lca_of_all,
+            score_mode: item.score_mode,
+            min_read_coverage: item.min_read_coverage,
+            min_classified_warn: item.min_classified_warn,
+            min_classified_error: item.min_classified_error,
+            status_file: item.status_file,
+            summary_output: item.summary_output,
             output_dir: item.output_dir,
+            split_output: item.split_output,
+            classified_ids_output: item.classified_ids_output,
+            unclassified_ids_output: item.unclassified_ids_output,
             report_kmer_data: item.report_kmer_data,
             report_zero_counts: item.report_zero_counts,
+            report_include_unclassified: item.report_include_unclassified,
+            report_max_indent: item.report_max_indent,
+            report_sparse: item.report_sparse,
+            report_rank: item.report_rank,
+            report_tree_json: item.report_tree_json,
+            groups: item.groups,
+            genome_sizes: item.genome_sizes,
         }
     }
 }
@@ -134,6 +195,7 @@ impl From<BuildArgs> for merge_fna::Args {
             download_dir: item.download_dir,
             database: item.build.database,
             max_file_size: item.max_file_size,
+            extra_taxa: item.extra_taxa,
         }
     }
 }
@@ -143,17 +205,28 @@ enum Commands {
     Estimate(estimate_capacity::Args),
     // Seqid2taxid(seqid2taxid::Args),
     Build(BuildArgs),
+    AddToDb(add_to_db::Args),
+    CompactDb(compact_db::Args),
     Hashshard(hashshard::Args),
     Splitr(splitr::Args),
     Annotate(annotate::Args),
     Resolve(resolve::Args),
     Classify(ClassifyArgs),
     Direct(direct::Args),
+    BulkClassify(bulk_classify::Args),
     MergeFna(merge_fna::Args),
+    ShowProvenance(show_provenance::Args),
+    Markers(markers::Args),
+    CompareOutput(compare_output::Args),
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let argv = expand_config_args(
+        std::env::args_os().collect(),
+        "classify",
+        &ClassifyArgs::command(),
+    )?;
+    let args = Args::parse_from(argv);
 
     match args.cmd {
         Commands::MergeFna(cmd_args) => {
@@ -163,6 +236,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             estimate_capacity::run(cmd_args);
         }
         Commands::Build(cmd_args) => {
+            let _lock = DbLock::acquire(&cmd_args.build.database, "build", cmd_args.force_unlock)?;
             let fna_args = merge_fna::Args::from(cmd_args.clone());
             merge_fna::run(fna_args)?;
             let ec_args = estimate_capacity::Args::from(cmd_args.clone());
@@ -171,7 +245,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let build_args = chunk_db::Args::from(cmd_args.clone());
             let database = &build_args.build.database.clone();
             chunk_db::run(build_args, required_capacity)?;
-            build_k2_db::run(database)?;
+            build_k2_db::run(database, cmd_args.build.max_minimizer_rank)?;
+
+            let library = find_files(database.join("library"), "library", ".fna");
+            let provenance =
+                Provenance::new("build", &cmd_args).with_inputs(&library, cmd_args.hash_inputs)?;
+            provenance.append_to(database.join("opts.k2d"))?;
+            provenance.append_to(database.join("hash_config.k2d"))?;
+        }
+        Commands::AddToDb(cmd_args) => {
+            add_to_db::run(cmd_args)?;
+        }
+        Commands::CompactDb(cmd_args) => {
+            compact_db::run(cmd_args)?;
         }
         Commands::Hashshard(cmd_args) => {
             hashshard::run(cmd_args)?;
@@ -202,17 +288,42 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 )));
             }
             splitr::run(splitr_args)?;
+            let splitr_duration = start.elapsed();
             let annotate_args = annotate::Args::from(cmd_args.clone());
             annotate::run(annotate_args)?;
+            let annotate_duration = start.elapsed() - splitr_duration;
             let resolve_args = resolve::Args::from(cmd_args.clone());
             resolve::run(resolve_args)?;
 
             let duration = start.elapsed();
             println!("Classify took: {:?}", duration);
+            // resolve wrote the counts, the summary covers the stages before it
+            if let Some(path) = &cmd_args.summary_output {
+                let mut summary = RunSummary::read(path)?;
+                let resolve_stages = std::mem::take(&mut summary.stages);
+                summary.command = "classify".to_string();
+                summary.add_stage("splitr", splitr_duration);
+                summary.add_stage("annotate", annotate_duration);
+                summary.stages.extend(resolve_stages);
+                summary.wall_time_seconds = duration.as_secs_f64();
+                summary.write(path)?;
+            }
         }
         Commands::Direct(cmd_args) => {
             direct::run(cmd_args)?;
         }
+        Commands::BulkClassify(cmd_args) => {
+            bulk_classify::run(cmd_args)?;
+        }
+        Commands::ShowProvenance(cmd_args) => {
+            show_provenance::run(cmd_args)?;
+        }
+        Commands::Markers(cmd_args) => {
+            markers::run(cmd_args)?;
+        }
+        Commands::CompareOutput(cmd_args) => {
+            compare_output::run(cmd_args)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,82 @@
+use clap::Parser;
+use kun_peng::args::KLMTArgs;
+use kun_peng::markers::MarkerPanel;
+use kun_peng::output::open_output;
+use kun_peng::reader::for_each_record;
+use std::io::{self, BufWriter, Result, Write};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "report the marker genes hit by each read",
+    long_about = "Report the marker genes hit by each read.
+
+Every read gets a line `read_id<TAB>marker:hits,...` with the markers whose minimizers it
+contains, or `-` when it hits none. Minimizers shared by two markers are not counted."
+)]
+pub struct Args {
+    /// FASTA file of the marker sequences, each named by the ID of its record
+    #[arg(long, required = true)]
+    pub markers: PathBuf,
+
+    #[clap(flatten)]
+    pub klmt: KLMTArgs,
+
+    /// Minimum number of minimizer hits for a marker to be reported
+    #[arg(long, default_value_t = 1)]
+    pub min_hits: u64,
+
+    /// File to write the lines to, instead of the standard output
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// A list of input file paths (FASTA/FASTQ) to be processed, single-end
+    #[clap(required = true)]
+    pub input_files: Vec<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let panel = MarkerPanel::from_fasta(&args.markers, args.klmt.as_meros())?;
+    if panel.names().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no markers in {}", args.markers.display()),
+        ));
+    }
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(open_output(path, false)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+
+    for file in &args.input_files {
+        let mut result = Ok(());
+        for_each_record(file, usize::MAX, |id, seq| {
+            if result.is_err() {
+                return;
+            }
+            let hits: Vec<String> = panel
+                .hits(seq)
+                .into_iter()
+                .filter(|&(_, count)| count >= args.min_hits)
+                .map(|(marker, count)| format!("{}:{}", panel.names()[marker], count))
+                .collect();
+            let hits = if hits.is_empty() {
+                "-".to_string()
+            } else {
+                hits.join(",")
+            };
+            result = writeln!(writer, "{}\t{}", id, hits);
+        })?;
+        result?;
+    }
+    writer.flush()
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
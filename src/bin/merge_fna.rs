@@ -26,6 +26,11 @@ pub struct Args {
     /// library fna temp file max size
     #[arg(long = "max-file-size", value_parser = parse_size, default_value = "2G")]
     pub max_file_size: usize,
+
+    /// Custom taxa, such as spike-in controls, one `taxid<TAB>parent_taxid<TAB>rank<TAB>name`
+    /// line each, with taxids from 2000000000 on
+    #[arg(long = "extra-taxa")]
+    pub extra_taxa: Option<PathBuf>,
 }
 
 struct SizedWriter {
@@ -355,6 +360,7 @@ pub fn run(args: Args) -> Result<()> {
         &ncbi_taxonomy_directory,
         &taxonomy_filename,
         &id_to_taxon_map,
+        args.extra_taxa.as_ref(),
     )?;
     // 计算持续时间
     let duration = start.elapsed();
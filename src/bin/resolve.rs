@@ -1,19 +1,31 @@
 use clap::Parser;
-use kun_peng::classify::process_hitgroup;
+use kun_peng::args::{default_threads, parse_fraction, parse_read_group, parse_threads};
+use kun_peng::classify::{hit_string, mate_calls, process_hitgroup, HitGroupOptions, ScoreMode};
 use kun_peng::compact_hash::{HashConfig, Row};
+use kun_peng::output::{tee_all, ConfidenceBands, IoPool, KrakenLine, SplitOutput};
+use kun_peng::provenance::Provenance;
 use kun_peng::readcounts::{TaxonCounters, TaxonCountersDash};
-use kun_peng::report::report_kraken_style;
-use kun_peng::taxonomy::Taxonomy;
-use kun_peng::utils::{find_and_trans_bin_files, find_and_trans_files, open_file};
-use kun_peng::HitGroup;
+use kun_peng::reader::format_label;
+use kun_peng::report::{
+    read_genome_sizes, read_report_groups, report_abundance, report_groups, report_kraken_style,
+    report_rank, report_sparse, report_tree_json,
+};
+use kun_peng::spill::read_spill_file;
+use kun_peng::summary::{
+    check_classified_rate, file_summary_table, low_classification_message, ReadStats, RunSummary,
+    StatusFile, STATUS_FILE_INTERVAL,
+};
+use kun_peng::taxonomy::{parse_rank, rank_name, Taxonomy};
+use kun_peng::utils::{find_and_trans_bin_files, find_and_trans_files, open_file, parse_seq_size};
+use kun_peng::{HitGroup, IndexOptions};
 // use rayon::prelude::*;
 use seqkmer::{buffer_map_parallel, trim_pair_info, OptionPair};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{create_dir_all, File};
-use std::io::{self, BufRead, BufReader, BufWriter, Read, Result, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::Instant;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 pub fn read_id_to_seq_map<P: AsRef<Path>>(
     filename: P,
@@ -65,10 +77,26 @@ pub struct Args {
     #[clap(long = "output-dir", value_parser)]
     pub output_dir: Option<PathBuf>,
 
+    /// Also split the Kraken output lines into `<prefix>.classified.kraken` and `<prefix>.unclassified.kraken`.
+    #[clap(long = "split-output", value_parser)]
+    pub split_output: Option<PathBuf>,
+
+    /// Write the IDs of the classified reads to this file, one per line (gzip if it ends with `.gz`).
+    #[clap(long = "classified-ids-output", value_parser)]
+    pub classified_ids_output: Option<PathBuf>,
+
+    /// Write the IDs of the unclassified reads to this file, one per line (gzip if it ends with `.gz`).
+    #[clap(long = "unclassified-ids-output", value_parser)]
+    pub unclassified_ids_output: Option<PathBuf>,
+
     /// The number of threads to use.
-    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    #[clap(short = 'p', long = "num-threads", value_parser = parse_threads, default_value_t = default_threads())]
     pub num_threads: usize,
 
+    /// The number of threads compressing the gzip outputs, on top of --num-threads.
+    #[clap(long = "io-threads", value_parser, default_value_t = 2)]
+    pub io_threads: usize,
+
     // /// output file contains all unclassified sequence
     // #[clap(long, value_parser, default_value_t = false)]
     // pub full_output: bool,
@@ -76,19 +104,108 @@ pub struct Args {
     #[clap(
         short = 'T',
         long = "confidence-threshold",
-        value_parser,
+        value_parser = parse_fraction,
         default_value_t = 0.0
     )]
     pub confidence_threshold: f64,
 
-    /// In comb. w/ -R, provide minimizer information in report
-    #[clap(short = 'K', long, value_parser, default_value_t = false)]
+    /// The minimum number of minimizers supporting a call, whatever the read length. Short
+    /// reads passing --confidence-threshold with a couple of hits stay unclassified.
+    #[clap(long = "min-supporting-minimizers", value_parser, default_value_t = 0)]
+    pub min_supporting_minimizers: u64,
+
+    /// In comb. w/ --output-dir, provide minimizer information in report
+    #[clap(
+        short = 'K',
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "output_dir"
+    )]
     pub report_kmer_data: bool,
 
-    /// In comb. w/ -R, report taxa w/ 0 count
-    #[clap(short = 'z', long, value_parser, default_value_t = false)]
+    /// In comb. w/ --output-dir, report taxa w/ 0 count
+    #[clap(
+        short = 'z',
+        long,
+        value_parser,
+        default_value_t = false,
+        requires = "output_dir"
+    )]
     pub report_zero_counts: bool,
 
+    /// Report unclassified sequences as a top-level `U` row.
+    #[clap(long, action = clap::ArgAction::Set, default_value_t = true)]
+    pub report_include_unclassified: bool,
+
+    /// Deepest level indented in the Kraken report, deeper taxa keep their counts and
+    /// the indentation of this level.
+    #[clap(
+        long = "report-max-indent",
+        value_name = "DEPTH",
+        default_value_t = 100
+    )]
+    pub report_max_indent: usize,
+
+    /// In comb. w/ --output-dir, write `output_<N>.sparse.tsv`, a flat table of the taxa
+    /// with reads, their counts and their full lineage, without the tree structure.
+    #[clap(long = "report-sparse", action, requires = "output_dir")]
+    pub report_sparse: bool,
+
+    /// In comb. w/ --output-dir, write `output_<N>.<rank>.tsv` with the reads of every taxon
+    /// of a rank (e.g. `G` or `genus`), the reads above it on an `unassigned at <rank>` row.
+    #[clap(long = "report-rank", value_name = "RANK", value_parser = parse_rank, requires = "output_dir")]
+    pub report_rank: Option<char>,
+
+    /// In comb. w/ --output-dir, write `output_<N>.tree.json`, the taxa with reads as a
+    /// nested tree, for sunburst and other hierarchical charts.
+    #[clap(long = "report-tree-json", action, requires = "output_dir")]
+    pub report_tree_json: bool,
+
+    /// In comb. w/ --output-dir, sum the reads of custom taxon groups into
+    /// `output_<N>.groups.tsv`, from a `group_name<TAB>taxid,taxid,...` file.
+    #[clap(long = "groups", requires = "output_dir")]
+    pub groups: Option<PathBuf>,
+
+    /// In comb. w/ --output-dir, write `output_<N>.abundance.tsv` with the reads of each
+    /// taxon normalized by its genome size, from a `taxid<TAB>bp` file.
+    #[clap(long = "genome-sizes", requires = "output_dir")]
+    pub genome_sizes: Option<PathBuf>,
+
+    /// Append a High/Medium/Low confidence column, from the `medium,high` thresholds
+    /// on the fraction of minimizers supporting the call (e.g. `0.5,0.8`).
+    #[clap(long = "confidence-bands", value_name = "MEDIUM,HIGH")]
+    pub confidence_bands: Option<ConfidenceBands>,
+
+    /// Append a column with the coverage breadth of the call, the fraction of the read
+    /// segments with a hit in the called clade.
+    #[clap(long = "report-read-coverage", action)]
+    pub report_read_coverage: bool,
+
+    /// Append a column with the calls of the mates of a pair classified on their own,
+    /// `taxid1|taxid2` (`-` for single reads), to spot pairs whose mates disagree.
+    #[clap(long = "report-mate-calls", action)]
+    pub report_mate_calls: bool,
+
+    /// Append this tag as the last column of every output line and record it in the reports,
+    /// to tell runs apart after merging.
+    #[clap(long = "read-group", value_parser = parse_read_group)]
+    pub read_group: Option<String>,
+
+    /// Record the version, the parameters and the inputs of the run in a
+    /// `# provenance:` header line of the reports.
+    #[clap(long = "provenance", action)]
+    pub provenance: bool,
+
+    /// With --provenance, also record the xxHash64 checksums of the input files.
+    #[clap(long = "hash-inputs", action, requires = "provenance")]
+    pub hash_inputs: bool,
+
+    /// Record the k-mer and minimizer parameters of the database (k, l, spaced seed mask,
+    /// toggle mask, type) in a `# index:` header line of the reports.
+    #[clap(long = "index-params", action)]
+    pub index_params: bool,
+
     /// The minimum number of hit groups needed for a call.
     #[clap(
         short = 'g',
@@ -97,20 +214,73 @@ pub struct Args {
         default_value_t = 2
     )]
     pub minimum_hit_groups: usize,
-}
 
-fn read_rows_from_file<P: AsRef<Path>>(file_path: P) -> io::Result<HashMap<u32, Vec<Row>>> {
-    let file = File::open(file_path)?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = [0u8; std::mem::size_of::<Row>()]; // 确保buffer的大小与Row结构体的大小一致
-    let mut map: HashMap<u32, Vec<Row>> = HashMap::new();
+    /// The number of non-matching minimizers allowed between two hits of the same hit group.
+    /// If not set, every minimizer of the read counts as a hit group.
+    #[clap(long = "hit-group-gap", value_parser)]
+    pub hit_group_gap: Option<usize>,
+
+    /// Leave the reads with hits in fewer than --minimum-hit-groups hit groups out of the
+    /// outputs, counted as filtered rather than unclassified.
+    #[clap(long = "filter-hit-groups", action)]
+    pub filter_hit_groups: bool,
+
+    /// Report calls above this rank (e.g. `S` or `genus`) as unclassified.
+    #[clap(long = "max-rank", value_parser = parse_rank)]
+    pub max_rank: Option<char>,
+
+    /// Weigh every hit by the rank of its taxon, hits of general taxa count less.
+    #[clap(long = "weight-by-rank", action)]
+    pub weight_by_rank: bool,
+
+    /// Call the LCA of every taxon hit by a minimizer of the read, whatever the counts,
+    /// the confidence threshold and the hit groups.
+    #[clap(long = "lca-of-all", action, conflicts_with = "weight_by_rank")]
+    pub lca_of_all: bool,
+
+    /// How the hits of a read add up: `positions` counts a minimizer at every position it
+    /// hits, as Kraken 2 does, `distinct` once per read, so repeats weigh less.
+    #[clap(long = "score-mode", value_name = "MODE", default_value = "positions")]
+    pub score_mode: ScoreMode,
+
+    /// Report calls with hits in less than this fraction of the read segments as
+    /// unclassified, e.g. `0.2` against hits clustered in a repeat.
+    #[clap(long = "min-read-coverage", value_parser = parse_fraction, default_value_t = 0.0)]
+    pub min_read_coverage: f64,
+
+    /// Warn when the fraction of classified sequences is below this value.
+    #[clap(long = "min-classified-warn", value_parser = parse_fraction, default_value_t = 0.05)]
+    pub min_classified_warn: f64,
+
+    /// Exit with an error instead of a warning when the classified fraction is below `--min-classified-warn`.
+    #[clap(long = "min-classified-error", action)]
+    pub min_classified_error: bool,
+
+    /// Periodically overwrite this file with the running counts as JSON
+    /// (`{"processed":N,"classified":M,"rate":R}`), for monitoring.
+    #[clap(long = "status-file", value_parser)]
+    pub status_file: Option<PathBuf>,
+
+    /// Write the totals, the per file counts, the stage timings and the warnings of the run
+    /// to this file as JSON, see `kun_peng::summary::RunSummary` for the schema.
+    #[clap(long = "summary-output", value_parser)]
+    pub summary_output: Option<PathBuf>,
+}
 
-    while reader.read_exact(&mut buffer).is_ok() {
-        let row: Row = unsafe { std::mem::transmute(buffer) }; // 将读取的字节直接转换为Row结构体
-        map.entry(row.seq_id).or_default().push(row); // 插入到HashMap中
+impl Args {
+    /// The options of [`process_hitgroup`] for a database with `value_mask`
+    fn hit_group_options(&self, value_mask: usize) -> HitGroupOptions {
+        HitGroupOptions {
+            minimum_hit_groups: self.minimum_hit_groups,
+            hit_group_gap: self.hit_group_gap,
+            max_rank: self.max_rank,
+            weight_by_rank: self.weight_by_rank,
+            lca_of_all: self.lca_of_all,
+            score_mode: self.score_mode,
+            min_read_coverage: self.min_read_coverage,
+            value_mask,
+        }
     }
-
-    Ok(map)
 }
 
 fn process_batch<P: AsRef<Path>>(
@@ -120,15 +290,30 @@ fn process_batch<P: AsRef<Path>>(
     id_map: &HashMap<u32, (String, String, usize, Option<usize>)>,
     writer: &mut Box<dyn Write + Send>,
     value_mask: usize,
-) -> Result<(TaxonCountersDash, usize)> {
+    k_mer: usize,
+) -> Result<(TaxonCountersDash, usize, usize, u64)> {
     let confidence_threshold = args.confidence_threshold;
+    let min_supporting_minimizers = args.min_supporting_minimizers;
     let minimum_hit_groups = args.minimum_hit_groups;
+    let hit_group_gap = args.hit_group_gap;
+    let options = args.hit_group_options(value_mask);
+    let filter_hit_groups = args.filter_hit_groups;
 
     let classify_counter = AtomicUsize::new(0);
+    let filter_counter = AtomicUsize::new(0);
+    let zero_taxon_hits = AtomicU64::new(0);
     let cur_taxon_counts = TaxonCountersDash::new();
+    let calls_of_mates = |hits: &HitGroup| {
+        let mate_counter = AtomicUsize::new(0);
+        mate_calls(hits, |mate| {
+            let required_score =
+                mate.required_score_with_floor(confidence_threshold, min_supporting_minimizers);
+            process_hitgroup(mate, taxonomy, &mate_counter, required_score, &options).taxid
+        })
+    };
 
     for sample_file in sample_files {
-        let hit_counts: HashMap<u32, Vec<Row>> = read_rows_from_file(sample_file)?;
+        let hit_counts: HashMap<u32, Vec<Row>> = read_spill_file(sample_file)?;
 
         buffer_map_parallel(
             &hit_counts,
@@ -141,18 +326,26 @@ fn process_batch<P: AsRef<Path>>(
                     let dna_id = trim_pair_info(&item.0);
                     let range =
                         OptionPair::from(((0, item.2), item.3.map(|size| (item.2, size + item.2))));
-                    let hits = HitGroup::new(rows, range);
+                    let hits =
+                        HitGroup::new(rows, range).with_seq_lens(parse_seq_size(&item.1), k_mer);
+                    if filter_hit_groups && hits.below_hit_groups(minimum_hit_groups, hit_group_gap)
+                    {
+                        filter_counter.fetch_add(1, Ordering::SeqCst);
+                        return None;
+                    }
 
-                    let hit_data = process_hitgroup(
+                    let required_score = hits
+                        .required_score_with_floor(confidence_threshold, min_supporting_minimizers);
+                    let call = process_hitgroup(
                         &hits,
                         taxonomy,
                         &classify_counter,
-                        hits.required_score(confidence_threshold),
-                        minimum_hit_groups,
-                        value_mask,
+                        required_score,
+                        &options,
                     );
+                    zero_taxon_hits.fetch_add(call.zero_taxon_hits, Ordering::Relaxed);
 
-                    hit_data.3.iter().for_each(|(key, value)| {
+                    call.taxon_counts.iter().for_each(|(key, value)| {
                         cur_taxon_counts
                             .entry(*key)
                             .or_default()
@@ -161,11 +354,23 @@ fn process_batch<P: AsRef<Path>>(
                     });
 
                     // 使用锁来同步写入
-                    let output_line = format!(
-                        "{}\t{}\t{}\t{}\t{}\n",
-                        hit_data.0, dna_id, hit_data.1, item.1, hit_data.2
-                    );
-                    Some(output_line)
+                    let hit_string = hit_string(&hits, value_mask, taxonomy);
+                    let mut line = KrakenLine::new(call.classified, &dna_id, call.taxid)
+                        .seq_size(&item.1)
+                        .hit_string(&hit_string);
+                    if let Some(bands) = &args.confidence_bands {
+                        line = line.column(bands.label(call.confidence));
+                    }
+                    if args.report_read_coverage {
+                        line = line.column(format!("{:.3}", call.read_coverage));
+                    }
+                    if args.report_mate_calls {
+                        line = line.mate_calls(calls_of_mates(&hits));
+                    }
+                    if let Some(read_group) = &args.read_group {
+                        line = line.column(read_group);
+                    }
+                    Some(format!("{}\n", line))
                 } else {
                     eprintln!("can't find {} in sample_id map file", k);
                     None
@@ -184,13 +389,88 @@ fn process_batch<P: AsRef<Path>>(
         .expect("failed");
     }
 
-    Ok((cur_taxon_counts, classify_counter.load(Ordering::SeqCst)))
+    // Reads without a single valid k-mer never reach the sample files.
+    for (seq_id, seq_size, _, kmer_count2) in id_map
+        .values()
+        .filter(|item| item.2 + item.3.unwrap_or(0) == 0)
+    {
+        let range = OptionPair::from(((0, 0), kmer_count2.map(|_| (0, 0))));
+        let hits = HitGroup::new(Vec::new(), range).with_seq_lens(parse_seq_size(seq_size), k_mer);
+        let call = process_hitgroup(&hits, taxonomy, &classify_counter, 0, &options);
+        let dna_id = trim_pair_info(seq_id);
+        let hit_string = hit_string(&hits, value_mask, taxonomy);
+        let mut line = KrakenLine::new(call.classified, &dna_id, call.taxid)
+            .seq_size(seq_size)
+            .hit_string(&hit_string);
+        if let Some(bands) = &args.confidence_bands {
+            line = line.column(bands.label(call.confidence));
+        }
+        if args.report_read_coverage {
+            line = line.column(format!("{:.3}", call.read_coverage));
+        }
+        if args.report_mate_calls {
+            line = line.mate_calls(calls_of_mates(&hits));
+        }
+        if let Some(read_group) = &args.read_group {
+            line = line.column(read_group);
+        }
+        writeln!(writer, "{}", line)?;
+    }
+
+    Ok((
+        cur_taxon_counts,
+        classify_counter.load(Ordering::SeqCst),
+        filter_counter.load(Ordering::SeqCst),
+        zero_taxon_hits.load(Ordering::Relaxed),
+    ))
+}
+
+/// Reads the input paths of every file index from `sample_file.map`
+fn read_sample_inputs(filename: &Path) -> HashMap<usize, String> {
+    std::fs::read_to_string(filename)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (index, input) = line.split_once('\t')?;
+            Some((index.parse().ok()?, input.to_string()))
+        })
+        .collect()
+}
+
+/// Header lines of the reports, the read group, the provenance of the run and the index
+/// parameters
+fn report_header(
+    args: &Args,
+    idx_opts: &IndexOptions,
+    sample_files: &BTreeMap<usize, Vec<PathBuf>>,
+    sample_inputs: &HashMap<usize, String>,
+) -> Result<Vec<String>> {
+    let mut header: Vec<String> = args
+        .read_group
+        .iter()
+        .map(|read_group| format!("read_group: {}", read_group))
+        .collect();
+    if args.provenance {
+        let inputs: Vec<&str> = sample_files
+            .keys()
+            .filter_map(|i| sample_inputs.get(i))
+            .flat_map(|input| input.split(','))
+            .collect();
+        let provenance = Provenance::new("resolve", args).with_inputs(&inputs, args.hash_inputs)?;
+        header.push(provenance.report_header()?);
+    }
+    if args.index_params {
+        header.push(idx_opts.report_header());
+    }
+    Ok(header)
 }
 
 pub fn run(args: Args) -> Result<()> {
     let k2d_dir = &args.database;
     let taxonomy_filename = k2d_dir.join("taxo.k2d");
     let taxo = Taxonomy::from_file(taxonomy_filename)?;
+    let idx_opts = IndexOptions::read_index_options(k2d_dir.join("opts.k2d"))?;
+    let meros = idx_opts.as_meros()?;
 
     let sample_files = find_and_trans_bin_files(&args.chunk_dir, "sample_file", ".bin", false)?;
     let sample_id_files = find_and_trans_files(&args.chunk_dir, "sample_id", ".map", false)?;
@@ -202,6 +482,21 @@ pub fn run(args: Args) -> Result<()> {
     let mut total_taxon_counts = TaxonCounters::new();
     let mut total_seqs = 0;
     let mut total_unclassified = 0;
+    let mut read_stats = ReadStats::default();
+    let io_pool = IoPool::new(args.io_threads)?;
+    let mut split_outputs: Vec<SplitOutput> = args
+        .split_output
+        .as_ref()
+        .map(|prefix| SplitOutput::create(prefix, false))
+        .transpose()?
+        .into_iter()
+        .collect();
+    split_outputs.extend(SplitOutput::ids(
+        args.classified_ids_output.as_ref(),
+        args.unclassified_ids_output.as_ref(),
+        false,
+        &io_pool,
+    )?);
 
     if let Some(output) = &args.output_dir {
         create_dir_all(output)?;
@@ -209,13 +504,41 @@ pub fn run(args: Args) -> Result<()> {
 
     // 开始计时
     let start = Instant::now();
+    let mut summary = RunSummary::new("resolve");
     println!("resolve start...");
 
+    let sample_inputs = read_sample_inputs(&args.chunk_dir.join("sample_file.map"));
+    let report_header = report_header(&args, &idx_opts, &sample_files, &sample_inputs)?;
+    let groups = args
+        .groups
+        .as_ref()
+        .map(|path| read_report_groups(path, &taxo))
+        .transpose()?;
+    let genome_sizes = args
+        .genome_sizes
+        .as_ref()
+        .map(|path| read_genome_sizes(path, &taxo))
+        .transpose()?;
+    let mut status_file = args
+        .status_file
+        .as_ref()
+        .map(|path| StatusFile::new(path, STATUS_FILE_INTERVAL));
+    let mut file_stats = Vec::new();
     for (i, sam_files) in &sample_files {
         let sample_id_map = read_id_to_seq_map(&sample_id_files[i])?;
+        let mut sample_stats = ReadStats::default();
+        for (_, seq_size, kmer_count1, kmer_count2) in sample_id_map.values() {
+            seq_size
+                .split('|')
+                .filter_map(|len| len.parse::<u64>().ok())
+                .for_each(|len| sample_stats.add_mate_len(len));
+            if kmer_count1 + kmer_count2.unwrap_or(0) == 0 {
+                sample_stats.add_no_kmers();
+            }
+        }
 
         let thread_sequences = sample_id_map.len();
-        let mut writer: Box<dyn Write + Send> = match &args.output_dir {
+        let writer: Box<dyn Write + Send> = match &args.output_dir {
             Some(ref file_path) => {
                 let filename = file_path.join(format!("output_{}.txt", i));
                 let file = File::create(filename)?;
@@ -223,14 +546,18 @@ pub fn run(args: Args) -> Result<()> {
             }
             None => Box::new(BufWriter::new(io::stdout())) as Box<dyn Write + Send>,
         };
-        let (thread_taxon_counts, thread_classified) = process_batch::<PathBuf>(
-            sam_files,
-            &args,
-            &taxo,
-            &sample_id_map,
-            &mut writer,
-            value_mask,
-        )?;
+        let mut writer = tee_all(&split_outputs, writer);
+        let (thread_taxon_counts, thread_classified, thread_filtered, thread_zero_taxon_hits) =
+            process_batch::<PathBuf>(
+                sam_files,
+                &args,
+                &taxo,
+                &sample_id_map,
+                &mut writer,
+                value_mask,
+                meros.k_mer,
+            )?;
+        let thread_unclassified = thread_sequences - thread_classified - thread_filtered;
 
         let mut sample_taxon_counts: HashMap<
             u64,
@@ -259,12 +586,67 @@ pub fn run(args: Args) -> Result<()> {
                 &taxo,
                 &sample_taxon_counts,
                 thread_sequences as u64,
-                (thread_sequences - thread_classified) as u64,
+                args.report_include_unclassified
+                    .then_some(thread_unclassified as u64),
+                args.report_max_indent,
+                &report_header,
             )?;
+            if args.report_sparse {
+                report_sparse(
+                    output.join(format!("output_{}.sparse.tsv", i)),
+                    &taxo,
+                    &sample_taxon_counts,
+                    thread_sequences as u64,
+                )?;
+            }
+            if let Some(rank) = args.report_rank {
+                report_rank(
+                    output.join(format!("output_{}.{}.tsv", i, rank_name(rank))),
+                    &taxo,
+                    &sample_taxon_counts,
+                    rank,
+                    thread_sequences as u64,
+                )?;
+            }
+            if args.report_tree_json {
+                report_tree_json(
+                    output.join(format!("output_{}.tree.json", i)),
+                    &taxo,
+                    &sample_taxon_counts,
+                )?;
+            }
+            if let Some(groups) = &groups {
+                report_groups(
+                    output.join(format!("output_{}.groups.tsv", i)),
+                    &taxo,
+                    &sample_taxon_counts,
+                    groups,
+                    thread_sequences as u64,
+                )?;
+            }
+            if let Some(genome_sizes) = &genome_sizes {
+                report_abundance(
+                    output.join(format!("output_{}.abundance.tsv", i)),
+                    &taxo,
+                    &sample_taxon_counts,
+                    genome_sizes,
+                )?;
+            }
         }
 
         total_seqs += thread_sequences;
-        total_unclassified += thread_sequences - thread_classified;
+        total_unclassified += thread_unclassified;
+        sample_stats.total_seqs = thread_sequences as u64;
+        sample_stats.classified = thread_classified as u64;
+        sample_stats.filtered = thread_filtered as u64;
+        sample_stats.zero_taxon_hits = thread_zero_taxon_hits;
+        read_stats.merge(&sample_stats);
+        if let Some(status) = status_file.as_mut() {
+            status.add(thread_sequences as u64, thread_classified as u64)?;
+        }
+        let input = sample_inputs.get(i).cloned().unwrap_or_default();
+        let paths: Vec<&str> = input.split(',').collect();
+        file_stats.push((*i, input.clone(), format_label(&paths), sample_stats));
     }
 
     if let Some(output) = &args.output_dir {
@@ -281,8 +663,52 @@ pub fn run(args: Args) -> Result<()> {
                     &taxo,
                     &total_taxon_counts,
                     total_seqs as u64,
-                    total_unclassified as u64,
+                    args.report_include_unclassified
+                        .then_some(total_unclassified as u64),
+                    args.report_max_indent,
+                    &report_header,
                 )?;
+                if args.report_sparse {
+                    report_sparse(
+                        output.join(format!("output_{}-{}.sparse.tsv", min, max)),
+                        &taxo,
+                        &total_taxon_counts,
+                        total_seqs as u64,
+                    )?;
+                }
+                if let Some(rank) = args.report_rank {
+                    report_rank(
+                        output.join(format!("output_{}-{}.{}.tsv", min, max, rank_name(rank))),
+                        &taxo,
+                        &total_taxon_counts,
+                        rank,
+                        total_seqs as u64,
+                    )?;
+                }
+                if args.report_tree_json {
+                    report_tree_json(
+                        output.join(format!("output_{}-{}.tree.json", min, max)),
+                        &taxo,
+                        &total_taxon_counts,
+                    )?;
+                }
+                if let Some(groups) = &groups {
+                    report_groups(
+                        output.join(format!("output_{}-{}.groups.tsv", min, max)),
+                        &taxo,
+                        &total_taxon_counts,
+                        groups,
+                        total_seqs as u64,
+                    )?;
+                }
+                if let Some(genome_sizes) = &genome_sizes {
+                    report_abundance(
+                        output.join(format!("output_{}-{}.abundance.tsv", min, max)),
+                        &taxo,
+                        &total_taxon_counts,
+                        genome_sizes,
+                    )?;
+                }
             }
 
             let source_sample_file = args.chunk_dir.join("sample_file.map");
@@ -291,10 +717,19 @@ pub fn run(args: Args) -> Result<()> {
         };
     }
 
+    // The gzip outputs are finished when they are dropped.
+    drop(split_outputs);
     // 计算持续时间
     let duration = start.elapsed();
     // 打印运行时间
     println!("resolve took: {:?}", duration);
+    if io_pool.busy() > Duration::ZERO {
+        println!(
+            "gzip compression took: {:?} over {} io threads",
+            io_pool.busy(),
+            io_pool.num_threads()
+        );
+    }
 
     for (_, sam_files) in &sample_files {
         for sample_file in sam_files {
@@ -307,6 +742,43 @@ pub fn run(args: Args) -> Result<()> {
     }
     // let source_sample_file = args.chunk_dir.join("sample_file.map");
     // let _ = std::fs::remove_file(source_sample_file);
+
+    if let Some(status) = status_file.as_mut() {
+        status.finish()?;
+    }
+    if file_stats.len() > 1 {
+        print!("{}", file_summary_table(&file_stats));
+    }
+    if read_stats.no_kmers > 0 {
+        println!("{} sequences without valid k-mers", read_stats.no_kmers);
+    }
+    if read_stats.filtered > 0 {
+        println!(
+            "{} sequences filtered below {} hit groups",
+            read_stats.filtered, args.minimum_hit_groups
+        );
+    }
+    let zero_taxon = read_stats.zero_taxon_message();
+    if let Some(message) = &zero_taxon {
+        eprintln!("{}", message);
+    }
+    if let Some(path) = &args.summary_output {
+        summary.set_counts(&read_stats, &file_stats);
+        summary.add_stage("resolve", duration);
+        summary.warnings.extend(low_classification_message(
+            &read_stats,
+            &meros,
+            args.min_classified_warn,
+        ));
+        summary.warnings.extend(zero_taxon);
+        summary.write(path)?;
+    }
+    check_classified_rate(
+        &read_stats,
+        &meros,
+        args.min_classified_warn,
+        args.min_classified_error,
+    )?;
     Ok(())
 }
 
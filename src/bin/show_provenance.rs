@@ -0,0 +1,29 @@
+use clap::Parser;
+use kun_peng::provenance::Provenance;
+use std::io::Result;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug, Clone)]
+#[clap(version, about = "show the provenance of database files and reports", long_about = None)]
+pub struct Args {
+    /// Database files (opts.k2d, hash_config.k2d) or kraken reports
+    #[clap(required = true)]
+    pub files: Vec<PathBuf>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    for file in &args.files {
+        let provenance = Provenance::from_file(file)?;
+        println!("{}", file.display());
+        println!("{}", serde_json::to_string_pretty(&provenance)?);
+    }
+    Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+    let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("Application error: {}", e);
+    }
+}
@@ -1,5 +1,13 @@
 use clap::Parser;
+use kun_peng::args::{default_threads, parse_threads};
 use kun_peng::compact_hash::{HashConfig, Slot};
+use kun_peng::mmscanner::IupacPolicy;
+use kun_peng::preprocess::{PreprocessReader, ReadPreprocessor};
+use kun_peng::reader::{
+    order_mate_files_warn, quality_score_note, sample_alphabet, sample_read_lengths, CheckedReader,
+    MultiFileReader, ALPHABET_SAMPLE_READS, LENGTH_SAMPLE_READS,
+};
+use kun_peng::summary::{alphabet_mismatch_message, short_read_message};
 use kun_peng::utils::{
     create_partition_files, create_partition_writers, create_sample_file, get_file_limit,
     get_lastest_file_index, set_fd_limit,
@@ -7,12 +15,12 @@ use kun_peng::utils::{
 use kun_peng::IndexOptions;
 use seqkmer::{read_parallel, FastxReader, Meros, MinimizerIterator, OptionPair, Reader};
 use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::io::{BufWriter, Write};
 use std::io::{Error, ErrorKind, Result};
 use std::path::PathBuf;
 use std::time::Instant;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
 
 /// Command line arguments for the splitr program.
 ///
@@ -40,6 +48,10 @@ pub struct Args {
     #[clap(short = 'S', long = "single-file-pairs", action)]
     pub single_file_pairs: bool,
 
+    /// Treat all input files as consecutive parts of one sample, with a global read index.
+    #[clap(long = "merge-parts", action)]
+    pub merge_parts: bool,
+
     /// Minimum quality score for FASTQ data.
     #[clap(
         short = 'Q',
@@ -49,8 +61,37 @@ pub struct Args {
     )]
     pub minimum_quality_score: i32,
 
+    /// Don't sample the read lengths of the first input to warn about reads short for the
+    /// k-mer length of the database.
+    #[clap(long)]
+    pub no_length_check: bool,
+
+    /// Don't sample the characters of the first input to refuse protein reads for a DNA
+    /// database, or nucleotide reads for a protein one.
+    #[clap(long)]
+    pub ignore_alphabet_check: bool,
+
+    /// How to scan the IUPAC ambiguity codes (R, Y, ...) of the reads: `break` the k-mers like
+    /// an N, read them as their `first-base` in ACGT order, or `skip` them.
+    #[clap(long = "iupac", value_name = "POLICY", default_value = "break")]
+    pub iupac: IupacPolicy,
+
+    /// Skip the spaces and tabs inside the sequences, like the line breaks, instead of
+    /// ending the k-mers at them.
+    #[clap(long = "skip-whitespace")]
+    pub skip_whitespace: bool,
+
+    /// Cut this many bases from the start of every mate before scanning, e.g. adapter or
+    /// low-quality bases. Mates trimmed to nothing count as too short.
+    #[clap(long = "trim-head", value_name = "N", default_value_t = 0)]
+    pub trim_head: usize,
+
+    /// Cut this many bases from the end of every mate before scanning.
+    #[clap(long = "trim-tail", value_name = "N", default_value_t = 0)]
+    pub trim_tail: usize,
+
     /// The number of threads to use.
-    #[clap(short = 'p', long = "num-threads", value_parser, default_value_t = num_cpus::get())]
+    #[clap(short = 'p', long = "num-threads", value_parser = parse_threads, default_value_t = default_threads())]
     pub num_threads: usize,
 
     /// chunk directory
@@ -68,9 +109,12 @@ impl Args {
     /// Process input_files to handle the case of a single text file containing multiple file paths
     pub fn process_input_files(mut self) -> Result<Self> {
         if self.input_files.is_empty() {
-            return Err(Error::new(ErrorKind::InvalidInput, "No input files provided."));
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "No input files provided.",
+            ));
         }
-        
+
         if self.input_files.len() == 1 {
             let file_path = &self.input_files[0];
             if file_path.is_file() && file_path.extension().map_or(false, |ext| ext == "txt") {
@@ -87,7 +131,10 @@ impl Args {
                 if !new_input_files.is_empty() {
                     self.input_files = new_input_files;
                 } else {
-                    return Err(Error::new(ErrorKind::InvalidInput, "No input files found in the provided list."));
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "No input files found in the provided list.",
+                    ));
                 }
             }
         }
@@ -101,8 +148,14 @@ impl Args {
         }
 
         if !missing_files.is_empty() {
-            let error_msg = format!("The following input files do not exist:\n{}", 
-                missing_files.iter().map(|f| f.display().to_string()).collect::<Vec<_>>().join("\n"));
+            let error_msg = format!(
+                "The following input files do not exist:\n{}",
+                missing_files
+                    .iter()
+                    .map(|f| f.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
             return Err(Error::new(ErrorKind::NotFound, error_msg));
         }
 
@@ -151,6 +204,10 @@ fn init_chunk_writers(
 }
 
 /// 处理record
+///
+/// Minimizers hashing below `min_clear_hash_value` are left out, like in the index. Returns
+/// the offset of the next mate, which continues the numbering of this one.
+#[allow(clippy::too_many_arguments)]
 fn process_record(
     k2_slot_list: &mut Vec<(usize, Slot<u64>)>,
     marker: &mut MinimizerIterator,
@@ -158,9 +215,13 @@ fn process_record(
     chunk_size: usize,
     seq_id: u64,
     idx_bits: usize,
-) {
-    let offset = k2_slot_list.len();
-    for (sort, hash_key) in marker {
+    min_clear_hash_value: Option<u64>,
+    offset: usize,
+) -> usize {
+    for (sort, hash_key) in &mut *marker {
+        if min_clear_hash_value.is_some_and(|min_clear| hash_key < min_clear) {
+            continue;
+        }
         let mut slot = hash_config.slot_u64(hash_key, seq_id);
         let seq_sort = sort + offset;
         let partition_index = slot.idx / chunk_size;
@@ -168,6 +229,7 @@ fn process_record(
         slot.idx = seq_sort << idx_bits | (slot.idx % chunk_size);
         k2_slot_list.push((partition_index, slot));
     }
+    offset + marker.size
 }
 
 fn write_data_to_file(
@@ -202,9 +264,18 @@ where
     let chunk_size = hash_config.hash_capacity;
     let idx_bits = ((chunk_size as f64).log2().ceil() as usize).max(1);
     let slot_size = std::mem::size_of::<Slot<u64>>();
+    let steps = ReadPreprocessor {
+        skip_whitespace: args.skip_whitespace,
+        trim_head: args.trim_head,
+        trim_tail: args.trim_tail,
+        iupac: args.iupac,
+        ..Default::default()
+    };
+    let mut checked = CheckedReader::new(reader);
+    let mut reader = PreprocessReader::new(&mut checked, steps);
 
     read_parallel(
-        reader,
+        &mut reader,
         args.num_threads as usize,
         &meros,
         |seqs| {
@@ -217,14 +288,17 @@ where
                 let dna_id = header.id.trim();
                 let seq_id = (file_index << 32 | index) as u64;
 
+                let mut offset = 0;
                 seq.body.apply_mut(|m_iter| {
-                    process_record(
+                    offset = process_record(
                         &mut init,
                         m_iter,
                         &hash_config,
                         chunk_size,
                         seq_id,
                         idx_bits,
+                        meros.min_clear_hash_value,
+                        offset,
                     );
                 });
                 k2_slot_list.extend_from_slice(&init);
@@ -246,13 +320,13 @@ where
     )
     .expect("failed");
 
-    Ok(())
+    checked.finish()
 }
 
 /// 处理样本文件
 fn process_files<F>(args: &Args, hash_config: HashConfig, mut action: F) -> Result<()>
 where
-    F: FnMut(usize, OptionPair<PathBuf>) -> Result<()>,
+    F: FnMut(usize, Vec<OptionPair<PathBuf>>) -> Result<()>,
 {
     let file_path = args.chunk_dir.join("sample_file.map");
     let mut file_writer = create_sample_file(&file_path);
@@ -263,34 +337,43 @@ where
     } else {
         1
     };
-    let files = args.input_files.chunks(chunk_size).collect::<Vec<_>>();
+    let files = args
+        .input_files
+        .chunks(chunk_size)
+        .map(OptionPair::from_slice)
+        .collect::<Vec<_>>();
+    let samples = if args.merge_parts {
+        vec![files]
+    } else {
+        files.into_iter().map(|part| vec![part]).collect()
+    };
 
-    let file_bits = (((files.len() + file_index) as f64).log2().ceil() as usize).max(1);
+    let file_bits = (((samples.len() + file_index) as f64).log2().ceil() as usize).max(1);
     if file_bits > hash_config.value_bits {
         panic!("The number of files is too large to process.");
     }
 
-    for file_pair in files {
+    for parts in samples {
         file_index += 1;
-        let path_pair = OptionPair::from_slice(file_pair);
-        writeln!(
-            file_writer,
-            "{}\t{}",
-            file_index,
-            path_pair.reduce_str(",", |a| a.to_str().unwrap().to_string())
-        )?;
+        let paths: Vec<String> = parts
+            .iter()
+            .map(|path_pair| path_pair.reduce_str(",", |a| a.to_str().unwrap().to_string()))
+            .collect();
+        writeln!(file_writer, "{}\t{}", file_index, paths.join(","))?;
         file_writer.flush().unwrap();
 
-        action(file_index, path_pair)?;
+        action(file_index, parts)?;
     }
 
     Ok(())
 }
 
 pub fn run(args: Args) -> Result<()> {
-    let args = args.process_input_files()?;
+    let mut args = args.process_input_files()?;
     let options_filename = &args.database.join("opts.k2d");
     let idx_opts = IndexOptions::read_index_options(options_filename)?;
+    idx_opts.require_canonical()?;
+    let meros = idx_opts.as_meros()?;
 
     if args.paired_end_processing && !args.single_file_pairs && args.input_files.len() % 2 != 0 {
         // 验证文件列表是否为偶数个
@@ -299,6 +382,28 @@ pub fn run(args: Args) -> Result<()> {
             "Paired-end processing requires an even number of input files.",
         ));
     }
+    if args.paired_end_processing && !args.single_file_pairs {
+        order_mate_files_warn(&mut args.input_files)?;
+    }
+    if let Some(note) = quality_score_note(&args.input_files, args.minimum_quality_score) {
+        eprintln!("{}", note);
+    }
+    if let Some(first) = args
+        .input_files
+        .first()
+        .filter(|_| !args.ignore_alphabet_check)
+    {
+        let counts = sample_alphabet(first, ALPHABET_SAMPLE_READS)?;
+        if let Some(msg) = alphabet_mismatch_message(&counts, idx_opts.dna_db) {
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+    }
+    if let Some(first) = args.input_files.first().filter(|_| !args.no_length_check) {
+        let lengths = sample_read_lengths(first, LENGTH_SAMPLE_READS)?;
+        if let Some(msg) = short_read_message(&lengths, meros.k_mer) {
+            eprintln!("{}", msg);
+        }
+    }
     let hash_config = HashConfig::from_hash_header(&args.database.join("hash_config.k2d"))?;
 
     println!("{:?}", hash_config);
@@ -317,28 +422,45 @@ pub fn run(args: Args) -> Result<()> {
         // panic!("Exceeds File Number Limit");
     }
 
-    let meros = idx_opts.as_meros();
     let start = Instant::now();
     let partition = hash_config.partition;
     let mut writers: Vec<BufWriter<fs::File>> =
         init_chunk_writers(&args, partition, hash_config.hash_capacity);
 
-    process_files(&args, hash_config, |file_index, path_pair| {
+    process_files(&args, hash_config, |file_index, mut parts| {
         let mut sample_writer =
             create_sample_file(args.chunk_dir.join(format!("sample_id_{}.map", file_index)));
 
         let score = args.minimum_quality_score;
-        let mut reader = FastxReader::from_paths(path_pair, file_index, score)?;
-        process_fastx_file(
-            &args,
-            meros,
-            hash_config,
-            file_index,
-            &mut reader,
-            &mut writers,
-            &mut sample_writer,
-        )
-        .expect("process fastx file error");
+        if let [OptionPair::Single(_)] = parts[..] {
+            let path_pair = parts.pop().unwrap();
+            let mut reader = FastxReader::from_paths(path_pair, file_index, score)?;
+            process_fastx_file(
+                &args,
+                meros,
+                hash_config,
+                file_index,
+                &mut reader,
+                &mut writers,
+                &mut sample_writer,
+            )?;
+        } else {
+            // The mates of a pair are opened one by one, each with its own compression
+            let several = parts.len() > 1;
+            let mut reader = MultiFileReader::new(parts, file_index, score)?;
+            process_fastx_file(
+                &args,
+                meros,
+                hash_config,
+                file_index,
+                &mut reader,
+                &mut writers,
+                &mut sample_writer,
+            )?;
+            if several {
+                println!("{}", reader.part_summary());
+            }
+        }
         Ok(())
     })?;
     let duration = start.elapsed();
@@ -0,0 +1,318 @@
+//! Per-taxon bins of the reads of a run, see `direct --bin-dir`
+//!
+//! Every read goes to the file of its taxon, `<taxid>.fastq`, or `unclassified.fastq`.
+//! The mates of a pair go to `<taxid>_1.fastq` and `<taxid>_2.fastq`, FASTA reads to
+//! `.fasta` files. The records are copied verbatim from the inputs, following the Kraken
+//! output of the sample, as the classification only keeps the bases of the reads, with
+//! the low-quality ones masked.
+use crate::compare::kraken_call;
+use crate::reader::{input_format, open_text, record_id};
+use seqkmer::SeqFormat;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+
+/// Bin of the unclassified reads
+pub const UNCLASSIFIED_BIN: &str = "unclassified";
+
+/// Number of bin files kept open by `--bin-dir`
+pub const MAX_OPEN_BINS: usize = 256;
+
+/// The bin files of a run, created when their first read comes
+///
+/// At most `max_open` files are open at once, the least recently written one is closed
+/// to open another and continued when it gets reads again. The first write of a run
+/// replaces an existing file.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::bins::BinWriters;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_bin_writers");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// let mut bins = BinWriters::new(&dir, 1).unwrap();
+/// for (bin, record) in [("562.fasta", ">a\n"), ("590.fasta", ">b\n"), ("562.fasta", ">c\n")] {
+///     bins.write(bin, record.as_bytes()).unwrap();
+/// }
+/// bins.flush().unwrap();
+/// assert_eq!(bins.files(), ["562.fasta", "590.fasta"]);
+/// assert_eq!(std::fs::read_to_string(dir.join("562.fasta")).unwrap(), ">a\n>c\n");
+///
+/// // A new run replaces the files
+/// let mut bins = BinWriters::new(&dir, 1).unwrap();
+/// bins.write("562.fasta", b">d\n").unwrap();
+/// bins.flush().unwrap();
+/// assert_eq!(std::fs::read_to_string(dir.join("562.fasta")).unwrap(), ">d\n");
+/// ```
+pub struct BinWriters {
+    dir: PathBuf,
+    max_open: usize,
+    /// Open files and the number of the write that last used them
+    open: HashMap<String, (BufWriter<File>, u64)>,
+    created: HashSet<String>,
+    writes: u64,
+}
+
+impl BinWriters {
+    pub fn new<P: AsRef<Path>>(dir: P, max_open: usize) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_open: max_open.max(1),
+            open: HashMap::new(),
+            created: HashSet::new(),
+            writes: 0,
+        })
+    }
+
+    /// Appends a record to the bin file `name`
+    pub fn write(&mut self, name: &str, record: &[u8]) -> Result<()> {
+        self.writes += 1;
+        if !self.open.contains_key(name) {
+            if self.open.len() >= self.max_open {
+                self.close_oldest()?;
+            }
+            let path = self.dir.join(name);
+            let file = if self.created.insert(name.to_string()) {
+                File::create(path)?
+            } else {
+                OpenOptions::new().append(true).open(path)?
+            };
+            self.open
+                .insert(name.to_string(), (BufWriter::new(file), 0));
+        }
+        let (writer, last_write) = self.open.get_mut(name).unwrap();
+        *last_write = self.writes;
+        writer.write_all(record)
+    }
+
+    fn close_oldest(&mut self) -> Result<()> {
+        let oldest = self
+            .open
+            .iter()
+            .min_by_key(|(_, (_, last_write))| *last_write)
+            .map(|(name, _)| name.clone());
+        match oldest.and_then(|name| self.open.remove(&name)) {
+            Some((mut writer, _)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// The bin files written in this run, sorted
+    pub fn files(&self) -> Vec<&str> {
+        let mut files: Vec<&str> = self.created.iter().map(String::as_str).collect();
+        files.sort_unstable();
+        files
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        for (writer, _) in self.open.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The records of a FASTA/FASTQ file as they are written, plain or gzipped
+struct RawRecords {
+    path: PathBuf,
+    format: SeqFormat,
+    reader: Box<dyn BufRead>,
+    /// Header of the next FASTA record, read at the end of the previous one
+    next_header: Option<Vec<u8>>,
+}
+
+impl RawRecords {
+    fn open(path: &Path) -> Result<Self> {
+        match (input_format(path)?, open_text(path)?) {
+            (Some(format), Some(reader)) => Ok(Self {
+                path: path.to_path_buf(),
+                format,
+                reader,
+                next_header: None,
+            }),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{}: only plain or gzipped FASTA/FASTQ reads can be binned",
+                    path.to_string_lossy()
+                ),
+            )),
+        }
+    }
+
+    /// Appends the next line to `record`, with its line break, `false` at the end
+    fn read_line(&mut self, record: &mut Vec<u8>) -> Result<bool> {
+        if self.reader.read_until(b'\n', record)? == 0 {
+            return Ok(false);
+        }
+        if !record.ends_with(b"\n") {
+            record.push(b'\n');
+        }
+        Ok(true)
+    }
+
+    /// The next non-blank line
+    fn next_header(&mut self) -> Result<Option<Vec<u8>>> {
+        if let Some(header) = self.next_header.take() {
+            return Ok(Some(header));
+        }
+        let mut line = Vec::new();
+        while self.read_line(&mut line)? {
+            if !line.iter().all(u8::is_ascii_whitespace) {
+                return Ok(Some(line));
+            }
+            line.clear();
+        }
+        Ok(None)
+    }
+
+    /// The ID and the text of the next record
+    fn next(&mut self) -> Result<Option<(String, Vec<u8>)>> {
+        let Some(mut record) = self.next_header()? else {
+            return Ok(None);
+        };
+        let id = record_id(String::from_utf8_lossy(&record).trim_end()).to_string();
+        match self.format {
+            SeqFormat::Fastq => {
+                for _ in 0..3 {
+                    if !self.read_line(&mut record)? {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("{} ends inside read {}", self.path.to_string_lossy(), id),
+                        ));
+                    }
+                }
+            }
+            _ => {
+                let mut line = Vec::new();
+                while self.read_line(&mut line)? {
+                    if line.starts_with(b">") {
+                        self.next_header = Some(line);
+                        break;
+                    }
+                    record.append(&mut line);
+                }
+            }
+        }
+        Ok(Some((id, record)))
+    }
+}
+
+/// File name of the bin of a taxon, `None` for the unclassified reads, and of a mate
+fn bin_name(taxid: Option<u64>, mate: Option<usize>, format: SeqFormat) -> String {
+    let bin = taxid.map_or(UNCLASSIFIED_BIN.to_string(), |taxid| taxid.to_string());
+    let mate = mate.map_or(String::new(), |mate| format!("_{}", mate));
+    let extension = match format {
+        SeqFormat::Fastq => "fastq",
+        _ => "fasta",
+    };
+    format!("{}{}.{}", bin, mate, extension)
+}
+
+/// Copies the reads of a sample into their bins, following its Kraken output
+///
+/// `parts` are the inputs of the sample in order, a file or the two mate files of a pair
+/// each, and the Kraken output has a line for every read of them, in the same order. A
+/// read whose ID differs from the one of its line is an error. Returns the number of
+/// reads binned.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::bins::{bin_reads, BinWriters};
+///
+/// let dir = std::env::temp_dir().join("kun_peng_bin_reads");
+/// let _ = std::fs::remove_dir_all(&dir);
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let (r1, r2, kraken) = (dir.join("r1.fq"), dir.join("r2.fq"), dir.join("output_1.txt"));
+/// std::fs::write(&r1, "@a/1\nACGT\n+\nIIII\n@b/1\nGGCC\n+\n####\n").unwrap();
+/// std::fs::write(&r2, "@a/2\nTTTT\n+\nIIII\n@b/2\nAAAA\n+\nIIII\n").unwrap();
+/// std::fs::write(&kraken, "C\ta\t562\t4|4\t562:1\nU\tb\t0\t4|4\t0:1\n").unwrap();
+///
+/// let mut bins = BinWriters::new(dir.join("bins"), 16).unwrap();
+/// assert_eq!(bin_reads(&[&[&r1, &r2][..]], &kraken, &mut bins).unwrap(), 2);
+/// bins.flush().unwrap();
+/// let bin = |name: &str| std::fs::read_to_string(dir.join("bins").join(name)).unwrap();
+/// assert_eq!(bin("562_1.fastq"), "@a/1\nACGT\n+\nIIII\n");
+/// assert_eq!(bin("562_2.fastq"), "@a/2\nTTTT\n+\nIIII\n");
+/// assert_eq!(bin("unclassified_1.fastq"), "@b/1\nGGCC\n+\n####\n");
+///
+/// std::fs::write(&kraken, "C\ta\t562\t4|4\t562:1\nU\tc\t0\t4|4\t0:1\n").unwrap();
+/// let err = bin_reads(&[&[&r1, &r2][..]], &kraken, &mut bins).unwrap_err();
+/// assert!(err.to_string().contains("read 2 is b, the Kraken output has c"));
+/// ```
+pub fn bin_reads<P: AsRef<Path>>(
+    parts: &[&[P]],
+    kraken_output: &Path,
+    bins: &mut BinWriters,
+) -> Result<u64> {
+    let mut lines = BufReader::new(File::open(kraken_output)?).lines();
+    let output_name = kraken_output.to_string_lossy();
+    let mut reads = 0;
+    for part in parts {
+        let mut mates = part
+            .iter()
+            .map(|path| RawRecords::open(path.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        let paired = mates.len() > 1;
+        loop {
+            let records = mates
+                .iter_mut()
+                .map(RawRecords::next)
+                .collect::<Result<Vec<_>>>()?;
+            if records.iter().all(Option::is_none) {
+                break;
+            }
+            reads += 1;
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} ends before read {}", output_name, reads),
+                    ))
+                }
+            };
+            let Some((output_id, taxid)) = kraken_call(&line) else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}: not a Kraken output line: {}", output_name, line),
+                ));
+            };
+            for (mate, (record, input)) in records.into_iter().zip(&mates).enumerate() {
+                let Some((id, record)) = record else {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "{} ends before read {}",
+                            input.path.to_string_lossy(),
+                            reads
+                        ),
+                    ));
+                };
+                if id != record_id(output_id) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "read {} is {}, the Kraken output has {}",
+                            reads, id, output_id
+                        ),
+                    ));
+                }
+                let taxid = (taxid != 0).then_some(taxid);
+                let mate = paired.then_some(mate + 1);
+                bins.write(&bin_name(taxid, mate, input.format), &record)?;
+            }
+        }
+    }
+    if lines.next().is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{} holds more reads than the inputs", output_name),
+        ));
+    }
+    Ok(reads)
+}
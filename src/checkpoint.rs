@@ -0,0 +1,674 @@
+use crate::fmt::{self, Kind};
+use crate::readcounts::TaxonCounters;
+use crate::summary::ReadStats;
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+use seqkmer::{Base, OptionPair, Reader, SeqFormat, SeqHeader};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Version of the [`crate::fmt`] header of the checkpoints
+pub const CHECKPOINT_VERSION: u8 = 1;
+
+/// Number of reads handed out per batch by [`SeekableFastxReader`]
+const BATCH_SIZE: usize = 4096;
+
+/// Progress of the classification of one sample, saved as a checkpoint
+///
+/// A restored progress continues the sample: the inputs are sought to `offsets`, the
+/// outputs are cut back to their length at the checkpoint and the counts go on from the
+/// saved ones.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SampleProgress {
+    /// Index of the sample in `sample_file.map`
+    pub file_index: usize,
+    /// Input files, one per mate
+    pub inputs: Vec<PathBuf>,
+    /// Position of the next read in every input, see [`SeekableFastxReader::offsets`]
+    pub offsets: Vec<u64>,
+    /// Number of reads written to the outputs
+    pub reads_index: usize,
+    /// Number of classified reads
+    pub classified: usize,
+    /// Length of every output file at the checkpoint
+    pub outputs: Vec<(PathBuf, u64)>,
+    pub read_stats: ReadStats,
+    pub taxon_counts: TaxonCounters,
+}
+
+impl SampleProgress {
+    pub fn new(file_index: usize, inputs: Vec<PathBuf>) -> Self {
+        Self {
+            file_index,
+            inputs,
+            offsets: Vec::new(),
+            reads_index: 0,
+            classified: 0,
+            outputs: Vec::new(),
+            read_stats: ReadStats::default(),
+            taxon_counts: TaxonCounters::new(),
+        }
+    }
+
+    /// Whether this progress was loaded from a checkpoint
+    pub fn is_restored(&self) -> bool {
+        !self.offsets.is_empty()
+    }
+
+    /// Adds the counts of a batch of reads written to the outputs
+    pub fn add_batch(
+        &mut self,
+        reads: usize,
+        classified: usize,
+        read_stats: &ReadStats,
+        taxon_counts: &TaxonCounters,
+    ) {
+        self.reads_index += reads;
+        self.classified += classified;
+        self.read_stats.merge(read_stats);
+        for (taxid, counts) in taxon_counts {
+            self.taxon_counts
+                .entry(*taxid)
+                .or_default()
+                .merge(counts)
+                .unwrap();
+        }
+    }
+
+    /// Writes the checkpoint, replacing the previous one only once the new one is on disk
+    ///
+    /// The JSON of the progress follows a [`crate::fmt`] header of version
+    /// [`CHECKPOINT_VERSION`], a cut or corrupt checkpoint is refused on load.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = path.with_extension("tmp");
+        let payload = serde_json::to_vec(self)?;
+        let mut file = File::create(&tmp_path)?;
+        fmt::write_payload(&mut file, Kind::Checkpoint, CHECKPOINT_VERSION, 0, &payload)?;
+        file.flush()?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    }
+
+    /// Loads a checkpoint, also one saved as plain JSON before the header
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::checkpoint::SampleProgress;
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_checkpoint_load");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let path = dir.join("checkpoint.json");
+    ///
+    /// let mut progress = SampleProgress::new(2, vec!["reads.fq".into()]);
+    /// progress.offsets = vec![4096];
+    /// progress.reads_index = 17;
+    /// progress.save(&path).unwrap();
+    /// let loaded = SampleProgress::load(&path).unwrap();
+    /// assert_eq!((loaded.file_index, loaded.offsets, loaded.reads_index), (2, vec![4096], 17));
+    ///
+    /// // Version 0, the JSON alone
+    /// let bytes = std::fs::read(&path).unwrap();
+    /// std::fs::write(&path, &bytes[kun_peng::fmt::HEADER_SIZE..]).unwrap();
+    /// assert_eq!(SampleProgress::load(&path).unwrap().reads_index, 17);
+    ///
+    /// // Cut by a crash while it was copied
+    /// std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+    /// assert!(SampleProgress::load(&path).is_err());
+    /// ```
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = BufReader::new(File::open(path)?);
+        let (_, payload) = fmt::read_payload(file, Kind::Checkpoint, CHECKPOINT_VERSION)?;
+        Ok(serde_json::from_slice(&payload)?)
+    }
+
+    /// Cuts the outputs back to their length at the checkpoint
+    ///
+    /// Lines written after the checkpoint are dropped, they are classified again.
+    pub fn truncate_outputs(&self) -> Result<()> {
+        for (path, len) in &self.outputs {
+            let file = OpenOptions::new().write(true).open(path)?;
+            if file.metadata()?.len() < *len {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} is shorter than at the checkpoint",
+                        path.to_string_lossy()
+                    ),
+                ));
+            }
+            file.set_len(*len)?;
+        }
+        Ok(())
+    }
+}
+
+/// Offsets of the inputs after the read with the given index
+type InputMark = (usize, Vec<u64>);
+
+/// Input offsets at the end of every batch handed out by a [`SeekableFastxReader`]
+///
+/// The reader runs ahead of the output, the offsets wait here until the reads of their
+/// batch are written. The handle is cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct InputMarks {
+    marks: Arc<Mutex<VecDeque<InputMark>>>,
+}
+
+impl InputMarks {
+    fn push(&self, reads_index: usize, offsets: Vec<u64>) {
+        self.marks.lock().unwrap().push_back((reads_index, offsets));
+    }
+
+    /// Takes the offsets after the read `reads_index` if a batch ends there
+    ///
+    /// The marks of earlier batches are dropped.
+    pub fn take(&self, reads_index: usize) -> Option<Vec<u64>> {
+        let mut marks = self.marks.lock().unwrap();
+        while marks.front().is_some_and(|(index, _)| *index < reads_index) {
+            marks.pop_front();
+        }
+        if marks
+            .front()
+            .is_some_and(|(index, _)| *index == reads_index)
+        {
+            marks.pop_front().map(|(_, offsets)| offsets)
+        } else {
+            None
+        }
+    }
+}
+
+/// Saves the progress of a sample every `every` reads
+pub struct Checkpointer {
+    path: PathBuf,
+    every: usize,
+    next: usize,
+    marks: InputMarks,
+    outputs: Vec<PathBuf>,
+}
+
+impl Checkpointer {
+    /// `outputs` are the files synced and measured at every checkpoint
+    pub fn new(
+        path: PathBuf,
+        every: usize,
+        marks: InputMarks,
+        outputs: Vec<PathBuf>,
+        progress: &SampleProgress,
+    ) -> Self {
+        Self {
+            path,
+            every,
+            next: (progress.reads_index / every + 1) * every,
+            marks,
+            outputs,
+        }
+    }
+
+    /// Saves `progress` once a checkpoint is due and a batch ends at its last read
+    ///
+    /// `flush` must write out every buffered output first.
+    pub fn update<F>(&mut self, progress: &mut SampleProgress, flush: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        let offsets = self.marks.take(progress.reads_index);
+        let Some(offsets) = offsets.filter(|_| progress.reads_index >= self.next) else {
+            return Ok(());
+        };
+        flush()?;
+        progress.outputs = self
+            .outputs
+            .iter()
+            .map(|path| {
+                let file = OpenOptions::new().append(true).open(path)?;
+                file.sync_all()?;
+                Ok((path.clone(), file.metadata()?.len()))
+            })
+            .collect::<Result<_>>()?;
+        progress.offsets = offsets;
+        progress.save(&self.path)?;
+        self.next = (progress.reads_index / self.every + 1) * self.every;
+        Ok(())
+    }
+}
+
+/// BGZF block size from the extra field of a gzip header
+fn bgzf_block_size(extra: &[u8]) -> Option<usize> {
+    let mut rest = extra;
+    while rest.len() >= 4 {
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let data = rest.get(4..4 + len)?;
+        if rest[..2] == *b"BC" && len == 2 {
+            return Some(u16::from_le_bytes([data[0], data[1]]) as usize + 1);
+        }
+        rest = &rest[4 + len..];
+    }
+    None
+}
+
+fn invalid_bgzf() -> Error {
+    Error::new(ErrorKind::InvalidData, "invalid BGZF block")
+}
+
+/// Reads a BGZF file block by block
+///
+/// Positions are virtual offsets: the offset of the compressed block shifted left by 16
+/// bits, plus the offset inside the uncompressed block.
+struct BgzfReader {
+    file: BufReader<File>,
+    /// Compressed offset of the current block
+    block_offset: u64,
+    /// Compressed offset of the next block
+    next_offset: u64,
+    block: Vec<u8>,
+    pos: usize,
+}
+
+impl BgzfReader {
+    fn new(file: BufReader<File>) -> Self {
+        Self {
+            file,
+            block_offset: 0,
+            next_offset: 0,
+            block: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        (self.block_offset << 16) | self.pos as u64
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.file.seek(SeekFrom::Start(offset >> 16))?;
+        self.next_offset = offset >> 16;
+        self.load_block()?;
+        let pos = (offset & 0xffff) as usize;
+        if pos > self.block.len() {
+            return Err(invalid_bgzf());
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    /// Reads the block at `next_offset`, `false` at the end of the file
+    fn load_block(&mut self) -> Result<bool> {
+        self.block_offset = self.next_offset;
+        self.block.clear();
+        self.pos = 0;
+        if self.file.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+
+        let mut header = [0u8; 12];
+        self.file.read_exact(&mut header)?;
+        if header[..4] != [0x1f, 0x8b, 8, 4] {
+            return Err(invalid_bgzf());
+        }
+        let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let mut extra = vec![0u8; xlen];
+        self.file.read_exact(&mut extra)?;
+        let block_size = bgzf_block_size(&extra).ok_or_else(invalid_bgzf)?;
+        // The block holds the header, the deflate data and an 8 byte CRC32/ISIZE trailer
+        let data_len = block_size
+            .checked_sub(header.len() + xlen + 8)
+            .ok_or_else(invalid_bgzf)?;
+        let mut data = vec![0u8; data_len + 8];
+        self.file.read_exact(&mut data)?;
+
+        DeflateDecoder::new(&data[..data_len]).read_to_end(&mut self.block)?;
+        let mut crc = Crc::new();
+        crc.update(&self.block);
+        let trailer = &data[data_len..];
+        if crc.sum().to_le_bytes() != trailer[..4] || crc.amount().to_le_bytes() != trailer[4..] {
+            return Err(invalid_bgzf());
+        }
+        self.next_offset += block_size as u64;
+        Ok(true)
+    }
+
+    fn read_line(&mut self, line: &mut Vec<u8>) -> Result<usize> {
+        let start = line.len();
+        loop {
+            if self.pos == self.block.len() && !self.load_block()? {
+                break;
+            }
+            let rest = &self.block[self.pos..];
+            match rest.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&rest[..=end]);
+                    self.pos += end + 1;
+                    break;
+                }
+                None => {
+                    line.extend_from_slice(rest);
+                    self.pos = self.block.len();
+                }
+            }
+        }
+        Ok(line.len() - start)
+    }
+}
+
+/// An input file read line by line that can be sought back to any line start
+enum SeekableInput {
+    Plain { file: BufReader<File>, offset: u64 },
+    Bgzf(BgzfReader),
+}
+
+impl SeekableInput {
+    /// Opens an uncompressed or a BGZF file, other compressed files can't be sought
+    fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let head = file.fill_buf()?;
+        let unsupported = |format: &str| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{}: {} inputs can't be sought, checkpoints need uncompressed or BGZF (bgzip) inputs",
+                    path.to_string_lossy(),
+                    format
+                ),
+            )
+        };
+        if head.starts_with(&[0x1f, 0x8b]) {
+            let is_bgzf = head.len() >= 12 && head[3] & 4 != 0 && {
+                let xlen = u16::from_le_bytes([head[10], head[11]]) as usize;
+                head.get(12..12 + xlen).and_then(bgzf_block_size).is_some()
+            };
+            if !is_bgzf {
+                return Err(unsupported("gzip"));
+            }
+            return Ok(Self::Bgzf(BgzfReader::new(file)));
+        }
+        if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Err(unsupported("zstd"));
+        }
+        Ok(Self::Plain { file, offset: 0 })
+    }
+
+    fn offset(&self) -> u64 {
+        match self {
+            Self::Plain { offset, .. } => *offset,
+            Self::Bgzf(reader) => reader.offset(),
+        }
+    }
+
+    fn seek(&mut self, to: u64) -> Result<()> {
+        match self {
+            Self::Plain { file, offset } => {
+                file.seek(SeekFrom::Start(to))?;
+                *offset = to;
+                Ok(())
+            }
+            Self::Bgzf(reader) => reader.seek(to),
+        }
+    }
+
+    /// Appends the next line, including its `\n`, returns 0 at the end of the file
+    fn read_line(&mut self, line: &mut Vec<u8>) -> Result<usize> {
+        match self {
+            Self::Plain { file, offset } => {
+                let len = file.read_until(b'\n', line)?;
+                *offset += len as u64;
+                Ok(len)
+            }
+            Self::Bgzf(reader) => reader.read_line(line),
+        }
+    }
+}
+
+fn trim_line(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// FASTA/FASTQ records of one seekable input
+struct RecordReader {
+    input: SeekableInput,
+    path: PathBuf,
+    /// Header of the next FASTA record and its offset, read with the previous record
+    next_header: Option<(Vec<u8>, u64)>,
+    line: Vec<u8>,
+}
+
+impl RecordReader {
+    fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            input: SeekableInput::open(path)?,
+            path: path.to_path_buf(),
+            next_header: None,
+            line: Vec::new(),
+        })
+    }
+
+    /// Offset of the next record
+    fn offset(&self) -> u64 {
+        match &self.next_header {
+            Some((_, offset)) => *offset,
+            None => self.input.offset(),
+        }
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<()> {
+        self.next_header = None;
+        self.input.seek(offset)
+    }
+
+    fn invalid(&self, message: &str) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{}: {}", self.path.to_string_lossy(), message),
+        )
+    }
+
+    /// Reads the next line without its line break, `None` at the end of the file
+    fn next_line(&mut self) -> Result<Option<&[u8]>> {
+        self.line.clear();
+        if self.input.read_line(&mut self.line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(trim_line(&self.line)))
+    }
+
+    /// Reads the ID, the format and the sequence of the next record
+    ///
+    /// FASTQ bases with a quality below `quality_score` are masked.
+    fn next_record(&mut self, quality_score: i32) -> Result<Option<(String, SeqFormat, Vec<u8>)>> {
+        let header = match self.next_header.take() {
+            Some((header, _)) => header,
+            None => loop {
+                match self.next_line()? {
+                    Some([]) => continue,
+                    Some(line) => break line.to_vec(),
+                    None => return Ok(None),
+                }
+            },
+        };
+        let id = header[1..]
+            .split(|b| b.is_ascii_whitespace())
+            .next()
+            .unwrap_or_default();
+        let id = String::from_utf8_lossy(id).to_string();
+
+        match header[0] {
+            b'>' => {
+                let mut seq = Vec::new();
+                loop {
+                    let offset = self.input.offset();
+                    match self.next_line()? {
+                        Some(line) if line.starts_with(b">") => {
+                            self.next_header = Some((line.to_vec(), offset));
+                            break;
+                        }
+                        Some(line) => seq.extend_from_slice(line),
+                        None => break,
+                    }
+                }
+                Ok(Some((id, SeqFormat::Fasta, seq)))
+            }
+            b'@' => {
+                let mut seq = match self.next_line()? {
+                    Some(line) => line.to_vec(),
+                    None => return Err(self.invalid("truncated FASTQ record")),
+                };
+                if !self.next_line()?.is_some_and(|line| line.starts_with(b"+")) {
+                    return Err(self.invalid("FASTQ record without a `+` line"));
+                }
+                let Some(qual) = self.next_line()? else {
+                    return Err(self.invalid("truncated FASTQ record"));
+                };
+                if quality_score > 0 {
+                    for (base, &q) in seq.iter_mut().zip(qual) {
+                        if (q as i32 - b'!' as i32) < quality_score {
+                            *base = b'x';
+                        }
+                    }
+                }
+                Ok(Some((id, SeqFormat::Fastq, seq)))
+            }
+            _ => Err(self.invalid("expected a FASTA or FASTQ record")),
+        }
+    }
+}
+
+/// Reads FASTA/FASTQ files that can be sought back to a read, for checkpoints
+///
+/// Inputs must be uncompressed or BGZF compressed, plain gzip and zstd files are
+/// rejected since they can't be sought. Two paths are read as the mates of pairs.
+/// The offsets at the end of every batch are published to [`SeekableFastxReader::marks`].
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::checkpoint::SeekableFastxReader;
+/// use seqkmer::Reader;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_seekable_reader");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let path = dir.join("reads.fq");
+/// let records: String = (0..5).map(|i| format!("@r{} x\nACGT\n+\nII#I\n", i)).collect();
+/// std::fs::write(&path, records).unwrap();
+///
+/// let mut reader = SeekableFastxReader::from_paths(&[path.clone()], 1, 10).unwrap();
+/// let seqs = reader.next().unwrap().unwrap();
+/// assert_eq!(seqs.len(), 5);
+/// assert_eq!(seqs[2].header.id, "r2");
+/// assert_eq!(seqs[2].header.reads_index, 3);
+/// assert_eq!(seqs[2].body.single().unwrap(), b"ACxT");
+/// assert_eq!(reader.marks().take(5), Some(vec![90]));
+/// assert!(reader.next().unwrap().is_none());
+///
+/// // Continue after the third read, 18 bytes per record
+/// let mut reader = SeekableFastxReader::from_paths(&[path.clone()], 1, 0).unwrap();
+/// reader.seek(&[54], 3).unwrap();
+/// let seqs = reader.next().unwrap().unwrap();
+/// let ids: Vec<_> = seqs.iter().map(|seq| (seq.header.id.as_str(), seq.header.reads_index)).collect();
+/// assert_eq!(ids, [("r3", 4), ("r4", 5)]);
+///
+/// let gzip = dir.join("reads.fq.gz");
+/// std::fs::write(&gzip, [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3]).unwrap();
+/// assert!(SeekableFastxReader::from_paths(&[gzip], 1, 0).is_err());
+/// ```
+pub struct SeekableFastxReader {
+    mates: Vec<RecordReader>,
+    file_index: usize,
+    quality_score: i32,
+    reads_index: usize,
+    marks: InputMarks,
+}
+
+impl SeekableFastxReader {
+    pub fn from_paths(paths: &[PathBuf], file_index: usize, quality_score: i32) -> Result<Self> {
+        if paths.is_empty() || paths.len() > 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "expected one file or the two files of a pair",
+            ));
+        }
+        let mates = paths
+            .iter()
+            .map(|path| RecordReader::open(path))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            mates,
+            file_index,
+            quality_score,
+            reads_index: 0,
+            marks: InputMarks::default(),
+        })
+    }
+
+    /// Position of the next read in every input
+    ///
+    /// A byte offset for uncompressed inputs, a virtual offset for BGZF inputs.
+    pub fn offsets(&self) -> Vec<u64> {
+        self.mates.iter().map(RecordReader::offset).collect()
+    }
+
+    /// Continues at `offsets`, the next read gets the index `reads_index + 1`
+    pub fn seek(&mut self, offsets: &[u64], reads_index: usize) -> Result<()> {
+        if offsets.len() != self.mates.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the checkpoint holds an offset for each input",
+            ));
+        }
+        for (mate, &offset) in self.mates.iter_mut().zip(offsets) {
+            mate.seek(offset)?;
+        }
+        self.reads_index = reads_index;
+        Ok(())
+    }
+
+    pub fn marks(&self) -> InputMarks {
+        self.marks.clone()
+    }
+}
+
+impl Reader for SeekableFastxReader {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        let mut seqs = Vec::with_capacity(BATCH_SIZE);
+        while seqs.len() < BATCH_SIZE {
+            let quality_score = self.quality_score;
+            let mut records = Vec::with_capacity(self.mates.len());
+            for mate in &mut self.mates {
+                records.push(mate.next_record(quality_score)?);
+            }
+            let (id, format, seq1) = match records.iter().filter(|r| r.is_some()).count() {
+                0 => break,
+                n if n < records.len() => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "the mate files hold a different number of reads",
+                    ))
+                }
+                _ => records.remove(0).unwrap(),
+            };
+            let body = match records.pop() {
+                Some(Some((_, _, seq2))) => OptionPair::Pair(seq1, seq2),
+                _ => OptionPair::Single(seq1),
+            };
+            self.reads_index += 1;
+            let header = SeqHeader {
+                id,
+                file_index: self.file_index,
+                reads_index: self.reads_index,
+                format,
+            };
+            seqs.push(Base::new(header, body));
+        }
+        if seqs.is_empty() {
+            return Ok(None);
+        }
+        self.marks.push(self.reads_index, self.offsets());
+        Ok(Some(seqs))
+    }
+}
@@ -0,0 +1,1133 @@
+use crate::classify::{
+    hit_string, lookup_minimizers, mates_hit_string, process_hitgroup, process_seq,
+    HitGroupOptions, ScoreMode,
+};
+use crate::compact_hash::{CHTable, HashConfig, Row};
+use crate::mmscanner::{trailing_minimizer, ScanOptions};
+use crate::output::KrakenLine;
+use crate::taxonomy::Taxonomy;
+use crate::utils::{find_and_sort_files, open_file, parse_seq_size};
+use crate::{HitGroup, IndexOptions};
+use seqkmer::{
+    scan_sequence, Base, Cursor, Meros, MinimizerIterator, MinimizerWindow, OptionPair, SeqFormat,
+    SeqHeader,
+};
+use std::fmt;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// A database fully loaded into memory, shared by all classifier workers
+///
+/// Lookups only read the tables, nothing is built lazily nor cached behind interior
+/// mutability, so a database is `Send + Sync` and one `Arc<Database>` can serve any number
+/// of threads or async tasks at once, see `examples/embedded.rs`.
+///
+/// ```
+/// use kun_peng::classifier::{ClassifierOptions, ClassifyResult, Database};
+/// use kun_peng::compact_hash::{CHTable, HashConfig};
+/// use kun_peng::taxonomy::Taxonomy;
+/// use std::sync::Arc;
+///
+/// fn shared<T: Send + Sync + 'static>() {}
+/// shared::<Database>();
+/// shared::<Arc<Database>>();
+/// shared::<CHTable>();
+/// shared::<HashConfig>();
+/// shared::<Taxonomy>();
+/// shared::<ClassifierOptions>();
+/// shared::<ClassifyResult>();
+/// ```
+pub struct Database {
+    pub meros: Meros,
+    pub hash_config: HashConfig,
+    pub chtable: CHTable,
+    pub taxonomy: Taxonomy,
+}
+
+impl Database {
+    pub fn new(
+        meros: Meros,
+        hash_config: HashConfig,
+        chtable: CHTable,
+        taxonomy: Taxonomy,
+    ) -> Self {
+        Self {
+            meros,
+            hash_config,
+            chtable,
+            taxonomy,
+        }
+    }
+
+    /// Loads all hash tables of a database directory, like the `direct` command does
+    pub fn load<P: AsRef<Path>>(database: P) -> Result<Self> {
+        let database = database.as_ref();
+        let idx_opts = IndexOptions::read_index_options(database.join("opts.k2d"))?;
+        let taxonomy = Taxonomy::from_file(database.join("taxo.k2d"))?;
+        let hash_config = HashConfig::from_hash_header(database.join("hash_config.k2d"))?;
+        if hash_config.hash_capacity == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "`hash_capacity` can't be zero!",
+            ));
+        }
+        let hash_files = find_and_sort_files(database, "hash", ".k2d", true)?;
+        let chtable = CHTable::from_hash_files(hash_config, &hash_files)?;
+        Ok(Self::new(
+            idx_opts.as_meros()?,
+            hash_config,
+            chtable,
+            taxonomy,
+        ))
+    }
+
+    /// Classifies a read from its pre-extracted minimizers, bypassing the scanner
+    ///
+    /// `classified` is incremented for every classified read.
+    ///
+    /// # Examples
+    ///
+    /// The calls match the ones obtained by scanning the original reads:
+    ///
+    /// ```
+    /// use kun_peng::classifier::{read_minimizer_file, Classifier, ClassifierOptions, Database, MinimizerRecord};
+    /// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use seqkmer::Meros;
+    /// use std::collections::HashMap;
+    /// use std::io::Write;
+    /// use std::sync::atomic::AtomicUsize;
+    /// use std::sync::Arc;
+    ///
+    /// let meros = Meros::new(15, 11, None, None, None);
+    /// let genome = b"ATGCGTACGTTAGCCTAGGCTAACGTTGCAAGCTTGCATGCCTGCAGGTCGACTCTAGAGGATCCCCGGGTACC";
+    ///
+    /// // A single-partition table holding the minimizers of `genome` under taxon 1.
+    /// let hash_config = HashConfig::new(1, 4096, 16, 0, 1, 4096);
+    /// let mut page = Page::with_capacity(0, 4096);
+    /// let genome_record = MinimizerRecord::from_seq("genome", genome, None, &meros);
+    /// for &hash_key in genome_record.minimizers.single().unwrap() {
+    ///     let slot = hash_config.slot(hash_key, 1);
+    ///     let idx = (slot.idx..).find(|&i| page.data[i] == 0 || page.data[i] == slot.value).unwrap();
+    ///     page.data[idx] = slot.value;
+    /// }
+    /// let chtable = CHTable { config: hash_config, pages: vec![page] };
+    ///
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// taxonomy.nodes.push(TaxonomyNode { external_id: 562, ..Default::default() });
+    /// taxonomy.path_cache.insert(1, vec![1]);
+    /// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+    ///
+    /// let reads: Vec<(&str, &[u8], Option<&[u8]>)> = vec![
+    ///     ("hit", &genome[5..60], None),
+    ///     ("pair", &genome[0..40], Some(&genome[30..])),
+    ///     ("miss", b"TTTTTTTTTTAAAAAAAAAACCCCCCCCCCGGGGGGGGGG", None),
+    /// ];
+    ///
+    /// // Write the minimizer file and classify from it.
+    /// let filename = std::env::temp_dir().join("kun_peng_bulk_classify.tsv");
+    /// let mut file = std::fs::File::create(&filename).unwrap();
+    /// for (id, seq1, seq2) in &reads {
+    ///     writeln!(file, "{}", MinimizerRecord::from_seq(id, seq1, *seq2, &meros)).unwrap();
+    /// }
+    /// drop(file);
+    ///
+    /// let options = ClassifierOptions::default();
+    /// let counter = AtomicUsize::new(0);
+    /// let bulk: HashMap<String, String> = read_minimizer_file(&filename)
+    ///     .unwrap()
+    ///     .iter()
+    ///     .map(|record| db.classify_minimizers(record, &options, &counter))
+    ///     .map(|result| (result.id.clone(), result.to_string()))
+    ///     .collect();
+    ///
+    /// // Scan the original reads.
+    /// let (classifier, results) = Classifier::with_channel(db.clone(), options);
+    /// for (id, seq1, seq2) in &reads {
+    ///     classifier.submit(*id, seq1.to_vec(), seq2.map(|s| s.to_vec())).unwrap();
+    /// }
+    /// classifier.finish().unwrap();
+    /// let scanned: HashMap<String, String> =
+    ///     results.iter().map(|result| (result.id.clone(), result.to_string())).collect();
+    ///
+    /// assert_eq!(bulk, scanned);
+    /// assert!(bulk["hit"].starts_with("C\thit\t562\t"));
+    /// assert!(bulk["miss"].starts_with("U\tmiss\t0\t"));
+    /// ```
+    pub fn classify_minimizers(
+        &self,
+        record: &MinimizerRecord,
+        options: &ClassifierOptions,
+        classified: &AtomicUsize,
+    ) -> ClassifyResult {
+        let mut rows: Vec<Row> = Vec::new();
+        let mut offset = 0;
+        let range = record.minimizers.apply(|minimizers| {
+            // Positions are numbered like the ones of `MinimizerIterator`, starting at 1.
+            let positions = minimizers.iter().enumerate().map(|(i, &m)| (i + 1, m));
+            lookup_minimizers(
+                &mut rows,
+                positions,
+                &self.hash_config,
+                &self.chtable,
+                self.meros.min_clear_hash_value,
+                offset,
+            );
+            let range = (offset, offset + minimizers.len());
+            offset += minimizers.len();
+            range
+        });
+        let hits = HitGroup::new(rows, range)
+            .with_seq_lens(parse_seq_size(&record.seq_size), self.meros.k_mer);
+        resolve_hits(
+            record.id.clone(),
+            record.seq_size.clone(),
+            &hits,
+            self,
+            options,
+            classified,
+        )
+    }
+
+    /// Classifies a read made of any number of associated mates, e.g. linked reads
+    ///
+    /// The minimizers of all mates are combined into one hit group, the hit string
+    /// separates the mates with ` |:| ` and the sequence length column with `|`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::classifier::{ClassifierOptions, Database, MinimizerRecord};
+    /// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use seqkmer::Meros;
+    /// use std::sync::atomic::AtomicUsize;
+    ///
+    /// let meros = Meros::new(15, 11, None, None, None);
+    /// let genome = b"ATGCGTACGTTAGCCTAGGCTAACGTTGCAAGCTTGCATGCCTGCAGGTCGACTCTAGAGGATCCCCGGGTACC";
+    /// let hash_config = HashConfig::new(1, 4096, 16, 0, 1, 4096);
+    /// let mut page = Page::with_capacity(0, 4096);
+    /// let genome_record = MinimizerRecord::from_seq("genome", genome, None, &meros);
+    /// for &hash_key in genome_record.minimizers.single().unwrap() {
+    ///     let slot = hash_config.slot(hash_key, 1);
+    ///     let idx = (slot.idx..).find(|&i| page.data[i] == 0 || page.data[i] == slot.value).unwrap();
+    ///     page.data[idx] = slot.value;
+    /// }
+    /// let chtable = CHTable { config: hash_config, pages: vec![page] };
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// taxonomy.nodes.push(TaxonomyNode { external_id: 562, ..Default::default() });
+    /// taxonomy.path_cache.insert(1, vec![1]);
+    /// let db = Database::new(meros, hash_config, chtable, taxonomy);
+    ///
+    /// let options = ClassifierOptions::default();
+    /// let counter = AtomicUsize::new(0);
+    /// let mates: Vec<&[u8]> = vec![&genome[0..30], &genome[25..50], &genome[45..]];
+    /// let result = db.classify_mates("linked", &mates, &options, &counter);
+    /// assert!(result.classified);
+    /// assert_eq!(result.taxid, 562);
+    /// assert_eq!(result.seq_size, "30|25|29");
+    /// assert_eq!(result.hit_string.matches(" |:| ").count(), 2);
+    ///
+    /// // Two mates give the same line as a pair
+    /// let pair = db.classify_mates("pair", &mates[..2], &options, &counter);
+    /// let record = MinimizerRecord::from_seq("pair", mates[0], Some(mates[1]), &meros);
+    /// assert_eq!(pair.to_string(), db.classify_minimizers(&record, &options, &counter).to_string());
+    ///
+    /// // A mate of k - 1 bases has no minimizer, unless the trailing window is looked up
+    /// let short: Vec<&[u8]> = vec![&genome[0..30], &genome[40..54]];
+    /// let result = db.classify_mates("short", &short, &options, &counter);
+    /// assert!(result.hit_string.ends_with(" |:| 0:0"), "{}", result.hit_string);
+    /// let trailing = ClassifierOptions { trailing_window: true, ..Default::default() };
+    /// let result = db.classify_mates("short", &short, &trailing, &counter);
+    /// assert!(result.hit_string.ends_with(" |:| 562:1"), "{}", result.hit_string);
+    /// ```
+    pub fn classify_mates<S: AsRef<[u8]>>(
+        &self,
+        id: &str,
+        mates: &[S],
+        options: &ClassifierOptions,
+        classified: &AtomicUsize,
+    ) -> ClassifyResult {
+        let mut rows: Vec<Row> = Vec::new();
+        let mut offset = 0;
+        let mut mate_ranges = Vec::with_capacity(mates.len());
+        let mut seq_lens = Vec::with_capacity(mates.len());
+        for mate in mates {
+            let cursor = Cursor::new(&self.meros);
+            let window = MinimizerWindow::new(self.meros.window_size());
+            let mut m_iter = MinimizerIterator::new(mate.as_ref(), cursor, window, &self.meros);
+            let mut next_offset = process_seq(
+                &mut rows,
+                &mut m_iter,
+                &self.hash_config,
+                &self.chtable,
+                self.meros.min_clear_hash_value,
+                offset,
+            );
+            let trailing = options
+                .trailing_window
+                .then(|| trailing_minimizer(mate.as_ref(), &self.meros, ScanOptions::default()));
+            if let Some(hash_key) = trailing.flatten() {
+                // Numbered after the minimizers of the iterator
+                let position = m_iter.size + 1;
+                lookup_minimizers(
+                    &mut rows,
+                    [(position, hash_key)],
+                    &self.hash_config,
+                    &self.chtable,
+                    self.meros.min_clear_hash_value,
+                    offset,
+                );
+                next_offset += 1;
+            }
+            let kmer_count = (m_iter.seq_size() + 1).saturating_sub(self.meros.k_mer);
+            mate_ranges.push(((offset, next_offset), kmer_count));
+            seq_lens.push(m_iter.seq_size().to_string());
+            offset = next_offset;
+        }
+
+        let hits = HitGroup::new(rows, OptionPair::Single((0, offset)));
+        let mut result = resolve_hits(
+            id.to_string(),
+            seq_lens.join("|"),
+            &hits,
+            self,
+            options,
+            classified,
+        );
+        result.hit_string = mates_hit_string(
+            &hits.rows,
+            &mate_ranges,
+            self.hash_config.value_mask,
+            &self.taxonomy,
+        );
+        result
+    }
+}
+
+/// The minimizers of one read, one line of a minimizer file.
+///
+/// Minimizer files are tab separated: the read ID, the sequence length ("len" or
+/// "len1|len2" for pairs), the space separated minimizer hashes of the first mate
+/// and, for pairs, those of the second mate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinimizerRecord {
+    pub id: String,
+    pub seq_size: String,
+    pub minimizers: OptionPair<Vec<u64>>,
+}
+
+impl MinimizerRecord {
+    /// Scans a read and collects its minimizer hashes
+    pub fn from_seq(id: &str, seq1: &[u8], seq2: Option<&[u8]>, meros: &Meros) -> Self {
+        let header = SeqHeader {
+            id: id.to_string(),
+            file_index: 0,
+            reads_index: 0,
+            format: SeqFormat::Fasta,
+        };
+        let seqs = match seq2 {
+            Some(seq2) => OptionPair::Pair(seq1.to_vec(), seq2.to_vec()),
+            None => OptionPair::Single(seq1.to_vec()),
+        };
+        let seq = Base::new(header, seqs);
+        let mut marker = scan_sequence(&seq, meros);
+        let seq_size = marker.fmt_seq_size();
+        let minimizers = marker
+            .body
+            .apply_mut(|m_iter| m_iter.map(|(_, hash_key)| hash_key).collect());
+        Self {
+            id: id.to_string(),
+            seq_size,
+            minimizers,
+        }
+    }
+
+    /// Parses one line of a minimizer file
+    pub fn parse(line: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Malformed minimizer line: {}", line),
+            )
+        };
+        let parse_hashes = |field: &str| -> Result<Vec<u64>> {
+            field
+                .split_whitespace()
+                .map(|hash| hash.parse::<u64>().map_err(|_| invalid()))
+                .collect()
+        };
+
+        let parts: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let minimizers = match parts.len() {
+            3 => OptionPair::Single(parse_hashes(parts[2])?),
+            4 => OptionPair::Pair(parse_hashes(parts[2])?, parse_hashes(parts[3])?),
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            id: parts[0].to_string(),
+            seq_size: parts[1].to_string(),
+            minimizers,
+        })
+    }
+}
+
+/// Formats the record as one line of a minimizer file (without newline)
+impl fmt::Display for MinimizerRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |minimizers: &Vec<u64>| {
+            minimizers
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        write!(
+            f,
+            "{}\t{}\t{}",
+            self.id,
+            self.seq_size,
+            self.minimizers.reduce_str("\t", join)
+        )
+    }
+}
+
+/// Reads all records of a minimizer file, see [`MinimizerRecord`]
+pub fn read_minimizer_file<P: AsRef<Path>>(filename: P) -> Result<Vec<MinimizerRecord>> {
+    let reader = BufReader::new(open_file(filename)?);
+    let mut records = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(MinimizerRecord::parse(&line)?);
+    }
+    Ok(records)
+}
+
+/// Classification parameters and queue sizing of a [`Classifier`]
+#[derive(Debug, Clone)]
+pub struct ClassifierOptions {
+    /// Confidence score threshold.
+    pub confidence_threshold: f64,
+    /// The minimum number of minimizers supporting a call, whatever the read length.
+    pub min_supporting_minimizers: u64,
+    /// The minimum number of hit groups needed for a call.
+    pub minimum_hit_groups: usize,
+    /// The number of non-matching minimizers allowed inside one hit group.
+    pub hit_group_gap: Option<usize>,
+    /// The report code of the highest rank a call may have.
+    pub max_rank: Option<char>,
+    /// Weigh every hit by the rank of its taxon.
+    pub weight_by_rank: bool,
+    /// Call the LCA of all the hit taxa, ignoring the thresholds.
+    pub lca_of_all: bool,
+    /// Whether a repeated minimizer scores at every position or once.
+    pub score_mode: ScoreMode,
+    /// The minimum coverage breadth of a call, see [`crate::classify::read_coverage`].
+    pub min_read_coverage: f64,
+    /// Look up the minimizer of a mate ending with an incomplete window, see
+    /// [`trailing_minimizer`]. [`Database::classify_mates`] only.
+    pub trailing_window: bool,
+    /// The number of worker threads.
+    pub num_threads: usize,
+    /// The maximum number of submitted reads waiting for a worker.
+    /// `submit` blocks while the queue is full.
+    pub queue_size: usize,
+    /// The maximum number of reads a worker takes from the queue at once.
+    pub batch_size: usize,
+}
+
+impl Default for ClassifierOptions {
+    fn default() -> Self {
+        Self {
+            confidence_threshold: 0.0,
+            min_supporting_minimizers: 0,
+            minimum_hit_groups: 2,
+            hit_group_gap: None,
+            max_rank: None,
+            weight_by_rank: false,
+            lca_of_all: false,
+            score_mode: ScoreMode::Positions,
+            min_read_coverage: 0.0,
+            trailing_window: false,
+            num_threads: num_cpus::get(),
+            queue_size: 8192,
+            batch_size: 256,
+        }
+    }
+}
+
+impl ClassifierOptions {
+    /// The options of [`process_hitgroup`] for a database with `value_mask`
+    pub fn hit_group_options(&self, value_mask: usize) -> HitGroupOptions {
+        HitGroupOptions {
+            minimum_hit_groups: self.minimum_hit_groups,
+            hit_group_gap: self.hit_group_gap,
+            max_rank: self.max_rank,
+            weight_by_rank: self.weight_by_rank,
+            lca_of_all: self.lca_of_all,
+            score_mode: self.score_mode,
+            min_read_coverage: self.min_read_coverage,
+            value_mask,
+        }
+    }
+
+    /// Queue sizing for reads classified as they arrive, e.g. during adaptive sequencing
+    ///
+    /// Workers take one read at a time, so a read never waits behind a batch, and `submit`
+    /// blocks once `max_in_flight` reads are waiting. At most `max_in_flight + num_threads`
+    /// reads are then in flight.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::classifier::{Classifier, ClassifierOptions, Database};
+    /// use kun_peng::compact_hash::{CHTable, HashConfig};
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use seqkmer::Meros;
+    /// use std::sync::Arc;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let hash_config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+    /// let chtable = CHTable { config: hash_config, pages: vec![] };
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// let meros = Meros::new(35, 31, None, None, None);
+    /// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+    ///
+    /// let options = ClassifierOptions::low_latency(4);
+    /// assert_eq!((options.queue_size, options.batch_size), (4, 1));
+    /// let (classifier, results) = Classifier::with_channel(db, options);
+    ///
+    /// // A trickle of reads: every read is answered long before the next one arrives.
+    /// let read = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGATTACGATCGA".to_vec();
+    /// for i in 0..20 {
+    ///     let submitted = Instant::now();
+    ///     classifier.submit(format!("read_{}", i), read.clone(), None).unwrap();
+    ///     let result = results.recv_timeout(Duration::from_secs(1)).unwrap();
+    ///     assert_eq!(result.id, format!("read_{}", i));
+    ///     assert!(submitted.elapsed() < Duration::from_millis(500));
+    ///     std::thread::sleep(Duration::from_millis(5));
+    /// }
+    /// classifier.finish().unwrap();
+    /// ```
+    pub fn low_latency(max_in_flight: usize) -> Self {
+        Self {
+            queue_size: max_in_flight,
+            batch_size: 1,
+            ..Default::default()
+        }
+    }
+}
+
+/// The classification of one submitted read
+///
+/// # Examples
+///
+/// Mates without a single valid k-mer report all their k-mers as unassigned:
+///
+/// ```
+/// use kun_peng::classifier::{Classifier, ClassifierOptions, Database};
+/// use kun_peng::compact_hash::{CHTable, HashConfig};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use seqkmer::Meros;
+/// use std::collections::HashMap;
+/// use std::sync::Arc;
+///
+/// let hash_config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+/// let chtable = CHTable { config: hash_config, pages: vec![] };
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// let meros = Meros::new(35, 31, None, None, None);
+/// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+///
+/// let (classifier, results) = Classifier::with_channel(db, ClassifierOptions::default());
+/// let mate = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGATTACGATCGA".to_vec();
+/// classifier.submit("all_n", vec![b'N'; 100], None).unwrap();
+/// classifier.submit("pair", mate.clone(), Some(vec![b'N'; 50])).unwrap();
+/// classifier.submit("short", vec![b'N'; 20], None).unwrap();
+/// classifier.finish().unwrap();
+///
+/// let lines: HashMap<String, String> =
+///     results.iter().map(|result| (result.id.clone(), result.to_string())).collect();
+/// assert_eq!(lines["all_n"], "U\tall_n\t0\t100\t0:66");
+/// assert_eq!(lines["short"], "U\tshort\t0\t20\t0:0");
+/// assert!(lines["pair"].starts_with("U\tpair\t0\t50|50\t"));
+/// assert!(lines["pair"].ends_with(" |:| 0:16"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClassifyResult {
+    pub id: String,
+    pub classified: bool,
+    /// External taxonomy ID of the call, 0 if unclassified
+    pub taxid: u64,
+    /// Sequence length, "len1|len2" for pairs
+    pub seq_size: String,
+    /// Minimizer hits in the Kraken 2 output format
+    pub hit_string: String,
+    /// Fraction of the read segments with a hit in the called clade, 0 if unclassified
+    pub read_coverage: f64,
+}
+
+impl ClassifyResult {
+    /// The standard Kraken output line of the result, more columns can be appended
+    pub fn kraken_line(&self) -> KrakenLine<'_> {
+        KrakenLine::new(self.classified, &self.id, self.taxid)
+            .seq_size(&self.seq_size)
+            .hit_string(&self.hit_string)
+    }
+}
+
+/// Formats the result as one line of the standard Kraken output (without newline)
+impl fmt::Display for ClassifyResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kraken_line())
+    }
+}
+
+/// Upper bounds of the request latency buckets in microseconds, the last bucket is unbounded
+const LATENCY_BUCKETS_US: [u64; 16] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 10_000_000,
+];
+
+/// Counters of a [`Classifier`], updated by its workers and readable at any time
+///
+/// Every update is a single relaxed atomic add, the latencies from `submit` to the
+/// result go to a fixed histogram. See [`ClassifierMetrics::prometheus_text`] for the
+/// exported metrics.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classifier::{Classifier, ClassifierOptions, Database};
+/// use kun_peng::compact_hash::{CHTable, HashConfig};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use seqkmer::Meros;
+/// use std::sync::Arc;
+///
+/// let hash_config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+/// let chtable = CHTable { config: hash_config, pages: vec![] };
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// let meros = Meros::new(35, 31, None, None, None);
+/// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+///
+/// let (classifier, results) = Classifier::with_channel(db, ClassifierOptions::default());
+/// let metrics = classifier.metrics();
+/// for i in 0..100 {
+///     classifier.submit(format!("read_{}", i), b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGA".to_vec(), None).unwrap();
+/// }
+/// classifier.finish().unwrap();
+/// assert_eq!(results.iter().count(), 100);
+///
+/// assert_eq!(metrics.reads_submitted(), 100);
+/// assert_eq!(metrics.reads_processed(), 100);
+/// assert_eq!((metrics.reads_classified(), metrics.errors(), metrics.queue_depth()), (0, 0, 0));
+/// assert!(metrics.latency_quantile(0.5) <= metrics.latency_quantile(0.99));
+///
+/// let text = metrics.prometheus_text();
+/// assert!(text.contains("# TYPE kun_peng_reads_processed_total counter\nkun_peng_reads_processed_total 100\n"));
+/// assert!(text.contains("kun_peng_request_latency_seconds_bucket{le=\"+Inf\"} 100\n"));
+/// assert!(text.contains("kun_peng_request_latency_quantile_seconds{quantile=\"0.99\"} "));
+/// ```
+#[derive(Debug, Default)]
+pub struct ClassifierMetrics {
+    submitted: AtomicU64,
+    dequeued: AtomicU64,
+    processed: AtomicU64,
+    classified: AtomicUsize,
+    errors: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS_US.len() + 1],
+    latency_sum_us: AtomicU64,
+}
+
+impl ClassifierMetrics {
+    /// Reads accepted by `submit`
+    pub fn reads_submitted(&self) -> u64 {
+        self.submitted.load(Ordering::Relaxed)
+    }
+
+    /// Reads whose result was delivered
+    pub fn reads_processed(&self) -> u64 {
+        self.processed.load(Ordering::Relaxed)
+    }
+
+    /// Reads with a call
+    pub fn reads_classified(&self) -> u64 {
+        self.classified.load(Ordering::SeqCst) as u64
+    }
+
+    /// Reads refused by `submit` because the workers had stopped
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Reads waiting in the queue for a worker
+    pub fn queue_depth(&self) -> u64 {
+        self.reads_submitted()
+            .saturating_sub(self.dequeued.load(Ordering::Relaxed))
+    }
+
+    /// Estimates the `q` quantile of the request latencies in seconds
+    ///
+    /// The estimate is the upper bound of the histogram bucket holding the quantile,
+    /// 0 without any request and infinite beyond the last bound.
+    pub fn latency_quantile(&self, q: f64) -> f64 {
+        let counts: Vec<u64> = self
+            .latency_buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let rank = (q * total as f64).ceil().max(1.0) as u64;
+        let mut cumulative = 0;
+        for (i, count) in counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank {
+                return LATENCY_BUCKETS_US
+                    .get(i)
+                    .map_or(f64::INFINITY, |&bound| bound as f64 / 1e6);
+            }
+        }
+        f64::INFINITY
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        let bucket = LATENCY_BUCKETS_US.partition_point(|&bound| bound < micros);
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_us.fetch_add(micros, Ordering::Relaxed);
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Formats the metrics in the Prometheus text exposition format
+    pub fn prometheus_text(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            text.push_str(&format!(
+                "# HELP {0} {1}\n# TYPE {0} {2}\n{0} {3}\n",
+                name, help, kind, value
+            ));
+        };
+        metric(
+            "kun_peng_reads_submitted_total",
+            "counter",
+            "Reads submitted for classification.",
+            self.reads_submitted().to_string(),
+        );
+        metric(
+            "kun_peng_reads_processed_total",
+            "counter",
+            "Reads whose classification was delivered.",
+            self.reads_processed().to_string(),
+        );
+        metric(
+            "kun_peng_reads_classified_total",
+            "counter",
+            "Reads with a call.",
+            self.reads_classified().to_string(),
+        );
+        metric(
+            "kun_peng_errors_total",
+            "counter",
+            "Reads refused because the workers had stopped.",
+            self.errors().to_string(),
+        );
+        metric(
+            "kun_peng_queue_depth",
+            "gauge",
+            "Reads waiting for a worker.",
+            self.queue_depth().to_string(),
+        );
+        let processed = self.reads_processed();
+        let fraction = if processed > 0 {
+            self.reads_classified() as f64 / processed as f64
+        } else {
+            0.0
+        };
+        metric(
+            "kun_peng_classified_fraction",
+            "gauge",
+            "Fraction of the processed reads with a call.",
+            fraction.to_string(),
+        );
+
+        let name = "kun_peng_request_latency_seconds";
+        text.push_str(&format!(
+            "# HELP {0} Time from submit to result.\n# TYPE {0} histogram\n",
+            name
+        ));
+        let mut cumulative = 0;
+        for (i, count) in self.latency_buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = LATENCY_BUCKETS_US
+                .get(i)
+                .map_or("+Inf".to_string(), |&bound| {
+                    (bound as f64 / 1e6).to_string()
+                });
+            text.push_str(&format!(
+                "{}_bucket{{le=\"{}\"}} {}\n",
+                name, le, cumulative
+            ));
+        }
+        let sum = self.latency_sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        text.push_str(&format!(
+            "{0}_sum {1}\n{0}_count {2}\n",
+            name, sum, cumulative
+        ));
+
+        let name = "kun_peng_request_latency_quantile_seconds";
+        text.push_str(&format!(
+            "# HELP {0} Request latency quantiles, upper bounds of their histogram buckets.\n\
+             # TYPE {0} gauge\n",
+            name
+        ));
+        for q in ["0.5", "0.99"] {
+            let value = self.latency_quantile(q.parse().unwrap());
+            let value = if value.is_finite() {
+                value.to_string()
+            } else {
+                "+Inf".to_string()
+            };
+            text.push_str(&format!("{}{{quantile=\"{}\"}} {}\n", name, q, value));
+        }
+        text
+    }
+}
+
+type Callback = Arc<dyn Fn(ClassifyResult) + Send + Sync>;
+
+struct Request {
+    id: String,
+    mates: Vec<Vec<u8>>,
+    submitted: Instant,
+}
+
+/// Push-style classification of reads with a bounded queue.
+///
+/// # Threading contract
+///
+/// * `submit` takes `&self`, so a `Classifier` can be shared by reference (or in an `Arc`)
+///   between any number of producer threads.
+/// * Submitted reads are classified by `num_threads` worker threads. Results are delivered
+///   exactly once each, on a worker thread, in no particular order.
+///   The callback must therefore be `Send + Sync` and should return quickly.
+/// * When `queue_size` reads are waiting, `submit` blocks until a worker takes a batch.
+/// * [`Classifier::finish`] stops accepting reads, waits until all outstanding reads are
+///   classified and joins the workers. A worker that panicked, in the callback for
+///   instance, is returned as an error. Dropping the classifier does the same but can only
+///   log that error to stderr.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classifier::{Classifier, ClassifierOptions, Database};
+/// use kun_peng::compact_hash::{CHTable, HashConfig};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use seqkmer::Meros;
+/// use std::collections::HashSet;
+/// use std::sync::Arc;
+///
+/// // An empty database: every read ends up unclassified.
+/// let hash_config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+/// let chtable = CHTable { config: hash_config, pages: vec![] };
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// let meros = Meros::new(35, 31, None, None, None);
+/// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+///
+/// let options = ClassifierOptions { num_threads: 4, queue_size: 1024, ..Default::default() };
+/// let (classifier, results) = Classifier::with_channel(db, options);
+///
+/// std::thread::scope(|s| {
+///     for producer in 0..4 {
+///         let classifier = &classifier;
+///         s.spawn(move || {
+///             for i in 0..25_000 {
+///                 let id = format!("{}_{}", producer, i);
+///                 classifier.submit(id, b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGA".to_vec(), None).unwrap();
+///             }
+///         });
+///     }
+/// });
+/// classifier.finish().unwrap();
+///
+/// let mut seen = HashSet::new();
+/// for result in results {
+///     assert!(!result.classified);
+///     assert!(seen.insert(result.id));
+/// }
+/// assert_eq!(seen.len(), 100_000);
+/// ```
+pub struct Classifier {
+    sender: Option<SyncSender<Request>>,
+    workers: Vec<JoinHandle<()>>,
+    metrics: Arc<ClassifierMetrics>,
+}
+
+impl Classifier {
+    /// Starts the workers, every result is passed to `callback`
+    pub fn new<F>(db: Arc<Database>, options: ClassifierOptions, callback: F) -> Self
+    where
+        F: Fn(ClassifyResult) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel::<Request>(options.queue_size.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        let callback: Callback = Arc::new(callback);
+        let metrics = Arc::new(ClassifierMetrics::default());
+
+        let workers = (0..options.num_threads.max(1))
+            .map(|_| {
+                let receiver = receiver.clone();
+                let db = db.clone();
+                let options = options.clone();
+                let callback = callback.clone();
+                let metrics = metrics.clone();
+                std::thread::spawn(move || {
+                    while let Some(batch) = next_batch(&receiver, options.batch_size.max(1)) {
+                        metrics
+                            .dequeued
+                            .fetch_add(batch.len() as u64, Ordering::Relaxed);
+                        for request in batch {
+                            let submitted = request.submitted;
+                            let result =
+                                classify_request(request, &db, &options, &metrics.classified);
+                            callback(result);
+                            metrics.record_latency(submitted.elapsed());
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+            metrics,
+        }
+    }
+
+    /// Starts the workers and returns a channel that receives every result.
+    ///
+    /// The channel is closed once the classifier is dropped and all reads are delivered.
+    pub fn with_channel(
+        db: Arc<Database>,
+        options: ClassifierOptions,
+    ) -> (Self, Receiver<ClassifyResult>) {
+        let (sender, receiver) = mpsc::channel();
+        let classifier = Self::new(db, options, move |result| {
+            // The receiver may be gone, the result is then of no interest.
+            let _ = sender.send(result);
+        });
+        (classifier, receiver)
+    }
+
+    /// Queues a read for classification, blocking while the queue is full
+    pub fn submit<S: Into<String>>(
+        &self,
+        id: S,
+        seq1: Vec<u8>,
+        seq2: Option<Vec<u8>>,
+    ) -> Result<()> {
+        let mut mates = vec![seq1];
+        mates.extend(seq2);
+        self.submit_mates(id, mates)
+    }
+
+    /// Queues a read made of any number of mates, see [`Database::classify_mates`]
+    pub fn submit_mates<S: Into<String>>(&self, id: S, mates: Vec<Vec<u8>>) -> Result<()> {
+        let request = Request {
+            id: id.into(),
+            mates,
+            submitted: Instant::now(),
+        };
+        let sent = self
+            .sender
+            .as_ref()
+            .expect("classifier is finished")
+            .send(request);
+        if sent.is_err() {
+            self.metrics.errors.fetch_add(1, Ordering::Relaxed);
+            return Err(Error::new(
+                ErrorKind::BrokenPipe,
+                "classifier workers have stopped",
+            ));
+        }
+        self.metrics.submitted.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Number of reads classified so far
+    pub fn classified_count(&self) -> usize {
+        self.metrics.classified.load(Ordering::SeqCst)
+    }
+
+    /// The counters of the classifier, they stay readable after it is finished
+    pub fn metrics(&self) -> Arc<ClassifierMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Waits for all submitted reads to be classified and joins the workers
+    ///
+    /// Fails if a worker panicked: the reads of its batch may not have been delivered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::classifier::{Classifier, ClassifierOptions, Database};
+    /// use kun_peng::compact_hash::{CHTable, HashConfig};
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use seqkmer::Meros;
+    /// use std::sync::Arc;
+    ///
+    /// let hash_config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+    /// let chtable = CHTable { config: hash_config, pages: vec![] };
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// let meros = Meros::new(35, 31, None, None, None);
+    /// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+    ///
+    /// let options = ClassifierOptions { num_threads: 2, ..Default::default() };
+    /// let classifier = Classifier::new(db, options, |result| {
+    ///     assert_ne!(result.id, "bad", "no room left for {}", result.id);
+    /// });
+    /// for id in ["good", "bad"] {
+    ///     classifier.submit(id, b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGA".to_vec(), None).unwrap();
+    /// }
+    /// let err = classifier.finish().unwrap_err();
+    /// assert!(err.to_string().contains("no room left for bad"), "{}", err);
+    /// ```
+    pub fn finish(mut self) -> Result<()> {
+        self.join_workers()
+    }
+
+    fn join_workers(&mut self) -> Result<()> {
+        // Closing the queue lets the workers exit once it is drained.
+        self.sender.take();
+        let panics: Vec<String> = self
+            .workers
+            .drain(..)
+            .filter_map(|worker| worker.join().err())
+            .map(|payload| match payload.downcast::<String>() {
+                Ok(message) => *message,
+                Err(payload) => payload
+                    .downcast_ref::<&str>()
+                    .map_or("unknown panic".to_string(), |message| message.to_string()),
+            })
+            .collect();
+        if panics.is_empty() {
+            return Ok(());
+        }
+        Err(Error::other(format!(
+            "{} classifier worker(s) panicked: {}",
+            panics.len(),
+            panics.join("; ")
+        )))
+    }
+}
+
+impl Drop for Classifier {
+    fn drop(&mut self) {
+        // A classifier that was not finished still has its workers
+        if let Err(e) = self.join_workers() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn next_batch(receiver: &Mutex<Receiver<Request>>, batch_size: usize) -> Option<Vec<Request>> {
+    let receiver = receiver.lock().unwrap();
+    let first = receiver.recv().ok()?;
+    let mut batch = Vec::with_capacity(batch_size);
+    batch.push(first);
+    while batch.len() < batch_size {
+        match receiver.try_recv() {
+            Ok(request) => batch.push(request),
+            Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+fn classify_request(
+    request: Request,
+    db: &Database,
+    options: &ClassifierOptions,
+    classified: &AtomicUsize,
+) -> ClassifyResult {
+    let mut mates = request.mates;
+    let seqs = match mates.len() {
+        1 => OptionPair::Single(mates.pop().unwrap()),
+        2 => {
+            let seq2 = mates.pop().unwrap();
+            OptionPair::Pair(mates.pop().unwrap(), seq2)
+        }
+        _ => return db.classify_mates(&request.id, &mates, options, classified),
+    };
+    let header = SeqHeader {
+        id: request.id,
+        file_index: 0,
+        reads_index: 0,
+        format: SeqFormat::Fasta,
+    };
+    let seq = Base::new(header, seqs);
+    let mut marker = scan_sequence(&seq, &db.meros);
+    let rows: Vec<Row> = marker.fold(|rows, m_iter, offset| {
+        let min_clear = db.meros.min_clear_hash_value;
+        process_seq(
+            rows,
+            m_iter,
+            &db.hash_config,
+            &db.chtable,
+            min_clear,
+            offset,
+        )
+    });
+    let seq_lens = marker.body.apply(|m_iter| m_iter.seq_size());
+    let hits = HitGroup::new(rows, marker.range()).with_seq_lens(seq_lens, db.meros.k_mer);
+    let id = marker.header.id.clone();
+    resolve_hits(id, marker.fmt_seq_size(), &hits, db, options, classified)
+}
+
+fn resolve_hits(
+    id: String,
+    seq_size: String,
+    hits: &HitGroup,
+    db: &Database,
+    options: &ClassifierOptions,
+    classified: &AtomicUsize,
+) -> ClassifyResult {
+    let required_score = hits.required_score_with_floor(
+        options.confidence_threshold,
+        options.min_supporting_minimizers,
+    );
+    let call = process_hitgroup(
+        hits,
+        &db.taxonomy,
+        classified,
+        required_score,
+        &options.hit_group_options(db.hash_config.value_mask),
+    );
+
+    ClassifyResult {
+        id,
+        classified: call.classified,
+        taxid: call.taxid,
+        seq_size,
+        hit_string: hit_string(hits, db.hash_config.value_mask, &db.taxonomy),
+        read_coverage: call.read_coverage,
+    }
+}
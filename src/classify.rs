@@ -1,10 +1,170 @@
-use crate::compact_hash::Compact;
+use crate::compact_hash::{CHTable, Compact, HashConfig, Row};
 use crate::readcounts::TaxonCounters;
-use crate::taxonomy::Taxonomy;
+use crate::taxonomy::{Taxonomy, MAX_RANK_WEIGHT};
 use crate::HitGroup;
-use seqkmer::SpaceDist;
-use std::collections::HashMap;
+use seqkmer::{Meros, MinimizerIterator, OptionPair, SpaceDist};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// Looks up minimizer hashes in a fully loaded hash table.
+///
+/// `minimizers` yields positions, as reported by `MinimizerIterator`, with their hash keys. A `Row` is pushed
+/// for every minimizer with a taxon, `offset` is added to the position so that
+/// the second mate continues the numbering of the first.
+///
+/// Minimizers hashing below `min_clear_hash_value` are not looked up, the build leaves
+/// them out of the index, and `MinimizerIterator` doesn't filter them. Their positions
+/// still count.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classify::lookup_minimizers;
+/// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+///
+/// let hash_config = HashConfig::new(1, 4096, 16, 0, 1, 4096);
+/// let mut page = Page::with_capacity(0, 4096);
+/// let (low, high) = (0x1000_0000_0000_0000u64, 0xf000_0000_0000_0000u64);
+/// for hash_key in [low, high] {
+///     let slot = hash_config.slot(hash_key, 1);
+///     let idx = (slot.idx..).find(|&i| page.data[i] == 0).unwrap();
+///     page.data[idx] = slot.value;
+/// }
+/// let chtable = CHTable { config: hash_config, pages: vec![page] };
+///
+/// let lookup = |min_clear| {
+///     let mut rows = Vec::new();
+///     lookup_minimizers(&mut rows, [(1, low), (2, high)], &hash_config, &chtable, min_clear, 0);
+///     rows.iter().map(|row| row.kmer_id).collect::<Vec<_>>()
+/// };
+/// assert_eq!(lookup(None), [2, 3]);
+/// // The build leaves out the hashes below the threshold, so do the reads
+/// assert_eq!(lookup(Some(u64::MAX / 2)), [3]);
+/// ```
+pub fn lookup_minimizers<I>(
+    rows: &mut Vec<Row>,
+    minimizers: I,
+    hash_config: &HashConfig,
+    chtable: &CHTable,
+    min_clear_hash_value: Option<u64>,
+    offset: usize,
+) where
+    I: IntoIterator<Item = (usize, u64)>,
+{
+    let chunk_size = hash_config.hash_capacity;
+    let value_bits = hash_config.value_bits;
+    for (sort, hash_key) in minimizers {
+        if min_clear_hash_value.is_some_and(|min_clear| hash_key < min_clear) {
+            continue;
+        }
+        let (idx, compacted) = hash_config.compact(hash_key);
+        let partition_index = idx / chunk_size;
+        let index = idx % chunk_size;
+
+        let taxid = chtable.get_from_page(index, compacted, partition_index);
+        if taxid > 0 {
+            let high = u32::combined(compacted, taxid, value_bits);
+            let row = Row::new(high, 0, Row::kmer_id_of(sort + 1 + offset));
+            rows.push(row);
+        }
+    }
+}
+
+/// Scans one mate and looks up its minimizers, see [`lookup_minimizers`].
+///
+/// # Returns
+///
+/// Returns the offset for the next mate.
+pub fn process_seq(
+    rows: &mut Vec<Row>,
+    m_iter: &mut MinimizerIterator,
+    hash_config: &HashConfig,
+    chtable: &CHTable,
+    min_clear_hash_value: Option<u64>,
+    offset: usize,
+) -> usize {
+    let data: Vec<(usize, u64)> = m_iter.collect();
+    lookup_minimizers(
+        rows,
+        data,
+        hash_config,
+        chtable,
+        min_clear_hash_value,
+        offset,
+    );
+    m_iter.size + offset
+}
+
+/// Approximate number of minimizers, and so of hash lookups, of a read
+///
+/// A window of `k - l + 1` l-mers yields a new minimizer every `(k - l + 2) / 2` k-mers on
+/// random sequence. Minimizers hashing below `min_clear_hash_value` are not looked up.
+/// Cheap enough to bin the reads by cost before scanning them.
+///
+/// # Examples
+///
+/// The estimate follows the minimizer counts of reads from 50 bp to 20 kbp:
+///
+/// ```
+/// use kun_peng::classify::estimate_cost;
+/// use seqkmer::{Cursor, Meros, MinimizerIterator, MinimizerWindow, OptionPair, DEFAULT_TOGGLE_MASK};
+///
+/// let meros = Meros::new(35, 31, None, Some(DEFAULT_TOGGLE_MASK), None);
+/// let mut state = 0x9e3779b97f4a7c15u64;
+/// let mut pairs = Vec::new();
+/// for len in [50, 100, 150, 300, 1000, 2500, 5000, 10000, 20000] {
+///     let seq: Vec<u8> = (0..len)
+///         .map(|_| {
+///             state ^= state << 13;
+///             state ^= state >> 7;
+///             state ^= state << 17;
+///             b"ACGT"[(state % 4) as usize]
+///         })
+///         .collect();
+///     let cursor = Cursor::new(&meros);
+///     let window = MinimizerWindow::new(meros.window_size());
+///     let actual = MinimizerIterator::new(&seq, cursor, window, &meros).count() as f64;
+///     let estimate = estimate_cost(&OptionPair::Single(seq), &meros) as f64;
+///     assert!(estimate <= 2.0 * actual && actual <= 2.0 * estimate);
+///     pairs.push((estimate, actual));
+/// }
+///
+/// // Pearson correlation
+/// let n = pairs.len() as f64;
+/// let (mean_e, mean_a) = (
+///     pairs.iter().map(|p| p.0).sum::<f64>() / n,
+///     pairs.iter().map(|p| p.1).sum::<f64>() / n,
+/// );
+/// let cov: f64 = pairs.iter().map(|(e, a)| (e - mean_e) * (a - mean_a)).sum();
+/// let var_e: f64 = pairs.iter().map(|(e, _)| (e - mean_e).powi(2)).sum();
+/// let var_a: f64 = pairs.iter().map(|(_, a)| (a - mean_a).powi(2)).sum();
+/// assert!(cov / (var_e * var_a).sqrt() > 0.99);
+///
+/// // Shorter than k, nothing to look up
+/// assert_eq!(estimate_cost(&OptionPair::Single(vec![b'A'; 20]), &meros), 0);
+/// ```
+pub fn estimate_cost(seqs: &OptionPair<Vec<u8>>, meros: &Meros) -> usize {
+    let window = meros.window_size();
+    let mate_cost = |seq: &Vec<u8>| {
+        let kmers = (seq.len() + 1).saturating_sub(meros.k_mer);
+        if kmers == 0 {
+            return 0;
+        }
+        let minimizers = (2 * kmers / (window + 1) + 1).min(kmers);
+        match meros.min_clear_hash_value {
+            Some(min_clear) => {
+                (minimizers as u128 * (u64::MAX - min_clear) as u128 / u64::MAX as u128) as usize
+            }
+            None => minimizers,
+        }
+    };
+    match seqs {
+        OptionPair::Single(seq) => mate_cost(seq),
+        OptionPair::Pair(seq1, seq2) => mate_cost(seq1) + mate_cost(seq2),
+    }
+}
 
 /// Resolves the taxonomic classification based on hit counts and taxonomy.
 ///
@@ -25,13 +185,25 @@ pub fn resolve_tree(
     taxonomy: &Taxonomy,
     required_score: u64,
 ) -> u32 {
+    resolve_counts(
+        hit_counts.iter().map(|(&taxon, &count)| (taxon, count)),
+        taxonomy,
+        required_score,
+    )
+}
+
+/// [`resolve_tree`] over any re-iterable set of distinct `(taxon, count)` pairs
+fn resolve_counts<I>(hit_counts: I, taxonomy: &Taxonomy, required_score: u64) -> u32
+where
+    I: Iterator<Item = (u32, u64)> + Clone,
+{
     let mut max_taxon = 0u32;
     let mut max_score = 0;
 
-    for (&taxon, _) in hit_counts {
+    for (taxon, _) in hit_counts.clone() {
         let mut score = 0;
 
-        for (&taxon2, &count2) in hit_counts {
+        for (taxon2, count2) in hit_counts.clone() {
             if taxonomy.is_a_ancestor_of_b(taxon2, taxon) {
                 score += count2;
             }
@@ -45,66 +217,631 @@ pub fn resolve_tree(
         }
     }
 
-    max_score = *hit_counts.get(&max_taxon).unwrap_or(&0);
+    max_score = hit_counts
+        .clone()
+        .find(|&(taxon, _)| taxon == max_taxon)
+        .map_or(0, |(_, count)| count);
 
     while max_taxon != 0 && max_score < required_score {
         max_score = hit_counts
-            .iter()
-            .filter(|(&taxon, _)| taxonomy.is_a_ancestor_of_b(max_taxon, taxon))
-            .map(|(_, &count)| count)
+            .clone()
+            .filter(|&(taxon, _)| taxonomy.is_a_ancestor_of_b(max_taxon, taxon))
+            .map(|(_, count)| count)
             .sum();
 
         if max_score >= required_score {
             break;
         }
-        max_taxon = taxonomy.nodes[max_taxon as usize].parent_id as u32;
+        max_taxon = taxonomy
+            .node(max_taxon)
+            .map_or(0, |node| node.parent_id as u32);
     }
 
     max_taxon
 }
 
+/// Number of hits inside the clade rooted at `call`
+fn clade_support<I>(hit_counts: I, call: u32, taxonomy: &Taxonomy) -> u64
+where
+    I: Iterator<Item = (u32, u64)>,
+{
+    hit_counts
+        .filter(|&(taxon, _)| taxon == call || taxonomy.is_a_ancestor_of_b(call, taxon))
+        .map(|(_, count)| count)
+        .sum()
+}
+
+/// Number of segments of a read told apart by [`read_coverage`]
+pub const READ_COVERAGE_BINS: usize = 10;
+
+/// Coverage breadth of a call, the fraction of the read segments with a hit in its clade
+///
+/// The minimizer positions of the read, over all its mates, are cut into
+/// [`READ_COVERAGE_BINS`] segments, one per position for shorter reads. Two reads with as many
+/// hits have the same confidence, but hits clustered in a repeat fill few segments while
+/// hits spread along the read fill most of them. 0 for an unclassified read.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classify::{process_hitgroup, read_coverage, HitGroupOptions};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 562, parent_id: 0, ..Default::default() });
+/// taxonomy.path_cache.insert(1, vec![1]);
+///
+/// // 12 of the 100 minimizers of a read hit E. coli, spread along it or clustered
+/// let read = |positions: Vec<u32>, len: usize| {
+///     let rows = positions.into_iter().map(|i| Row::new(u32::combined(7, 1, 16), 0, i));
+///     HitGroup::new(rows.collect(), OptionPair::Single((0, len)))
+/// };
+/// let spread = read((0..12).map(|i| 1 + i * 9).collect(), 100);
+/// let clustered = read((41..53).collect(), 100);
+/// assert_eq!(read_coverage(&spread, 1, &taxonomy, 0xFFFF), 1.0);
+/// assert_eq!(read_coverage(&clustered, 1, &taxonomy, 0xFFFF), 0.2);
+/// assert_eq!(read_coverage(&clustered, 0, &taxonomy, 0xFFFF), 0.0);
+///
+/// // Both have the same confidence, only the spread one passes a minimum coverage of 0.3
+/// let counter = AtomicUsize::new(0);
+/// let call = |hits: &HitGroup, min_read_coverage| {
+///     let options = HitGroupOptions {
+///         minimum_hit_groups: 0,
+///         min_read_coverage,
+///         ..HitGroupOptions::new(0xFFFF)
+///     };
+///     let read = process_hitgroup(hits, &taxonomy, &counter, 0, &options);
+///     (read.taxid, read.confidence, read.read_coverage)
+/// };
+/// assert_eq!(call(&spread, 0.0), (562, 0.12, 1.0));
+/// assert_eq!(call(&clustered, 0.0), (562, 0.12, 0.2));
+/// assert_eq!(call(&spread, 0.3), (562, 0.12, 1.0));
+/// assert_eq!(call(&clustered, 0.3), (0, 0.0, 0.0));
+///
+/// // Reads shorter than the bins count every minimizer position
+/// assert_eq!(read_coverage(&read(vec![1, 2], 4), 1, &taxonomy, 0xFFFF), 0.5);
+/// ```
+pub fn read_coverage(hits: &HitGroup, call: u32, taxonomy: &Taxonomy, value_mask: usize) -> f64 {
+    let capacity = hits.capacity();
+    if call == 0 || capacity == 0 {
+        return 0.0;
+    }
+    let bins = READ_COVERAGE_BINS.min(capacity);
+    let mut covered = [false; READ_COVERAGE_BINS];
+    for row in &hits.rows {
+        let taxon = row.value.right(value_mask);
+        if taxon == call || taxonomy.is_a_ancestor_of_b(call, taxon) {
+            let position = (row.kmer_id as usize).saturating_sub(1).min(capacity - 1);
+            covered[position * bins / capacity] = true;
+        }
+    }
+    covered.iter().filter(|&&bin| bin).count() as f64 / bins as f64
+}
+
+/// The LCA of all the hit taxa, 0 without any
+fn lca_of_taxa<I: Iterator<Item = u32>>(taxa: I, taxonomy: &Taxonomy) -> u32 {
+    taxa.fold(0, |lca, taxon| taxonomy.lca(lca, taxon))
+}
+
+/// How the hits of a read add up to the score of a taxon
+///
+/// # Examples
+///
+/// A read with a tandem repeat: its one minimizer of E. albertii comes back five times,
+/// three other minimizers hit E. coli once each. Counting positions calls E. albertii,
+/// counting distinct minimizers E. coli.
+///
+/// ```
+/// use kun_peng::classify::{process_hitgroup, HitGroupOptions, ScoreMode};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// // root -> Escherichia -> {E. coli, E. albertii}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id) in [(1, 1), (561, 1), (562, 2), (208962, 2)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 2, 3]), (4, vec![1, 2, 4])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// // (minimizer, taxon) of every position
+/// let positions = [(9, 4), (5, 3), (9, 4), (6, 3), (9, 4), (7, 3), (9, 4), (9, 4)];
+/// let rows = positions.iter().enumerate();
+/// let rows = rows.map(|(i, &(minimizer, taxid))| {
+///     Row::new(u32::combined(minimizer, taxid, 16), 0, i as u32 + 1)
+/// });
+/// let hits = HitGroup::new(rows.collect(), OptionPair::Single((0, positions.len())));
+/// let counter = AtomicUsize::new(0);
+/// let call = |score_mode| {
+///     let options = HitGroupOptions {
+///         minimum_hit_groups: 0,
+///         score_mode,
+///         ..HitGroupOptions::new(0xFFFF)
+///     };
+///     let read = process_hitgroup(&hits, &taxonomy, &counter, 3, &options);
+///     (read.taxid, read.confidence)
+/// };
+/// assert_eq!(call(ScoreMode::Positions), (208962, 0.625));
+/// assert_eq!(call(ScoreMode::Distinct), (562, 0.375));
+///
+/// assert_eq!("distinct".parse(), Ok(ScoreMode::Distinct));
+/// assert!("unique".parse::<ScoreMode>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScoreMode {
+    /// Every position hitting a taxon counts, as Kraken 2 does
+    #[default]
+    Positions,
+    /// A minimizer counts once per taxon and read, however often it repeats, so
+    /// low-complexity stretches that survive masking weigh less
+    Distinct,
+}
+
+impl ScoreMode {
+    /// Whether a hit adds to the score, `seen` holds the hits of the read so far
+    #[inline]
+    fn counts(self, seen: &mut HashSet<u32>, row: &Row) -> bool {
+        match self {
+            Self::Positions => true,
+            // The value is the compacted minimizer and the taxon
+            Self::Distinct => seen.insert(row.value),
+        }
+    }
+}
+
+impl FromStr for ScoreMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "positions" => Ok(Self::Positions),
+            "distinct" => Ok(Self::Distinct),
+            _ => Err(format!("expected `positions` or `distinct`, got `{}`", s)),
+        }
+    }
+}
+
+/// Counts the hits of each taxon if the read hits at most two distinct taxa
+///
+/// Deep amplicon runs are dominated by such reads, they are resolved without a `HashMap`.
+fn count_few_taxa(
+    hits: &HitGroup,
+    value_mask: usize,
+    score_mode: ScoreMode,
+) -> Option<([(u32, u64); 2], usize)> {
+    let mut taxa = [(0u32, 0u64); 2];
+    let mut len = 0;
+    let mut seen = HashSet::new();
+    for row in &hits.rows {
+        let key = row.value.right(value_mask);
+        match taxa[..len].iter_mut().find(|(taxon, _)| *taxon == key) {
+            Some((_, count)) => {
+                if score_mode.counts(&mut seen, row) {
+                    *count += 1;
+                }
+            }
+            None if len < 2 => {
+                score_mode.counts(&mut seen, row);
+                taxa[len] = (key, 1);
+                len += 1;
+            }
+            None => return None,
+        }
+    }
+    Some((taxa, len))
+}
+
 /// Processes hit statistics for a group of hits.
 ///
-/// This function calculates various statistics for a group of hits, including
-/// updating hit counts, taxon counters, and computing space distribution.
+/// This function updates the hit counts and taxon counters for every hit.
 ///
 /// # Arguments
 ///
 /// * `hits` - The HitGroup to process.
 /// * `counts` - A mutable reference to a HashMap to store hit counts.
 /// * `value_mask` - A mask used for processing hit values.
-/// * `taxonomy` - The Taxonomy object representing the taxonomic hierarchy.
+/// * `score_mode` - Whether repeats of a minimizer add to `counts`.
 /// * `cur_taxon_counts` - A mutable reference to TaxonCounters to update.
-///
-/// # Returns
-///
-/// Returns a String representing the space distribution of the hits.
-fn stat_hits<'a>(
+fn stat_hits(
     hits: &HitGroup,
     counts: &mut HashMap<u32, u64>,
     value_mask: usize,
-    taxonomy: &Taxonomy,
+    score_mode: ScoreMode,
     cur_taxon_counts: &mut TaxonCounters,
-) -> String {
-    let mut space_dist = hits.range.apply(|range| SpaceDist::new(*range));
+) {
+    let mut seen = HashSet::new();
     for row in &hits.rows {
         let value = row.value;
         let key = value.right(value_mask);
 
-        *counts.entry(key).or_insert(0) += 1;
+        if score_mode.counts(&mut seen, row) {
+            *counts.entry(key).or_insert(0) += 1;
+        }
 
         cur_taxon_counts
             .entry(key as u64)
             .or_default()
             .add_kmer(value as u64);
+    }
+}
+
+/// The minimizer range and the k-mer count of every mate of a group of hits
+fn hit_mates(hits: &HitGroup) -> Vec<((usize, usize), usize)> {
+    match (&hits.range, &hits.kmer_counts) {
+        (OptionPair::Pair(range1, range2), OptionPair::Pair(count1, count2)) => {
+            vec![(*range1, *count1), (*range2, *count2)]
+        }
+        (OptionPair::Single(range), OptionPair::Single(count)) => vec![(*range, *count)],
+        (range, _) => range.reduce(Vec::new(), |mut mates, range| {
+            mates.push((*range, 0));
+            mates
+        }),
+    }
+}
+
+/// Builds the space distribution string of a group of hits.
+pub fn hit_string(hits: &HitGroup, value_mask: usize, taxonomy: &Taxonomy) -> String {
+    mates_hit_string(&hits.rows, &hit_mates(hits), value_mask, taxonomy)
+}
 
-        let ext_code = taxonomy.nodes[key as usize].external_id;
+/// Builds the space distribution string of a read with any number of mates.
+///
+/// `mates` holds the minimizer range and the k-mer count of every mate, the mate
+/// strings are separated by ` |:| `.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classify::mates_hit_string;
+/// use kun_peng::compact_hash::Row;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 562, ..Default::default() });
+///
+/// let rows = vec![Row::new(1, 0, 2), Row::new(1, 0, 5), Row::new(1, 0, 6)];
+/// let mates = [((0, 3), 3), ((3, 4), 1), ((4, 6), 2), ((6, 6), 0)];
+/// assert_eq!(
+///     mates_hit_string(&rows, &mates, 0xFFFF, &taxonomy),
+///     "0:1 562:1 0:1 |:| 0:1 |:| 562:2 |:| 0:0"
+/// );
+/// ```
+///
+/// The positions of a 100 kb read stay in order up to its last minimizer:
+///
+/// ```
+/// # use kun_peng::classify::mates_hit_string;
+/// # use kun_peng::compact_hash::Row;
+/// # use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// # let mut taxonomy = Taxonomy::default();
+/// # taxonomy.nodes.push(TaxonomyNode::default());
+/// # taxonomy.nodes.push(TaxonomyNode { external_id: 562, ..Default::default() });
+/// let rows: Vec<Row> = (1..=100)
+///     .map(|i| Row::new(1, 0, Row::kmer_id_of(i * 1000)))
+///     .collect();
+/// let hit_string = mates_hit_string(&rows, &[((0, 100_000), 100_000)], 0xFFFF, &taxonomy);
+/// assert_eq!(hit_string, vec!["0:999 562:1"; 100].join(" "));
+/// ```
+pub fn mates_hit_string(
+    rows: &[Row],
+    mates: &[((usize, usize), usize)],
+    value_mask: usize,
+    taxonomy: &Taxonomy,
+) -> String {
+    let mut space_dists: Vec<SpaceDist> = mates
+        .iter()
+        .map(|(range, _)| SpaceDist::new(*range))
+        .collect();
+    for row in rows {
+        let key = row.value.right(value_mask);
+        let ext_code = taxonomy.external_id(key);
         let pos = row.kmer_id as usize;
-        space_dist.add(ext_code, pos);
+        if let Some(sd) = space_dists
+            .iter_mut()
+            .find(|sd| sd.range.0 < pos && pos <= sd.range.1)
+        {
+            sd.add(ext_code, pos);
+        }
     }
 
-    space_dist.fill_tail_with_zeros();
-    space_dist.reduce_str(" |:| ", |str| str.to_string())
+    space_dists
+        .iter_mut()
+        .zip(mates)
+        .map(|(sd, (_, kmer_count))| {
+            // A mate without a single minimizer (all N, masked or shorter than k) has an
+            // empty distribution, report its k-mers as unassigned instead.
+            if sd.range.0 == sd.range.1 {
+                format!("0:{}", kmer_count)
+            } else {
+                sd.fill_tail_with_zeros();
+                sd.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" |:| ")
+}
+
+/// Escapes the GFF3 reserved characters of a column with `%XX`
+fn gff3_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c.is_ascii_control() || matches!(c, ' ' | '%' | ';' | '=' | '&' | ',') {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Builds the GFF3 features of the minimizer hits of a read, one line per hit.
+///
+/// The positions are the k-mer positions of the hit string, a hit at the `i`th k-mer
+/// of a mate covers the bases `i..=i + k - 1` of that mate. Pairs get the sequence
+/// IDs `<id>/1` and `<id>/2`. Every feature carries the taxid of its minimizer and the
+/// call of the read, `call` being an external taxid.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classify::hits_gff3;
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+///
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 562, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 561, ..Default::default() });
+///
+/// let value_mask = (1 << 16) - 1;
+/// let row = |taxid, kmer_id| Row::new(u32::combined(7, taxid, 16), 0, kmer_id);
+///
+/// // Hits at the 2nd and 5th k-mers of a read with 10 k-mers, k = 31
+/// let hits = HitGroup::new(vec![row(1, 2), row(2, 5)], OptionPair::Single((0, 10)));
+/// assert_eq!(
+///     hits_gff3("read1", &hits, 561, 31, value_mask, &taxonomy),
+///     "read1\tkun_peng\tsequence_feature\t2\t32\t.\t.\t.\tName=562;taxid=562;read_taxid=561\n\
+///      read1\tkun_peng\tsequence_feature\t5\t35\t.\t.\t.\tName=561;taxid=561;read_taxid=561\n"
+/// );
+///
+/// // The second mate starts over at 1, the read ID is escaped
+/// let hits = HitGroup::new(vec![row(1, 3), row(1, 12)], OptionPair::Pair((0, 10), (10, 20)));
+/// let gff3 = hits_gff3("r 2", &hits, 562, 31, value_mask, &taxonomy);
+/// let columns: Vec<Vec<&str>> = gff3.lines().map(|line| line.split('\t').collect()).collect();
+/// assert_eq!(columns.len(), 2);
+/// assert_eq!(columns[0][..5], ["r%202/1", "kun_peng", "sequence_feature", "3", "33"]);
+/// assert_eq!(columns[1][..5], ["r%202/2", "kun_peng", "sequence_feature", "2", "32"]);
+/// assert!(columns.iter().all(|c| c.len() == 9 && c[8] == "Name=562;taxid=562;read_taxid=562"));
+/// ```
+pub fn hits_gff3(
+    id: &str,
+    hits: &HitGroup,
+    call: u64,
+    k_mer: usize,
+    value_mask: usize,
+    taxonomy: &Taxonomy,
+) -> String {
+    let id = gff3_escape(id);
+    let mates: Vec<(String, (usize, usize))> = match &hits.range {
+        OptionPair::Single(range) => vec![(id, *range)],
+        OptionPair::Pair(range1, range2) => {
+            vec![
+                (format!("{}/1", id), *range1),
+                (format!("{}/2", id), *range2),
+            ]
+        }
+    };
+    let mut gff3 = String::new();
+    for row in &hits.rows {
+        let pos = row.kmer_id as usize;
+        let Some((seq_id, range)) = mates
+            .iter()
+            .find(|(_, range)| range.0 < pos && pos <= range.1)
+        else {
+            continue;
+        };
+        let start = pos - range.0;
+        let taxid = taxonomy.external_id(row.value.right(value_mask));
+        gff3.push_str(&format!(
+            "{}\tkun_peng\tsequence_feature\t{}\t{}\t.\t.\t.\tName={};taxid={};read_taxid={}\n",
+            seq_id,
+            start,
+            start + k_mer - 1,
+            taxid,
+            taxid,
+            call
+        ));
+    }
+    gff3
+}
+
+/// Paints every base of a read with the taxon of the minimizers covering it.
+///
+/// A hit at the `i`th k-mer of a mate covers the bases `i..=i + k - 1` of that mate. A base
+/// takes the taxon most of its covering hits point to, ties go to the LCA of the tied
+/// taxa and bases without a covering hit to 0. The painting is run-length encoded like the
+/// hit string, `taxid:bases` runs with the mates separated by ` |:| `.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classify::paint_bases;
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+///
+/// // 1 -> {562, 1280}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id) in [(1, 0), (562, 1), (1280, 1)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 3])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+/// let value_mask = (1 << 16) - 1;
+/// let row = |taxid, kmer_id| Row::new(u32::combined(7, taxid, 16), 0, kmer_id);
+///
+/// // k = 3, 5 k-mers and 7 bases: the 4th base is covered by one hit of each taxon
+/// let hits = HitGroup::new(vec![row(2, 1), row(2, 2), row(3, 4)], OptionPair::Single((0, 5)));
+/// assert_eq!(paint_bases(&hits, 3, value_mask, &taxonomy), "562:3 1:1 1280:2 0:1");
+///
+/// // A chimeric long read, 3000 k-mers of E. coli followed by 3000 of S. aureus with a
+/// // hit every 7th k-mer. The last E. coli hit starts at 2997, the first S. aureus hit
+/// // at 3004. Across the junction the majority moves from E. coli to S. aureus, with a
+/// // tie painted as their LCA in between.
+/// let rows = (1..=6000u32)
+///     .step_by(7)
+///     .map(|pos| row(if pos <= 3000 { 2 } else { 3 }, pos))
+///     .collect();
+/// let hits = HitGroup::new(rows, OptionPair::Single((0, 6000)));
+/// assert_eq!(paint_bases(&hits, 31, value_mask, &taxonomy), "562:3013 1:4 1280:3013");
+///
+/// // Pairs are painted mate by mate
+/// let hits = HitGroup::new(vec![row(2, 2), row(3, 5)], OptionPair::Pair((0, 3), (3, 5)));
+/// assert_eq!(paint_bases(&hits, 3, value_mask, &taxonomy), "0:1 562:3 0:1 |:| 0:1 1280:3");
+/// ```
+pub fn paint_bases(
+    hits: &HitGroup,
+    k_mer: usize,
+    value_mask: usize,
+    taxonomy: &Taxonomy,
+) -> String {
+    hit_mates(hits)
+        .iter()
+        .map(|&(range, kmer_count)| {
+            let mut starts: Vec<(usize, u32)> = hits
+                .rows
+                .iter()
+                .map(|row| (row.kmer_id as usize, row.value.right(value_mask)))
+                .filter(|&(pos, _)| range.0 < pos && pos <= range.1)
+                .map(|(pos, taxon)| (pos - range.0, taxon))
+                .collect();
+            starts.sort_unstable();
+            let kmers = (range.1 - range.0).max(kmer_count);
+            let bases = if kmers > 0 { kmers + k_mer - 1 } else { 0 };
+
+            let mut runs: Vec<(u32, usize)> = Vec::new();
+            let mut covering: HashMap<u32, usize> = HashMap::new();
+            let (mut next, mut first) = (0, 0);
+            for base in 1..=bases {
+                while next < starts.len() && starts[next].0 == base {
+                    *covering.entry(starts[next].1).or_insert(0) += 1;
+                    next += 1;
+                }
+                while first < next && starts[first].0 + k_mer <= base {
+                    let taxon = starts[first].1;
+                    if let Some(count) = covering.get_mut(&taxon) {
+                        *count -= 1;
+                        if *count == 0 {
+                            covering.remove(&taxon);
+                        }
+                    }
+                    first += 1;
+                }
+                let most = covering.values().copied().max().unwrap_or(0);
+                let taxon = covering.iter().filter(|&(_, &count)| count == most).fold(
+                    0,
+                    |taxon, (&tied, _)| {
+                        if taxon == 0 {
+                            tied
+                        } else {
+                            taxonomy.lca(taxon, tied)
+                        }
+                    },
+                );
+                match runs.last_mut() {
+                    Some((last, len)) if *last == taxon => *len += 1,
+                    _ => runs.push((taxon, 1)),
+                }
+            }
+            if runs.is_empty() {
+                return "0:0".to_string();
+            }
+            runs.iter()
+                .map(|&(taxon, len)| format!("{}:{}", taxonomy.external_id(taxon), len))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join(" |:| ")
+}
+
+/// How [`process_hitgroup`] calls a read, beyond the required score of the read
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HitGroupOptions {
+    /// The minimum number of hit groups required for a valid classification.
+    pub minimum_hit_groups: usize,
+    /// The number of non-matching minimizers allowed inside one hit group.
+    /// When `None`, every minimizer of the read counts as a group.
+    pub hit_group_gap: Option<usize>,
+    /// The report code of the highest rank a call may have, see
+    /// [`Taxonomy::is_within_rank`]. Coarser calls are reported as unclassified.
+    pub max_rank: Option<char>,
+    /// Weigh every hit by the rank of its taxon, see [`Taxonomy::rank_weight`].
+    /// The required score stays in minimizers, a read mostly hitting general taxa gets
+    /// a higher call.
+    pub weight_by_rank: bool,
+    /// Call the LCA of all the hit taxa, the most specific taxon consistent with every
+    /// hit. The required score and `minimum_hit_groups` are ignored.
+    pub lca_of_all: bool,
+    /// Whether a minimizer repeated in the read adds to the score at every position or
+    /// once, see [`ScoreMode`].
+    pub score_mode: ScoreMode,
+    /// The minimum [`read_coverage`] of a call, reads with the hits of their call
+    /// clustered in a few segments are reported as unclassified.
+    pub min_read_coverage: f64,
+    /// The mask of the taxon in the hit values.
+    pub value_mask: usize,
+}
+
+impl HitGroupOptions {
+    /// The defaults of the command line, for a database with `value_mask`
+    pub fn new(value_mask: usize) -> Self {
+        Self {
+            minimum_hit_groups: 2,
+            hit_group_gap: None,
+            max_rank: None,
+            weight_by_rank: false,
+            lca_of_all: false,
+            score_mode: ScoreMode::Positions,
+            min_read_coverage: 0.0,
+            value_mask,
+        }
+    }
+}
+
+/// The call of a read, see [`process_hitgroup`]
+#[derive(Debug)]
+pub struct HitGroupCall {
+    /// Whether the read is classified.
+    pub classified: bool,
+    /// The external ID of the called taxon, 0 if unclassified.
+    pub taxid: u64,
+    /// The k-mers of every hit taxon, and the read of the call.
+    pub taxon_counts: TaxonCounters,
+    /// The fraction of the read's minimizers that hit the called clade (0 if
+    /// unclassified).
+    pub confidence: f64,
+    /// The [`read_coverage`] of the call (0 if unclassified).
+    pub read_coverage: f64,
+    /// The hits on index cells holding taxid 0, skipped like misses. No taxon has the
+    /// internal ID 0, such cells come from a damaged build.
+    pub zero_taxon_hits: u64,
 }
 
 /// Processes a hit group to determine classification and gather statistics.
@@ -118,52 +855,424 @@ fn stat_hits<'a>(
 /// * `taxonomy` - The Taxonomy object representing the taxonomic hierarchy.
 /// * `classify_counter` - An atomic counter for tracking classifications.
 /// * `required_score` - The minimum score required for a classification to be considered valid.
-/// * `minimum_hit_groups` - The minimum number of hit groups required for a valid classification.
-/// * `value_mask` - A mask used for processing hit values.
+/// * `options` - The thresholds and scoring of the call, see [`HitGroupOptions`].
+///
+/// The hit string is left to [`hit_string`], to be built only for the reads whose
+/// output line is written.
 ///
 /// # Returns
 ///
-/// Returns a tuple containing:
-/// 1. A String indicating the classification result ("C" for classified, "U" for unclassified).
-/// 2. The external ID of the classified taxon.
-/// 3. A String representing the hit statistics.
-/// 4. The updated TaxonCounters.
+/// Returns the [`HitGroupCall`] of the read.
+///
+/// # Examples
+///
+/// Reads hitting at most two taxa take a fast path, the calls match [`resolve_tree`]:
+///
+/// ```
+/// use kun_peng::classify::{process_hitgroup, resolve_tree, HitGroupOptions};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::collections::HashMap;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// // 1 -> {2 -> 4, 3}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id) in [(1, 0), (2, 1), (3, 1), (4, 2)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 3]), (4, vec![1, 2, 4])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// let value_mask = (1 << 16) - 1;
+/// let options = HitGroupOptions { minimum_hit_groups: 0, ..HitGroupOptions::new(value_mask) };
+/// let counter = AtomicUsize::new(0);
+/// let mut state = 0x9e3779b97f4a7c15u64;
+/// let mut next = || {
+///     state ^= state << 13;
+///     state ^= state >> 7;
+///     state ^= state << 17;
+///     state
+/// };
+/// for taxa in [&[4][..], &[2, 4], &[3, 4], &[1, 3], &[2, 3, 4], &[1, 2, 3, 4]] {
+///     for _ in 0..50 {
+///         let len = 1 + next() as usize % 20;
+///         let rows: Vec<Row> = (0..len)
+///             .map(|i| {
+///                 let taxid = taxa[next() as usize % taxa.len()];
+///                 Row::new(u32::combined(7, taxid, 16), 0, i as u32 + 1)
+///             })
+///             .collect();
+///         let mut counts = HashMap::new();
+///         for row in &rows {
+///             *counts.entry(row.value.right(value_mask)).or_insert(0u64) += 1;
+///         }
+///         let required_score = next() % (len as u64 + 1);
+///         let expected = resolve_tree(&counts, &taxonomy, required_score) as u64;
+///
+///         let hits = HitGroup::new(rows, OptionPair::Single((0, len)));
+///         let read = process_hitgroup(&hits, &taxonomy, &counter, required_score, &options);
+///         assert_eq!(read.taxid, expected);
+///         assert!(counts.keys().all(|taxon| read.taxon_counts.contains_key(&(*taxon as u64))));
+///     }
+/// }
+///
+/// // Three of four minimizers support the call 4, one hits its cousin 3
+/// let rows = [4, 4, 3, 4].iter().enumerate();
+/// let rows = rows.map(|(i, &taxid)| Row::new(u32::combined(7, taxid, 16), 0, i as u32 + 1));
+/// let hits = HitGroup::new(rows.collect(), OptionPair::Single((0, 4)));
+/// let read = process_hitgroup(&hits, &taxonomy, &counter, 0, &options);
+/// assert_eq!((read.taxid, read.confidence), (4, 0.75));
+/// ```
+///
+/// With a `max_rank`, calls above that rank become unclassified:
+///
+/// ```
+/// use kun_peng::classify::{process_hitgroup, HitGroupOptions};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// // root -> Bacteria -> Proteobacteria -> Escherichia
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.rank_data = b"no rank\0superkingdom\0phylum\0genus\0".to_vec();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id, rank_offset) in [(1, 0, 0), (2, 1, 8), (1224, 2, 21), (561, 3, 28)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, rank_offset, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 2, 3]), (4, vec![1, 2, 3, 4])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// let options = HitGroupOptions { minimum_hit_groups: 0, ..HitGroupOptions::new((1 << 16) - 1) };
+/// let counter = AtomicUsize::new(0);
+/// let classify = |taxid: u32| {
+///     let rows = (1..=3).map(|i| Row::new(u32::combined(7, taxid, 16), 0, i)).collect();
+///     let hits = HitGroup::new(rows, OptionPair::Single((0, 3)));
+///     let options = HitGroupOptions { max_rank: Some('G'), ..options };
+///     let read = process_hitgroup(&hits, &taxonomy, &counter, 0, &options);
+///     (read.classified, read.taxid)
+/// };
+/// assert_eq!(classify(4), (true, 561));
+/// assert_eq!(classify(3), (false, 0));
+///
+/// // Four of ten minimizers hit the genus, six its phylum. At a confidence of 0.4 the
+/// // genus hits are enough, unless the phylum and genus hits weigh less than species hits.
+/// let rows = [3, 4, 3, 3, 4, 3, 4, 3, 3, 4].iter().enumerate();
+/// let rows = rows.map(|(i, &taxid)| Row::new(u32::combined(7, taxid, 16), 0, i as u32 + 1));
+/// let hits = HitGroup::new(rows.collect(), OptionPair::Single((0, 10)));
+/// let required_score = hits.required_score(0.4);
+/// let call = |weight_by_rank| {
+///     let options = HitGroupOptions { weight_by_rank, ..options };
+///     process_hitgroup(&hits, &taxonomy, &counter, required_score, &options).taxid
+/// };
+/// assert_eq!(call(false), 561);
+/// assert_eq!(call(true), 1224);
+/// ```
+///
+/// With `lca_of_all`, the call is the LCA of every hit taxon, whatever the counts:
+///
+/// ```
+/// use kun_peng::classify::{process_hitgroup, HitGroupOptions};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// // root -> Escherichia -> {E. coli, E. albertii}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id) in [(1, 1), (561, 1), (562, 2), (208962, 2)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 2, 3]), (4, vec![1, 2, 4])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// // Nine hits to E. coli, one to E. albertii and two misses
+/// let taxa = [3, 3, 3, 0, 3, 3, 4, 3, 3, 0, 3, 3];
+/// let rows = taxa.iter().enumerate().filter(|&(_, &taxid)| taxid > 0);
+/// let rows = rows.map(|(i, &taxid)| Row::new(u32::combined(7, taxid, 16), 0, i as u32 + 1));
+/// let hits = HitGroup::new(rows.collect(), OptionPair::Single((0, taxa.len())));
+/// let counter = AtomicUsize::new(0);
+/// let call = |required_score, lca_of_all| {
+///     let options = HitGroupOptions { lca_of_all, ..HitGroupOptions::new(0xFFFF) };
+///     process_hitgroup(&hits, &taxonomy, &counter, required_score, &options).taxid
+/// };
+/// assert_eq!(call(0, false), 562);
+/// assert_eq!(call(0, true), 561);
+/// // The thresholds don't apply
+/// assert_eq!(call(100, true), 561);
+/// ```
+///
+/// Hits on taxa outside the taxonomy, from a corrupt or mismatched index, are skipped like
+/// misses instead of ending the run, the hit string reports them as unassigned:
+///
+/// ```
+/// use kun_peng::classify::{hit_string, process_hitgroup, HitGroupOptions};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// // root -> E. coli, 3 nodes with the unused node 0
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id) in [(1, 0), (562, 1)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// let value_mask = 0xFFFF;
+/// let options = HitGroupOptions { minimum_hit_groups: 0, ..HitGroupOptions::new(value_mask) };
+/// let counter = AtomicUsize::new(0);
+/// let classify = |taxa: &[u32]| {
+///     let rows = taxa.iter().enumerate();
+///     let rows = rows.map(|(i, &taxid)| Row::new(u32::combined(7, taxid, 16), 0, i as u32 + 1));
+///     let hits = HitGroup::new(rows.collect(), OptionPair::Single((0, taxa.len())));
+///     let read = process_hitgroup(&hits, &taxonomy, &counter, 0, &options);
+///     let mut taxa: Vec<u64> = read.taxon_counts.keys().copied().collect();
+///     taxa.sort();
+///     let hits = hit_string(&hits, value_mask, &taxonomy);
+///     (read, taxa, hits)
+/// };
+///
+/// let (read, taxa, hits) = classify(&[2, 2, 3, 2]);
+/// assert_eq!((read.classified, read.taxid, read.confidence), (true, 562, 0.75));
+/// assert_eq!(taxa, [2]);
+/// assert_eq!(hits, "562:2 0:1 562:1");
+/// assert_eq!(read.zero_taxon_hits, 0);
+///
+/// let (read, taxa, _) = classify(&[40000, 3]);
+/// assert_eq!((read.classified, read.taxid), (false, 0));
+/// assert!(taxa.is_empty());
+///
+/// // Cells holding taxid 0 are misses too, out of the counts but in the hit string
+/// let (read, taxa, hits) = classify(&[2, 0, 0, 1, 2]);
+/// assert_eq!((read.classified, read.taxid, read.confidence), (true, 562, 0.4));
+/// assert_eq!(taxa, [1, 2]);
+/// assert_eq!(hits, "562:1 0:2 1:1 562:1");
+/// assert_eq!(read.zero_taxon_hits, 2);
+/// ```
 pub fn process_hitgroup(
     hits: &HitGroup,
     taxonomy: &Taxonomy,
     classify_counter: &AtomicUsize,
     required_score: u64,
-    minimum_hit_groups: usize,
-    value_mask: usize,
-) -> (String, u64, String, TaxonCounters) {
-    let mut cur_taxon_counts = TaxonCounters::new();
-    let mut counts = HashMap::new();
-    let hit_groups = hits.capacity();
-    let hit_string = stat_hits(
-        hits,
-        &mut counts,
+    options: &HitGroupOptions,
+) -> HitGroupCall {
+    let HitGroupOptions {
+        minimum_hit_groups,
+        hit_group_gap,
+        max_rank,
+        weight_by_rank,
+        lca_of_all,
+        score_mode,
+        min_read_coverage,
         value_mask,
-        taxonomy,
-        &mut cur_taxon_counts,
-    );
+    } = *options;
+    let weigh = |taxon: u32, count: u64| {
+        if weight_by_rank {
+            count * taxonomy.rank_weight(taxon)
+        } else {
+            count
+        }
+    };
+    let required_score = if weight_by_rank {
+        required_score * MAX_RANK_WEIGHT
+    } else {
+        required_score
+    };
+
+    let in_taxonomy = |row: &Row| taxonomy.node(row.value.right(value_mask)).is_some();
+    let is_zero = |row: &&Row| row.value.right(value_mask) == 0;
+    let is_hit = |row: &Row| !is_zero(&row) && in_taxonomy(row);
+    let valid_hits;
+    let mut zero_taxon_hits = 0;
+    let hits = if hits.rows.iter().all(is_hit) {
+        hits
+    } else {
+        zero_taxon_hits = hits.rows.iter().filter(is_zero).count() as u64;
+        if zero_taxon_hits > 0 {
+            static WARN_ZERO: Once = Once::new();
+            WARN_ZERO.call_once(|| {
+                eprintln!(
+                    "WARNING: the index holds cells with taxid 0, a damaged database, their hits are skipped"
+                )
+            });
+        }
+        if !hits.rows.iter().all(in_taxonomy) {
+            static WARN: Once = Once::new();
+            WARN.call_once(|| {
+                eprintln!(
+                    "WARNING: the index holds taxa outside the {} nodes of the taxonomy, their hits are skipped",
+                    taxonomy.node_count()
+                )
+            });
+        }
+        valid_hits = HitGroup {
+            rows: hits.rows.iter().copied().filter(is_hit).collect(),
+            range: hits.range.apply(|&range| range),
+            kmer_counts: hits.kmer_counts.apply(|&count| count),
+        };
+        &valid_hits
+    };
 
-    let mut call = resolve_tree(&counts, taxonomy, required_score);
-    if call > 0 && hit_groups < minimum_hit_groups {
+    let (mut call, mut cur_taxon_counts, support) =
+        match count_few_taxa(hits, value_mask, score_mode) {
+            Some((taxa, len)) => {
+                let call = if lca_of_all {
+                    lca_of_taxa(taxa[..len].iter().map(|&(taxon, _)| taxon), taxonomy)
+                } else {
+                    let weighted = taxa.map(|(taxon, count)| (taxon, weigh(taxon, count)));
+                    resolve_counts(weighted[..len].iter().copied(), taxonomy, required_score)
+                };
+                let support = clade_support(taxa[..len].iter().copied(), call, taxonomy);
+                let mut cur_taxon_counts = TaxonCounters::new();
+                for row in &hits.rows {
+                    cur_taxon_counts
+                        .entry(row.value.right(value_mask) as u64)
+                        .or_default()
+                        .add_kmer(row.value as u64);
+                }
+                (call, cur_taxon_counts, support)
+            }
+            None => {
+                let mut cur_taxon_counts = TaxonCounters::new();
+                let mut counts = HashMap::new();
+                stat_hits(
+                    hits,
+                    &mut counts,
+                    value_mask,
+                    score_mode,
+                    &mut cur_taxon_counts,
+                );
+                let call = if lca_of_all {
+                    lca_of_taxa(counts.keys().copied(), taxonomy)
+                } else if weight_by_rank {
+                    let weighted = counts
+                        .iter()
+                        .map(|(&taxon, &count)| (taxon, weigh(taxon, count)))
+                        .collect();
+                    resolve_tree(&weighted, taxonomy, required_score)
+                } else {
+                    resolve_tree(&counts, taxonomy, required_score)
+                };
+                let support = clade_support(
+                    counts.iter().map(|(&taxon, &count)| (taxon, count)),
+                    call,
+                    taxonomy,
+                );
+                (call, cur_taxon_counts, support)
+            }
+        };
+    if call > 0 && !lca_of_all && !hits.meets_hit_groups(minimum_hit_groups, hit_group_gap) {
+        call = 0;
+    }
+    if call > 0 && max_rank.is_some_and(|rank| !taxonomy.is_within_rank(call, rank)) {
+        call = 0;
+    }
+    let mut coverage = read_coverage(hits, call, taxonomy, value_mask);
+    if call > 0 && !lca_of_all && coverage < min_read_coverage {
         call = 0;
+        coverage = 0.0;
+    }
+
+    let confidence = if call > 0 && hits.capacity() > 0 {
+        support as f64 / hits.capacity() as f64
+    } else {
+        0.0
     };
 
-    let ext_call = taxonomy.nodes[call as usize].external_id;
-    let clasify = if call > 0 {
+    if call > 0 {
         classify_counter.fetch_add(1, Ordering::SeqCst);
         cur_taxon_counts
             .entry(call as u64)
             .or_default()
             .increment_read_count();
+    }
 
-        "C"
-    } else {
-        "U"
-    };
+    HitGroupCall {
+        classified: call > 0,
+        taxid: taxonomy.external_id(call),
+        taxon_counts: cur_taxon_counts,
+        confidence,
+        read_coverage: coverage,
+        zero_taxon_hits,
+    }
+}
 
-    (clasify.to_owned(), ext_call, hit_string, cur_taxon_counts)
+/// The calls of the mates of a pair, each classified on its own hits
+///
+/// `classify` calls a group of hits, e.g. with [`process_hitgroup`]. The hits of the
+/// second mate keep their positions after those of the first. `None` for a single read.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::classify::{mate_calls, process_hitgroup, HitGroupOptions};
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use kun_peng::HitGroup;
+/// use seqkmer::OptionPair;
+/// use std::sync::atomic::AtomicUsize;
+///
+/// // root -> Enterobacteriaceae -> {E. coli, Salmonella}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id) in [(1, 0), (543, 1), (562, 2), (590, 2)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 2, 3]), (4, vec![1, 2, 4])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// // 8 hits to E. coli in the first mate, 8 to Salmonella in the second
+/// let row = |taxid, kmer_id| Row::new(u32::combined(7, taxid, 16), 0, kmer_id);
+/// let rows = (1..=8).map(|i| row(3, i)).chain((11..=18).map(|i| row(4, i))).collect();
+/// let pair = HitGroup::new(rows, OptionPair::Pair((0, 10), (10, 20)));
+/// let options = HitGroupOptions { minimum_hit_groups: 0, ..HitGroupOptions::new(0xFFFF) };
+/// let counter = AtomicUsize::new(0);
+/// let call = |hits: &HitGroup| process_hitgroup(hits, &taxonomy, &counter, 0, &options).taxid;
+///
+/// assert_eq!(call(&pair), 543);
+/// assert_eq!(mate_calls(&pair, call), Some((562, 590)));
+///
+/// let single = HitGroup::new(vec![row(3, 1)], OptionPair::Single((0, 10)));
+/// assert_eq!(mate_calls(&single, call), None);
+/// ```
+pub fn mate_calls<F>(hits: &HitGroup, mut classify: F) -> Option<(u64, u64)>
+where
+    F: FnMut(&HitGroup) -> u64,
+{
+    let OptionPair::Pair(range1, range2) = hits.range else {
+        return None;
+    };
+    let (count1, count2) = match hits.kmer_counts {
+        OptionPair::Pair(count1, count2) => (count1, count2),
+        OptionPair::Single(_) => (0, 0),
+    };
+    let mate = |range: (usize, usize), kmer_count: usize| HitGroup {
+        rows: hits
+            .rows
+            .iter()
+            .copied()
+            .filter(|row| range.0 < row.kmer_id as usize && row.kmer_id as usize <= range.1)
+            .collect(),
+        range: OptionPair::Single(range),
+        kmer_counts: OptionPair::Single(kmer_count),
+    };
+    Some((
+        classify(&mate(range1, count1)),
+        classify(&mate(range2, count2)),
+    ))
 }
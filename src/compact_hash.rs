@@ -1,272 +1,84 @@
+use crate::fmt::{read_payload, write_payload, Kind};
+use crate::utils::write_atomically;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
-use std::cmp::Ordering as CmpOrdering;
+use std::collections::HashMap;
 use std::fmt::{self, Debug};
 use std::fs::File;
 use std::fs::OpenOptions;
-use std::io::{BufWriter, Read, Result, Write};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
 use std::path::Path;
-
-/// Trait for compact hash operations
-pub trait Compact: Default + PartialEq + Clone + Copy + Eq + Sized + Send + Sync + Debug {
-    /// Creates a compacted value from a hash key
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let compacted = u32::compacted(0x1234567890ABCDEF, 16);
-    /// assert_eq!(compacted, 0x1234);
-    /// ```
-    fn compacted(hash_key: u64, value_bits: usize) -> Self;
-
-    /// Creates a hash value from a hash key and a value
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let hash_value = u32::hash_value(0x1234567890ABCDEF, 16, 0xABCD);
-    /// assert_eq!(hash_value, 0x1234ABCD);
-    /// ```
-    fn hash_value(hash_key: u64, value_bits: usize, value: Self) -> Self;
-
-    /// Returns the left part of the value
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let value: u32 = 0x1234ABCD;
-    /// assert_eq!(value.left(16), 0x1234);
-    /// ```
-    fn left(&self, value_bits: usize) -> Self;
-
-    /// Returns the right part of the value
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let value: u32 = 0x1234ABCD;
-    /// assert_eq!(value.right(0xFFFF), 0xABCD);
-    /// ```
-    fn right(&self, value_mask: usize) -> Self;
-
-    /// Combines left and right parts into a single value
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let combined = u32::combined(0x1234, 0xABCD, 16);
-    /// assert_eq!(combined, 0x1234ABCD);
-    /// ```
-    fn combined(left: Self, right: Self, value_bits: usize) -> Self;
-
-    /// Converts the value to u32
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let value: u32 = 0x1234ABCD;
-    /// assert_eq!(value.to_u32(), 0x1234ABCD);
-    /// ```
-    fn to_u32(&self) -> u32;
-
-    /// Creates a value from u32
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Compact;
-    ///
-    /// let value = u32::from_u32(0x1234ABCD);
-    /// assert_eq!(value, 0x1234ABCD);
-    /// ```
-    fn from_u32(value: u32) -> Self;
+use std::str::FromStr;
+
+pub use kun_peng_core::compact::{Compact, Row, Slot};
+
+/// Version of the [`crate::fmt`] header of `hash_config.k2d`
+///
+/// Version 2 adds the [`TableLayout`], the configs of [`TableLayout::Linear`] tables are
+/// still written as version 1 so that older builds keep reading them.
+pub const HASH_CONFIG_VERSION: u8 = 2;
+/// Number of bytes of the fields of `hash_config.k2d` up to version 1
+const HASH_CONFIG_LEN: usize = 48;
+
+/// Cells of a bucket of [`TableLayout::Bucketed`], the `u32` cells of a 64 byte cache line
+pub const BUCKET_CELLS: usize = 16;
+
+/// Where the probing for a key starts in the cells of the hash table
+///
+/// Both layouts probe the following cells in order, without wrapping around, until the
+/// cell of the key or an empty one, so a key is found where it was inserted.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::compact_hash::{HashConfig, TableLayout};
+///
+/// let linear = HashConfig::new(1, 1000, 16, 0, 1, 1024);
+/// let bucketed = HashConfig { layout: TableLayout::Bucketed, ..linear };
+/// assert_eq!(linear.index(537), 537);
+/// assert_eq!(bucketed.index(537), 528);
+///
+/// assert_eq!("bucketed".parse(), Ok(TableLayout::Bucketed));
+/// assert!("cuckoo".parse::<TableLayout>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableLayout {
+    /// From the cell the hash key points to, the layout of Kraken 2
+    #[default]
+    Linear,
+    /// From the first cell of the bucket of [`BUCKET_CELLS`] holding that cell, the probe
+    /// compares all the cells of a bucket before moving on to the next one
+    ///
+    /// The buckets line up with the cache lines when the chunk size is a multiple of
+    /// [`BUCKET_CELLS`], a probe then mostly stays in a single line even at high load.
+    Bucketed,
 }
 
-impl Compact for u32 {
-    fn hash_value(hash_key: u64, value_bits: usize, value: u32) -> u32 {
-        Self::compacted(hash_key, value_bits) << value_bits | value
-    }
-    fn compacted(value: u64, value_bits: usize) -> u32 {
-        (value >> (32 + value_bits)) as u32
-    }
-
-    fn left(&self, value_bits: usize) -> u32 {
-        *self >> value_bits
-    }
-
-    fn right(&self, value_mask: usize) -> u32 {
-        *self & value_mask as u32
-    }
-    fn combined(left: Self, right: Self, value_bits: usize) -> Self {
-        left << value_bits | right
-    }
-
-    fn to_u32(&self) -> u32 {
-        *self
-    }
-    fn from_u32(value: u32) -> Self {
-        value
-    }
-}
-
-impl Compact for u64 {
-    fn hash_value(hash_key: u64, value_bits: usize, value: u64) -> u64 {
-        Self::compacted(hash_key, value_bits) << (32 + value_bits) | value
-    }
-    fn compacted(value: u64, value_bits: usize) -> u64 {
-        (value >> (32 + value_bits)) as u64
-    }
-
-    fn left(&self, value_bits: usize) -> u64 {
-        *self >> (32 + value_bits)
-    }
-
-    fn right(&self, value_mask: usize) -> u64 {
-        let mask: u64 = ((value_mask as u64) << 32) | 0xFFFFFFFF;
-        mask & *self
-    }
-
-    fn combined(left: Self, right: Self, value_bits: usize) -> Self {
-        left << (32 + value_bits) | right
-    }
-
-    fn to_u32(&self) -> u32 {
-        *self as u32
-    }
-    fn from_u32(value: u32) -> Self {
-        value as u64
-    }
-}
-
-#[repr(C)]
-#[derive(PartialEq, Clone, Copy, Eq, Debug)]
-pub struct Row {
-    pub value: u32,
-    pub seq_id: u32,
-    pub kmer_id: u32,
-}
-
-impl Row {
-    /// Creates a new Row
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::Row;
-    ///
-    /// let row = Row::new(0x1234, 1, 2);
-    /// assert_eq!(row.value, 0x1234);
-    /// assert_eq!(row.seq_id, 1);
-    /// assert_eq!(row.kmer_id, 2);
-    /// ```
-    pub fn new(value: u32, seq_id: u32, kmer_id: u32) -> Self {
-        Self {
-            value,
-            seq_id,
-            kmer_id,
+impl TableLayout {
+    fn code(self) -> u64 {
+        match self {
+            Self::Linear => 0,
+            Self::Bucketed => 1,
         }
     }
-    #[inline]
-    pub fn as_slice(&self, row_size: usize) -> &[u8] {
-        let slot_ptr = self as *const Self as *const u8;
-        unsafe { std::slice::from_raw_parts(slot_ptr, row_size) }
-    }
-}
-
-// Implement PartialOrd, comparing only the kmer_id field
-impl PartialOrd for Row {
-    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
-        self.kmer_id.partial_cmp(&other.kmer_id)
-    }
-}
-
-// Implement Ord, comparing only the kmer_id field
-impl Ord for Row {
-    fn cmp(&self, other: &Self) -> CmpOrdering {
-        self.kmer_id.cmp(&other.kmer_id)
-    }
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, PartialEq, Eq)]
-pub struct Slot<B>
-where
-    B: Compact,
-{
-    pub idx: usize,
-    pub value: B,
-}
-
-impl<B> Slot<B>
-where
-    B: Compact,
-{
-    /// Creates a new Slot
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::{Slot, Compact};
-    ///
-    /// let slot = Slot::<u32>::new(1, 0x1234);
-    /// assert_eq!(slot.idx, 1);
-    /// assert_eq!(slot.value, 0x1234);
-    /// ```
-    pub fn new(idx: usize, value: B) -> Self {
-        Self { idx, value }
-    }
 
-    #[inline]
-    pub fn as_slice(&self, slot_size: usize) -> &[u8] {
-        let slot_ptr = self as *const Self as *const u8;
-        unsafe { std::slice::from_raw_parts(slot_ptr, slot_size) }
-    }
-}
-
-impl Slot<u64> {
-    /// Returns the sequence ID (lower 32 bits) for a u64 Slot
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use kun_peng::compact_hash::{Slot, Compact};
-    ///
-    /// let slot = Slot::<u64>::new(1, 0x1234567890ABCDEF);
-    /// assert_eq!(slot.get_seq_id(), 0x90ABCDEF);
-    /// ```
-    pub fn get_seq_id(&self) -> u32 {
-        self.value.right(0) as u32
+    fn from_code(code: u64) -> Option<Self> {
+        match code {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Bucketed),
+            _ => None,
+        }
     }
 }
 
-// Implement PartialOrd, comparing only the idx field
-impl<B> PartialOrd for Slot<B>
-where
-    B: Compact,
-{
-    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
-        self.idx.partial_cmp(&other.idx)
-    }
-}
+impl FromStr for TableLayout {
+    type Err = String;
 
-// Implement Ord, comparing only the idx field
-impl<B> Ord for Slot<B>
-where
-    B: Compact,
-{
-    fn cmp(&self, other: &Self) -> CmpOrdering {
-        self.idx.cmp(&other.idx)
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "bucketed" => Ok(Self::Bucketed),
+            _ => Err(format!("expected `linear` or `bucketed`, got `{}`", s)),
+        }
     }
 }
 
@@ -286,6 +98,8 @@ pub struct HashConfig {
     pub hash_capacity: usize,
     // Database version (0 is converted from Kraken 2 database)
     pub version: usize,
+    // Where the probing for a key starts
+    pub layout: TableLayout,
 }
 
 // Manually implement Debug trait for HashConfig
@@ -299,6 +113,7 @@ impl fmt::Debug for HashConfig {
             .field("size", &self.size)
             .field("value_bits", &self.value_bits)
             .field("value_mask", &self.value_mask)
+            .field("layout", &self.layout)
             .finish()
     }
 }
@@ -332,21 +147,38 @@ impl HashConfig {
             partition,
             hash_capacity,
             version,
+            layout: TableLayout::Linear,
         }
     }
 
+    /// Writes the config, replacing the file at once
+    ///
+    /// The six fields follow a [`crate::fmt`] header of version 1, then the code of the
+    /// layout for a [`TableLayout::Bucketed`] table, with version [`HASH_CONFIG_VERSION`].
     pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> Result<()> {
-        // Open the file for writing
-        let file = File::create(file_path)?;
-        let mut writer = BufWriter::new(file);
-        writer.write_u64::<LittleEndian>(self.version as u64)?;
-        writer.write_u64::<LittleEndian>(self.partition as u64)?;
-        writer.write_u64::<LittleEndian>(self.hash_capacity as u64)?;
-        writer.write_u64::<LittleEndian>(self.capacity as u64)?;
-        writer.write_u64::<LittleEndian>(self.size as u64)?;
-        writer.write_u64::<LittleEndian>(self.value_bits as u64)?;
-        writer.flush()?;
-        Ok(())
+        let mut payload = Vec::with_capacity(HASH_CONFIG_LEN + 8);
+        for field in [
+            self.version,
+            self.partition,
+            self.hash_capacity,
+            self.capacity,
+            self.size,
+            self.value_bits,
+        ] {
+            payload.write_u64::<LittleEndian>(field as u64)?;
+        }
+        let version = match self.layout {
+            TableLayout::Linear => 1,
+            layout => {
+                payload.write_u64::<LittleEndian>(layout.code())?;
+                HASH_CONFIG_VERSION
+            }
+        };
+        write_atomically(file_path, |tmp| {
+            let mut writer = BufWriter::new(File::create(tmp)?);
+            write_payload(&mut writer, Kind::HashConfig, version, 0, &payload)?;
+            writer.flush()
+        })
     }
 
     pub fn from_kraken2_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
@@ -358,23 +190,88 @@ impl HashConfig {
         Ok(Self::new(0, capacity, value_bits, size, 0, 0))
     }
 
+    /// Reads a config written by [`HashConfig::write_to_file`], or by a version without
+    /// the header
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::{HashConfig, TableLayout};
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_hash_config");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let path = dir.join("hash_config.k2d");
+    /// let config = HashConfig::new(1, 1000, 16, 500, 10, 100);
+    ///
+    /// config.write_to_file(&path).unwrap();
+    /// let reloaded = HashConfig::from_hash_header(&path).unwrap();
+    /// assert_eq!(format!("{:?}", reloaded), format!("{:?}", config));
+    ///
+    /// // Version 0, the fields without a header, then the provenance trailer
+    /// let fields = [1u64, 10, 100, 1000, 500, 16];
+    /// let mut legacy: Vec<u8> = fields.iter().flat_map(|field| field.to_le_bytes()).collect();
+    /// legacy.extend_from_slice(b"\nKUN_PENG_PROVENANCE\n{}");
+    /// std::fs::write(&path, legacy).unwrap();
+    /// let reloaded = HashConfig::from_hash_header(&path).unwrap();
+    /// assert_eq!(format!("{:?}", reloaded), format!("{:?}", config));
+    ///
+    /// // A flipped bit of the payload
+    /// config.write_to_file(&path).unwrap();
+    /// let mut bytes = std::fs::read(&path).unwrap();
+    /// bytes[kun_peng::fmt::HEADER_SIZE + 8] ^= 1;
+    /// std::fs::write(&path, bytes).unwrap();
+    /// assert!(HashConfig::from_hash_header(&path).is_err());
+    ///
+    /// // The layout of a bucketed table
+    /// let bucketed = HashConfig { layout: TableLayout::Bucketed, ..config };
+    /// bucketed.write_to_file(&path).unwrap();
+    /// let reloaded = HashConfig::from_hash_header(&path).unwrap();
+    /// assert_eq!(reloaded.layout, TableLayout::Bucketed);
+    /// ```
     pub fn from_hash_header<P: AsRef<Path>>(filename: P) -> Result<Self> {
-        let mut file = OpenOptions::new().read(true).open(&filename)?;
-        let version = file.read_u64::<LittleEndian>()? as usize;
-        let partition = file.read_u64::<LittleEndian>()? as usize;
-        let hash_capacity = file.read_u64::<LittleEndian>()? as usize;
-        let capacity = file.read_u64::<LittleEndian>()? as usize;
-        let size = file.read_u64::<LittleEndian>()? as usize;
-        let value_bits = file.read_u64::<LittleEndian>()? as usize;
-
-        Ok(Self::new(
-            version,
-            capacity,
-            value_bits,
-            size,
-            partition,
-            hash_capacity,
-        ))
+        let file = BufReader::new(File::open(&filename)?);
+        let (header, payload) = read_payload(file, Kind::HashConfig, HASH_CONFIG_VERSION)?;
+        let len = if header.version >= 2 {
+            HASH_CONFIG_LEN + 8
+        } else {
+            HASH_CONFIG_LEN
+        };
+        if payload.len() < len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "truncated hash config {}",
+                    filename.as_ref().to_string_lossy()
+                ),
+            ));
+        }
+        let mut fields = [0u64; 6];
+        LittleEndian::read_u64_into(&payload[..HASH_CONFIG_LEN], &mut fields);
+        let [version, partition, hash_capacity, capacity, size, value_bits] =
+            fields.map(|field| field as usize);
+        let layout = if header.version >= 2 {
+            let code = LittleEndian::read_u64(&payload[HASH_CONFIG_LEN..len]);
+            TableLayout::from_code(code).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown hash table layout {}", code),
+                )
+            })?
+        } else {
+            TableLayout::Linear
+        };
+
+        Ok(Self {
+            layout,
+            ..Self::new(
+                version,
+                capacity,
+                value_bits,
+                size,
+                partition,
+                hash_capacity,
+            )
+        })
     }
 
     pub fn get_idx_mask(&self) -> usize {
@@ -394,8 +291,13 @@ impl HashConfig {
         self.value_bits
     }
 
+    /// The cell the probing for a hash key starts at, see [`TableLayout`]
     pub fn index(&self, hash_key: u64) -> usize {
-        hash_key as usize % self.capacity
+        let idx = hash_key as usize % self.capacity;
+        match self.layout {
+            TableLayout::Linear => idx,
+            TableLayout::Bucketed => idx - idx % BUCKET_CELLS,
+        }
     }
 
     pub fn compact(&self, hash_key: u64) -> (usize, u32) {
@@ -472,7 +374,7 @@ fn read_page_data(file: &mut File, data: &mut [u32]) -> Result<()> {
     Ok(())
 }
 
-fn read_page_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
+pub(crate) fn read_page_from_file<P: AsRef<Path>>(filename: P) -> Result<Page> {
     let mut file = std::fs::File::open(filename)?;
     let (index, capacity) = read_page_metadata(&mut file)?;
     let mut data = vec![0u32; capacity];
@@ -595,6 +497,54 @@ impl Page {
         }
         0
     }
+
+    /// Probes from `index` a whole bucket at a time, for [`TableLayout::Bucketed`]
+    ///
+    /// Finds the same cell as [`Page::find_index`]. The cells of a bucket are all compared
+    /// into a bit mask, without a branch per cell, and the first set bit wins.
+    pub fn find_in_buckets(
+        &self,
+        index: usize,
+        compacted_key: u32,
+        value_bits: usize,
+        value_mask: usize,
+    ) -> u32 {
+        let end = self.size.min(self.data.len());
+        let cells = self.data.get(index..end).unwrap_or_default();
+        for bucket in cells.chunks(BUCKET_CELLS) {
+            let stops = bucket.iter().enumerate().fold(0u32, |stops, (i, cell)| {
+                let stop = (cell.right(value_mask) == 0) | (cell.left(value_bits) == compacted_key);
+                stops | (stop as u32) << i
+            });
+            if stops != 0 {
+                return bucket[stops.trailing_zeros() as usize].right(value_mask);
+            }
+        }
+        0
+    }
+
+    /// Probes from `index` with the probe of the layout of `config`
+    pub fn find(&self, index: usize, compacted_key: u32, config: &HashConfig) -> u32 {
+        match config.layout {
+            TableLayout::Linear => {
+                self.find_index(index, compacted_key, config.value_bits, config.value_mask)
+            }
+            TableLayout::Bucketed => {
+                self.find_in_buckets(index, compacted_key, config.value_bits, config.value_mask)
+            }
+        }
+    }
+
+    /// Probes from `index` like [`Page::find_index`], comparing only the keys
+    pub fn contains(&self, index: usize, compacted_key: u32, value_bits: usize) -> bool {
+        let end = self.size.min(self.data.len());
+        self.data
+            .get(index..end)
+            .unwrap_or_default()
+            .iter()
+            .take_while(|&&cell| cell != 0)
+            .any(|cell| cell.left(value_bits) == compacted_key)
+    }
 }
 
 #[allow(unused)]
@@ -641,14 +591,154 @@ impl CHTable {
 
     pub fn get_from_page(&self, indx: usize, compacted: u32, page_index: usize) -> u32 {
         if let Some(page) = self.pages.get(page_index) {
-            page.find_index(
-                indx,
-                compacted,
-                self.config.value_bits,
-                self.config.value_mask,
-            )
+            page.find(indx, compacted, &self.config)
         } else {
             0
         }
     }
+
+    /// Looks up the taxid of a minimizer hash key, `None` if the key is not in the table
+    pub fn get(&self, hash_key: u64) -> Option<u32> {
+        let (idx, compacted) = self.config.compact(hash_key);
+        let chunk_size = self.config.hash_capacity;
+        let taxid = self.get_from_page(idx % chunk_size, compacted, idx / chunk_size);
+        (taxid > 0).then_some(taxid)
+    }
+
+    /// The cell the lookup of a minimizer hash key stops at, as an index into the whole
+    /// table, `None` if the key is not in the table
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+    ///
+    /// let hash_config = HashConfig::new(1, 16, 16, 0, 2, 8);
+    /// let (first, second) = (3u64 << 48 | 9, 5u64 << 48 | 9);
+    /// let mut pages = vec![Page::with_capacity(0, 8), Page::with_capacity(1, 8)];
+    /// pages[1].data[1] = hash_config.slot(first, 7).value;
+    /// pages[1].data[2] = hash_config.slot(second, 4).value;
+    /// let chtable = CHTable { config: hash_config, pages };
+    ///
+    /// assert_eq!(chtable.cell_of(first), Some(9));
+    /// assert_eq!(chtable.cell_of(second), Some(10));
+    /// assert_eq!(chtable.cell_of(6u64 << 48 | 9), None);
+    /// assert_eq!(chtable.get(second), Some(4));
+    /// ```
+    pub fn cell_of(&self, hash_key: u64) -> Option<usize> {
+        let (idx, compacted) = self.config.compact(hash_key);
+        let chunk_size = self.config.hash_capacity;
+        let page = self.pages.get(idx / chunk_size)?;
+        let end = page.size.min(page.data.len());
+        (idx % chunk_size..end)
+            .take_while(|&i| page.data[i].right(self.config.value_mask) != 0)
+            .find(|&i| page.data[i].left(self.config.value_bits) == compacted)
+            .map(|i| (idx - idx % chunk_size + i) % self.config.capacity)
+    }
+
+    /// Checks if a minimizer hash key is in the table, without extracting its taxid
+    ///
+    /// For screening, where only the presence of a minimizer matters.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+    ///
+    /// let hash_config = HashConfig::new(1, 4096, 16, 0, 1, 4096);
+    /// let mut page = Page::with_capacity(0, 4096);
+    /// let keys: Vec<u64> = (1..=4000u64).map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15)).collect();
+    /// for (i, &hash_key) in keys.iter().step_by(2).enumerate() {
+    ///     let slot = hash_config.slot(hash_key, i as u32 % 1000 + 1);
+    ///     let idx = (slot.idx..).find(|&i| page.data[i] == 0).unwrap();
+    ///     page.data[idx] = slot.value;
+    /// }
+    /// let chtable = CHTable { config: hash_config, pages: vec![page] };
+    ///
+    /// for &hash_key in &keys {
+    ///     assert_eq!(chtable.contains(hash_key), chtable.get(hash_key).is_some());
+    /// }
+    /// assert!(keys.iter().step_by(2).all(|&hash_key| chtable.contains(hash_key)));
+    /// assert!(keys.iter().skip(1).step_by(2).any(|&hash_key| !chtable.contains(hash_key)));
+    /// ```
+    pub fn contains(&self, hash_key: u64) -> bool {
+        let (idx, compacted) = self.config.compact(hash_key);
+        let chunk_size = self.config.hash_capacity;
+        self.pages
+            .get(idx / chunk_size)
+            .is_some_and(|page| page.contains(idx % chunk_size, compacted, self.config.value_bits))
+    }
+
+    /// Counts the minimizers stored in the table for each taxid
+    ///
+    /// Only the cells of each chunk are counted, not the copy of the next block that is
+    /// appended to a page for probing past its end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+    ///
+    /// let hash_config = HashConfig::new(1, 8, 16, 0, 2, 4);
+    /// let pages = vec![Page::new(0, 6, vec![1, 0, 2, 2, 2, 0]), Page::new(1, 4, vec![3, 0, 1, 0])];
+    /// let chtable = CHTable { config: hash_config, pages };
+    ///
+    /// let counts = chtable.taxon_minimizer_counts();
+    /// assert_eq!((counts[&1], counts[&2], counts[&3]), (2, 2, 1));
+    /// assert_eq!(counts.len(), 3);
+    /// ```
+    pub fn taxon_minimizer_counts(&self) -> HashMap<u32, u64> {
+        let chunk_size = self.config.hash_capacity;
+        let mut counts = HashMap::new();
+        for (i, page) in self.pages.iter().enumerate() {
+            let own = chunk_size.min(self.config.capacity.saturating_sub(i * chunk_size));
+            let size = own.min(page.size).min(page.data.len());
+            for cell in &page.data[..size] {
+                let taxid = cell.right(self.config.value_mask);
+                if taxid > 0 {
+                    *counts.entry(taxid).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Finds the taxids stored in the table that are not below `node_count`
+    ///
+    /// Every `stride`-th cell of each page is checked, 1 checks all of them. Returns the
+    /// unknown taxids with the number of checked cells holding them, sorted by taxid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+    ///
+    /// let hash_config = HashConfig::new(1, 4096, 16, 0, 1, 4096);
+    /// let mut page = Page::with_capacity(0, 4096);
+    /// for (hash_key, taxid) in [(11u64, 1), (22, 2), (33, 5), (44, 5), (55, 9)] {
+    ///     let slot = hash_config.slot(hash_key, taxid);
+    ///     let idx = (slot.idx..).find(|&i| page.data[i] == 0).unwrap();
+    ///     page.data[idx] = slot.value;
+    /// }
+    /// let chtable = CHTable { config: hash_config, pages: vec![page] };
+    ///
+    /// // A taxonomy with the nodes 0, 1 and 2
+    /// assert_eq!(chtable.unknown_taxids(3, 1), vec![(5, 2), (9, 1)]);
+    /// assert!(chtable.unknown_taxids(10, 1).is_empty());
+    /// ```
+    pub fn unknown_taxids(&self, node_count: usize, stride: usize) -> Vec<(u32, usize)> {
+        let mut unknown = HashMap::new();
+        for page in &self.pages {
+            let size = page.size.min(page.data.len());
+            for cell in page.data[..size].iter().step_by(stride.max(1)) {
+                let taxid = cell.right(self.config.value_mask);
+                if taxid as usize >= node_count {
+                    *unknown.entry(taxid).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut unknown: Vec<(u32, usize)> = unknown.into_iter().collect();
+        unknown.sort_unstable();
+        unknown
+    }
 }
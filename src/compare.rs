@@ -0,0 +1,268 @@
+//! Read-by-read comparison of two Kraken-style outputs
+//!
+//! The calls of a read in both outputs are sorted into [`Agreement`] categories with the
+//! ancestor paths of the taxonomy: the same taxon, one call in the clade of the other, two
+//! unrelated taxa, or a read classified in one output only. The outputs are also compared
+//! as reports, by the correlation of the clade counts they give to every taxon.
+use crate::taxonomy::Taxonomy;
+use crate::utils::open_file;
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+
+/// How the calls of a read in two outputs relate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Agreement {
+    /// The same taxon, or unclassified in both
+    Same,
+    /// The first call is an ancestor of the second, it is less specific
+    Ancestor,
+    /// The first call is a descendant of the second, it is more specific
+    Descendant,
+    /// Neither call is in the clade of the other
+    Disagree,
+    /// Classified in one output only
+    OneUnclassified,
+}
+
+impl Agreement {
+    pub const ALL: [Self; 5] = [
+        Self::Same,
+        Self::Ancestor,
+        Self::Descendant,
+        Self::Disagree,
+        Self::OneUnclassified,
+    ];
+
+    /// The label of the category in the summary table and the disagreement file
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Same => "same",
+            Self::Ancestor => "ancestor",
+            Self::Descendant => "descendant",
+            Self::Disagree => "disagree",
+            Self::OneUnclassified => "one-unclassified",
+        }
+    }
+
+    /// Compares two calls, given as external taxids, 0 for unclassified
+    ///
+    /// Taxids missing from the taxonomy only agree with themselves.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compare::Agreement;
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    ///
+    /// // root(1) -> Bacteria(2) -> {Escherichia(561) -> E. coli(562), Salmonella(590)}
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// for (external_id, parent_id, first_child, child_count) in
+    ///     [(1, 0, 2, 1), (2, 1, 3, 2), (561, 2, 5, 1), (590, 2, 0, 0), (562, 3, 0, 0)]
+    /// {
+    ///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, first_child, child_count, ..Default::default() });
+    /// }
+    /// taxonomy.generate_external_to_internal_id_map();
+    /// taxonomy.build_path_cache();
+    ///
+    /// let compare = |first, second| Agreement::of(&taxonomy, first, second);
+    /// assert_eq!(compare(562, 562), Agreement::Same);
+    /// assert_eq!(compare(0, 0), Agreement::Same);
+    /// assert_eq!(compare(561, 562), Agreement::Ancestor);
+    /// assert_eq!(compare(562, 2), Agreement::Descendant);
+    /// assert_eq!(compare(562, 590), Agreement::Disagree);
+    /// assert_eq!(compare(562, 12345), Agreement::Disagree);
+    /// assert_eq!(compare(0, 590), Agreement::OneUnclassified);
+    /// ```
+    pub fn of(taxonomy: &Taxonomy, first: u64, second: u64) -> Self {
+        if first == second {
+            return Self::Same;
+        }
+        if first == 0 || second == 0 {
+            return Self::OneUnclassified;
+        }
+        let (a, b) = (
+            taxonomy.get_internal_id(first),
+            taxonomy.get_internal_id(second),
+        );
+        if taxonomy.is_a_ancestor_of_b(a, b) {
+            Self::Ancestor
+        } else if taxonomy.is_a_ancestor_of_b(b, a) {
+            Self::Descendant
+        } else {
+            Self::Disagree
+        }
+    }
+}
+
+/// The read ID and the taxid of a Kraken output line, 0 if unclassified
+///
+/// The taxid column may hold a name, as written by `kraken2 --use-names`.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::compare::kraken_call;
+///
+/// assert_eq!(kraken_call("C\tread_1\t562\t150\t562:116"), Some(("read_1", 562)));
+/// assert_eq!(kraken_call("U\tread_2\t0\t150\t0:116"), Some(("read_2", 0)));
+/// assert_eq!(
+///     kraken_call("C\tread_3\tEscherichia coli (taxid 562)\t150\t562:116"),
+///     Some(("read_3", 562))
+/// );
+/// assert_eq!(kraken_call("read_4 562"), None);
+/// ```
+pub fn kraken_call(line: &str) -> Option<(&str, u64)> {
+    let mut columns = line.split('\t');
+    let status = columns.next()?;
+    let id = columns.next()?;
+    let taxid = columns.next()?;
+    let taxid = match taxid.rsplit_once("(taxid ") {
+        Some((_, taxid)) => taxid.strip_suffix(')')?,
+        None => taxid,
+    };
+    match (status, taxid.trim().parse()) {
+        ("U", _) => Some((id, 0)),
+        ("C", Ok(taxid)) => Some((id, taxid)),
+        _ => None,
+    }
+}
+
+/// The per-read categories and the clade counts of two outputs
+#[derive(Debug, Default)]
+pub struct Comparison {
+    /// Reads of both outputs by category, in the order of [`Agreement::ALL`]
+    pub categories: [u64; 5],
+    /// Reads of the first output missing from the second
+    pub only_first: u64,
+    /// Reads of the second output missing from the first
+    pub only_second: u64,
+    /// Clade counts of the first and the second output, by external taxid
+    pub clade_counts: HashMap<u64, (u64, u64)>,
+}
+
+impl Comparison {
+    /// Reads found in both outputs
+    pub fn paired_reads(&self) -> u64 {
+        self.categories.iter().sum()
+    }
+
+    /// Reads of the category
+    pub fn count(&self, agreement: Agreement) -> u64 {
+        self.categories[agreement as usize]
+    }
+
+    /// Pearson correlation of the clade counts of both outputs over the taxa either calls
+    ///
+    /// `None` without taxa, or when the counts of an output are all the same.
+    pub fn correlation(&self) -> Option<f64> {
+        let n = self.clade_counts.len() as f64;
+        let mean = |pick: fn(&(u64, u64)) -> u64| {
+            self.clade_counts
+                .values()
+                .map(|c| pick(c) as f64)
+                .sum::<f64>()
+                / n
+        };
+        let (mean_first, mean_second) = (mean(|c| c.0), mean(|c| c.1));
+        let (mut covariance, mut var_first, mut var_second) = (0.0, 0.0, 0.0);
+        for &(first, second) in self.clade_counts.values() {
+            let (d1, d2) = (first as f64 - mean_first, second as f64 - mean_second);
+            covariance += d1 * d2;
+            var_first += d1 * d1;
+            var_second += d2 * d2;
+        }
+        (var_first > 0.0 && var_second > 0.0).then(|| covariance / (var_first * var_second).sqrt())
+    }
+
+    /// Adds a call to the clade counts of its taxon and of the ancestors of the taxon
+    fn add_clade(&mut self, taxonomy: &Taxonomy, taxid: u64, second: bool) {
+        if taxid == 0 {
+            return;
+        }
+        let mut add = |taxid: u64| {
+            let counts = self.clade_counts.entry(taxid).or_default();
+            if second {
+                counts.1 += 1;
+            } else {
+                counts.0 += 1;
+            }
+        };
+        match taxonomy.path_cache.get(&taxonomy.get_internal_id(taxid)) {
+            Some(path) => path
+                .iter()
+                .for_each(|&node| add(taxonomy.nodes[node as usize].external_id)),
+            None => add(taxid),
+        }
+    }
+}
+
+/// Calls every line of a plain or gzip compressed Kraken output with its read ID and taxid
+fn for_each_call<P, F>(path: P, mut f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str, u64) -> Result<()>,
+{
+    let path = path.as_ref();
+    let mut file = BufReader::new(open_file(path)?);
+    let reader: Box<dyn BufRead> = if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        Box::new(BufReader::new(MultiGzDecoder::new(file)))
+    } else {
+        Box::new(file)
+    };
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let (id, taxid) = kraken_call(&line).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{}:{}: not a Kraken output line",
+                    path.display(),
+                    number + 1
+                ),
+            )
+        })?;
+        f(id, taxid)?;
+    }
+    Ok(())
+}
+
+/// Compares the calls of two Kraken outputs, matching the reads by ID
+///
+/// `each` gets the ID, the category and both calls of every read found in both outputs,
+/// in the order of the first one.
+pub fn compare_outputs<P, F>(
+    taxonomy: &Taxonomy,
+    first: P,
+    second: P,
+    mut each: F,
+) -> Result<Comparison>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str, Agreement, u64, u64) -> Result<()>,
+{
+    let mut comparison = Comparison::default();
+    let mut second_calls: HashMap<String, u64> = HashMap::new();
+    for_each_call(second, |id, taxid| {
+        comparison.add_clade(taxonomy, taxid, true);
+        second_calls.insert(id.to_string(), taxid);
+        Ok(())
+    })?;
+    for_each_call(first, |id, taxid| {
+        comparison.add_clade(taxonomy, taxid, false);
+        let Some(other) = second_calls.remove(id) else {
+            comparison.only_first += 1;
+            return Ok(());
+        };
+        let agreement = Agreement::of(taxonomy, taxid, other);
+        comparison.categories[agreement as usize] += 1;
+        each(id, agreement, taxid, other)
+    })?;
+    comparison.only_second = second_calls.len() as u64;
+    Ok(comparison)
+}
@@ -1,22 +1,45 @@
-use crate::compact_hash::{Compact, HashConfig, Slot};
-// use crate::mmscanner::MinimizerScanner;
+use crate::compact_hash::{read_page_from_file, CHTable, Compact, HashConfig, Slot};
+use crate::mmscanner::{scan_minimizers_range, scan_pieces, ScanOptions, SCAN_PIECE_SIZE};
 use crate::taxonomy::{NCBITaxonomy, Taxonomy};
-use seqkmer::{read_parallel, BufferFastaReader, Meros};
+use seqkmer::{BufferFastaReader, Meros, Reader};
 
-use crate::utils::open_file;
+use crate::utils::{open_file, write_atomically};
 use byteorder::{LittleEndian, WriteBytesExt};
 use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Result as IOResult, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 // Define the number of Cells processed per batch
 const BATCH_SIZE: usize = 81920;
 
+/// The minimizer hashes of a genome, scanned in parallel pieces of [`SCAN_PIECE_SIZE`]
+///
+/// A chromosome of several megabases would otherwise keep one thread busy while the
+/// others wait at the end of the batch. See [`scan_pieces`].
+fn genome_minimizers(seq: &[u8], meros: &Meros, scan_options: ScanOptions) -> Vec<u64> {
+    scan_pieces(seq, meros, SCAN_PIECE_SIZE)
+        .into_par_iter()
+        .flat_map_iter(|range| {
+            // Only the genome ends with a trailing window, not its pieces
+            let scan_options = ScanOptions {
+                trailing_window: scan_options.trailing_window && range.end == seq.len(),
+                ..scan_options
+            };
+            let mut minimizers = Vec::new();
+            scan_minimizers_range(seq, range, meros, scan_options, &mut minimizers);
+            minimizers.into_iter().map(|(_, hash_key)| hash_key)
+        })
+        .collect()
+}
+
 /// Sets a cell in the page with the given item, handling collisions and LCA calculations
 ///
+/// Probes forward from the home cell of the item up to the end of the page, the item is
+/// left out if every cell on the way holds another key.
+///
 /// # Arguments
 ///
 /// * `taxonomy` - The taxonomy used for LCA calculations
@@ -36,7 +59,6 @@ fn set_page_cell(
     let mut idx = item.idx % page_size;
     let item_taxid: u32 = item.value.right(value_mask).to_u32();
     let compact_key = item.value.left(value_bits);
-    let first_idx = idx;
 
     loop {
         let result = page[idx].fetch_update(Ordering::SeqCst, Ordering::Relaxed, |current| {
@@ -53,12 +75,18 @@ fn set_page_cell(
             }
         });
 
-        if result.is_ok() || idx == first_idx {
+        if result.is_ok() {
             break;
         }
 
-        idx = (idx + 1) % page_size;
-        if idx == first_idx {
+        // Probe like `Page::find_index`, which does not wrap around. Stopping at the first
+        // occupied cell would keep whichever key got there first, the content of the
+        // table would then depend on the order of the parallel insertions. A key still
+        // without a cell at the end of the page is dropped: no lookup reaches a cell
+        // before its home, and wrapping it to the start of the page would take the cell
+        // of a key homed there, depending on which of the two came first.
+        idx += 1;
+        if idx >= page_size {
             break;
         }
     }
@@ -82,28 +110,33 @@ fn write_hashtable_to_file(
     page_index: u64,
     capacity: u64,
 ) -> IOResult<usize> {
-    // Open the file for writing
-    let file = File::create(file_path)?;
-    let mut writer = BufWriter::new(file);
     let mut count = 0;
-    writer.write_u64::<LittleEndian>(page_index)?;
-    writer.write_u64::<LittleEndian>(capacity)?;
+    write_atomically(file_path, |tmp| {
+        let mut writer = BufWriter::new(File::create(tmp)?);
+        writer.write_u64::<LittleEndian>(page_index)?;
+        writer.write_u64::<LittleEndian>(capacity)?;
+
+        for item in page {
+            let value = item.load(Ordering::Relaxed);
+            if value != 0 {
+                count += 1;
+            }
 
-    for item in page {
-        let value = item.load(Ordering::Relaxed);
-        if value != 0 {
-            count += 1;
+            writer.write_u32::<LittleEndian>(value)?;
         }
 
-        writer.write_u32::<LittleEndian>(value)?;
-    }
-
-    writer.flush()?; // Ensure all content is written to the file
+        writer.flush() // Ensure all content is written to the file
+    })?;
     Ok(count)
 }
 
 /// Processes a k2 file and updates the hash table
 ///
+/// With `max_rank`, the minimizers whose LCA sits above that rank (e.g. shared by the
+/// rRNA operons or the transposons of many genera) are left out of the page. The cells
+/// can't simply be cleared, that would cut the probing of the keys stored after them:
+/// the page is filled again from the chunk file with the kept minimizers only.
+///
 /// # Arguments
 ///
 /// * `config` - The HashConfig for the process
@@ -112,10 +145,57 @@ fn write_hashtable_to_file(
 /// * `taxonomy` - The taxonomy used for processing
 /// * `page_size` - The size of each page
 /// * `page_index` - The index of the current page
+/// * `max_rank` - The report code of the highest rank a minimizer LCA may have, see
+///   [`Taxonomy::is_within_rank`]
 ///
 /// # Returns
 ///
-/// The number of items processed
+/// The number of items written and the number of minimizers left out
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::compact_hash::{Compact, HashConfig, Slot};
+/// use kun_peng::db::process_k2file;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// // root -> Escherichia -> {E. coli, E. albertii}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.rank_data = b"no rank\0genus\0species\0".to_vec();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id, rank_offset) in [(1, 1, 0), (561, 1, 8), (562, 2, 14), (208962, 2, 14)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, rank_offset, ..Default::default() });
+/// }
+/// for (taxon, path) in [(1, vec![1]), (2, vec![1, 2]), (3, vec![1, 2, 3]), (4, vec![1, 2, 4])] {
+///     taxonomy.path_cache.insert(taxon, path);
+/// }
+///
+/// let dir = std::env::temp_dir().join("kun_peng_process_k2file");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let config = HashConfig::new(1, 16, 16, 0, 1, 16);
+/// let chunk_file = dir.join("chunk_1.k2");
+/// // The repeat 0xA is in both species, the minimizer 0xB of E. coli probes past it.
+/// let slots = [(0xA, 3), (0xA, 4), (0xB, 3)].map(|(key, taxid)| Slot::new(5, u32::combined(key, taxid, 16)));
+/// let slot_size = std::mem::size_of::<Slot<u32>>();
+/// let bytes: Vec<u8> = slots.iter().flat_map(|slot| slot.as_slice(slot_size).to_vec()).collect();
+/// let page = |max_rank| {
+///     std::fs::write(&chunk_file, &bytes).unwrap();
+///     let counts = process_k2file(config, &dir, &chunk_file, &taxonomy, 16, 1, max_rank).unwrap();
+///     let data = std::fs::read(dir.join("hash_1.k2d")).unwrap();
+///     let cells: Vec<u32> = data[16..].chunks(4).map(|b| u32::from_le_bytes(b.try_into().unwrap())).collect();
+///     (counts, cells)
+/// };
+///
+/// let (counts, cells) = page(None);
+/// assert_eq!(counts, (2, 0));
+/// assert_eq!(cells.iter().filter(|&&cell| cell != 0).count(), 2);
+/// assert!(cells.contains(&u32::combined(0xA, 2, 16)));
+///
+/// let (counts, cells) = page(Some('S'));
+/// assert_eq!(counts, (1, 1));
+/// assert_eq!(cells[5], u32::combined(0xB, 3, 16));
+/// assert_eq!(cells.iter().filter(|&&cell| cell != 0).count(), 1);
+/// ```
 pub fn process_k2file(
     config: HashConfig,
     database: &PathBuf,
@@ -123,9 +203,8 @@ pub fn process_k2file(
     taxonomy: &Taxonomy,
     page_size: usize,
     page_index: usize,
-) -> IOResult<usize> {
-    let total_counter = AtomicUsize::new(0);
-
+    max_rank: Option<char>,
+) -> IOResult<(usize, usize)> {
     let value_mask = config.value_mask;
     let value_bits = config.value_bits;
 
@@ -135,8 +214,60 @@ pub fn process_k2file(
     let capacity = end_index - start_index;
     let page_file = database.join(format!("hash_{}.k2d", page_index));
 
-    let page: Vec<AtomicU32> = (0..capacity).map(|_| AtomicU32::new(0)).collect();
+    let mut page: Vec<AtomicU32> = (0..capacity).map(|_| AtomicU32::new(0)).collect();
+    merge_k2file(&page, chunk_file, taxonomy, value_bits, value_mask, |_| {
+        true
+    })?;
+
+    let mut removed = 0;
+    if let Some(max_rank) = max_rank {
+        let merged: Vec<u32> = page.into_iter().map(AtomicU32::into_inner).collect();
+        let kept = |taxid: u32| taxonomy.is_within_rank(taxid, max_rank);
+        removed = merged
+            .iter()
+            .filter(|&&cell| cell != 0 && !kept(cell.right(value_mask)))
+            .count();
+        page = (0..capacity).map(|_| AtomicU32::new(0)).collect();
+        merge_k2file(
+            &page,
+            chunk_file,
+            taxonomy,
+            value_bits,
+            value_mask,
+            |item| kept(page_cell_taxid(&merged, item, value_bits, value_mask)),
+        )?;
+    }
+
+    let size_count =
+        write_hashtable_to_file(&page, &page_file, page_index as u64, capacity as u64)?;
+    Ok((size_count, removed))
+}
+
+/// The taxid stored in a page for the key of `item`, probing like [`set_page_cell`]
+fn page_cell_taxid(page: &[u32], item: &Slot<u32>, value_bits: usize, value_mask: usize) -> u32 {
+    let compact_key = item.value.left(value_bits);
+    page[item.idx % page.len()..]
+        .iter()
+        .find(|cell| cell.right(value_mask) == 0 || cell.left(value_bits) == compact_key)
+        .map_or(0, |cell| cell.right(value_mask))
+}
 
+/// Sets the cells of a chunk file in a page, see [`set_page_cell`]
+///
+/// Only the cells passing `keep` are set. Returns the number of cells read.
+fn merge_k2file<F>(
+    page: &[AtomicU32],
+    chunk_file: &PathBuf,
+    taxonomy: &Taxonomy,
+    value_bits: usize,
+    value_mask: usize,
+    keep: F,
+) -> IOResult<usize>
+where
+    F: Fn(&Slot<u32>) -> bool + Sync,
+{
+    let mut total_cells = 0;
+    let capacity = page.len();
     let file = open_file(&chunk_file)?;
     let mut reader = BufReader::new(file);
 
@@ -155,15 +286,123 @@ pub fn process_k2file(
         let cells = unsafe {
             std::slice::from_raw_parts(batch_buffer.as_ptr() as *const Slot<u32>, cells_in_batch)
         };
-        cells.par_iter().for_each(|item| {
-            set_page_cell(taxonomy, &page, item, capacity, value_bits, value_mask);
+        cells.par_iter().filter(|item| keep(item)).for_each(|item| {
+            set_page_cell(taxonomy, page, item, capacity, value_bits, value_mask);
         });
-        total_counter.fetch_add(cells.len(), Ordering::SeqCst);
+        total_cells += cells.len();
     }
+    Ok(total_cells)
+}
+
+/// Adds the cells of a chunk file to an existing hash page
+///
+/// The page is read from `page_file`. With `remap`, indexed by the old internal taxid, its
+/// values are first translated to the internal taxids of the updated `taxonomy`. The cells
+/// are merged with the LCA of the existing values and the page is written to `out_file`,
+/// `page_file` is left untouched.
+///
+/// # Returns
+///
+/// The number of non-empty cells of the updated page and its capacity
+pub fn add_k2file_to_page(
+    config: HashConfig,
+    page_file: &PathBuf,
+    chunk_file: &PathBuf,
+    out_file: &PathBuf,
+    taxonomy: &Taxonomy,
+    remap: Option<&[u32]>,
+) -> IOResult<(usize, usize)> {
+    let value_mask = config.value_mask;
+    let value_bits = config.value_bits;
+
+    let old_page = read_page_from_file(page_file)?;
+    let capacity = old_page.size;
+    let page: Vec<AtomicU32> = old_page
+        .data
+        .into_iter()
+        .take(capacity)
+        .map(|value| match remap {
+            Some(remap) if value != 0 => {
+                let taxid = remap[value.right(value_mask) as usize];
+                AtomicU32::new(u32::combined(value.left(value_bits), taxid, value_bits))
+            }
+            _ => AtomicU32::new(value),
+        })
+        .collect();
+    merge_k2file(&page, chunk_file, taxonomy, value_bits, value_mask, |_| {
+        true
+    })?;
 
     let size_count =
-        write_hashtable_to_file(&page, &page_file, page_index as u64, capacity as u64)?;
-    Ok(size_count)
+        write_hashtable_to_file(&page, out_file, old_page.index as u64, capacity as u64)?;
+    Ok((size_count, capacity))
+}
+
+/// Counts the cells of an index that the genomes of a library don't account for
+///
+/// Every minimizer of the library is looked up in `table`: the LCA of the minimizers whose
+/// lookup stops at a cell is the taxid a build from the library stored there. A cell no
+/// minimizer reaches, or holding another taxid, has minimizers of other genomes, e.g.
+/// ones added with `add-to-db`. Unlike the cells themselves, this doesn't depend on the
+/// capacity of the table, where keys sharing a compacted key only sometimes meet in a
+/// cell, or on its layout.
+pub fn unexplained_cells(
+    fna_files: &[PathBuf],
+    meros: Meros,
+    scan_options: ScanOptions,
+    taxonomy: &Taxonomy,
+    id_to_taxon_map: &HashMap<String, u64>,
+    table: &CHTable,
+    threads: usize,
+) -> IOResult<usize> {
+    let config = table.config;
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("failed to build thread pool");
+    let mut taxids = vec![0u32; config.capacity];
+    for fna_file in fna_files {
+        let mut reader = BufferFastaReader::from_path(fna_file, 1)?;
+        while let Some(seqs) = reader.next()? {
+            let reached: Vec<(usize, u32)> = pool.install(|| {
+                seqs.par_iter()
+                    .filter_map(|record| {
+                        id_to_taxon_map
+                            .get(&record.header.id)
+                            .map(|ext_taxid| (record, taxonomy.get_internal_id(*ext_taxid)))
+                    })
+                    .flat_map_iter(|(record, taxid)| {
+                        let mut minimizers = Vec::new();
+                        record.body.apply(|seq| {
+                            minimizers.extend(genome_minimizers(seq, &meros, scan_options))
+                        });
+                        minimizers
+                            .into_iter()
+                            .filter_map(move |hash_key| Some((table.cell_of(hash_key)?, taxid)))
+                    })
+                    .collect()
+            });
+            for (cell, taxid) in reached {
+                taxids[cell] = match taxids[cell] {
+                    0 => taxid,
+                    current => taxonomy.lca(current, taxid),
+                };
+            }
+        }
+    }
+
+    let unexplained = table
+        .pages
+        .iter()
+        .enumerate()
+        .flat_map(|(i, page)| {
+            let start = i * config.hash_capacity;
+            let end = config.capacity.min(start + config.hash_capacity);
+            page.data[..end - start].iter().zip(&taxids[start..end])
+        })
+        .filter(|(&cell, &taxid)| cell.right(config.value_mask) != taxid)
+        .count();
+    Ok(unexplained)
 }
 
 /// Generates a taxonomy tree file
@@ -173,6 +412,8 @@ pub fn process_k2file(
 /// * `ncbi_taxonomy_directory` - The directory containing NCBI taxonomy files
 /// * `taxonomy_filename` - The output filename for the generated taxonomy
 /// * `id_map` - A map of string IDs to u64 IDs
+/// * `extra_taxa` - Custom taxa merged into the NCBI ones, see
+///   [`NCBITaxonomy::add_extra_taxa`]
 ///
 /// # Returns
 ///
@@ -181,10 +422,15 @@ pub fn generate_taxonomy(
     ncbi_taxonomy_directory: &PathBuf,
     taxonomy_filename: &PathBuf,
     id_map: &HashMap<String, u64>,
+    extra_taxa: Option<&PathBuf>,
 ) -> IOResult<Taxonomy> {
     let nodes_filename = ncbi_taxonomy_directory.join("nodes.dmp");
     let names_filename = ncbi_taxonomy_directory.join("names.dmp");
     let mut ncbi = NCBITaxonomy::from_ncbi(nodes_filename, names_filename)?;
+    if let Some(extra_taxa) = extra_taxa {
+        let added = ncbi.add_extra_taxa(extra_taxa)?;
+        println!("{} custom taxa added to the taxonomy", added);
+    }
 
     for (_, id) in id_map.into_iter() {
         ncbi.mark_node(*id);
@@ -197,6 +443,52 @@ pub fn generate_taxonomy(
     Ok(taxo)
 }
 
+/// Extends a taxonomy with new taxids from an NCBI taxonomy directory
+///
+/// The new taxonomy holds the taxa of `taxonomy` and of `new_taxids`, with their lineages
+/// from `nodes.dmp` and `names.dmp`. Its internal taxids differ from the ones of
+/// `taxonomy`, the returned map gives the new internal taxid of every old one.
+///
+/// # Returns
+///
+/// The extended Taxonomy and the old to new internal taxid map
+pub fn extend_taxonomy(
+    ncbi_taxonomy_directory: &PathBuf,
+    taxonomy: &Taxonomy,
+    new_taxids: &[u64],
+) -> IOResult<(Taxonomy, Vec<u32>)> {
+    let nodes_filename = ncbi_taxonomy_directory.join("nodes.dmp");
+    let names_filename = ncbi_taxonomy_directory.join("names.dmp");
+    let mut ncbi = NCBITaxonomy::from_ncbi(nodes_filename, names_filename)?;
+
+    for node in taxonomy.nodes.iter().skip(1) {
+        ncbi.mark_node(node.external_id);
+    }
+    for &taxid in new_taxids {
+        ncbi.mark_node(taxid);
+    }
+    let mut taxo = ncbi.convert_to_kraken_taxonomy();
+    taxo.generate_external_to_internal_id_map();
+    taxo.build_path_cache();
+
+    let mut remap = vec![0u32; taxonomy.nodes.len()];
+    for (old_id, node) in taxonomy.nodes.iter().enumerate().skip(1) {
+        remap[old_id] = match taxo.get_internal_id(node.external_id) {
+            0 => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "taxid {} of the database is missing from {:?}",
+                        node.external_id, ncbi_taxonomy_directory
+                    ),
+                ))
+            }
+            new_id => new_id,
+        };
+    }
+    Ok((taxo, remap))
+}
+
 /// Calculates the number of bits required to store the maximum internal taxid
 ///
 /// # Arguments
@@ -206,13 +498,32 @@ pub fn generate_taxonomy(
 ///
 /// # Returns
 ///
-/// The number of bits required for taxid storage
+/// The number of bits required for taxid storage. The rest of the 32 bits of a cell keep
+/// the minimizer hash, the fewer are left the more minimizers share a cell key.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::db::get_bits_for_taxid;
+///
+/// // 5 million taxa leave 9 bits to the minimizer hash
+/// assert_eq!(get_bits_for_taxid(0, 5_000_001.0), Ok(23));
+/// assert!(get_bits_for_taxid(16, 5_000_001.0).is_err());
+/// // A cell needs at least one bit of the hash
+/// assert!(get_bits_for_taxid(0, 3e9).is_err());
+/// ```
 pub fn get_bits_for_taxid(
     requested_bits_for_taxid: usize,
     node_count: f64,
 ) -> Result<usize, String> {
     // Calculate the minimum number of bits needed to store the taxonomy node count
     let bits_needed_for_value = (node_count.log2().ceil() as usize).max(1);
+    if bits_needed_for_value >= u32::BITS as usize {
+        return Err(format!(
+            "{} taxonomy nodes don't fit the 32 bit cells of the index",
+            node_count
+        ));
+    }
 
     // Check if more bits are required for storing taxid
     if requested_bits_for_taxid > 0 && bits_needed_for_value > requested_bits_for_taxid as usize {
@@ -228,67 +539,80 @@ pub fn get_bits_for_taxid(
 ///
 /// * `fna_file` - The input FNA file path
 /// * `meros` - The Meros instance for k-mer processing
+/// * `scan_options` - The IUPAC policy, whitespace and strand options of the scanner
 /// * `taxonomy` - The taxonomy used for processing
 /// * `id_to_taxon_map` - A map of string IDs to taxon IDs
 /// * `hash_config` - The HashConfig for the process
 /// * `writers` - A vector of BufWriters for output
 /// * `chunk_size` - The size of each chunk
 /// * `threads` - The number of threads to use for processing
+///
+/// A read or decompression error of the FNA file is returned, instead of building the
+/// database of the sequences before it.
+#[allow(clippy::too_many_arguments)]
 pub fn convert_fna_to_k2_format<P: AsRef<Path>>(
     fna_file: P,
     meros: Meros,
+    scan_options: ScanOptions,
     taxonomy: &Taxonomy,
     id_to_taxon_map: &HashMap<String, u64>,
     hash_config: HashConfig,
     writers: &mut Vec<BufWriter<File>>,
     chunk_size: usize,
     threads: usize,
-) {
-    let mut reader = BufferFastaReader::from_path(fna_file, 1).unwrap();
+) -> IOResult<()> {
+    let mut reader = BufferFastaReader::from_path(fna_file, 1)?;
     let value_bits = hash_config.value_bits;
     let cell_size = std::mem::size_of::<Slot<u32>>();
-
-    read_parallel(
-        &mut reader,
-        threads,
-        &meros,
-        |seqs| {
-            let mut k2_cell_list = Vec::new();
-
-            for record in seqs {
-                let header = &record.header;
-                record.body.apply_mut(|m_iter| {
-                    if let Some(ext_taxid) = id_to_taxon_map.get(&header.id) {
-                        let taxid = taxonomy.get_internal_id(*ext_taxid);
-                        let k2_cell: Vec<(usize, Slot<u32>)> = m_iter
-                            .map(|(_, hash_key)| {
-                                let index: usize = hash_config.index(hash_key);
-                                let idx = index % chunk_size;
-                                let partition_index = index / chunk_size;
-                                let cell =
-                                    Slot::new(idx, u32::hash_value(hash_key, value_bits, taxid));
-                                (partition_index, cell)
-                            })
-                            .collect();
-
-                        k2_cell_list.extend_from_slice(&k2_cell);
-                    }
-                });
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("failed to build thread pool");
+
+    // The build scans every position of every genome, it uses the batched
+    // scanner instead of the one of `read_parallel`.
+    let (sender, receiver) = std::sync::mpsc::sync_channel(threads + 2);
+    std::thread::scope(|scope| {
+        let reading = scope.spawn(move || -> IOResult<()> {
+            while let Some(seqs) = reader.next()? {
+                if sender.send(seqs).is_err() {
+                    break;
+                }
             }
+            Ok(())
+        });
 
-            k2_cell_list
-        },
-        |record_sets| {
-            while let Some(data) = record_sets.next() {
-                let k2_cell_map = data.unwrap();
-                for cell in k2_cell_map {
-                    let partition_index = cell.0;
-                    if let Some(writer) = writers.get_mut(partition_index) {
-                        writer.write_all(&cell.1.as_slice(cell_size)).unwrap();
-                    }
+        for seqs in receiver {
+            let k2_cell_list: Vec<(usize, Slot<u32>)> = pool.install(|| {
+                seqs.par_iter()
+                    .filter_map(|record| {
+                        id_to_taxon_map
+                            .get(&record.header.id)
+                            .map(|ext_taxid| (record, taxonomy.get_internal_id(*ext_taxid)))
+                    })
+                    .flat_map_iter(|(record, taxid)| {
+                        let mut minimizers = Vec::new();
+                        record.body.apply(|seq| {
+                            minimizers.extend(genome_minimizers(seq, &meros, scan_options))
+                        });
+                        minimizers.into_iter().map(move |hash_key| {
+                            let index: usize = hash_config.index(hash_key);
+                            let idx = index % chunk_size;
+                            let partition_index = index / chunk_size;
+                            let cell = Slot::new(idx, u32::hash_value(hash_key, value_bits, taxid));
+                            (partition_index, cell)
+                        })
+                    })
+                    .collect()
+            });
+
+            for cell in k2_cell_list {
+                let partition_index = cell.0;
+                if let Some(writer) = writers.get_mut(partition_index) {
+                    writer.write_all(&cell.1.as_slice(cell_size))?;
                 }
             }
-        },
-    )
-    .expect("failed");
+        }
+        reading.join().expect("the FNA reader panicked")
+    })
 }
@@ -0,0 +1,257 @@
+//! Header shared by the binary files of Kun-peng
+//!
+//! A file starts with a fixed header, all integers little endian:
+//!
+//! ```text
+//! magic "KUNPENG\0" | kind u8 | version u8 | flags u16 | payload_len u64 | checksum u32
+//! ```
+//!
+//! `payload_len` and `checksum`, the CRC-32 of the payload, cover the bytes right after
+//! the header. Files written block by block, whose length isn't known upfront, store
+//! [`STREAMED`] and a zero checksum, their blocks carry their own lengths. Bytes after the
+//! payload are left to the format, e.g. the provenance trailer of `hash_config.k2d`.
+//!
+//! | kind | file | versions |
+//! |------|------|----------|
+//! | 1 | `hash_config.k2d` | 0: the six `u64` fields without a header, 1, 2: the table layout |
+//! | 2 | spill files of `annotate` | 1: the `KPSP` header, 2 |
+//! | 3 | classification checkpoints | 0: the JSON without a header, 1 |
+//!
+//! Files that predate the header are read as version 0. The Kraken 2 files, `taxo.k2d`
+//! and `opts.k2d`, keep their Kraken 2 layout so that both tools can read them, and the
+//! hash table pages keep theirs, they are read in place at fixed offsets.
+use flate2::Crc;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+/// First bytes of every file with a header
+pub const MAGIC: [u8; 8] = *b"KUNPENG\0";
+/// Number of bytes of the header
+pub const HEADER_SIZE: usize = 24;
+/// `payload_len` of a file written block by block
+pub const STREAMED: u64 = u64::MAX;
+
+/// The type of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    HashConfig = 1,
+    Spill = 2,
+    Checkpoint = 3,
+}
+
+impl Kind {
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::HashConfig),
+            2 => Some(Self::Spill),
+            3 => Some(Self::Checkpoint),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::HashConfig => "hash config",
+            Self::Spill => "spill",
+            Self::Checkpoint => "checkpoint",
+        }
+    }
+}
+
+/// The header of a file, see the [module documentation](self)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub kind: Kind,
+    /// 0 for a file written before the header
+    pub version: u8,
+    pub flags: u16,
+    /// [`STREAMED`] for a file written block by block
+    pub payload_len: u64,
+    pub checksum: u32,
+}
+
+impl Header {
+    /// The header of a file that predates the header
+    pub fn legacy(kind: Kind) -> Self {
+        Self {
+            kind,
+            version: 0,
+            flags: 0,
+            payload_len: STREAMED,
+            checksum: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0u8; HEADER_SIZE];
+        bytes[..8].copy_from_slice(&MAGIC);
+        bytes[8] = self.kind as u8;
+        bytes[9] = self.version;
+        bytes[10..12].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[12..20].copy_from_slice(&self.payload_len.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Parses a header, checking it is one of a `kind` file no newer than `max_version`
+    pub fn from_bytes(bytes: &[u8; HEADER_SIZE], kind: Kind, max_version: u8) -> Result<Self> {
+        if bytes[..8] != MAGIC {
+            return Err(invalid(format!("not a Kun-peng {} file", kind.name())));
+        }
+        let found = Kind::from_code(bytes[8]).ok_or_else(|| {
+            invalid(format!(
+                "unknown file kind {}, expected a {} file",
+                bytes[8],
+                kind.name()
+            ))
+        })?;
+        if found != kind {
+            return Err(invalid(format!(
+                "found a {} file, expected a {} file",
+                found.name(),
+                kind.name()
+            )));
+        }
+        let version = bytes[9];
+        if version == 0 || version > max_version {
+            return Err(invalid(format!(
+                "{} file version {}, this build reads versions up to {}",
+                kind.name(),
+                version,
+                max_version
+            )));
+        }
+        Ok(Self {
+            kind,
+            version,
+            flags: u16::from_le_bytes([bytes[10], bytes[11]]),
+            payload_len: u64::from_le_bytes(bytes[12..20].try_into().unwrap()),
+            checksum: u32::from_le_bytes(bytes[20..24].try_into().unwrap()),
+        })
+    }
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// CRC-32 of a payload
+pub fn checksum(payload: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(payload);
+    crc.sum()
+}
+
+/// Writes the header of a file written block by block
+pub fn write_header<W: Write>(writer: &mut W, kind: Kind, version: u8, flags: u16) -> Result<()> {
+    let header = Header {
+        kind,
+        version,
+        flags,
+        payload_len: STREAMED,
+        checksum: 0,
+    };
+    writer.write_all(&header.to_bytes())
+}
+
+/// Reads the header of a file, failing for another kind, a newer version or no header
+pub fn read_header<R: Read>(reader: &mut R, kind: Kind, max_version: u8) -> Result<Header> {
+    let mut bytes = [0u8; HEADER_SIZE];
+    reader.read_exact(&mut bytes)?;
+    Header::from_bytes(&bytes, kind, max_version)
+}
+
+/// Writes a header with the length and the checksum of `payload`, then the payload
+pub fn write_payload<W: Write>(
+    writer: &mut W,
+    kind: Kind,
+    version: u8,
+    flags: u16,
+    payload: &[u8],
+) -> Result<()> {
+    let header = Header {
+        kind,
+        version,
+        flags,
+        payload_len: payload.len() as u64,
+        checksum: checksum(payload),
+    };
+    writer.write_all(&header.to_bytes())?;
+    writer.write_all(payload)
+}
+
+/// Reads a file written by [`write_payload`] or one that predates the header
+///
+/// The payload is checked against the length and the checksum of the header, the bytes
+/// after it are not read. A file without the magic is read to its end as the payload of
+/// a version 0 header, its format is known by the caller.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::fmt::{read_payload, write_payload, Kind, HEADER_SIZE};
+///
+/// let mut file = Vec::new();
+/// write_payload(&mut file, Kind::Checkpoint, 1, 0, b"{\"reads\":7}").unwrap();
+/// file.extend_from_slice(b"trailer");
+/// let (header, payload) = read_payload(&file[..], Kind::Checkpoint, 1).unwrap();
+/// assert_eq!((header.version, &payload[..]), (1, &b"{\"reads\":7}"[..]));
+///
+/// // A file written before the header
+/// let (header, payload) = read_payload(&b"{\"reads\":7}"[..], Kind::Checkpoint, 1).unwrap();
+/// assert_eq!((header.version, &payload[..]), (0, &b"{\"reads\":7}"[..]));
+///
+/// let error = |file: &[u8]| read_payload(file, Kind::Checkpoint, 1).unwrap_err().to_string();
+/// // A flipped bit, a cut payload
+/// let mut corrupt = file.clone();
+/// corrupt[HEADER_SIZE + 3] ^= 1;
+/// assert!(error(&corrupt).contains("checksum"), "{}", error(&corrupt));
+/// assert!(error(&file[..HEADER_SIZE + 4]).contains("truncated"));
+/// // Another kind, a newer version
+/// let mut spill = file.clone();
+/// spill[8] = Kind::Spill as u8;
+/// assert_eq!(error(&spill), "found a spill file, expected a checkpoint file");
+/// let mut newer = file.clone();
+/// newer[9] = 2;
+/// assert!(error(&newer).contains("version 2"));
+/// ```
+pub fn read_payload<R: Read>(
+    mut reader: R,
+    kind: Kind,
+    max_version: u8,
+) -> Result<(Header, Vec<u8>)> {
+    let mut prefix = Vec::with_capacity(HEADER_SIZE);
+    (&mut reader)
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut prefix)?;
+    if !prefix.starts_with(&MAGIC) {
+        reader.read_to_end(&mut prefix)?;
+        return Ok((Header::legacy(kind), prefix));
+    }
+    let bytes: &[u8; HEADER_SIZE] = prefix[..]
+        .try_into()
+        .map_err(|_| invalid(format!("truncated {} file header", kind.name())))?;
+    let header = Header::from_bytes(bytes, kind, max_version)?;
+    if header.payload_len == STREAMED {
+        return Err(invalid(format!(
+            "{} file written block by block, expected a single payload",
+            kind.name()
+        )));
+    }
+    let mut payload = Vec::new();
+    reader.take(header.payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != header.payload_len {
+        return Err(invalid(format!(
+            "truncated {} file, {} of {} payload bytes",
+            kind.name(),
+            payload.len(),
+            header.payload_len
+        )));
+    }
+    if checksum(&payload) != header.checksum {
+        return Err(invalid(format!(
+            "{} file checksum mismatch, the file is corrupt",
+            kind.name()
+        )));
+    }
+    Ok((header, payload))
+}
@@ -1,10 +1,11 @@
 use crate::compact_hash::Row;
-use crate::utils::open_file;
-use seqkmer::Meros;
+use crate::utils::{check_meros, open_file, write_atomically};
 use seqkmer::OptionPair;
 use seqkmer::CURRENT_REVCOM_VERSION;
+use seqkmer::{Meros, BITS_PER_CHAR};
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::{Read, Result as IoResult, Write};
+use std::io::{Error as IoError, ErrorKind, Read, Result as IoResult, Write};
 use std::mem;
 use std::path::Path;
 
@@ -63,12 +64,25 @@ pub struct HitGroup {
     pub rows: Vec<Row>,
     /// Range example: (0..10], left-open right-closed
     pub range: OptionPair<(usize, usize)>,
+    /// Number of k-mers of each mate, reported in the hit string of mates without any minimizer
+    pub kmer_counts: OptionPair<usize>,
 }
 
 impl HitGroup {
     /// Creates a new HitGroup
     pub fn new(rows: Vec<Row>, range: OptionPair<(usize, usize)>) -> Self {
-        Self { rows, range }
+        let kmer_counts = range.apply(|_| 0);
+        Self {
+            rows,
+            range,
+            kmer_counts,
+        }
+    }
+
+    /// Sets the k-mer counts from the mate lengths, mates shorter than `k` have none
+    pub fn with_seq_lens(mut self, seq_lens: OptionPair<usize>, k: usize) -> Self {
+        self.kmer_counts = seq_lens.apply(|len| (len + 1).saturating_sub(k));
+        self
     }
 
     /// Calculates the capacity of the HitGroup
@@ -76,21 +90,156 @@ impl HitGroup {
         self.range.reduce(0, |acc, range| acc + range.1 - range.0)
     }
 
+    /// Counts the hit groups of the read
+    ///
+    /// Consecutive hits separated by at most `gap` non-matching minimizers
+    /// are counted as a single group.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::Row;
+    /// use kun_peng::HitGroup;
+    /// use seqkmer::OptionPair;
+    ///
+    /// // Hits at minimizer positions 1 and 3, position 2 is a miss
+    /// let rows = vec![Row::new(1, 0, 1), Row::new(1, 0, 3)];
+    /// let hits = HitGroup::new(rows, OptionPair::Single((0, 3)));
+    ///
+    /// assert_eq!(hits.num_hit_groups(0), 2);
+    /// assert_eq!(hits.num_hit_groups(1), 1);
+    /// ```
+    pub fn num_hit_groups(&self, gap: usize) -> usize {
+        let mut groups = 0;
+        let mut last_pos: Option<u32> = None;
+        for row in &self.rows {
+            match last_pos {
+                Some(pos) if (row.kmer_id as usize) <= pos as usize + gap + 1 => {}
+                _ => groups += 1,
+            }
+            last_pos = Some(row.kmer_id);
+        }
+        groups
+    }
+
+    /// Checks if the read has at least `minimum_hit_groups` hit groups, a call needs them
+    ///
+    /// Hit groups are counted with [`HitGroup::num_hit_groups`] when `gap` is set,
+    /// otherwise every minimizer of the read counts as a group.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::Row;
+    /// use kun_peng::HitGroup;
+    /// use seqkmer::OptionPair;
+    ///
+    /// // Hits at minimizer positions 1, 2 and 5 of 6, two groups with a gap of 0
+    /// let rows = vec![Row::new(1, 0, 1), Row::new(1, 0, 2), Row::new(1, 0, 5)];
+    /// let hits = HitGroup::new(rows, OptionPair::Single((0, 6)));
+    ///
+    /// assert!(hits.meets_hit_groups(2, Some(0)));
+    /// assert!(!hits.meets_hit_groups(3, Some(0)));
+    /// assert!(hits.meets_hit_groups(0, Some(0)));
+    /// assert!(hits.meets_hit_groups(6, None));
+    /// assert!(!hits.meets_hit_groups(7, None));
+    ///
+    /// let empty = HitGroup::new(Vec::new(), OptionPair::Single((0, 0)));
+    /// assert!(empty.meets_hit_groups(0, Some(0)));
+    /// assert!(!empty.meets_hit_groups(1, None));
+    /// ```
+    pub fn meets_hit_groups(&self, minimum_hit_groups: usize, gap: Option<usize>) -> bool {
+        let hit_groups = match gap {
+            Some(gap) => self.num_hit_groups(gap),
+            None => self.capacity(),
+        };
+        hit_groups >= minimum_hit_groups
+    }
+
+    /// Checks if the read has hits, but fewer hit groups than a call needs
+    ///
+    /// Such a read has insufficient evidence, a read without any hit has none.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::compact_hash::Row;
+    /// use kun_peng::HitGroup;
+    /// use seqkmer::OptionPair;
+    ///
+    /// let rows = vec![Row::new(1, 0, 1), Row::new(1, 0, 2), Row::new(1, 0, 5)];
+    /// let hits = HitGroup::new(rows, OptionPair::Single((0, 6)));
+    /// assert!(hits.below_hit_groups(3, Some(0)));
+    /// assert!(!hits.below_hit_groups(2, Some(0)));
+    ///
+    /// let empty = HitGroup::new(Vec::new(), OptionPair::Single((0, 6)));
+    /// assert!(!empty.below_hit_groups(3, Some(0)));
+    /// ```
+    pub fn below_hit_groups(&self, minimum_hit_groups: usize, gap: Option<usize>) -> bool {
+        !self.rows.is_empty() && !self.meets_hit_groups(minimum_hit_groups, gap)
+    }
+
     /// Calculates the required score based on a confidence threshold
     pub fn required_score(&self, confidence_threshold: f64) -> u64 {
         (confidence_threshold * self.capacity() as f64).ceil() as u64
     }
+
+    /// Calculates the required score of a confidence threshold, at least `min_score`
+    ///
+    /// A fraction of the few minimizers of a short read is reached by a couple of hits,
+    /// the floor makes every read need `min_score` supporting minimizers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::classify::{process_hitgroup, HitGroupOptions};
+    /// use kun_peng::compact_hash::{Compact, Row};
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use kun_peng::HitGroup;
+    /// use seqkmer::OptionPair;
+    /// use std::sync::atomic::AtomicUsize;
+    ///
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// taxonomy.nodes.push(TaxonomyNode { external_id: 562, parent_id: 0, ..Default::default() });
+    /// taxonomy.path_cache.insert(1, vec![1]);
+    ///
+    /// let options = HitGroupOptions { minimum_hit_groups: 0, ..HitGroupOptions::new(0xFFFF) };
+    /// let counter = AtomicUsize::new(0);
+    /// // `hits` of the `len` minimizers of a read hit E. coli
+    /// let call = |len: u32, hits: u32, min_score: u64| {
+    ///     let rows = (1..=hits).map(|i| Row::new(u32::combined(7, 1, 16), 0, i)).collect();
+    ///     let hits = HitGroup::new(rows, OptionPair::Single((0, len as usize)));
+    ///     let required_score = hits.required_score_with_floor(0.5, min_score);
+    ///     process_hitgroup(&hits, &taxonomy, &counter, required_score, &options).taxid
+    /// };
+    ///
+    /// // Two of three minimizers pass the fraction, not a floor of 5
+    /// assert_eq!(call(3, 2, 0), 562);
+    /// assert_eq!(call(3, 2, 5), 0);
+    /// // Longer reads are held to the fraction
+    /// assert_eq!(call(40, 20, 5), 562);
+    /// assert_eq!(call(40, 19, 5), 0);
+    /// ```
+    pub fn required_score_with_floor(&self, confidence_threshold: f64, min_score: u64) -> u64 {
+        self.required_score(confidence_threshold).max(min_score)
+    }
 }
 
 /// Represents options for indexing
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct IndexOptions {
     pub k: usize,
     pub l: usize,
     pub spaced_seed_mask: u64,
     pub toggle_mask: u64,
     pub dna_db: bool,
+    /// Minimizers of the forward strand only, not canonicalized, for strand-specific
+    /// indices. The flag sits in the padding after `dna_db`, it is zero in the options
+    /// written by Kraken 2 and by earlier builds, which are canonical.
+    #[serde(default)]
+    pub forward_only: bool,
     pub minimum_acceptable_hash_value: u64,
     pub revcom_version: i32, // Throws an error if equal to 0
     pub db_version: i32,     // Reserved for future database structure changes
@@ -113,6 +262,7 @@ impl IndexOptions {
             spaced_seed_mask,
             toggle_mask,
             dna_db,
+            forward_only: false,
             minimum_acceptable_hash_value,
             revcom_version: CURRENT_REVCOM_VERSION as i32,
             db_version: 0,
@@ -133,6 +283,13 @@ impl IndexOptions {
         let mut file = open_file(file_path)?;
         let mut buffer = vec![0; std::mem::size_of::<Self>()];
         file.read_exact(&mut buffer)?;
+        // `forward_only` is read from what was padding before, a bool is 0 or 1
+        if buffer[mem::offset_of!(Self, forward_only)] > 1 {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                "Invalid forward_only flag in the index options",
+            ));
+        }
 
         let idx_opts = unsafe {
             // Ensure this conversion is safe, depending on the exact layout and source of the data
@@ -156,19 +313,145 @@ impl IndexOptions {
     ///
     /// An IoResult indicating success or failure of the write operation
     pub fn write_to_file<P: AsRef<Path>>(&self, file_path: P) -> IoResult<()> {
-        let mut file = File::create(file_path)?;
-
-        // Convert the struct to a byte slice. This is an unsafe operation as we're
-        // forcing memory content to be interpreted as bytes. This requires IndexOptions
-        // to be #[repr(C)], and all fields must be safely copyable in their raw memory representation.
-        let bytes: &[u8] = unsafe {
-            std::slice::from_raw_parts(
-                (self as *const IndexOptions) as *const u8,
-                mem::size_of::<IndexOptions>(),
-            )
+        write_atomically(file_path, |tmp| {
+            File::create(tmp)?.write_all(&self.to_bytes())
+        })
+    }
+
+    /// Returns the binary representation written to `opts.k2d`
+    ///
+    /// Fields are laid out as in the `#[repr(C)]` struct, padding bytes are zeroed
+    /// so that the same options always produce the same file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; mem::size_of::<Self>()];
+        let mut put = |offset: usize, src: &[u8]| {
+            bytes[offset..offset + src.len()].copy_from_slice(src);
         };
+        put(mem::offset_of!(Self, k), &self.k.to_ne_bytes());
+        put(mem::offset_of!(Self, l), &self.l.to_ne_bytes());
+        put(
+            mem::offset_of!(Self, spaced_seed_mask),
+            &self.spaced_seed_mask.to_ne_bytes(),
+        );
+        put(
+            mem::offset_of!(Self, toggle_mask),
+            &self.toggle_mask.to_ne_bytes(),
+        );
+        put(mem::offset_of!(Self, dna_db), &[self.dna_db as u8]);
+        put(
+            mem::offset_of!(Self, forward_only),
+            &[self.forward_only as u8],
+        );
+        put(
+            mem::offset_of!(Self, minimum_acceptable_hash_value),
+            &self.minimum_acceptable_hash_value.to_ne_bytes(),
+        );
+        put(
+            mem::offset_of!(Self, revcom_version),
+            &self.revcom_version.to_ne_bytes(),
+        );
+        put(
+            mem::offset_of!(Self, db_version),
+            &self.db_version.to_ne_bytes(),
+        );
+        put(mem::offset_of!(Self, db_type), &self.db_type.to_ne_bytes());
+        bytes
+    }
+
+    /// The report header line of the parameters the minimizers are scanned with, without
+    /// the leading `# `
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::readcounts::TaxonCounters;
+    /// use kun_peng::report::report_kraken_style;
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    /// use kun_peng::IndexOptions;
+    ///
+    /// let opts = IndexOptions::new(35, 31, 0x3ffffffff, 0xe37e28c4271b5a2d, true, 0);
+    /// let header = opts.report_header();
+    /// assert_eq!(
+    ///     header,
+    ///     "index: k=35 l=31 spaced_seed_mask=0x00000003ffffffff \
+    ///      toggle_mask=0xe37e28c4271b5a2d dna_db=true db_type=0"
+    /// );
+    ///
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// let filename = std::env::temp_dir().join("kun_peng_index_header.kreport2");
+    /// report_kraken_style(&filename, false, false, &taxonomy, &TaxonCounters::new(), 0, None, 100, &[header]).unwrap();
+    /// let report = std::fs::read_to_string(&filename).unwrap();
+    /// let line = report.lines().next().unwrap();
+    /// for param in ["k=35", "l=31", "spaced_seed_mask=0x00000003ffffffff", "toggle_mask=0xe37e28c4271b5a2d", "db_type=0"] {
+    ///     assert!(line.starts_with("# index: ") && line.split(' ').any(|field| field == param));
+    /// }
+    /// ```
+    pub fn report_header(&self) -> String {
+        format!(
+            "index: k={} l={} spaced_seed_mask={:#018x} toggle_mask={:#018x} dna_db={} db_type={}",
+            self.k, self.l, self.spaced_seed_mask, self.toggle_mask, self.dna_db, self.db_type
+        )
+    }
+
+    /// Serializes IndexOptions to a human-readable JSON string
+    pub fn to_json(&self) -> IoResult<String> {
+        serde_json::to_string_pretty(self).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Parses IndexOptions from a JSON string produced by [`IndexOptions::to_json`]
+    ///
+    /// # Examples
+    ///
+    /// Round trip from the binary file to JSON and back:
+    ///
+    /// ```
+    /// use kun_peng::IndexOptions;
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_index_options_json");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let opts_file = dir.join("opts.k2d");
+    /// IndexOptions::new(35, 31, 0, 0xe37e28c4271b5a2d, true, 0).write_to_file(&opts_file).unwrap();
+    ///
+    /// let binary = std::fs::read(&opts_file).unwrap();
+    /// let opts = IndexOptions::read_index_options(&opts_file).unwrap();
+    /// let json = opts.to_json().unwrap();
+    /// let reloaded = IndexOptions::from_json(&json).unwrap();
+    ///
+    /// assert_eq!(reloaded, opts);
+    /// assert_eq!(reloaded.to_bytes(), binary);
+    /// ```
+    pub fn from_json(json: &str) -> IoResult<Self> {
+        let idx_opts: Self =
+            serde_json::from_str(json).map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+        if idx_opts.revcom_version != CURRENT_REVCOM_VERSION as i32 {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Unsupported revcom_version {} (expected {})",
+                    idx_opts.revcom_version, CURRENT_REVCOM_VERSION
+                ),
+            ));
+        }
+        Ok(idx_opts)
+    }
 
-        file.write_all(bytes)?;
+    /// Fails for a forward-only index, the read scanner of `seqkmer` always canonicalizes
+    ///
+    /// The minimizers of such an index are only matched by reads scanned with
+    /// [`ScanOptions::forward_only`], e.g. through [`MinimizerRecord`] and
+    /// [`Database::classify_minimizers`].
+    ///
+    /// [`ScanOptions::forward_only`]: crate::mmscanner::ScanOptions::forward_only
+    /// [`MinimizerRecord`]: crate::classifier::MinimizerRecord
+    /// [`Database::classify_minimizers`]: crate::classifier::Database::classify_minimizers
+    pub fn require_canonical(&self) -> IoResult<()> {
+        if self.forward_only {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                "The index holds forward-only minimizers, reads would be scanned canonically",
+            ));
+        }
         Ok(())
     }
 
@@ -185,13 +468,50 @@ impl IndexOptions {
     }
 
     /// Converts IndexOptions to a Meros instance
-    pub fn as_meros(&self) -> Meros {
-        Meros::new(
+    ///
+    /// The options are read from disk, so k and l are checked before the masks and
+    /// the minimizer window are derived from them.
+    ///
+    /// # Examples
+    ///
+    /// A corrupt options file with l greater than k is rejected:
+    ///
+    /// ```
+    /// use kun_peng::IndexOptions;
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_index_options_meros");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let opts_file = dir.join("opts.k2d");
+    /// IndexOptions::new(15, 31, 0, 0, true, 0).write_to_file(&opts_file).unwrap();
+    ///
+    /// let opts = IndexOptions::read_index_options(&opts_file).unwrap();
+    /// let err = opts.as_meros().unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    /// assert!(err.to_string().contains("k = 15"));
+    /// assert!(err.to_string().contains("l = 31"));
+    ///
+    /// let meros = IndexOptions::new(35, 31, 0, 0, true, 0).as_meros().unwrap();
+    /// assert_eq!((meros.k_mer, meros.l_mer), (35, 31));
+    /// ```
+    pub fn as_meros(&self) -> IoResult<Meros> {
+        let max_l = 64 / BITS_PER_CHAR - 1;
+        if self.l == 0 || self.l > max_l || self.k < self.l {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Invalid index options k = {}, l = {}, expected 1 <= l <= {} and l <= k",
+                    self.k, self.l, max_l
+                ),
+            ));
+        }
+        let meros = Meros::new(
             self.k,
             self.l,
             u64_to_option(self.spaced_seed_mask),
             u64_to_option(self.toggle_mask),
             u64_to_option(self.minimum_acceptable_hash_value),
-        )
+        );
+        check_meros(&meros).map_err(|e| IoError::new(ErrorKind::InvalidData, e))?;
+        Ok(meros)
     }
 }
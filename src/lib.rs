@@ -1,15 +1,32 @@
 mod kr2r_data;
-mod kv_store;
+pub mod output;
+pub mod provenance;
 pub mod readcounts;
+pub mod reader;
 pub mod report;
+pub mod summary;
 pub mod taxonomy;
 pub mod utils;
 
 pub mod db;
+pub mod mmscanner;
 pub use kr2r_data::*;
-pub use kv_store::*;
+pub use kun_peng_core::{fmix64, murmur_hash3, sea_hash, KBuildHasher, KHasher, SBuildHasher};
 pub use readcounts::TaxonCounts;
 
 pub mod args;
+pub mod batching;
+pub mod bins;
+pub mod checkpoint;
+pub mod classifier;
 pub mod classify;
 pub mod compact_hash;
+pub mod compare;
+pub mod fmt;
+pub mod lock;
+pub mod markers;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod preprocess;
+pub mod spill;
+pub mod watch;
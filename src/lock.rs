@@ -0,0 +1,187 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the lock file of a database directory
+pub const LOCK_FILE: &str = "kun_peng.lock";
+
+/// The process holding a [`DbLock`], one `pid<TAB>host<TAB>command` line in the lock file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub host: String,
+    pub command: String,
+}
+
+impl LockOwner {
+    /// The current process, running `command`
+    pub fn current(command: &str) -> Self {
+        Self {
+            pid: std::process::id(),
+            host: hostname(),
+            command: command.to_string(),
+        }
+    }
+
+    /// Reads the owner of a lock file, `None` if the file is empty or malformed, e.g.
+    /// while its owner is still writing it
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Option<Self>> {
+        let content = fs::read_to_string(path)?;
+        let mut fields = content.trim_end().splitn(3, '\t');
+        let (Some(pid), Some(host), Some(command)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Ok(None);
+        };
+        Ok(pid.parse().ok().map(|pid| Self {
+            pid,
+            host: host.to_string(),
+            command: command.to_string(),
+        }))
+    }
+
+    /// Writes the lock file, failing with `AlreadyExists` if there is one
+    pub fn create<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        writeln!(file, "{}\t{}\t{}", self.pid, self.host, self.command)?;
+        file.sync_all()
+    }
+
+    /// Whether the owner may still be running. The processes of other hosts can't be
+    /// checked, they are always taken as running.
+    pub fn is_alive(&self) -> bool {
+        self.host != hostname() || pid_alive(self.pid)
+    }
+}
+
+/// Lock of a database directory, held while a command writes the database
+///
+/// The lock is the [`LOCK_FILE`] of the directory, created exclusively and removed when
+/// the lock is dropped. A second writer is refused while the owner runs. The lock of a
+/// process of this host that is gone, e.g. killed, is only removed with `force_unlock`.
+/// The lock of another host is never removed, its process can't be checked.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::lock::{DbLock, LockOwner, LOCK_FILE};
+///
+/// let dir = std::env::temp_dir().join("kun_peng_db_lock");
+/// let _ = std::fs::remove_dir_all(&dir);
+///
+/// let lock = DbLock::acquire(&dir, "build", false).unwrap();
+/// let owner = LockOwner::read(dir.join(LOCK_FILE)).unwrap().unwrap();
+/// assert_eq!(owner, LockOwner::current("build"));
+/// // A second writer is refused, even with force_unlock, while the owner runs
+/// let err = DbLock::acquire(&dir, "add-to-db", true).err().unwrap();
+/// assert!(err.to_string().contains("is locked by `build`"), "{}", err);
+/// drop(lock);
+/// assert!(!dir.join(LOCK_FILE).exists());
+///
+/// // The lock of a process that is gone
+/// let stale = LockOwner { pid: i32::MAX as u32, ..LockOwner::current("build") };
+/// stale.create(dir.join(LOCK_FILE)).unwrap();
+/// let err = DbLock::acquire(&dir, "compact-db", false).err().unwrap();
+/// assert!(err.to_string().contains("--force-unlock"), "{}", err);
+/// let lock = DbLock::acquire(&dir, "compact-db", true).unwrap();
+/// assert_eq!(LockOwner::read(dir.join(LOCK_FILE)).unwrap().unwrap().command, "compact-db");
+/// drop(lock);
+///
+/// // The lock of another host
+/// let remote = LockOwner { host: "elsewhere".to_string(), ..stale };
+/// remote.create(dir.join(LOCK_FILE)).unwrap();
+/// assert!(DbLock::acquire(&dir, "build", true).is_err());
+/// ```
+#[derive(Debug)]
+pub struct DbLock {
+    path: PathBuf,
+}
+
+impl DbLock {
+    /// Takes the lock of a database directory for `command`, creating the directory
+    pub fn acquire<P: AsRef<Path>>(dir: P, command: &str, force_unlock: bool) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+        let owner = LockOwner::current(command);
+        match owner.create(&path) {
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            result => return result.map(|_| Self { path }),
+        }
+
+        let locked = |message: String| Error::new(ErrorKind::AlreadyExists, message);
+        let holder = match LockOwner::read(&path)? {
+            Some(holder) => holder,
+            None => {
+                return Err(locked(format!(
+                    "{} is locked, {} is being written or is malformed",
+                    dir.display(),
+                    path.display()
+                )))
+            }
+        };
+        let held_by = format!(
+            "{} is locked by `{}` (PID {} on {})",
+            dir.display(),
+            holder.command,
+            holder.pid,
+            holder.host
+        );
+        if holder.host != owner.host {
+            return Err(locked(format!(
+                "{}, remove {} if that process is gone",
+                held_by,
+                path.display()
+            )));
+        }
+        if holder.is_alive() {
+            return Err(locked(held_by));
+        }
+        if !force_unlock {
+            return Err(locked(format!(
+                "{}, which is no longer running, --force-unlock removes the stale lock",
+                held_by
+            )));
+        }
+        eprintln!(
+            "removing the stale lock of `{}` (PID {})",
+            holder.command, holder.pid
+        );
+        fs::remove_file(&path)?;
+        owner.create(&path)?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for DbLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if result != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// Whether a process exists, signal 0 only checks it can be signalled
+#[cfg(unix)]
+fn pid_alive(pid: u32) -> bool {
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn pid_alive(_pid: u32) -> bool {
+    true
+}
@@ -0,0 +1,119 @@
+//! Presence/absence panels of marker genes
+//!
+//! A panel stores the minimizers of a set of marker sequences, resistance or virulence
+//! genes for instance, in a [`CHTable`] whose values are marker numbers instead of taxids.
+//! Reads are looked up with the same probing as classification, a hit is resolved to the
+//! marker name rather than through the taxonomy. Minimizers shared by two markers say
+//! nothing about which one is present, they are left out of the panel.
+use crate::compact_hash::{CHTable, HashConfig, Page};
+use crate::mmscanner::scan_minimizers;
+use crate::reader::for_each_record;
+use seqkmer::Meros;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Slots of the smallest panel table
+const MIN_PANEL_CAPACITY: usize = 1024;
+
+/// The minimizers of a set of markers, by marker
+pub struct MarkerPanel {
+    names: Vec<String>,
+    meros: Meros,
+    chtable: CHTable,
+}
+
+impl MarkerPanel {
+    /// Builds the panel of `(name, sequence)` markers
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::markers::MarkerPanel;
+    /// use seqkmer::{Meros, DEFAULT_TOGGLE_MASK};
+    ///
+    /// let meros = Meros::new(15, 11, None, Some(DEFAULT_TOGGLE_MASK), None);
+    /// let bla = b"ATGAGTATTCAACATTTCCGTGTCGCCCTTATTCCCTTTTTTGCGGCATTTTGCC".to_vec();
+    /// let tet = b"ATGAAATCTAACAATGCGCTCATCGTCATCCTCGGCACCGTCACCCTGGATGCTG".to_vec();
+    /// let markers = [("blaTEM".to_string(), bla.clone()), ("tetA".to_string(), tet.clone())];
+    /// let panel = MarkerPanel::build(&markers, meros).unwrap();
+    /// assert_eq!(panel.names(), ["blaTEM", "tetA"]);
+    ///
+    /// // A read made of the end of one marker and the start of the other
+    /// let read = [&bla[25..], &tet[..30]].concat();
+    /// let hits = panel.hits(&read);
+    /// assert_eq!(hits.iter().map(|&(marker, _)| marker).collect::<Vec<_>>(), [0, 1]);
+    /// assert!(hits.iter().all(|&(_, count)| count > 0));
+    /// assert!(panel.hits(b"CCCCCCCCCCCCCCCCCCCCCCCCC").is_empty());
+    /// ```
+    pub fn build(markers: &[(String, Vec<u8>)], meros: Meros) -> Result<Self> {
+        if markers.len() >= u32::MAX as usize {
+            return Err(Error::new(ErrorKind::InvalidInput, "too many markers"));
+        }
+        // The marker of each minimizer, 0 once two markers share it
+        let mut owners: HashMap<u64, u32> = HashMap::new();
+        let mut minimizers = Vec::new();
+        for (i, (_, seq)) in markers.iter().enumerate() {
+            let marker = i as u32 + 1;
+            minimizers.clear();
+            scan_minimizers(seq, &meros, &mut minimizers);
+            for &(_, hash_key) in &minimizers {
+                let owner = owners.entry(hash_key).or_insert(marker);
+                if *owner != marker {
+                    *owner = 0;
+                }
+            }
+        }
+
+        let value_bits = (u32::BITS - (markers.len() as u32).leading_zeros()).max(1) as usize;
+        let capacity = (owners.len() * 2).max(MIN_PANEL_CAPACITY);
+        // The spare cells past the capacity take the probes running off its end
+        let size = capacity + 1024;
+        let config = HashConfig::new(1, capacity, value_bits, owners.len(), 1, size);
+        let mut page = Page::new(0, size, vec![0; size]);
+        for (&hash_key, &marker) in owners.iter().filter(|(_, &marker)| marker > 0) {
+            let slot = config.slot(hash_key, marker);
+            let idx = (slot.idx..size)
+                .find(|&idx| page.data[idx] == 0)
+                .ok_or_else(|| Error::other("marker panel table is full"))?;
+            page.data[idx] = slot.value;
+        }
+
+        Ok(Self {
+            names: markers.iter().map(|(name, _)| name.clone()).collect(),
+            meros,
+            chtable: CHTable {
+                config,
+                pages: vec![page],
+            },
+        })
+    }
+
+    /// Builds the panel of the records of a FASTA file, named by their IDs
+    pub fn from_fasta<P: AsRef<Path>>(path: P, meros: Meros) -> Result<Self> {
+        let mut markers = Vec::new();
+        for_each_record(path, usize::MAX, |id, seq| {
+            markers.push((id.to_string(), seq.to_vec()))
+        })?;
+        Self::build(&markers, meros)
+    }
+
+    /// The marker names, in the order of the markers
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The markers hit by the minimizers of a read, with the number of hits of each,
+    /// by marker number
+    pub fn hits(&self, seq: &[u8]) -> Vec<(usize, u64)> {
+        let mut minimizers = Vec::new();
+        scan_minimizers(seq, &self.meros, &mut minimizers);
+        let mut counts: BTreeMap<usize, u64> = BTreeMap::new();
+        for (_, hash_key) in minimizers {
+            if let Some(marker) = self.chtable.get(hash_key) {
+                *counts.entry(marker as usize - 1).or_default() += 1;
+            }
+        }
+        counts.into_iter().collect()
+    }
+}
@@ -0,0 +1,154 @@
+use crate::classifier::ClassifierMetrics;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Largest request head read from a scraper
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// A minimal HTTP endpoint serving the [`ClassifierMetrics`] of a classifier
+///
+/// `GET /metrics` answers with the Prometheus text format, any other path with 404.
+/// Connections are served one at a time on a single thread, which is plenty for a
+/// scraper. The endpoint stops when the server is dropped.
+///
+/// # Examples
+///
+/// Scraping while reads are being classified:
+///
+/// ```
+/// use kun_peng::classifier::{Classifier, ClassifierOptions, Database};
+/// use kun_peng::compact_hash::{CHTable, HashConfig};
+/// use kun_peng::metrics::MetricsServer;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+/// use seqkmer::Meros;
+/// use std::io::{Read, Write};
+/// use std::net::TcpStream;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let hash_config = HashConfig::new(1, 1024, 16, 0, 1, 1024);
+/// let chtable = CHTable { config: hash_config, pages: vec![] };
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// let meros = Meros::new(35, 31, None, None, None);
+/// let db = Arc::new(Database::new(meros, hash_config, chtable, taxonomy));
+///
+/// let (classifier, results) = Classifier::with_channel(db, ClassifierOptions::default());
+/// let server = MetricsServer::bind("127.0.0.1:0", classifier.metrics()).unwrap();
+/// let scrape = |path: &str| {
+///     let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+///     write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+///     let mut response = String::new();
+///     stream.read_to_string(&mut response).unwrap();
+///     response
+/// };
+/// let processed = |response: &str| -> u64 {
+///     let line = response.lines().find(|line| line.starts_with("kun_peng_reads_processed_total "));
+///     line.unwrap().rsplit(' ').next().unwrap().parse().unwrap()
+/// };
+///
+/// let read = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGATTACGATCGA".to_vec();
+/// let mut last = 0;
+/// for round in 1..=3 {
+///     for i in 0..50 {
+///         classifier.submit(format!("read_{}_{}", round, i), read.clone(), None).unwrap();
+///     }
+///     for _ in 0..50 {
+///         results.recv_timeout(Duration::from_secs(5)).unwrap();
+///     }
+///     // The latency is recorded right after the result is handed over
+///     std::thread::sleep(Duration::from_millis(50));
+///
+///     let response = scrape("/metrics");
+///     assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+///     assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+///     assert!(response.contains(&format!("kun_peng_reads_submitted_total {}\n", round * 50)));
+///     assert!(response.contains("kun_peng_request_latency_quantile_seconds{quantile=\"0.5\"}"));
+///     let count = processed(&response);
+///     assert!(count > last);
+///     last = count;
+/// }
+/// assert_eq!(last, 150);
+/// assert!(scrape("/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+/// ```
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Listens on `addr` (e.g. `127.0.0.1:9184`, port 0 picks a free port)
+    pub fn bind<A: ToSocketAddrs>(addr: A, metrics: Arc<ClassifierMetrics>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    // A failed scrape must not stop the endpoint.
+                    if let Ok(stream) = stream {
+                        let _ = respond(stream, &metrics);
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// The address the endpoint listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wakes up the blocking accept, the server thread then sees the stop flag.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Reads the request head of one connection and writes the response
+fn respond(mut stream: TcpStream, metrics: &ClassifierMetrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let request_line = String::from_utf8_lossy(&request);
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.prometheus_text()),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
@@ -0,0 +1,1063 @@
+// Minimizer scanner used by the database build.
+//
+// It produces exactly the minimizers of `seqkmer::MinimizerIterator`, but keeps the reverse
+// complement of the current l-mer up to date base by base instead of recomputing it for
+// every l-mer. The l-mers are buffered and fed to the window in batches.
+//
+// The reverse complement below swaps 2-bit nucleotide codes, the batched scanner is DNA
+// only. The reduced alphabet of the proteins, behind the `protein` feature, is handled
+// apart from it.
+use seqkmer::{char_to_value, fmix64, Meros, MinimizerWindow, BITS_PER_CHAR};
+use std::str::FromStr;
+
+const _: () = assert!(
+    BITS_PER_CHAR == 2,
+    "the build scanner expects 2-bit nucleotides"
+);
+
+/// Number of canonical l-mers buffered before they are fed to the window
+const LMER_BUFFER_SIZE: usize = 256;
+
+/// Reverse complement of a 2-bit encoded l-mer of length `n`
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::reverse_complement;
+///
+/// // ACG -> CGT
+/// assert_eq!(reverse_complement(0b000110, 3), 0b011011);
+/// ```
+#[inline]
+pub fn reverse_complement(mut kmer: u64, n: usize) -> u64 {
+    kmer = (kmer >> 2 & 0x3333333333333333) | (kmer << 2 & 0xCCCCCCCCCCCCCCCC);
+    kmer = (kmer >> 4 & 0x0F0F0F0F0F0F0F0F) | (kmer << 4 & 0xF0F0F0F0F0F0F0F0);
+    kmer = (kmer >> 8 & 0x00FF00FF00FF00FF) | (kmer << 8 & 0xFF00FF00FF00FF00);
+    kmer = (kmer >> 16 & 0x0000FFFF0000FFFF) | (kmer << 16 & 0xFFFF0000FFFF0000);
+    kmer = kmer.rotate_left(32);
+    (!kmer >> (64 - n * BITS_PER_CHAR)) & ((1u64 << (n * BITS_PER_CHAR)) - 1)
+}
+
+/// An l-mer and its reverse complement, both updated by every base
+///
+/// Appending a base shifts it in at the low end of `value` and its complement in at the
+/// high end of `revcom`, two shifts instead of a full [`reverse_complement`].
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::{reverse_complement, RollingLmer};
+///
+/// let mut state = 0x9e3779b97f4a7c15u64;
+/// for n in [1, 11, 15, 31] {
+///     let mut lmer = RollingLmer::new(n);
+///     for i in 0..1000 {
+///         state ^= state << 13;
+///         state ^= state >> 7;
+///         state ^= state << 17;
+///         let full = lmer.push(state % 4);
+///         assert_eq!(full, i + 1 >= n);
+///         if full {
+///             assert_eq!(lmer.revcom, reverse_complement(lmer.value, n));
+///             assert_eq!(lmer.canonical(), lmer.value.min(lmer.revcom));
+///         }
+///     }
+///     lmer.reset();
+///     assert!(!lmer.push(0) || n == 1);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RollingLmer {
+    /// The last `n` bases, 2 bits each
+    pub value: u64,
+    /// The reverse complement of `value`
+    pub revcom: u64,
+    len: usize,
+    n: usize,
+    mask: u64,
+}
+
+impl RollingLmer {
+    pub fn new(n: usize) -> Self {
+        Self {
+            value: 0,
+            revcom: 0,
+            len: 0,
+            n,
+            mask: u64::MAX >> (64 - n * BITS_PER_CHAR),
+        }
+    }
+
+    /// Appends a 2-bit base, returns `true` once the l-mer holds `n` bases
+    #[inline]
+    pub fn push(&mut self, code: u64) -> bool {
+        self.value = ((self.value << BITS_PER_CHAR) | code) & self.mask;
+        self.revcom =
+            (self.revcom >> BITS_PER_CHAR) | ((code ^ 3) << ((self.n - 1) * BITS_PER_CHAR));
+        self.len += 1;
+        self.len >= self.n
+    }
+
+    /// Drops the bases, after an invalid one
+    pub fn reset(&mut self) {
+        self.value = 0;
+        self.revcom = 0;
+        self.len = 0;
+    }
+
+    #[inline]
+    pub fn canonical(&self) -> u64 {
+        self.value.min(self.revcom)
+    }
+}
+
+/// What the scanners do with the IUPAC ambiguity codes R, Y, S, W, K, M, B, D, H and V
+///
+/// N and the other invalid characters always end the current run of k-mers.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::IupacPolicy;
+///
+/// let rewrite = |policy: IupacPolicy| {
+///     let mut seq = b"ACRTNGyA".to_vec();
+///     policy.rewrite(&mut seq);
+///     seq
+/// };
+/// assert_eq!(rewrite(IupacPolicy::Break), b"ACRTNGyA");
+/// assert_eq!(rewrite(IupacPolicy::FirstBase), b"ACATNGCA");
+/// assert_eq!(rewrite(IupacPolicy::Skip), b"ACTNGA");
+///
+/// assert_eq!("first-base".parse(), Ok(IupacPolicy::FirstBase));
+/// assert!("first".parse::<IupacPolicy>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IupacPolicy {
+    /// End the run of k-mers, like an N
+    #[default]
+    Break,
+    /// Read the code as its first compatible base in ACGT order, e.g. R (A or G) as A
+    FirstBase,
+    /// Drop the code, the bases around it form k-mers together
+    Skip,
+}
+
+impl IupacPolicy {
+    /// Applies the policy to a sequence, for the scanners outside this module
+    pub fn rewrite(self, seq: &mut Vec<u8>) {
+        match self {
+            Self::Break => {}
+            Self::FirstBase => {
+                for base in seq.iter_mut() {
+                    if let Some(first) = iupac_first_base(*base) {
+                        *base = first;
+                    }
+                }
+            }
+            Self::Skip => seq.retain(|&base| iupac_first_base(base).is_none()),
+        }
+    }
+
+    /// Applies the policy to the X (any residue) of a protein sequence
+    ///
+    /// `first-base` reads an X as A, the first residue. The other ambiguity codes always
+    /// have a group, see [`protein_char_to_value`].
+    #[cfg(feature = "protein")]
+    pub fn rewrite_protein(self, seq: &mut Vec<u8>) {
+        let is_any = |ch: &u8| ch.eq_ignore_ascii_case(&b'X');
+        match self {
+            Self::Break => {}
+            Self::FirstBase => seq
+                .iter_mut()
+                .filter(|ch| is_any(ch))
+                .for_each(|ch| *ch = b'A'),
+            Self::Skip => seq.retain(|ch| !is_any(ch)),
+        }
+    }
+}
+
+impl FromStr for IupacPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "break" => Ok(Self::Break),
+            "first-base" => Ok(Self::FirstBase),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!(
+                "expected `break`, `first-base` or `skip`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// The first base an IUPAC ambiguity code stands for in ACGT order
+///
+/// `None` for the other characters, N included.
+pub fn iupac_first_base(ch: u8) -> Option<u8> {
+    match ch.to_ascii_uppercase() {
+        b'R' | b'W' | b'M' | b'D' | b'H' | b'V' => Some(b'A'),
+        b'Y' | b'S' | b'B' => Some(b'C'),
+        b'K' => Some(b'G'),
+        _ => None,
+    }
+}
+
+/// Code of an amino acid in the 15 letter reduced alphabet of the protein databases
+///
+/// Upper or lower case. The ambiguity codes J (I or L), B (D or N) and Z (E or Q) take the
+/// group of their first residue, J is exact since I and L share a group. X (any residue)
+/// is `None` like the invalid characters, see [`IupacPolicy::rewrite_protein`].
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::{protein_char_to_value, IupacPolicy};
+///
+/// let codes = |seq: &[u8], policy: IupacPolicy| {
+///     let mut seq = seq.to_vec();
+///     policy.rewrite_protein(&mut seq);
+///     seq.iter().map(|&ch| protein_char_to_value(ch)).collect::<Vec<_>>()
+/// };
+/// // The runs of valid codes, the k-mers of a scanner can't span the others
+/// let runs = |seq: &[u8], policy| {
+///     codes(seq, policy)
+///         .split(Option::is_none)
+///         .map(|run| run.iter().map(|code| code.unwrap()).collect::<Vec<_>>())
+///         .filter(|run| !run.is_empty())
+///         .collect::<Vec<_>>()
+/// };
+///
+/// assert_eq!(protein_char_to_value(b'J'), protein_char_to_value(b'L'));
+/// assert_eq!(protein_char_to_value(b'B'), protein_char_to_value(b'D'));
+/// assert_eq!(protein_char_to_value(b'Z'), protein_char_to_value(b'E'));
+/// assert_eq!(codes(b"mkbzjlv", IupacPolicy::Break), codes(b"MKDEILV", IupacPolicy::Break));
+///
+/// let seq = b"MKTAYBIAKQZRQJSFXVKSHFSRQ";
+/// assert!(seq.iter().filter(|&&ch| ch != b'X').all(|&ch| protein_char_to_value(ch).is_some()));
+/// assert_eq!(runs(seq, IupacPolicy::Break).iter().map(Vec::len).collect::<Vec<_>>(), [16, 8]);
+/// assert_eq!(runs(seq, IupacPolicy::Skip), vec![runs(seq, IupacPolicy::Break).concat()]);
+/// assert_eq!(runs(seq, IupacPolicy::FirstBase), runs(b"MKTAYBIAKQZRQJSFAVKSHFSRQ", IupacPolicy::Break));
+/// ```
+#[cfg(feature = "protein")]
+pub fn protein_char_to_value(ch: u8) -> Option<u64> {
+    match ch.to_ascii_uppercase() {
+        b'*' | b'U' | b'O' => Some(0x00),
+        b'A' => Some(0x01),
+        b'N' | b'Q' | b'S' => Some(0x02),
+        b'C' => Some(0x03),
+        b'D' | b'E' | b'B' | b'Z' => Some(0x04),
+        b'F' => Some(0x05),
+        b'G' => Some(0x06),
+        b'H' => Some(0x07),
+        b'I' | b'L' | b'J' => Some(0x08),
+        b'K' => Some(0x09),
+        b'P' => Some(0x0a),
+        b'R' => Some(0x0b),
+        b'M' | b'V' => Some(0x0c),
+        b'T' => Some(0x0d),
+        b'W' => Some(0x0e),
+        b'Y' => Some(0x0f),
+        _ => None,
+    }
+}
+
+/// A space or a tab, the whitespace left inside the sequences of malformed inputs
+#[inline]
+pub fn is_blank(ch: u8) -> bool {
+    ch == b' ' || ch == b'\t'
+}
+
+/// Settings of the build scanner beyond the [`Meros`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ScanOptions {
+    pub iupac: IupacPolicy,
+    /// Skip spaces and tabs like the line breaks, instead of ending the run of k-mers
+    pub skip_whitespace: bool,
+    /// Keep the l-mers of the forward strand instead of their canonical representation,
+    /// for strand-specific indices, see [`IndexOptions::forward_only`]
+    ///
+    /// [`IndexOptions::forward_only`]: crate::IndexOptions::forward_only
+    pub forward_only: bool,
+    /// Also push the minimizer of a sequence ending less than k bases after its last
+    /// invalid base, the smallest of its l-mers. Kraken 2 and `seqkmer` drop it, so this
+    /// is off by default, see [`trailing_minimizer`].
+    pub trailing_window: bool,
+}
+
+/// Scans a sequence, canonicalizing with the rolling reverse complement of [`RollingLmer`]
+///
+/// Pushes the same `(position, hash)` pairs `seqkmer::MinimizerIterator` yields.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::scan_minimizers;
+/// use seqkmer::{Cursor, Meros, MinimizerIterator, MinimizerWindow};
+///
+/// let seq = b"ACGTTGCAACGTAGCTAGNCTAGCATCGATCGATGCATC\nGATTACGATCGAACGGTACCATGGTTTAGCATCAGGACNNACGATCAGTTACGATCAGCGATTACGA";
+/// for meros in [Meros::new(15, 11, None, None, None), Meros::new(35, 31, Some(0), None, None)] {
+///     let cursor = Cursor::new(&meros);
+///     let window = MinimizerWindow::new(meros.window_size());
+///     let expected: Vec<(usize, u64)> = MinimizerIterator::new(seq, cursor, window, &meros).collect();
+///
+///     let mut minimizers = Vec::new();
+///     scan_minimizers(seq, &meros, &mut minimizers);
+///     assert_eq!(minimizers, expected);
+/// }
+/// ```
+///
+/// The minimizers do not depend on the strand, also with a spaced seed that is not its own
+/// reverse complement. A palindromic sequence yields a palindromic list of minimizers (a
+/// minimizer found at two positions of a window may be pushed twice, so repeats are collapsed):
+///
+/// ```
+/// use kun_peng::mmscanner::scan_minimizers;
+/// use seqkmer::Meros;
+///
+/// fn revcom(seq: &[u8]) -> Vec<u8> {
+///     seq.iter()
+///         .rev()
+///         .map(|base| match base {
+///             b'A' => b'T',
+///             b'C' => b'G',
+///             b'G' => b'C',
+///             _ => b'A',
+///         })
+///         .collect()
+/// }
+///
+/// fn hashes(seq: &[u8], meros: &Meros) -> Vec<u64> {
+///     let mut minimizers = Vec::new();
+///     scan_minimizers(seq, meros, &mut minimizers);
+///     let mut hashes: Vec<u64> = minimizers.into_iter().map(|(_, hash)| hash).collect();
+///     hashes.dedup();
+///     hashes
+/// }
+///
+/// let seeds = [
+///     Meros::new(15, 11, None, None, None),
+///     Meros::new(15, 11, Some(0b11_11_00_11_11_00_11_11_11_11_11), None, None),
+///     Meros::new(35, 31, Some(0x3FFF_3F3F_3F3F_3FFF & ((1 << 62) - 1)), None, None),
+/// ];
+/// let mut state = 0x9e3779b97f4a7c15u64;
+/// for len in 20..120 {
+///     let half: Vec<u8> = (0..len)
+///         .map(|_| {
+///             state ^= state << 13;
+///             state ^= state >> 7;
+///             state ^= state << 17;
+///             b"ACGT"[(state % 4) as usize]
+///         })
+///         .collect();
+///     let mut palindrome = half.clone();
+///     palindrome.extend(revcom(&half));
+///
+///     for meros in &seeds {
+///         let mut reversed = hashes(&half, meros);
+///         reversed.reverse();
+///         reversed.dedup();
+///         assert_eq!(hashes(&revcom(&half), meros), reversed);
+///
+///         let forward = hashes(&palindrome, meros);
+///         let mut backward = forward.clone();
+///         backward.reverse();
+///         assert_eq!(forward, backward);
+///     }
+/// }
+/// ```
+///
+/// Golden values, a change of the hash, the canonical choice or the window breaks
+/// compatibility with existing databases. `fmix64` only uses integer arithmetic on
+/// `u64`, the values do not depend on the platform endianness.
+///
+/// ```
+/// use kun_peng::mmscanner::scan_minimizers;
+/// use seqkmer::{canonical_representation, fmix64, Meros};
+///
+/// assert_eq!(fmix64(0), 0);
+/// assert_eq!(fmix64(1), 0xb456bcfc34c2cb2c);
+/// assert_eq!(fmix64(0x9e3779b97f4a7c15), 0x9ca066f1a4ab2eea);
+/// assert_eq!(fmix64(u64::MAX), 0x64b5720b4b825f21);
+///
+/// // ACGT is its own reverse complement, AAAC is smaller than GTTT
+/// assert_eq!(canonical_representation(0b00_01_10_11, 4), 0b00_01_10_11);
+/// assert_eq!(canonical_representation(0b10_11_11_11, 4), 0b00_00_00_01);
+///
+/// let meros = Meros::new(15, 11, None, None, None);
+/// let golden: [(&[u8], &[u64]); 2] = [
+///     (
+///         // A palindrome, the minimizers mirror each other
+///         b"ACGTTGCAACGTAGCTAGCATGCTAGCTACGTTGCAACGT",
+///         &[
+///             0x7310af91587e892c, 0xa840c2ebcdf0a12b, 0xf95876903bafd6cb, 0xbce7fc570b0869fb,
+///             0xbce7fc570b0869fb, 0xf95876903bafd6cb, 0xa840c2ebcdf0a12b, 0x7310af91587e892c,
+///         ],
+///     ),
+///     (
+///         b"GATTACAGATTACAGATTACA",
+///         &[0x1fe13a34327c626b, 0x6a82e7ecec9c0d33, 0x317f94f5da190aa2],
+///     ),
+/// ];
+/// for (seq, expected) in golden {
+///     let mut minimizers = Vec::new();
+///     scan_minimizers(seq, &meros, &mut minimizers);
+///     let hashes: Vec<u64> = minimizers.iter().map(|&(_, hash)| hash).collect();
+///     assert_eq!(hashes, expected);
+/// }
+/// ```
+///
+/// A minimizer comes out once a whole k-mer is read, as in Kraken 2, whose scanner does
+/// not return the l-mers of an incomplete last window either, unless
+/// [`ScanOptions::trailing_window`] is set. A sequence one base shorter
+/// than k gives none, every base from k on gives at most one more.
+///
+/// ```
+/// use kun_peng::mmscanner::scan_minimizers;
+/// use seqkmer::Meros;
+///
+/// let seq = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGATTACG";
+/// for meros in [Meros::new(15, 11, None, None, None), Meros::new(35, 31, None, None, None)] {
+///     let k = meros.k_mer;
+///     let count = |len: usize| {
+///         let mut minimizers = Vec::new();
+///         scan_minimizers(&seq[..len], &meros, &mut minimizers);
+///         minimizers.len()
+///     };
+///     assert_eq!(count(k - 1), 0);
+///     assert_eq!(count(k), 1);
+///     for len in k..seq.len() {
+///         assert!((0..=1).contains(&(count(len + 1) - count(len))));
+///     }
+/// }
+/// ```
+pub fn scan_minimizers(seq: &[u8], meros: &Meros, minimizers: &mut Vec<(usize, u64)>) {
+    scan_minimizers_with(seq, meros, ScanOptions::default(), minimizers);
+}
+
+/// Scans a sequence like [`scan_minimizers`], with the IUPAC policy of `options`
+///
+/// The toggle mask is undone before hashing, as `seqkmer` does for the reads. Minimizers
+/// hashing below `min_clear_hash_value` are dropped, their positions are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_minimizers_with, ScanOptions};
+/// use seqkmer::{Meros, DEFAULT_TOGGLE_MASK};
+///
+/// let seq = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGATTACGATCGAACGGTACCATGGTTTAGCATCAGGAC\
+///             ACGATCAGTTACGATCAGCGATTACGAGGCATTACGGATCCATTAGCAGCTTAGGCAATCGGATCAGGCATTAC";
+/// let scan = |meros: &Meros| {
+///     let mut minimizers = Vec::new();
+///     scan_minimizers_with(seq, meros, ScanOptions::default(), &mut minimizers);
+///     minimizers
+/// };
+///
+/// let all = scan(&Meros::new(15, 11, None, Some(DEFAULT_TOGGLE_MASK), None));
+/// let min_clear = u64::MAX / 2;
+/// let kept = scan(&Meros::new(15, 11, None, Some(DEFAULT_TOGGLE_MASK), Some(min_clear)));
+/// assert!(!kept.is_empty() && kept.len() < all.len());
+/// assert!(kept.iter().all(|&(_, hash)| hash >= min_clear));
+/// assert!(kept.iter().all(|minimizer| all.contains(minimizer)));
+/// ```
+///
+/// A single R in a read: `Break` scans it like an N, `FirstBase` like the A it is read as
+/// and `Skip` like the read without it. The k-mers spanning the R are lost with `Break`
+/// only.
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_minimizers, scan_minimizers_with, IupacPolicy, ScanOptions};
+/// use seqkmer::Meros;
+///
+/// let meros = Meros::new(15, 11, None, None, None);
+/// let scan = |seq: &[u8], iupac| {
+///     let options = ScanOptions { iupac, ..Default::default() };
+///     let mut minimizers = Vec::new();
+///     scan_minimizers_with(seq, &meros, options, &mut minimizers);
+///     minimizers
+/// };
+/// let with = |base: &[u8]| [&b"ACGTTGCAACGTAGCTAGCTAGCATCG"[..], base, b"ATCGATGCATCGATTACGATCGAA"].concat();
+/// let read = with(b"R");
+///
+/// let mut current = Vec::new();
+/// scan_minimizers(&read, &meros, &mut current);
+/// assert_eq!(scan(&read, IupacPolicy::Break), current);
+/// assert_eq!(scan(&read, IupacPolicy::Break), scan(&with(b"N"), IupacPolicy::Break));
+/// assert_eq!(scan(&read, IupacPolicy::FirstBase), scan(&with(b"A"), IupacPolicy::Break));
+/// assert_eq!(scan(&read, IupacPolicy::Skip), scan(&with(b""), IupacPolicy::Break));
+/// assert!(scan(&read, IupacPolicy::Break).len() < scan(&read, IupacPolicy::FirstBase).len());
+///
+/// // The rewritten read gives the same minimizers to the other scanners
+/// for policy in [IupacPolicy::Break, IupacPolicy::FirstBase, IupacPolicy::Skip] {
+///     let mut rewritten = read.clone();
+///     policy.rewrite(&mut rewritten);
+///     assert_eq!(scan(&rewritten, IupacPolicy::Break), scan(&read, policy));
+/// }
+/// ```
+///
+/// A space inside a sequence ends the run of k-mers like an N, unless `skip_whitespace`
+/// is set: then the minimizers span it, as if the space was a line break.
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_minimizers_with, ScanOptions};
+/// use seqkmer::Meros;
+///
+/// let meros = Meros::new(15, 11, None, None, None);
+/// let scan = |seq: &[u8], skip_whitespace| {
+///     let options = ScanOptions { skip_whitespace, ..Default::default() };
+///     let mut minimizers = Vec::new();
+///     scan_minimizers_with(seq, &meros, options, &mut minimizers);
+///     minimizers
+/// };
+/// let with = |sep: &[u8]| [&b"ACGTTGCAACGTAGCTAGCTAGCATCG"[..], sep, b"ATCGATGCATCGATTACGATCGAA"].concat();
+///
+/// assert_eq!(scan(&with(b" "), false), scan(&with(b"N"), false));
+/// assert_eq!(scan(&with(b" "), true), scan(&with(b""), false));
+/// assert_eq!(scan(&with(b" \t\n"), true), scan(&with(b"\n"), false));
+/// assert!(scan(&with(b" "), false).len() < scan(&with(b" "), true).len());
+/// ```
+pub fn scan_minimizers_with(
+    seq: &[u8],
+    meros: &Meros,
+    options: ScanOptions,
+    minimizers: &mut Vec<(usize, u64)>,
+) {
+    scan_minimizers_range(seq, 0..seq.len(), meros, options, minimizers);
+}
+
+/// The minimizer of the last run of a sequence too short for a whole k-mer
+///
+/// A sequence whose last run of valid bases, after its last invalid base, holds l to
+/// k - 1 bases ends with an incomplete window. Kraken 2 and `seqkmer` give that run no
+/// minimizer, [`ScanOptions::trailing_window`] pushes the smallest of its l-mers, hashed
+/// like the others, after the minimizers of the sequence. This returns it alone, `None`
+/// when the last run holds a whole k-mer or no l-mer, or when the hash is below
+/// `min_clear_hash_value`.
+///
+/// # Examples
+///
+/// A sequence of k - 1 bases, whose minimizer is computed directly.
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_minimizers_with, trailing_minimizer, ScanOptions};
+/// use seqkmer::{canonical_representation, char_to_value, fmix64, Meros};
+///
+/// let meros = Meros::new(15, 11, None, None, None);
+/// let seq = b"ACGTTGCAACGTAGC";
+/// let short = &seq[..meros.k_mer - 1];
+/// let scan = |seq: &[u8], trailing_window| {
+///     let options = ScanOptions { trailing_window, ..Default::default() };
+///     let mut minimizers = Vec::new();
+///     scan_minimizers_with(seq, &meros, options, &mut minimizers);
+///     minimizers
+/// };
+///
+/// let codes: Vec<u64> = short.iter().map(|&ch| char_to_value(ch).unwrap()).collect();
+/// let smallest = codes
+///     .windows(meros.l_mer)
+///     .map(|bases| {
+///         let lmer = bases.iter().fold(0, |lmer, &code| (lmer << 2) | code);
+///         let mut candidate = canonical_representation(lmer, meros.l_mer);
+///         if meros.spaced_seed_mask > 0 {
+///             candidate &= meros.spaced_seed_mask;
+///         }
+///         candidate ^ meros.toggle_mask
+///     })
+///     .min()
+///     .unwrap();
+/// let expected = fmix64(smallest ^ meros.toggle_mask);
+///
+/// assert_eq!(scan(short, false), []);
+/// assert_eq!(scan(short, true), [(1, expected)]);
+/// assert_eq!(trailing_minimizer(short, &meros, ScanOptions::default()), Some(expected));
+///
+/// // After an N, the last run is the short one
+/// let after_n = [&b"GATTACAGATTACAGATTACA"[..], b"N", short].concat();
+/// let minimizers = scan(&after_n, true);
+/// assert_eq!(minimizers[..minimizers.len() - 1], scan(&after_n, false)[..]);
+/// assert_eq!(minimizers.last(), Some(&(minimizers.len(), expected)));
+///
+/// // A whole k-mer, or a short run followed by one, has nothing left in its window
+/// for seq in [&seq[..], &[short, b"N", seq].concat(), &short[..meros.l_mer - 1]] {
+///     assert_eq!(scan(seq, true), scan(seq, false));
+///     assert_eq!(trailing_minimizer(seq, &meros, ScanOptions::default()), None);
+/// }
+/// ```
+pub fn trailing_minimizer(seq: &[u8], meros: &Meros, options: ScanOptions) -> Option<u64> {
+    let options = ScanOptions {
+        trailing_window: true,
+        ..options
+    };
+    let mut scanner = BatchScanner::new(meros, options);
+    let mut minimizers = Vec::new();
+    scanner.feed(seq, options, &mut minimizers);
+    scanner.flush(&mut minimizers);
+    minimizers.clear();
+    scanner.finish(&mut minimizers);
+    minimizers.pop().map(|(_, hash)| hash)
+}
+
+/// Scans the bytes `range` of a sequence like [`scan_minimizers_with`]
+///
+/// The scan starts at `range.start` with an empty window, as if the range was the whole
+/// sequence: the positions count from the start of the range. The range is clamped to
+/// the sequence, so regions of a sequence, or pieces of it for parallel scanning, are
+/// scanned without copying them out.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_minimizers_range, scan_minimizers_with, ScanOptions};
+/// use seqkmer::Meros;
+///
+/// let seq = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATC\nGATTACGATCGAACGGTACCATGGTTTAGCATCAGGAC\
+///             NNACGATCAGTTACGATCAGCGATTACGAGGCATTACGGATCCATTAGCAGCTTAGGCAATCGGATCAGGCATTAC";
+/// let meros = Meros::new(15, 11, None, None, None);
+/// let options = ScanOptions::default();
+/// for (start, end) in [(0, seq.len()), (5, 60), (37, 120), (38, 39), (80, 400), (90, 10)] {
+///     let mut range = Vec::new();
+///     scan_minimizers_range(seq, start..end, &meros, options, &mut range);
+///
+///     // The extracted subsequence, the range clamped to the sequence
+///     let end = end.min(seq.len());
+///     let sub = seq[start.min(end)..end].to_vec();
+///     let mut extracted = Vec::new();
+///     scan_minimizers_with(&sub, &meros, options, &mut extracted);
+///     assert_eq!(range, extracted);
+/// }
+/// ```
+pub fn scan_minimizers_range(
+    seq: &[u8],
+    range: std::ops::Range<usize>,
+    meros: &Meros,
+    options: ScanOptions,
+    minimizers: &mut Vec<(usize, u64)>,
+) {
+    let end = range.end.min(seq.len());
+    let start = range.start.min(end);
+    let mut scanner = BatchScanner::new(meros, options);
+    scanner.feed(&seq[start..end], options, minimizers);
+    scanner.finish(minimizers);
+}
+
+/// Bytes of the pieces of a genome scanned in parallel by the build
+pub const SCAN_PIECE_SIZE: usize = 1 << 20;
+
+/// Cuts a sequence into ranges of about `piece_size` bytes for [`scan_minimizers_range`]
+///
+/// Every range but the first starts k - 1 bases before the end of the previous one, so
+/// each k-mer of the sequence lies whole in a range. The ranges yield every minimizer of
+/// the sequence, a few of them twice, which the build merges into one cell. Only A, C, G
+/// and T count as bases of the overlap, the ranges overlap at least k - 1 bases whatever
+/// the options skip.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::{
+///     scan_minimizers_range, scan_minimizers_with, scan_pieces, IupacPolicy, ScanOptions,
+/// };
+/// use seqkmer::Meros;
+/// use std::collections::BTreeSet;
+///
+/// let seq = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATC\nGATTACGATCGAACGGTACCATGGTTTAGCATCAGGAC\
+///             NNACGATCAGTTAC GATCAGCGATTACGAGGCATTRCGGATCCATTAGCAGCTTAGGCAATCGGATCAGGCATTAC";
+/// let meros = Meros::new(15, 11, None, None, None);
+/// for iupac in [IupacPolicy::Break, IupacPolicy::FirstBase, IupacPolicy::Skip] {
+///     for skip_whitespace in [false, true] {
+///         let options = ScanOptions { iupac, skip_whitespace, ..Default::default() };
+///         let mut whole = Vec::new();
+///         scan_minimizers_with(seq, &meros, options, &mut whole);
+///         let whole: BTreeSet<u64> = whole.into_iter().map(|(_, hash)| hash).collect();
+///
+///         for piece_size in [1, 10, 40, 100, seq.len()] {
+///             let pieces = scan_pieces(seq, &meros, piece_size);
+///             assert_eq!(pieces.first().unwrap().start, 0);
+///             assert_eq!(pieces.last().unwrap().end, seq.len());
+///             let mut scanned = Vec::new();
+///             for range in pieces {
+///                 scan_minimizers_range(seq, range, &meros, options, &mut scanned);
+///             }
+///             let scanned: BTreeSet<u64> = scanned.into_iter().map(|(_, hash)| hash).collect();
+///             assert_eq!(scanned, whole, "{:?} {} {}", iupac, skip_whitespace, piece_size);
+///         }
+///     }
+/// }
+/// assert_eq!(scan_pieces(seq, &meros, seq.len()), [0..seq.len()]);
+/// assert_eq!(scan_pieces(b"", &meros, 10), [0..0]);
+/// ```
+pub fn scan_pieces(seq: &[u8], meros: &Meros, piece_size: usize) -> Vec<std::ops::Range<usize>> {
+    let is_base = |ch: &u8| char_to_value(*ch).is_some();
+    let mut pieces = Vec::with_capacity(seq.len() / piece_size.max(1) + 1);
+    let mut start = 0;
+    loop {
+        // At least k bases, so the next range starts after this one
+        let mut end = seq.len().min(start + piece_size);
+        let mut bases = seq[start..end].iter().filter(|ch| is_base(ch)).count();
+        while end < seq.len() && bases < meros.k_mer {
+            bases += is_base(&seq[end]) as usize;
+            end += 1;
+        }
+        pieces.push(start..end);
+        if end == seq.len() {
+            return pieces;
+        }
+        // Back k - 1 bases from the end, the next k-mer lies whole in the next range
+        let mut overlap = 0;
+        start = end;
+        while overlap + 1 < meros.k_mer {
+            start -= 1;
+            overlap += is_base(&seq[start]) as usize;
+        }
+    }
+}
+
+/// Scans a sequence delivered in successive chunks, e.g. streamed from a long read
+///
+/// The l-mer and the window carry over from one chunk to the next, the chunks yield the
+/// minimizers of their concatenation, including the ones spanning chunk boundaries, with
+/// positions counted from the start of the sequence. [`ChunkScanner::end_sequence`] starts
+/// the next sequence.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_minimizers_with, ChunkScanner, ScanOptions};
+/// use seqkmer::Meros;
+///
+/// let seq = b"ACGTTGCAACGTAGCTAGCTAGCATCGATCGATGCATCGATTACGATCGAACGGTACCATGGTTTAGCATCAGGAC\
+///             ACGATCAGTTACGATCAGCGATTACGAGGCATTACGGNATCCATTAGCAGCTTAGGCAATCGGATCAGGCATTAC";
+/// let meros = Meros::new(15, 11, None, None, None);
+/// let options = ScanOptions::default();
+/// let mut whole = Vec::new();
+/// scan_minimizers_with(seq, &meros, options, &mut whole);
+///
+/// let mut scanner = ChunkScanner::new(&meros, options);
+/// for chunk_size in [1, 7, 16, 50, seq.len()] {
+///     let mut chunked = Vec::new();
+///     for chunk in seq.chunks(chunk_size) {
+///         scanner.feed_chunk(chunk, &mut chunked);
+///     }
+///     scanner.end_sequence(&mut chunked);
+///     assert_eq!(chunked, whole, "chunks of {}", chunk_size);
+/// }
+/// ```
+pub struct ChunkScanner<'a> {
+    scanner: BatchScanner<'a>,
+    options: ScanOptions,
+}
+
+impl<'a> ChunkScanner<'a> {
+    pub fn new(meros: &'a Meros, options: ScanOptions) -> Self {
+        Self {
+            scanner: BatchScanner::new(meros, options),
+            options,
+        }
+    }
+
+    /// Scans the next chunk of the sequence, pushing the minimizers it completes
+    pub fn feed_chunk(&mut self, seq: &[u8], minimizers: &mut Vec<(usize, u64)>) {
+        self.scanner.feed(seq, self.options, minimizers);
+        self.scanner.flush(minimizers);
+    }
+
+    /// Ends the sequence, pushing its trailing minimizer with `options.trailing_window`,
+    /// the next chunk starts a new one
+    pub fn end_sequence(&mut self, minimizers: &mut Vec<(usize, u64)>) {
+        self.scanner.finish(minimizers);
+        self.scanner = BatchScanner::new(self.scanner.meros, self.options);
+    }
+}
+
+/// Bits of a residue code of the reduced protein alphabet
+#[cfg(feature = "protein")]
+pub const PROTEIN_BITS_PER_CHAR: usize = 4;
+
+/// Scans a protein sequence in the reduced alphabet of [`protein_char_to_value`]
+///
+/// A protein has one strand, the canonical l-mer is the l-mer itself: the scanner never
+/// takes a [`reverse_complement`], which swaps 2-bit nucleotide codes and is meaningless
+/// on residue codes. The window, the spaced seed, the toggle mask and the hash are those
+/// of [`scan_minimizers_with`]. `options.iupac` applies to X, see
+/// [`IupacPolicy::rewrite_protein`]. An l-mer holds at most 16 residues.
+///
+/// # Examples
+///
+/// The residues of a group share a code, peptides that only differ within the groups
+/// (N, Q and S, D and E, I and L, M and V) are the same sequence to the scanner, they
+/// yield the same minimizers. A residue of another group changes them.
+///
+/// ```
+/// use kun_peng::mmscanner::{scan_protein_minimizers, ScanOptions};
+/// use seqkmer::Meros;
+///
+/// let meros = Meros::new(15, 12, None, None, None);
+/// let scan = |seq: &[u8]| {
+///     let mut minimizers = Vec::new();
+///     scan_protein_minimizers(seq, &meros, ScanOptions::default(), &mut minimizers);
+///     minimizers
+/// };
+///
+/// let peptide = b"MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKALPDAQFEVVHSLAKW";
+/// let collapsed: Vec<u8> = peptide
+///     .iter()
+///     .map(|&ch| match ch {
+///         b'N' => b'Q',
+///         b'Q' => b'S',
+///         b'S' => b'N',
+///         b'D' => b'E',
+///         b'E' => b'D',
+///         b'I' => b'L',
+///         b'L' => b'I',
+///         b'M' => b'V',
+///         b'V' => b'M',
+///         ch => ch,
+///     })
+///     .collect();
+/// assert_ne!(&peptide[..], &collapsed[..]);
+/// assert!(!scan(peptide).is_empty());
+/// assert_eq!(scan(peptide), scan(&collapsed));
+/// assert_eq!(scan(peptide), scan(&peptide.to_ascii_lowercase()));
+///
+/// // K and R are in different groups
+/// let mut mutated = peptide.to_vec();
+/// assert_eq!(mutated[20], b'R');
+/// mutated[20] = b'K';
+/// assert_ne!(scan(peptide), scan(&mutated));
+///
+/// // No canonical choice, the reversed peptide is another sequence
+/// let reversed: Vec<u8> = peptide.iter().rev().copied().collect();
+/// let mut forward: Vec<u64> = scan(peptide).into_iter().map(|(_, hash)| hash).collect();
+/// forward.reverse();
+/// let backward: Vec<u64> = scan(&reversed).into_iter().map(|(_, hash)| hash).collect();
+/// assert_ne!(forward, backward);
+/// ```
+///
+/// The minimizers of a short peptide, computed directly: the smallest toggled l-mer of
+/// every k-mer, kept when it differs from the one of the previous k-mer.
+///
+/// ```
+/// use kun_peng::mmscanner::{
+///     protein_char_to_value, scan_protein_minimizers, ScanOptions, PROTEIN_BITS_PER_CHAR,
+/// };
+/// use seqkmer::{fmix64, Meros};
+///
+/// let meros = Meros::new(8, 5, None, None, None);
+/// let peptide = b"MKTAYIAKQRQISFVKSHFSRQ";
+/// let mut minimizers = Vec::new();
+/// scan_protein_minimizers(peptide, &meros, ScanOptions::default(), &mut minimizers);
+///
+/// let codes: Vec<u64> = peptide
+///     .iter()
+///     .map(|&ch| protein_char_to_value(ch).unwrap())
+///     .collect();
+/// let candidates: Vec<u64> = codes
+///     .windows(meros.l_mer)
+///     .map(|residues| {
+///         let lmer = residues
+///             .iter()
+///             .fold(0, |lmer, &code| (lmer << PROTEIN_BITS_PER_CHAR) | code);
+///         let lmer = match meros.spaced_seed_mask {
+///             0 => lmer,
+///             mask => lmer & mask,
+///         };
+///         lmer ^ meros.toggle_mask
+///     })
+///     .collect();
+/// let mut expected = Vec::new();
+/// let mut last = None;
+/// for lmers in candidates.windows(meros.k_mer - meros.l_mer + 1) {
+///     let minimizer = lmers.iter().min().copied();
+///     if minimizer != last {
+///         expected.push(fmix64(minimizer.unwrap() ^ meros.toggle_mask));
+///         last = minimizer;
+///     }
+/// }
+///
+/// assert!(expected.len() > 1);
+/// let positions: Vec<usize> = minimizers.iter().map(|&(position, _)| position).collect();
+/// assert_eq!(positions, (1..=expected.len()).collect::<Vec<_>>());
+/// let hashes: Vec<u64> = minimizers.iter().map(|&(_, hash)| hash).collect();
+/// assert_eq!(hashes, expected);
+/// ```
+#[cfg(feature = "protein")]
+pub fn scan_protein_minimizers(
+    seq: &[u8],
+    meros: &Meros,
+    options: ScanOptions,
+    minimizers: &mut Vec<(usize, u64)>,
+) {
+    let l_mer = meros.l_mer;
+    assert!(
+        (1..=64 / PROTEIN_BITS_PER_CHAR).contains(&l_mer),
+        "a protein l-mer holds 1 to 16 residues"
+    );
+    let lmer_mask = u64::MAX >> (64 - PROTEIN_BITS_PER_CHAR * l_mer);
+    let mut window = MinimizerWindow::new(meros.window_size());
+    let (mut lmer, mut filled, mut size) = (0u64, 0usize, 0usize);
+    for &ch in seq {
+        if ch == b'\n' || ch == b'\r' || (options.skip_whitespace && is_blank(ch)) {
+            continue;
+        }
+        let any = ch.eq_ignore_ascii_case(&b'X');
+        let code = match (protein_char_to_value(ch), options.iupac) {
+            (Some(code), _) => code,
+            (None, IupacPolicy::FirstBase) if any => protein_char_to_value(b'A').unwrap(),
+            (None, IupacPolicy::Skip) if any => continue,
+            (None, _) => {
+                window = MinimizerWindow::new(meros.window_size());
+                filled = 0;
+                continue;
+            }
+        };
+        lmer = ((lmer << PROTEIN_BITS_PER_CHAR) | code) & lmer_mask;
+        filled += 1;
+        if filled < l_mer {
+            continue;
+        }
+        let mut candidate_lmer = lmer;
+        if meros.spaced_seed_mask > 0 {
+            candidate_lmer &= meros.spaced_seed_mask;
+        }
+        candidate_lmer ^= meros.toggle_mask;
+        if let Some(minimizer) = window.next(candidate_lmer) {
+            size += 1;
+            let hash = fmix64(minimizer ^ meros.toggle_mask);
+            if meros
+                .min_clear_hash_value
+                .is_none_or(|min_clear| hash >= min_clear)
+            {
+                minimizers.push((size, hash));
+            }
+        }
+    }
+}
+
+struct BatchScanner<'a> {
+    meros: &'a Meros,
+    forward_only: bool,
+    trailing_window: bool,
+    window: MinimizerWindow,
+    lmer: RollingLmer,
+    /// Canonical, or forward, l-mers waiting for the window
+    lmers: Vec<u64>,
+    /// The smallest candidate l-mer of the current run until its window is full
+    run_min: Option<u64>,
+    window_full: bool,
+    size: usize,
+}
+
+impl<'a> BatchScanner<'a> {
+    fn new(meros: &'a Meros, options: ScanOptions) -> Self {
+        Self {
+            meros,
+            forward_only: options.forward_only,
+            trailing_window: options.trailing_window,
+            window: MinimizerWindow::new(meros.window_size()),
+            lmer: RollingLmer::new(meros.l_mer),
+            lmers: Vec::with_capacity(LMER_BUFFER_SIZE),
+            run_min: None,
+            window_full: false,
+            size: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, code: u64, minimizers: &mut Vec<(usize, u64)>) {
+        if self.lmer.push(code) {
+            let lmer = if self.forward_only {
+                self.lmer.value
+            } else {
+                self.lmer.canonical()
+            };
+            self.lmers.push(lmer);
+            if self.lmers.len() == LMER_BUFFER_SIZE {
+                self.flush(minimizers);
+            }
+        }
+    }
+
+    /// Pushes the bases of `seq`, `options` telling which are skipped or end a run
+    fn feed(&mut self, seq: &[u8], options: ScanOptions, minimizers: &mut Vec<(usize, u64)>) {
+        for &ch in seq {
+            if ch == b'\n' || ch == b'\r' || (options.skip_whitespace && is_blank(ch)) {
+                continue;
+            }
+            match char_to_value(ch) {
+                Some(code) => self.push(code, minimizers),
+                None => match (options.iupac, iupac_first_base(ch).and_then(char_to_value)) {
+                    (IupacPolicy::FirstBase, Some(code)) => self.push(code, minimizers),
+                    (IupacPolicy::Skip, Some(_)) => {}
+                    _ => self.reset(minimizers),
+                },
+            }
+        }
+    }
+
+    /// An invalid base ends the current run of l-mers
+    fn reset(&mut self, minimizers: &mut Vec<(usize, u64)>) {
+        self.flush(minimizers);
+        self.window = MinimizerWindow::new(self.meros.window_size());
+        self.lmer.reset();
+        self.run_min = None;
+        self.window_full = false;
+    }
+
+    /// Ends the sequence, the minimizer of a last run shorter than k comes out with
+    /// `trailing_window`
+    fn finish(&mut self, minimizers: &mut Vec<(usize, u64)>) {
+        self.flush(minimizers);
+        match self.run_min.take() {
+            Some(minimizer) if self.trailing_window && !self.window_full => {
+                self.emit(minimizer, minimizers)
+            }
+            _ => {}
+        }
+    }
+
+    /// Pushes a minimizer taken from the window, still XORed with the toggle mask
+    #[inline]
+    fn emit(&mut self, minimizer: u64, minimizers: &mut Vec<(usize, u64)>) {
+        self.size += 1;
+        let hash = fmix64(minimizer ^ self.meros.toggle_mask);
+        if self
+            .meros
+            .min_clear_hash_value
+            .is_none_or(|min_clear| hash >= min_clear)
+        {
+            minimizers.push((self.size, hash));
+        }
+    }
+
+    fn flush(&mut self, minimizers: &mut Vec<(usize, u64)>) {
+        let lmers = std::mem::take(&mut self.lmers);
+        for &lmer in &lmers {
+            // The spaced seed is applied to the canonical l-mer, so both strands, and the
+            // two halves of a palindrome, are masked the same way. A palindromic l-mer equals
+            // its reverse complement, either choice gives the same canonical value.
+            let mut candidate_lmer = lmer;
+            if self.meros.spaced_seed_mask > 0 {
+                candidate_lmer &= self.meros.spaced_seed_mask;
+            }
+            candidate_lmer ^= self.meros.toggle_mask;
+            match self.window.next(candidate_lmer) {
+                Some(minimizer) => {
+                    self.window_full = true;
+                    self.emit(minimizer, minimizers);
+                }
+                None if !self.window_full => {
+                    let smallest = self
+                        .run_min
+                        .map_or(candidate_lmer, |m| m.min(candidate_lmer));
+                    self.run_min = Some(smallest);
+                }
+                None => {}
+            }
+        }
+        self.lmers = lmers;
+        self.lmers.clear();
+    }
+}
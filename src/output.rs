@@ -0,0 +1,717 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use seqkmer::OptionPair;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Appends `suffix` to a path prefix, `out/sample` + `.x` gives `out/sample.x`
+fn with_suffix(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = prefix.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Creates an output file, or appends to it when `append` is set
+pub fn open_output<P: AsRef<Path>>(path: P, append: bool) -> io::Result<File> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+}
+
+/// Uncompressed size of the gzip members written by a [`GzipWriter`]
+const GZIP_BLOCK_SIZE: usize = 1 << 20;
+
+/// Longest time a [`GzipWriter`] holds written data before compressing it, for slow outputs
+const GZIP_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Thread pool of the gzip compression
+///
+/// The classification runs on its own `--num-threads` threads. Gzip outputs hand their
+/// blocks to this pool instead of compressing on those threads, so a run uses up to
+/// `--num-threads` + `--io-threads` threads. The input decompression happens in the
+/// readers and stays on the classification threads.
+#[derive(Clone)]
+pub struct IoPool {
+    pool: Arc<rayon::ThreadPool>,
+    busy_nanos: Arc<AtomicU64>,
+}
+
+impl IoPool {
+    pub fn new(num_threads: usize) -> io::Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads.max(1))
+            .thread_name(|i| format!("io-{}", i))
+            .build()
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            pool: Arc::new(pool),
+            busy_nanos: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pool.current_num_threads()
+    }
+
+    /// Time spent compressing so far, summed over the threads of the pool
+    pub fn busy(&self) -> Duration {
+        Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Compresses everything written to `inner` on the pool, see [`GzipWriter`]
+    pub fn gzip_writer<W: Write>(&self, inner: W) -> GzipWriter<W> {
+        GzipWriter {
+            inner,
+            pool: self.clone(),
+            buffer: Vec::with_capacity(GZIP_BLOCK_SIZE),
+            pending: VecDeque::new(),
+            members: 0,
+            last_submit: Instant::now(),
+        }
+    }
+}
+
+/// A gzip stream compressed on an [`IoPool`]
+///
+/// The data is cut into blocks, every block is compressed as an independent gzip member
+/// on the pool and the members are written in order. Gzip readers decompress such a
+/// multi-member stream as a whole.
+///
+/// A block is compressed once it is full, or once it has waited 5 seconds,
+/// and the compressed members are written as soon as they are ready: the file grows while
+/// the run goes on and holds every member completed so far. At most one block plus
+/// 2 x `--io-threads` blocks in flight are held in memory, whatever the output size. The
+/// stream is finished when the writer is dropped, also when a run stops on an error.
+///
+/// # Examples
+///
+/// ```
+/// use flate2::read::MultiGzDecoder;
+/// use kun_peng::output::IoPool;
+/// use std::io::{Read, Write};
+///
+/// let pool = IoPool::new(2).unwrap();
+/// let data: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+/// let mut compressed = Vec::new();
+/// {
+///     let mut writer = pool.gzip_writer(&mut compressed);
+///     writer.write_all(&data).unwrap();
+///     writer.finish().unwrap();
+/// }
+/// let mut decompressed = Vec::new();
+/// MultiGzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+/// assert_eq!(decompressed, data);
+///
+/// // An empty output is still a valid gzip file.
+/// let mut empty = Vec::new();
+/// drop(pool.gzip_writer(&mut empty));
+/// let mut decompressed = Vec::new();
+/// MultiGzDecoder::new(&empty[..]).read_to_end(&mut decompressed).unwrap();
+/// assert!(decompressed.is_empty());
+/// ```
+///
+/// A large output holds a bounded number of blocks, and is written while it is produced:
+///
+/// ```
+/// use flate2::read::MultiGzDecoder;
+/// use kun_peng::output::IoPool;
+/// use std::io::{Read, Write};
+///
+/// let pool = IoPool::new(2).unwrap();
+/// let line: Vec<u8> = (0..200u32).map(|i| b"ACGT"[(i * 7 % 4) as usize]).chain([b'\n']).collect();
+/// let lines = 200_000; // 40 MB
+/// let mut compressed = Vec::new();
+/// {
+///     let mut writer = pool.gzip_writer(&mut compressed);
+///     for i in 0..lines {
+///         writer.write_all(&line).unwrap();
+///         assert!(writer.in_flight() <= 2 * pool.num_threads() + 1);
+///         if i == lines / 2 {
+///             assert!(writer.members() > 0);
+///         }
+///     }
+/// }
+/// let mut decompressed = Vec::new();
+/// MultiGzDecoder::new(&compressed[..]).read_to_end(&mut decompressed).unwrap();
+/// assert_eq!(decompressed.len(), line.len() * lines);
+/// assert!(decompressed.chunks(line.len()).all(|chunk| chunk == line));
+/// ```
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    pool: IoPool,
+    buffer: Vec<u8>,
+    /// Members being compressed, in output order
+    pending: VecDeque<Receiver<io::Result<Vec<u8>>>>,
+    members: usize,
+    last_submit: Instant,
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// Number of blocks handed to the pool and not written yet
+    pub fn in_flight(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Number of gzip members written so far
+    pub fn members(&self) -> usize {
+        self.members
+    }
+
+    /// Hands the buffered block to the pool
+    fn submit(&mut self) {
+        self.last_submit = Instant::now();
+        let block = std::mem::replace(&mut self.buffer, Vec::with_capacity(GZIP_BLOCK_SIZE));
+        let (sender, receiver) = mpsc::channel();
+        let busy_nanos = self.pool.busy_nanos.clone();
+        self.pool.pool.spawn(move || {
+            let start = Instant::now();
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let member = encoder.write_all(&block).and_then(|_| encoder.finish());
+            busy_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            // The writer may be gone after an error, the member is then of no interest.
+            let _ = sender.send(member);
+        });
+        self.pending.push_back(receiver);
+    }
+
+    /// Waits for the oldest member and writes it
+    fn write_next(&mut self) -> io::Result<()> {
+        if let Some(receiver) = self.pending.pop_front() {
+            let member = receiver.recv().map_err(|_| compression_stopped())??;
+            self.write_member(&member)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the members already compressed, in order, without waiting
+    fn write_ready(&mut self) -> io::Result<()> {
+        while let Some(receiver) = self.pending.front() {
+            let member = match receiver.try_recv() {
+                Ok(member) => member?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(compression_stopped()),
+            };
+            self.pending.pop_front();
+            self.write_member(&member)?;
+        }
+        Ok(())
+    }
+
+    fn write_member(&mut self, member: &[u8]) -> io::Result<()> {
+        self.inner.write_all(member)?;
+        self.members += 1;
+        Ok(())
+    }
+
+    /// Writes all data and ends the stream, an empty stream still gets one member
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.members == 0 && self.pending.is_empty() {
+            self.submit();
+        }
+        self.flush()
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= GZIP_BLOCK_SIZE || self.last_submit.elapsed() >= GZIP_FLUSH_INTERVAL
+        {
+            self.submit();
+            self.write_ready()?;
+            // Bounds the memory held by the blocks in flight.
+            while self.pending.len() > 2 * self.pool.num_threads() {
+                self.write_next()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.submit();
+        }
+        while !self.pending.is_empty() {
+            self.write_next()?;
+        }
+        self.inner.flush()
+    }
+}
+
+fn compression_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "gzip compression thread stopped")
+}
+
+impl<W: Write> Drop for GzipWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Creates an output file, gzip compressed on `io_pool` if the path ends with `.gz`
+///
+/// The compressed stream is finished when the writer is dropped. A compressed output
+/// can't be appended to, its stream would end in the middle.
+pub fn create_output<P: AsRef<Path>>(
+    path: P,
+    append: bool,
+    io_pool: &IoPool,
+) -> io::Result<Box<dyn Write + Send>> {
+    let path = path.as_ref();
+    let gzip = path.extension().is_some_and(|ext| ext == "gz");
+    if gzip && append {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("can't append to the gzip output {}", path.to_string_lossy()),
+        ));
+    }
+    let file = open_output(path, append)?;
+    if gzip {
+        Ok(Box::new(io_pool.gzip_writer(file)))
+    } else {
+        Ok(Box::new(BufWriter::new(file)))
+    }
+}
+
+/// Output of the lines of one classification status
+struct StatusSink {
+    writer: Box<dyn Write + Send>,
+    /// Writes only the read ID column
+    ids_only: bool,
+}
+
+impl StatusSink {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if !self.ids_only {
+            return self.writer.write_all(line);
+        }
+        let id = line.split(|&b| b == b'\t').nth(1).unwrap_or_default();
+        let id = id.strip_suffix(b"\n").unwrap_or(id);
+        self.writer.write_all(id)?;
+        self.writer.write_all(b"\n")
+    }
+}
+
+/// Kraken output lines split by classification status
+///
+/// [`SplitOutput::create`] copies the full lines to `<prefix>.classified.kraken` and
+/// `<prefix>.unclassified.kraken`, [`SplitOutput::ids`] only writes the read IDs.
+/// The handle is cheap to clone, all clones write to the same files. With `append`, the
+/// constructors continue existing files instead of replacing them.
+#[derive(Clone)]
+pub struct SplitOutput {
+    /// Classified and unclassified sink
+    sinks: Arc<Mutex<[Option<StatusSink>; 2]>>,
+    paths: Vec<PathBuf>,
+}
+
+impl SplitOutput {
+    fn new(
+        classified: Option<StatusSink>,
+        unclassified: Option<StatusSink>,
+        paths: Vec<PathBuf>,
+    ) -> Self {
+        Self {
+            sinks: Arc::new(Mutex::new([classified, unclassified])),
+            paths,
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(prefix: P, append: bool) -> io::Result<Self> {
+        let prefix = prefix.as_ref();
+        let paths = vec![
+            with_suffix(prefix, ".classified.kraken"),
+            with_suffix(prefix, ".unclassified.kraken"),
+        ];
+        let sink = |path: &Path| -> io::Result<StatusSink> {
+            Ok(StatusSink {
+                writer: Box::new(BufWriter::new(open_output(path, append)?)),
+                ids_only: false,
+            })
+        };
+        Ok(Self::new(
+            Some(sink(&paths[0])?),
+            Some(sink(&paths[1])?),
+            paths,
+        ))
+    }
+
+    /// Writes the IDs of the classified and/or the unclassified reads, one per line
+    ///
+    /// A pair is a single Kraken line, its ID is written once. Paths ending with `.gz`
+    /// are compressed. Returns `None` if neither path is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::output::{IoPool, SplitOutput};
+    /// use std::io::Write;
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_ids_output");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let unclassified = dir.join("unclassified.txt");
+    /// let io_pool = IoPool::new(1).unwrap();
+    /// let ids = SplitOutput::ids(None, Some(&unclassified), false, &io_pool)
+    ///     .unwrap()
+    ///     .unwrap();
+    /// let mut writer = ids.tee(Vec::new());
+    /// writer.write_all(b"C\tr1\t562\t150\t562:116\nU\tr2\t0\t150|150\t0:116 |:| 0:116\n").unwrap();
+    /// writer.write_all(b"U\tr3\t0\t150\t0:116\n").unwrap();
+    /// writer.flush().unwrap();
+    /// assert_eq!(std::fs::read_to_string(&unclassified).unwrap(), "r2\nr3\n");
+    ///
+    /// assert!(SplitOutput::ids::<&std::path::Path>(None, None, false, &io_pool)
+    ///     .unwrap()
+    ///     .is_none());
+    /// ```
+    pub fn ids<P: AsRef<Path>>(
+        classified: Option<P>,
+        unclassified: Option<P>,
+        append: bool,
+        io_pool: &IoPool,
+    ) -> io::Result<Option<Self>> {
+        if classified.is_none() && unclassified.is_none() {
+            return Ok(None);
+        }
+        let mut paths = Vec::new();
+        let mut sink = |path: Option<P>| -> io::Result<Option<StatusSink>> {
+            path.map(|path| {
+                paths.push(path.as_ref().to_path_buf());
+                Ok(StatusSink {
+                    writer: create_output(path, append, io_pool)?,
+                    ids_only: true,
+                })
+            })
+            .transpose()
+        };
+        let (classified, unclassified) = (sink(classified)?, sink(unclassified)?);
+        Ok(Some(Self::new(classified, unclassified, paths)))
+    }
+
+    /// Paths of the files written by this output
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Wraps a Kraken output writer, every complete line written to it is copied to
+    /// the classified or the unclassified file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::output::SplitOutput;
+    /// use std::io::Write;
+    ///
+    /// let prefix = std::env::temp_dir().join("kun_peng_split_output");
+    /// let split = SplitOutput::create(&prefix, false).unwrap();
+    /// let mut writer = split.tee(Vec::new());
+    /// writer.write_all(b"C\tr1\t562\t150\t562:116\nU\tr2\t0\t150\t0:116\n").unwrap();
+    /// writer.write_all(b"C\tr3\t561\t150|150\t561:116 |:| 0:116\nU\tr4").unwrap();
+    /// writer.write_all(b"\t0\t150\t0:116\n").unwrap();
+    /// writer.flush().unwrap();
+    ///
+    /// let full = String::from_utf8(writer.into_inner()).unwrap();
+    /// let classified = std::fs::read_to_string(prefix.with_extension("classified.kraken")).unwrap();
+    /// let unclassified = std::fs::read_to_string(prefix.with_extension("unclassified.kraken")).unwrap();
+    ///
+    /// assert!(classified.lines().all(|line| line.starts_with("C\t")));
+    /// assert!(unclassified.lines().all(|line| line.starts_with("U\t")));
+    /// let mut parts: Vec<&str> = classified.lines().chain(unclassified.lines()).collect();
+    /// let mut lines: Vec<&str> = full.lines().collect();
+    /// parts.sort();
+    /// lines.sort();
+    /// assert_eq!(parts, lines);
+    /// ```
+    pub fn tee<W: Write>(&self, inner: W) -> SplitWriter<W> {
+        SplitWriter {
+            inner,
+            split: self.clone(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_line(&self, line: &[u8]) -> io::Result<()> {
+        let mut sinks = self.sinks.lock().unwrap();
+        let status = if line.first() == Some(&b'C') { 0 } else { 1 };
+        match &mut sinks[status] {
+            Some(sink) => sink.write_line(line),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let mut sinks = self.sinks.lock().unwrap();
+        for sink in sinks.iter_mut().flatten() {
+            sink.writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Wraps `writer` so that its lines are also copied to every output of `splits`
+pub fn tee_all(splits: &[SplitOutput], writer: Box<dyn Write + Send>) -> Box<dyn Write + Send> {
+    splits
+        .iter()
+        .fold(writer, |writer, split| Box::new(split.tee(writer)))
+}
+
+/// Writer returned by [`SplitOutput::tee`]
+pub struct SplitWriter<W: Write> {
+    inner: W,
+    split: SplitOutput,
+    /// Incomplete last line
+    pending: Vec<u8>,
+}
+
+impl<W: Write> SplitWriter<W> {
+    /// Returns the wrapped writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for SplitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write_all(buf)?;
+        self.pending.extend_from_slice(buf);
+        if let Some(end) = self.pending.iter().rposition(|&b| b == b'\n') {
+            for line in self.pending[..=end].split_inclusive(|&b| b == b'\n') {
+                self.split.write_line(line)?;
+            }
+            self.pending.drain(..=end);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.split.flush()
+    }
+}
+
+/// One line of the standard Kraken output
+///
+/// Owns the column order: status, read ID, taxonomy ID, sequence length and hit string,
+/// followed by the optional columns in the order they were added. Tabs and line breaks in
+/// the read ID and the optional columns are replaced by spaces so that they cannot shift
+/// the columns. The line is written without the trailing newline.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::output::{KrakenLine, SplitOutput};
+/// use seqkmer::OptionPair;
+/// use std::io::Write;
+///
+/// let line = KrakenLine::new(true, "read1", 562).seq_size("150").hit_string("562:116");
+/// assert_eq!(line.to_string(), "C\tread1\t562\t150\t562:116");
+///
+/// let pair = KrakenLine::new(false, "read2", 0)
+///     .seq_lens(&OptionPair::Pair(150, 148))
+///     .hit_string("0:116 |:| 0:114");
+/// assert_eq!(pair.to_string(), "U\tread2\t0\t150|148\t0:116 |:| 0:114");
+///
+/// // Optional columns always come after the standard five, in insertion order.
+/// let golden = [
+///     (vec![], "C\tr\t9\t10\t9:1"),
+///     (vec!["0.50"], "C\tr\t9\t10\t9:1\t0.50"),
+///     (vec!["a\tb"], "C\tr\t9\t10\t9:1\ta b"),
+///     (vec!["0.50", "x"], "C\tr\t9\t10\t9:1\t0.50\tx"),
+///     (vec!["x", "0.50"], "C\tr\t9\t10\t9:1\tx\t0.50"),
+/// ];
+/// for (columns, expected) in golden {
+///     let mut line = KrakenLine::new(true, "r", 9).seq_lens(&OptionPair::Single(10)).hit_string("9:1");
+///     for column in columns {
+///         line = line.column(column);
+///     }
+///     assert_eq!(line.to_string(), expected);
+/// }
+///
+/// let escaped = KrakenLine::new(false, "id\twith tab\n", 0).seq_size("5").hit_string("0:0");
+/// assert_eq!(escaped.to_string(), "U\tid with tab \t0\t5\t0:0");
+///
+/// // The `--read-group` tag is appended last, so the split outputs copy it as well.
+/// let prefix = std::env::temp_dir().join("kun_peng_read_group");
+/// let split = SplitOutput::create(&prefix, false).unwrap();
+/// let mut writer = split.tee(Vec::new());
+/// for (classified, id) in [(true, "r1"), (false, "r2")] {
+///     let line = KrakenLine::new(classified, id, classified as u64).seq_size("10").hit_string("0:1");
+///     writeln!(writer, "{}", line.column("High").column("run7")).unwrap();
+/// }
+/// writer.flush().unwrap();
+/// let full = String::from_utf8(writer.into_inner()).unwrap();
+/// let classified = std::fs::read_to_string(prefix.with_extension("classified.kraken")).unwrap();
+/// let unclassified = std::fs::read_to_string(prefix.with_extension("unclassified.kraken")).unwrap();
+/// for line in full.lines().chain(classified.lines()).chain(unclassified.lines()) {
+///     assert!(line.ends_with("\tHigh\trun7"));
+/// }
+/// assert_eq!(full.lines().count(), 2);
+/// ```
+#[derive(Debug, Clone)]
+pub struct KrakenLine<'a> {
+    classified: bool,
+    id: &'a str,
+    taxid: u64,
+    seq_size: Cow<'a, str>,
+    hit_string: &'a str,
+    columns: Vec<String>,
+}
+
+impl<'a> KrakenLine<'a> {
+    pub fn new(classified: bool, id: &'a str, taxid: u64) -> Self {
+        Self {
+            classified,
+            id,
+            taxid,
+            seq_size: Cow::Borrowed(""),
+            hit_string: "",
+            columns: Vec::new(),
+        }
+    }
+
+    /// Sets an already formatted sequence length column, "len1|len2" for pairs
+    pub fn seq_size(mut self, seq_size: &'a str) -> Self {
+        self.seq_size = Cow::Borrowed(seq_size);
+        self
+    }
+
+    /// Sets the sequence length column from the mate lengths
+    pub fn seq_lens(mut self, seq_lens: &OptionPair<usize>) -> Self {
+        self.seq_size = Cow::Owned(seq_lens.reduce_str("|", |len| len.to_string()));
+        self
+    }
+
+    pub fn hit_string(mut self, hit_string: &'a str) -> Self {
+        self.hit_string = hit_string;
+        self
+    }
+
+    /// Appends an optional column after the standard ones
+    pub fn column<T: fmt::Display>(mut self, value: T) -> Self {
+        self.columns.push(value.to_string());
+        self
+    }
+
+    /// Appends the calls of the mates, `taxid1|taxid2` like the lengths, `-` for a single read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::output::KrakenLine;
+    ///
+    /// let line = KrakenLine::new(true, "r", 543).seq_size("150|150").hit_string("562:3 |:| 590:3");
+    /// assert!(line.clone().mate_calls(Some((562, 590))).to_string().ends_with("\t562|590"));
+    /// assert!(line.mate_calls(None).to_string().ends_with("\t-"));
+    /// ```
+    pub fn mate_calls(self, calls: Option<(u64, u64)>) -> Self {
+        match calls {
+            Some((call1, call2)) => self.column(format!("{}|{}", call1, call2)),
+            None => self.column("-"),
+        }
+    }
+}
+
+/// Writes `value` with tabs and line breaks replaced by spaces
+fn write_escaped(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    if !value.contains(['\t', '\n', '\r']) {
+        return f.write_str(value);
+    }
+    for ch in value.chars() {
+        match ch {
+            '\t' | '\n' | '\r' => f.write_str(" ")?,
+            _ => write!(f, "{}", ch)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for KrakenLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.classified { "C\t" } else { "U\t" })?;
+        write_escaped(f, self.id)?;
+        write!(
+            f,
+            "\t{}\t{}\t{}",
+            self.taxid, self.seq_size, self.hit_string
+        )?;
+        for column in &self.columns {
+            f.write_str("\t")?;
+            write_escaped(f, column)?;
+        }
+        Ok(())
+    }
+}
+
+/// Thresholds that label a call confidence as `Low`, `Medium` or `High`
+///
+/// Parsed from `medium,high`, a confidence below `medium` is `Low`.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::output::ConfidenceBands;
+///
+/// let bands: ConfidenceBands = "0.5,0.8".parse().unwrap();
+/// for (confidence, band) in [
+///     (0.0, "Low"),
+///     (0.49, "Low"),
+///     (0.5, "Medium"),
+///     (0.79, "Medium"),
+///     (0.8, "High"),
+///     (1.0, "High"),
+/// ] {
+///     assert_eq!(bands.label(confidence), band);
+/// }
+///
+/// assert!("0.8,0.5".parse::<ConfidenceBands>().is_err());
+/// assert!("0.5".parse::<ConfidenceBands>().is_err());
+/// assert!("0.5,1.5".parse::<ConfidenceBands>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceBands {
+    pub medium: f64,
+    pub high: f64,
+}
+
+impl ConfidenceBands {
+    pub fn label(&self, confidence: f64) -> &'static str {
+        if confidence >= self.high {
+            "High"
+        } else if confidence >= self.medium {
+            "Medium"
+        } else {
+            "Low"
+        }
+    }
+}
+
+impl FromStr for ConfidenceBands {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected two fractions `medium,high`, got `{}`", s);
+        let (medium, high) = s.split_once(',').ok_or_else(invalid)?;
+        let medium: f64 = medium.trim().parse().map_err(|_| invalid())?;
+        let high: f64 = high.trim().parse().map_err(|_| invalid())?;
+        if !(0.0..=1.0).contains(&medium) || !(0.0..=1.0).contains(&high) || medium > high {
+            return Err(format!(
+                "`{}` must hold two fractions in [0, 1] with medium <= high",
+                s
+            ));
+        }
+        Ok(Self { medium, high })
+    }
+}
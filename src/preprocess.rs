@@ -0,0 +1,314 @@
+//! Per-read preparation of the sequences before they are scanned
+//!
+//! [`ReadPreprocessor`] holds the steps enabled by the options of a run and applies them to
+//! every mate, in this order:
+//!
+//! 1. drop the spaces and tabs inside the read (`--skip-whitespace`)
+//! 2. cut bases from both ends (`--trim-head`, `--trim-tail`)
+//! 3. rewrite the IUPAC ambiguity codes (`--iupac`)
+//! 4. truncate or skip the reads above a maximum length (`--max-read-length`)
+//!
+//! [`PreprocessReader`] runs it on the reads of another reader, the single reads and the
+//! pairs of `direct` and `classify` go through the same steps. The quality masking of the
+//! FASTQ reader and the alphabet check, done once per input, are not per-read steps.
+use crate::mmscanner::{is_blank, iupac_first_base, IupacPolicy};
+use crate::reader::LongReadPolicy;
+use seqkmer::{Base, OptionPair, Reader};
+use std::borrow::Cow;
+use std::io::Result;
+
+/// Why a read is left out of the classification, it is still reported as unclassified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Longer than the maximum length, with [`LongReadPolicy::Skip`]
+    TooLong,
+}
+
+/// A mate after the steps of a [`ReadPreprocessor`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreparedRead<'a> {
+    /// The bases to scan, borrowed when no step changed them
+    pub seq: Cow<'a, [u8]>,
+    /// Cut to the maximum length, with [`LongReadPolicy::Truncate`]
+    pub truncated: bool,
+    /// Emptied, the reason it is left out
+    pub skip_reason: Option<SkipReason>,
+}
+
+impl PreparedRead<'_> {
+    /// Longer than the maximum length, truncated or skipped
+    pub fn too_long(&self) -> bool {
+        self.truncated || self.skip_reason == Some(SkipReason::TooLong)
+    }
+}
+
+/// The per-read steps of a run, see the [module documentation](self)
+///
+/// The default does nothing, the reads are passed through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReadPreprocessor {
+    pub skip_whitespace: bool,
+    pub trim_head: usize,
+    pub trim_tail: usize,
+    pub iupac: IupacPolicy,
+    /// Maximum length of a mate, after trimming
+    pub max_len: Option<usize>,
+    pub long_reads: LongReadPolicy,
+}
+
+impl ReadPreprocessor {
+    /// Whether no step is enabled
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Drops the spaces and tabs of a mate, see [`is_blank`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::preprocess::ReadPreprocessor;
+    ///
+    /// let step = ReadPreprocessor { skip_whitespace: true, ..Default::default() };
+    /// assert_eq!(step.prepare(b"ACG T\tNA").seq, &b"ACGTNA"[..]);
+    /// assert_eq!(ReadPreprocessor::default().prepare(b"ACG T").seq, &b"ACG T"[..]);
+    /// ```
+    fn strip_whitespace(&self, seq: &mut Cow<'_, [u8]>) {
+        if self.skip_whitespace && seq.iter().any(|&base| is_blank(base)) {
+            seq.to_mut().retain(|&base| !is_blank(base));
+        }
+    }
+
+    /// Cuts `trim_head` bases from the start and `trim_tail` from the end of a mate
+    ///
+    /// A mate shorter than the trimmed bases is emptied and left without k-mers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::preprocess::ReadPreprocessor;
+    ///
+    /// let step = ReadPreprocessor { trim_head: 2, trim_tail: 1, ..Default::default() };
+    /// assert_eq!(step.prepare(b"ACGRTNA").seq, &b"GRTN"[..]);
+    /// assert!(step.prepare(b"ACG").seq.is_empty());
+    /// ```
+    fn trim(&self, seq: &mut Cow<'_, [u8]>) {
+        let end = seq.len().saturating_sub(self.trim_tail);
+        let start = self.trim_head.min(end);
+        match seq {
+            Cow::Borrowed(bases) => *bases = &bases[start..end],
+            Cow::Owned(bases) => {
+                bases.truncate(end);
+                bases.drain(..start);
+            }
+        }
+    }
+
+    /// Applies the [`IupacPolicy`] to a mate
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::mmscanner::IupacPolicy;
+    /// use kun_peng::preprocess::ReadPreprocessor;
+    ///
+    /// for (iupac, expected) in [
+    ///     (IupacPolicy::Break, &b"ACGRTNA"[..]),
+    ///     (IupacPolicy::FirstBase, b"ACGATNA"),
+    ///     (IupacPolicy::Skip, b"ACGTNA"),
+    /// ] {
+    ///     let step = ReadPreprocessor { iupac, ..Default::default() };
+    ///     assert_eq!(step.prepare(b"ACGRTNA").seq, expected);
+    /// }
+    /// ```
+    fn rewrite_iupac(&self, seq: &mut Cow<'_, [u8]>) {
+        if self.iupac != IupacPolicy::Break
+            && seq.iter().any(|&base| iupac_first_base(base).is_some())
+        {
+            let mut bases = std::mem::take(seq).into_owned();
+            self.iupac.rewrite(&mut bases);
+            *seq = Cow::Owned(bases);
+        }
+    }
+
+    /// Truncates or skips a mate longer than `max_len`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::preprocess::{ReadPreprocessor, SkipReason};
+    /// use kun_peng::reader::LongReadPolicy;
+    ///
+    /// let truncate = ReadPreprocessor { max_len: Some(5), ..Default::default() };
+    /// let prepared = truncate.prepare(b"ACGTACGT");
+    /// assert_eq!((prepared.seq.as_ref(), prepared.truncated), (&b"ACGTA"[..], true));
+    /// assert!(!truncate.prepare(b"ACG").too_long());
+    ///
+    /// let skip = ReadPreprocessor { long_reads: LongReadPolicy::Skip, ..truncate };
+    /// let prepared = skip.prepare(b"ACGTACGT");
+    /// assert!(prepared.seq.is_empty());
+    /// assert_eq!(prepared.skip_reason, Some(SkipReason::TooLong));
+    /// ```
+    fn limit_length(&self, prepared: &mut PreparedRead<'_>) {
+        let Some(max_len) = self.max_len.filter(|&max_len| prepared.seq.len() > max_len) else {
+            return;
+        };
+        match self.long_reads {
+            LongReadPolicy::Truncate => {
+                match &mut prepared.seq {
+                    Cow::Borrowed(bases) => *bases = &bases[..max_len],
+                    Cow::Owned(bases) => bases.truncate(max_len),
+                }
+                prepared.truncated = true;
+            }
+            LongReadPolicy::Skip => {
+                prepared.seq = Cow::Borrowed(&[]);
+                prepared.skip_reason = Some(SkipReason::TooLong);
+            }
+        }
+    }
+
+    /// Applies the enabled steps to a mate
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::mmscanner::IupacPolicy;
+    /// use kun_peng::preprocess::ReadPreprocessor;
+    /// use std::borrow::Cow;
+    ///
+    /// let all = ReadPreprocessor {
+    ///     skip_whitespace: true,
+    ///     trim_head: 1,
+    ///     trim_tail: 1,
+    ///     iupac: IupacPolicy::FirstBase,
+    ///     max_len: Some(4),
+    ///     ..Default::default()
+    /// };
+    /// // Spaces first, so that the trimmed bases are bases
+    /// let prepared = all.prepare(b" TAC RYGA ");
+    /// assert_eq!(prepared.seq, &b"ACAC"[..]);
+    /// assert!(prepared.truncated);
+    ///
+    /// // Nothing to change, nothing copied
+    /// assert!(matches!(all.prepare(b"TACGT").seq, Cow::Borrowed(b"ACG")));
+    /// ```
+    pub fn prepare<'a>(&self, mate: &'a [u8]) -> PreparedRead<'a> {
+        let mut seq = Cow::Borrowed(mate);
+        self.strip_whitespace(&mut seq);
+        self.trim(&mut seq);
+        self.rewrite_iupac(&mut seq);
+        let mut prepared = PreparedRead {
+            seq,
+            truncated: false,
+            skip_reason: None,
+        };
+        self.limit_length(&mut prepared);
+        prepared
+    }
+
+    /// Prepares the mates of a read in place, returns whether the read is too long
+    ///
+    /// The mates of a pair are truncated one by one, a pair is skipped whole when one of
+    /// its mates is too long.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::preprocess::ReadPreprocessor;
+    /// use kun_peng::reader::LongReadPolicy;
+    /// use seqkmer::OptionPair;
+    ///
+    /// let skip = ReadPreprocessor {
+    ///     trim_head: 1,
+    ///     max_len: Some(4),
+    ///     long_reads: LongReadPolicy::Skip,
+    ///     ..Default::default()
+    /// };
+    /// let mut pair = OptionPair::Pair(b"TACG".to_vec(), b"TACGTACG".to_vec());
+    /// assert!(skip.prepare_read(&mut pair));
+    /// assert!(matches!(&pair, OptionPair::Pair(seq1, seq2) if seq1.is_empty() && seq2.is_empty()));
+    ///
+    /// let mut single = OptionPair::Single(b"TACG".to_vec());
+    /// assert!(!skip.prepare_read(&mut single));
+    /// assert!(matches!(&single, OptionPair::Single(seq) if seq == b"ACG"));
+    /// ```
+    pub fn prepare_read(&self, body: &mut OptionPair<Vec<u8>>) -> bool {
+        let mut mates: Vec<&mut Vec<u8>> = match body {
+            OptionPair::Single(seq) => vec![seq],
+            OptionPair::Pair(seq1, seq2) => vec![seq1, seq2],
+        };
+        let mut too_long = false;
+        let mut skipped = false;
+        for mate in mates.iter_mut() {
+            let prepared = self.prepare(mate);
+            too_long |= prepared.too_long();
+            skipped |= prepared.skip_reason.is_some();
+            match prepared.seq {
+                Cow::Borrowed(bases) if bases.len() == mate.len() => {}
+                seq => **mate = seq.into_owned(),
+            }
+        }
+        if skipped {
+            mates.into_iter().for_each(|mate| mate.clear());
+        }
+        too_long
+    }
+}
+
+/// Applies a [`ReadPreprocessor`] to the reads of another reader
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::preprocess::{PreprocessReader, ReadPreprocessor};
+/// use kun_peng::reader::LongReadPolicy;
+/// use seqkmer::{FastxReader, OptionPair, Reader};
+///
+/// let path = std::env::temp_dir().join("kun_peng_preprocess_reader.fa");
+/// std::fs::write(&path, ">r1\nACGTACGT\n>r2\nACG\n").unwrap();
+/// for (long_reads, expected) in [(LongReadPolicy::Truncate, &b"ACGTA"[..]), (LongReadPolicy::Skip, b"")] {
+///     let steps = ReadPreprocessor { max_len: Some(5), long_reads, ..Default::default() };
+///     let mut reader = FastxReader::from_paths(OptionPair::Single(&path), 1, 0).unwrap();
+///     let mut reader = PreprocessReader::new(&mut reader, steps);
+///     let seqs = reader.next().unwrap().unwrap();
+///     assert!(matches!(&seqs[0].body, OptionPair::Single(seq) if seq == expected));
+///     assert!(matches!(&seqs[1].body, OptionPair::Single(seq) if seq == b"ACG"));
+///     assert_eq!(reader.long_reads(), 1);
+/// }
+/// ```
+pub struct PreprocessReader<'a, R: Reader> {
+    inner: &'a mut R,
+    steps: ReadPreprocessor,
+    long_reads: u64,
+}
+
+impl<'a, R: Reader> PreprocessReader<'a, R> {
+    pub fn new(inner: &'a mut R, steps: ReadPreprocessor) -> Self {
+        Self {
+            inner,
+            steps,
+            long_reads: 0,
+        }
+    }
+
+    /// Number of reads truncated or skipped for their length so far
+    pub fn long_reads(&self) -> u64 {
+        self.long_reads
+    }
+}
+
+impl<R: Reader> Reader for PreprocessReader<'_, R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        let mut seqs = self.inner.next()?;
+        if self.steps.is_noop() {
+            return Ok(seqs);
+        }
+        for seq in seqs.iter_mut().flatten() {
+            if self.steps.prepare_read(&mut seq.body) {
+                self.long_reads += 1;
+            }
+        }
+        Ok(seqs)
+    }
+}
@@ -0,0 +1,184 @@
+use crate::utils::{open_file, write_atomically};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use twox_hash::XxHash64;
+
+/// Marks the provenance appended to a binary database file, followed by one JSON line
+const TRAILER_MAGIC: &[u8] = b"\nKUN_PENG_PROVENANCE\n";
+
+/// Start of the provenance line in the header of a report, after the `# `
+pub const REPORT_HEADER_KEY: &str = "provenance: ";
+
+/// Checksum of one input file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputDigest {
+    pub path: PathBuf,
+    /// xxHash64 (seed 0) of the file content in hex, `None` if the inputs were not hashed
+    pub xxh64: Option<String>,
+}
+
+/// Where a database or a report comes from
+///
+/// Database files carry it as a trailer after their fixed size content, reports as a
+/// `# provenance: {...}` header line. [`Provenance::from_file`] reads both.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::provenance::Provenance;
+/// use kun_peng::IndexOptions;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_provenance");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let input = dir.join("library.fna");
+/// std::fs::write(&input, ">s1\nACGT\n").unwrap();
+///
+/// let provenance = Provenance::new("build", &("--db", "out"))
+///     .with_inputs(&[&input], true)
+///     .unwrap();
+/// assert_eq!(provenance.inputs[0].xxh64.as_deref().map(str::len), Some(16));
+///
+/// // The trailer is ignored by the readers of the database files.
+/// let opts_file = dir.join("opts.k2d");
+/// let opts = IndexOptions::new(35, 31, 0, 0, true, 0);
+/// opts.write_to_file(&opts_file).unwrap();
+/// provenance.append_to(&opts_file).unwrap();
+/// assert_eq!(IndexOptions::read_index_options(&opts_file).unwrap(), opts);
+/// assert_eq!(Provenance::from_file(&opts_file).unwrap(), provenance);
+///
+/// let report = dir.join("output.kreport2");
+/// let header = provenance.report_header().unwrap();
+/// std::fs::write(&report, format!("# {}\n100.00\t1\t1\tR\t1\troot\n", header)).unwrap();
+/// assert_eq!(Provenance::from_file(&report).unwrap(), provenance);
+///
+/// assert!(Provenance::from_file(&input).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Version of the tool that produced the file
+    pub version: String,
+    /// Git commit the tool was built from, if it was known at build time
+    pub git_commit: Option<String>,
+    /// The subcommand that produced the file
+    pub command: String,
+    /// The parsed parameters of the subcommand
+    pub params: String,
+    pub inputs: Vec<InputDigest>,
+    /// Creation time in seconds since the Unix epoch
+    pub created: u64,
+}
+
+impl Provenance {
+    pub fn new<A: Debug>(command: &str, params: &A) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: option_env!("KUN_PENG_GIT_COMMIT").map(String::from),
+            command: command.to_string(),
+            params: format!("{:?}", params),
+            inputs: Vec::new(),
+            created,
+        }
+    }
+
+    /// Records the input files, with their checksums if `hash` is set
+    pub fn with_inputs<P: AsRef<Path>>(mut self, inputs: &[P], hash: bool) -> io::Result<Self> {
+        for path in inputs {
+            let xxh64 = if hash {
+                Some(format!("{:016x}", xxh64_file(path)?))
+            } else {
+                None
+            };
+            self.inputs.push(InputDigest {
+                path: path.as_ref().to_path_buf(),
+                xxh64,
+            });
+        }
+        Ok(self)
+    }
+
+    /// The report header line, without the leading `# `
+    pub fn report_header(&self) -> io::Result<String> {
+        Ok(format!(
+            "{}{}",
+            REPORT_HEADER_KEY,
+            serde_json::to_string(self)?
+        ))
+    }
+
+    /// Appends the provenance to a database file, replacing the file at once
+    pub fn append_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        write_atomically(path, |tmp| {
+            fs::copy(path, tmp)?;
+            let mut file = OpenOptions::new().append(true).open(tmp)?;
+            file.write_all(TRAILER_MAGIC)?;
+            serde_json::to_writer(&mut file, self)?;
+            file.write_all(b"\n")
+        })
+    }
+
+    /// Reads the provenance of a database file or a report
+    ///
+    /// The whole file is read, this is meant for the small `opts.k2d`,
+    /// `hash_config.k2d` and report files.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let trailer = data
+            .windows(TRAILER_MAGIC.len())
+            .rposition(|window| window == TRAILER_MAGIC)
+            .map(|pos| &data[pos + TRAILER_MAGIC.len()..]);
+        let json = match trailer {
+            Some(json) => json,
+            None => data
+                .split(|&b| b == b'\n')
+                .take_while(|line| line.starts_with(b"#"))
+                .find_map(|line| {
+                    line.strip_prefix(b"# ")?
+                        .strip_prefix(REPORT_HEADER_KEY.as_bytes())
+                })
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no provenance in {}", path.display()),
+                    )
+                })?,
+        };
+        Ok(serde_json::from_slice(json.trim_ascii())?)
+    }
+}
+
+/// Computes the xxHash64 (seed 0) of a file, streaming its content
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::provenance::xxh64_file;
+///
+/// let path = std::env::temp_dir().join("kun_peng_xxh64.txt");
+/// std::fs::write(&path, b"abc").unwrap();
+/// assert_eq!(xxh64_file(&path).unwrap(), 0x44bc2cf5ad770999);
+/// std::fs::write(&path, b"").unwrap();
+/// assert_eq!(xxh64_file(&path).unwrap(), 0xef46db3751d8e999);
+/// ```
+pub fn xxh64_file<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    let mut file = open_file(path)?;
+    let mut hasher = XxHash64::with_seed(0);
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buffer[..n]);
+    }
+    Ok(hasher.finish())
+}
@@ -1,6 +1,7 @@
-use crate::KBuildHasher;
+use crate::{fmix64, KBuildHasher};
 use dashmap::DashMap;
-use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+use hyperloglogplus::{HyperLogLog, HyperLogLogPF, HyperLogLogPlus};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::BuildHasher;
@@ -42,7 +43,30 @@ where
     }
 
     fn add_kmer(&mut self, kmer: u64) {
-        self.insert(&kmer);
+        // `KBuildHasher` keeps the value as is, the compacted cell values added by the
+        // classification only fill the low bits the estimator hardly looks at.
+        self.insert(&fmix64(kmer));
+    }
+}
+
+impl<B> Unionable for HyperLogLogPF<u64, B>
+where
+    B: BuildHasher,
+{
+    type Error = UnionError;
+
+    fn union(&mut self, other: &Self) -> Result<(), Self::Error> {
+        self.merge(other).map_err(|_| UnionError)
+    }
+
+    fn distinct_count(&mut self) -> usize {
+        self.count().round() as usize
+    }
+
+    fn add_kmer(&mut self, kmer: u64) {
+        // Only the low 32 bits of the hash are used, mixed first so they depend on the
+        // whole k-mer.
+        self.insert(&fmix64(kmer));
     }
 }
 
@@ -63,7 +87,7 @@ impl Unionable for HashSet<u64> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ReadCounts<T>
 where
     T: Unionable,
@@ -115,35 +139,77 @@ where
     }
 }
 
+/// Precision of the [`CompactKmers`] estimators, 2^12 registers of 5 bits
+pub const COMPACT_PRECISION: u8 = 12;
+
+/// A HyperLogLog of fixed size, about 2.7 KiB whatever the number of k-mers added, for
+/// runs with millions of taxa. Its distinct counts are within a few percent of the exact
+/// ones, against about 0.4% for the HyperLogLog++ of the default counters, which grow up
+/// to about 52 KiB per taxon.
+pub type CompactKmers = HyperLogLogPF<u64, KBuildHasher>;
+
+/// An empty [`CompactKmers`]
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{compact_kmers, ReadCounts};
+/// use std::collections::HashSet;
+///
+/// let mut compact = ReadCounts::with_capacity(compact_kmers(), 0, 0);
+/// let mut exact = ReadCounts::with_capacity(HashSet::new(), 0, 0);
+/// for distinct in [1_000u64, 100_000] {
+///     for kmer in 0..distinct {
+///         compact.add_kmer(kmer << 20);
+///         exact.add_kmer(kmer << 20);
+///     }
+///     let (estimate, count) = (compact.distinct_kmer_count(), exact.distinct_kmer_count());
+///     assert_eq!(count, distinct as usize);
+///     assert!(estimate.abs_diff(count) * 20 < count, "{} {}", estimate, count);
+/// }
+/// assert_eq!(compact.kmer_count(), 101_000);
+/// ```
+pub fn compact_kmers() -> CompactKmers {
+    HyperLogLogPF::new(COMPACT_PRECISION, KBuildHasher).unwrap()
+}
+
 #[cfg(feature = "exact_counting")]
-pub type ReadCounter = ReadCounts<HashSet<u64>>;
+pub type Kmers = HashSet<u64>;
+
+#[cfg(all(feature = "compact_counting", not(feature = "exact_counting")))]
+pub type Kmers = CompactKmers;
+
+#[cfg(not(any(feature = "exact_counting", feature = "compact_counting")))]
+pub type Kmers = HyperLogLogPlus<u64, KBuildHasher>;
 
 #[cfg(feature = "exact_counting")]
-impl Default for ReadCounter {
-    fn default() -> Self {
-        let kmers: HashSet<u64> = HashSet::new();
-        ReadCounts::new(kmers)
-    }
+fn empty_kmers() -> Kmers {
+    HashSet::new()
+}
+
+#[cfg(all(feature = "compact_counting", not(feature = "exact_counting")))]
+fn empty_kmers() -> Kmers {
+    compact_kmers()
+}
+
+#[cfg(not(any(feature = "exact_counting", feature = "compact_counting")))]
+fn empty_kmers() -> Kmers {
+    HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap()
 }
 
-#[cfg(not(feature = "exact_counting"))]
-pub type ReadCounter = ReadCounts<HyperLogLogPlus<u64, KBuildHasher>>;
+/// The reads and k-mers of a taxon. The distinct k-mers are exact with the `exact_counting`
+/// feature, a fixed-size [`CompactKmers`] with `compact_counting`, a HyperLogLog++ otherwise.
+pub type ReadCounter = ReadCounts<Kmers>;
 
-#[cfg(not(feature = "exact_counting"))]
 impl Default for ReadCounter {
     fn default() -> Self {
-        let kmers: HyperLogLogPlus<u64, KBuildHasher> =
-            HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
-        ReadCounts::with_capacity(kmers, 0, 0)
+        ReadCounts::with_capacity(empty_kmers(), 0, 0)
     }
 }
 
-#[cfg(not(feature = "exact_counting"))]
 impl ReadCounter {
     pub fn new(n_reads: u64, n_kmers: u64) -> Self {
-        let kmers: HyperLogLogPlus<u64, KBuildHasher> =
-            HyperLogLogPlus::new(16, KBuildHasher::default()).unwrap();
-        ReadCounts::with_capacity(kmers, n_reads, n_kmers)
+        ReadCounts::with_capacity(empty_kmers(), n_reads, n_kmers)
     }
 }
 
@@ -0,0 +1,1061 @@
+use flate2::read::MultiGzDecoder;
+use seqkmer::{Base, FastxReader, OptionPair, Reader, SeqFormat, SeqHeader};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+type PartReader = FastxReader<Box<dyn Reader + Send>>;
+type SourceStarts = Arc<RwLock<Vec<(usize, Arc<str>)>>>;
+
+/// The sequence of a record read from a single file
+fn into_single(body: OptionPair<Vec<u8>>) -> Vec<u8> {
+    match body {
+        OptionPair::Single(seq) | OptionPair::Pair(seq, _) => seq,
+    }
+}
+
+/// Position of a record inside a sample made of several parts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordIndex {
+    pub file_index: usize,
+    /// Index of the part, starting at 0
+    pub part: usize,
+    /// 1-based read index inside the part
+    pub local_index: usize,
+    /// 1-based read index over all parts
+    pub global_index: usize,
+}
+
+/// Input files of the reads of a sample, by their 1-based global read index
+///
+/// Filled by the reader as it opens the files and read by the threads formatting the
+/// output lines, see `--tag-source`. A pair of files is named after both, joined with a comma.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::ReadSources;
+///
+/// let sources = ReadSources::default();
+/// sources.push(1, "a.fq");
+/// sources.push(3, "empty.fq");
+/// sources.push(3, "b.fq");
+/// assert_eq!(sources.source(0), None);
+/// assert_eq!(sources.source(2).as_deref(), Some("a.fq"));
+/// assert_eq!(sources.source(3).as_deref(), Some("b.fq"));
+/// assert_eq!(sources.source(100).as_deref(), Some("b.fq"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReadSources {
+    starts: SourceStarts,
+}
+
+impl ReadSources {
+    /// Sources of a sample read from a single file or pair
+    pub fn single(name: &str) -> Self {
+        let sources = Self::default();
+        sources.push(1, name);
+        sources
+    }
+
+    /// Records that the reads from `first_index` on come from `name`
+    pub fn push(&self, first_index: usize, name: &str) {
+        self.starts
+            .write()
+            .unwrap()
+            .push((first_index, Arc::from(name)));
+    }
+
+    /// Name of the input file of a read
+    pub fn source(&self, global_index: usize) -> Option<Arc<str>> {
+        let starts = self.starts.read().unwrap();
+        let opened = starts.partition_point(|(first, _)| *first <= global_index);
+        opened.checked_sub(1).map(|i| starts[i].1.clone())
+    }
+}
+
+/// Reads the parts of one sample (`part_001.fq.gz`, `part_002.fq.gz`, ...) as a single stream
+///
+/// Every record gets the sample `file_index` and its global read index as `reads_index`,
+/// so that the Nth record of the stream is the Nth read overall. Paired parts read the
+/// R1 and R2 files in lockstep, a part whose R1 and R2 files hold a different number
+/// of reads is an error. Parts are opened one at a time.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::{MultiFileReader, ReadSources, RecordIndex};
+/// use seqkmer::{OptionPair, Reader};
+///
+/// let dir = std::env::temp_dir().join("kun_peng_multi_file_reader");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let fastq = |name: &str, ids: &[&str]| {
+///     let path = dir.join(name);
+///     let records: String = ids.iter().map(|id| format!("@{}\nACGT\n+\nIIII\n", id)).collect();
+///     std::fs::write(&path, records).unwrap();
+///     path
+/// };
+///
+/// let parts = vec![
+///     OptionPair::Single(fastq("part_001.fq", &["a", "b"])),
+///     OptionPair::Single(fastq("part_002.fq", &["c"])),
+///     OptionPair::Single(fastq("part_003.fq", &["d", "e"])),
+/// ];
+/// let mut reader = MultiFileReader::new(parts, 7, 0).unwrap();
+/// let mut records = Vec::new();
+/// while let Some(batch) = reader.next().unwrap() {
+///     records.extend(batch.into_iter().map(|seq| (seq.header.id, seq.header.reads_index)));
+/// }
+/// assert_eq!(
+///     records,
+///     [("a", 1), ("b", 2), ("c", 3), ("d", 4), ("e", 5)].map(|(id, i)| (id.to_string(), i))
+/// );
+/// assert_eq!(reader.part_counts(), &[2, 1, 2]);
+/// assert_eq!(reader.completed_parts(), 3);
+/// assert_eq!(
+///     reader.locate(4),
+///     Some(RecordIndex { file_index: 7, part: 2, local_index: 1, global_index: 4 })
+/// );
+///
+/// // The parts of the reads, as they are read
+/// let parts = vec![
+///     OptionPair::Single(fastq("part_001.fq", &["a", "b"])),
+///     OptionPair::Single(fastq("part_002.fq", &["c"])),
+/// ];
+/// let sources = ReadSources::default();
+/// let mut reader = MultiFileReader::new(parts, 7, 0).unwrap().with_sources(sources.clone());
+/// while reader.next().unwrap().is_some() {}
+/// assert!(sources.source(2).unwrap().ends_with("part_001.fq"));
+/// assert!(sources.source(3).unwrap().ends_with("part_002.fq"));
+///
+/// // R2 of the first part holds one read more than R1
+/// let parts = vec![
+///     OptionPair::Pair(fastq("r1_001.fq", &["a"]), fastq("r2_001.fq", &["a", "b"])),
+///     OptionPair::Pair(fastq("r1_002.fq", &["b"]), fastq("r2_002.fq", &[])),
+/// ];
+/// let mut reader = MultiFileReader::new(parts, 1, 0).unwrap();
+/// let err = std::iter::from_fn(|| reader.next().transpose()).find_map(Result::err);
+/// assert!(err.unwrap().to_string().starts_with("part 1: "));
+/// ```
+pub struct MultiFileReader {
+    parts: Vec<OptionPair<PathBuf>>,
+    file_index: usize,
+    quality_score: i32,
+    current: Option<MateReader>,
+    part_counts: Vec<usize>,
+    global_index: usize,
+    sources: Option<ReadSources>,
+}
+
+impl MultiFileReader {
+    pub fn new(
+        parts: Vec<OptionPair<PathBuf>>,
+        file_index: usize,
+        quality_score: i32,
+    ) -> Result<Self> {
+        if parts.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no input parts"));
+        }
+        let is_pair = |part: &OptionPair<PathBuf>| matches!(part, OptionPair::Pair(..));
+        if parts.iter().any(|part| is_pair(part) != is_pair(&parts[0])) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "input parts mix single and paired files",
+            ));
+        }
+        Ok(Self {
+            parts,
+            file_index,
+            quality_score,
+            current: None,
+            part_counts: Vec::new(),
+            global_index: 0,
+            sources: None,
+        })
+    }
+
+    /// Records the part of every read in `sources` as the parts are opened
+    pub fn with_sources(mut self, sources: ReadSources) -> Self {
+        self.sources = Some(sources);
+        self
+    }
+
+    /// Number of reads of every part started so far
+    pub fn part_counts(&self) -> &[usize] {
+        &self.part_counts
+    }
+
+    /// Number of fully read parts
+    pub fn completed_parts(&self) -> usize {
+        if self.current.is_some() {
+            self.part_counts.len() - 1
+        } else {
+            self.part_counts.len()
+        }
+    }
+
+    /// Finds the part of a record from its 1-based global read index
+    pub fn locate(&self, global_index: usize) -> Option<RecordIndex> {
+        let mut first = 1;
+        for (part, &count) in self.part_counts.iter().enumerate() {
+            if global_index >= first && global_index < first + count {
+                return Some(RecordIndex {
+                    file_index: self.file_index,
+                    part,
+                    local_index: global_index - first + 1,
+                    global_index,
+                });
+            }
+            first += count;
+        }
+        None
+    }
+
+    /// Read counts of every part and of the whole sample, one line each
+    pub fn part_summary(&self) -> String {
+        let mut summary = String::new();
+        for (part, count) in self.part_counts.iter().enumerate() {
+            summary.push_str(&format!(
+                "part {} ({}): {} reads\n",
+                part + 1,
+                self.parts[part].reduce_str(",", |path| path.to_string_lossy().to_string()),
+                count
+            ));
+        }
+        summary.push_str(&format!(
+            "total: {} reads in {} parts",
+            self.global_index,
+            self.part_counts.len()
+        ));
+        summary
+    }
+
+    fn open_part(&self, part: usize) -> Result<MateReader> {
+        let paths = self.parts[part].reduce(Vec::new(), |mut paths, path| {
+            paths.push(path.clone());
+            paths
+        });
+        MateReader::from_paths(paths, self.file_index, self.quality_score)
+    }
+
+    /// Reads the next batch of the current part, `None` at its end
+    fn next_in_part(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        let Some(current) = self.current.as_mut() else {
+            return Ok(None);
+        };
+        let part = self.part_counts.len();
+        let several = self.parts.len() > 1;
+        let reads = current.next_mates().map_err(|e| {
+            if several {
+                Error::new(e.kind(), format!("part {}: {}", part, e))
+            } else {
+                e
+            }
+        })?;
+        Ok(reads.map(|reads| {
+            reads
+                .into_iter()
+                .map(|(header, mut mates)| {
+                    let body = match mates.pop() {
+                        Some(seq2) if !mates.is_empty() => {
+                            OptionPair::Pair(mates.pop().unwrap(), seq2)
+                        }
+                        seq => OptionPair::Single(seq.unwrap_or_default()),
+                    };
+                    Base::new(header, body)
+                })
+                .collect()
+        }))
+    }
+}
+
+impl Reader for MultiFileReader {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        loop {
+            if self.current.is_none() {
+                let part = self.part_counts.len();
+                if part == self.parts.len() {
+                    return Ok(None);
+                }
+                self.current = Some(self.open_part(part)?);
+                self.part_counts.push(0);
+                if let Some(sources) = &self.sources {
+                    let name =
+                        self.parts[part].reduce_str(",", |path| path.to_string_lossy().to_string());
+                    sources.push(self.global_index + 1, &name);
+                }
+            }
+
+            match self.next_in_part()? {
+                Some(mut seqs) => {
+                    for seq in &mut seqs {
+                        self.global_index += 1;
+                        seq.header.file_index = self.file_index;
+                        seq.header.reads_index = self.global_index;
+                    }
+                    *self.part_counts.last_mut().unwrap() += seqs.len();
+                    return Ok(Some(seqs));
+                }
+                None => self.current = None,
+            }
+        }
+    }
+}
+
+/// What `--max-read-length` does with the reads longer than the maximum length, see
+/// [`crate::preprocess::ReadPreprocessor`]
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::LongReadPolicy;
+///
+/// assert_eq!("skip".parse(), Ok(LongReadPolicy::Skip));
+/// assert!("drop".parse::<LongReadPolicy>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LongReadPolicy {
+    /// Keep the first bases of the read, up to the maximum length
+    #[default]
+    Truncate,
+    /// Empty the read, it is left unclassified
+    Skip,
+}
+
+impl FromStr for LongReadPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(Self::Truncate),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!("expected `truncate` or `skip`, got `{}`", s)),
+        }
+    }
+}
+
+/// The header of a read and the sequences of its mates
+pub type MateRead = (SeqHeader, Vec<Vec<u8>>);
+
+/// Reads associated mates stored in one file per mate, e.g. the three reads of a linked read
+///
+/// The files are read in lockstep, every read holds its mates in file order and the
+/// header of its first mate. Each file is opened on its own, so mates may be compressed
+/// differently. Files holding a different number of reads are an error naming the first
+/// read without all its mates.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::MateReader;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_mate_reader");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let fastq = |name: &str, seqs: &[&str]| {
+///     let path = dir.join(name);
+///     let records: String = seqs
+///         .iter()
+///         .enumerate()
+///         .map(|(i, seq)| format!("@r{}\n{}\n+\n{}\n", i, seq, "I".repeat(seq.len())))
+///         .collect();
+///     std::fs::write(&path, records).unwrap();
+///     path
+/// };
+///
+/// let paths = vec![
+///     fastq("m1.fq", &["AAAA", "CCCC"]),
+///     fastq("m2.fq", &["GG", "TT"]),
+///     fastq("m3.fq", &["ACG", "TGC"]),
+/// ];
+/// let mut reader = MateReader::from_paths(paths, 1, 0).unwrap();
+/// let reads = reader.next_mates().unwrap().unwrap();
+/// assert_eq!(reads.len(), 2);
+/// assert_eq!(reads[1].0.id, "r1");
+/// assert_eq!(reads[1].1, vec![b"CCCC".to_vec(), b"TT".to_vec(), b"TGC".to_vec()]);
+/// assert!(reader.next_mates().unwrap().is_none());
+///
+/// assert_eq!(reader.reads(), 2);
+///
+/// let paths = vec![fastq("short1.fq", &["AAAA", "CCCC"]), fastq("short2.fq", &["GG"])];
+/// let mut reader = MateReader::from_paths(paths, 1, 0).unwrap();
+/// let err = reader.next_mates().unwrap_err().to_string();
+/// assert!(err.contains("short2.fq ends before read 2"), "{}", err);
+/// ```
+pub struct MateReader {
+    paths: Vec<PathBuf>,
+    readers: Vec<PartReader>,
+    /// Records read ahead of the first mate, one queue per mate
+    pending: Vec<VecDeque<Base<Vec<u8>>>>,
+    reads: usize,
+}
+
+impl MateReader {
+    pub fn from_paths(paths: Vec<PathBuf>, file_index: usize, quality_score: i32) -> Result<Self> {
+        if paths.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "no mate files"));
+        }
+        let readers = paths
+            .iter()
+            .map(|path| {
+                FastxReader::from_paths(OptionPair::Single(path), file_index, quality_score)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let pending = paths.iter().map(|_| VecDeque::new()).collect();
+        Ok(Self {
+            paths,
+            readers,
+            pending,
+            reads: 0,
+        })
+    }
+
+    /// Number of reads read so far, pairs for two mate files
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+
+    /// The error for the file of `mate` ending before the 1-based read `index`
+    fn mismatch(&self, mate: usize, index: usize) -> Error {
+        let paths: Vec<_> = self
+            .paths
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect();
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} ends before read {}, the mate files ({}) hold a different number of reads",
+                paths[mate],
+                index,
+                paths.join(",")
+            ),
+        )
+    }
+
+    /// Reads the next batch of reads, `None` once all files are read
+    pub fn next_mates(&mut self) -> Result<Option<Vec<MateRead>>> {
+        let Some(first) = self.readers[0].next()? else {
+            for mate in 1..self.readers.len() {
+                if !self.pending[mate].is_empty() || self.readers[mate].next()?.is_some() {
+                    return Err(self.mismatch(0, self.reads + 1));
+                }
+            }
+            return Ok(None);
+        };
+        for mate in 1..self.readers.len() {
+            while self.pending[mate].len() < first.len() {
+                match self.readers[mate].next()? {
+                    Some(seqs) => self.pending[mate].extend(seqs),
+                    None => {
+                        let index = self.reads + self.pending[mate].len() + 1;
+                        return Err(self.mismatch(mate, index));
+                    }
+                }
+            }
+        }
+        self.reads += first.len();
+
+        let reads = first
+            .into_iter()
+            .map(|seq| {
+                let mut mates = Vec::with_capacity(self.readers.len());
+                mates.push(into_single(seq.body));
+                for pending in self.pending.iter_mut().skip(1) {
+                    mates.push(into_single(pending.pop_front().unwrap().body));
+                }
+                (seq.header, mates)
+            })
+            .collect();
+        Ok(Some(reads))
+    }
+}
+
+/// Keeps the first error of another reader until the reads are processed
+///
+/// An error ends the batches like the end of the input and is returned by
+/// [`CheckedReader::finish`], whatever the parallel readers do with the errors of their
+/// reader, so a truncated mate file can't end a sample silently.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::{CheckedReader, MultiFileReader};
+/// use seqkmer::{OptionPair, Reader};
+///
+/// let dir = std::env::temp_dir().join("kun_peng_checked_reader");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let (r1, r2) = (dir.join("r1.fq"), dir.join("r2.fq"));
+/// std::fs::write(&r1, "@a\nACGT\n+\nIIII\n@b\nACGT\n+\nIIII\n").unwrap();
+/// std::fs::write(&r2, "@a\nACGT\n+\nIIII\n").unwrap();
+///
+/// let mut reader = MultiFileReader::new(vec![OptionPair::Pair(r1, r2)], 1, 0).unwrap();
+/// let mut reader = CheckedReader::new(&mut reader);
+/// assert!(reader.next().unwrap().is_none());
+/// let err = reader.finish().unwrap_err().to_string();
+/// assert!(err.contains("r2.fq ends before read 2"), "{}", err);
+/// ```
+pub struct CheckedReader<'a, R: Reader> {
+    inner: &'a mut R,
+    error: Option<Error>,
+}
+
+impl<'a, R: Reader> CheckedReader<'a, R> {
+    pub fn new(inner: &'a mut R) -> Self {
+        Self { inner, error: None }
+    }
+
+    /// The error that ended the reads, if any
+    pub fn finish(self) -> Result<()> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Reader> Reader for CheckedReader<'_, R> {
+    fn next(&mut self) -> Result<Option<Vec<Base<Vec<u8>>>>> {
+        if self.error.is_some() {
+            return Ok(None);
+        }
+        self.inner.next().or_else(|e| {
+            self.error = Some(e);
+            Ok(None)
+        })
+    }
+}
+
+/// The mate a read header declares, `1` or `2`
+///
+/// Reads either a `/1` `/2` suffix of the read ID or the Casava 1.8 comment
+/// (`1:N:0:ATCACG`).
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::header_mate;
+///
+/// assert_eq!(header_mate("@read1/2"), Some(2));
+/// assert_eq!(header_mate("@M00123:8:000:1:1101:1:1 1:N:0:ATCACG"), Some(1));
+/// assert_eq!(header_mate(">SRR001.1 2:Y:18:1"), Some(2));
+/// assert_eq!(header_mate("@read1 length=150"), None);
+/// assert_eq!(header_mate("@read1/3"), None);
+/// ```
+pub fn header_mate(header: &str) -> Option<u8> {
+    let mut fields = header.trim_start_matches(['@', '>']).split_whitespace();
+    let id = fields.next()?.as_bytes();
+    if let [.., b'/', mate @ (b'1' | b'2')] = id {
+        return Some(mate - b'0');
+    }
+    match fields.next()?.as_bytes() {
+        [mate @ (b'1' | b'2'), b':', ..] => Some(mate - b'0'),
+        _ => None,
+    }
+}
+
+/// The ID of a read header: the header up to the first whitespace, without its `/1` or
+/// `/2` mate marker
+///
+/// Every other part of the ID is kept. The numeric suffix of an SRA spot name (`SRR1.1`,
+/// `SRR1.2`) is the spot number, stripping it would give distinct spots the same ID.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::record_id;
+///
+/// // SRA spots, dumped with and without mate markers
+/// assert_eq!(record_id("@SRR1.1 1 length=150"), "SRR1.1");
+/// assert_eq!(record_id("@SRR1.2 2 length=150"), "SRR1.2");
+/// assert_eq!(record_id("@SRR1.1/1"), "SRR1.1");
+/// assert_eq!(record_id("@SRR1.1/2"), "SRR1.1");
+/// // Illumina, the mate is in the comment
+/// assert_eq!(
+///     record_id("@M00123:8:000000000-A1B2C:1:1101:15589:1331 2:N:0:ATCACG"),
+///     "M00123:8:000000000-A1B2C:1:1101:15589:1331"
+/// );
+/// assert_eq!(record_id(">contig_7/3"), "contig_7/3");
+/// ```
+pub fn record_id(header: &str) -> &str {
+    let id = header
+        .strip_prefix(['@', '>'])
+        .unwrap_or(header)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default();
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
+/// A plain or gzip compressed file, `None` for other compressions
+pub(crate) fn open_text(path: &Path) -> Result<Option<Box<dyn BufRead>>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let head = file.fill_buf()?;
+    Ok(if head.starts_with(&[0x1f, 0x8b]) {
+        Some(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) || head.starts_with(b"BZh") {
+        None
+    } else {
+        Some(Box::new(file))
+    })
+}
+
+/// The first line of a plain or gzip compressed file, `None` for other compressions
+fn first_line(path: &Path) -> Result<Option<String>> {
+    let Some(mut reader) = open_text(path)? else {
+        return Ok(None);
+    };
+    let mut line = String::new();
+    // Binary content is not a header, the reader reports it later.
+    match reader.read_line(&mut line) {
+        Ok(_) => Ok(Some(line)),
+        Err(e) if e.kind() == ErrorKind::InvalidData => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Number of reads sampled by [`sample_read_lengths`] for the read length check
+pub const LENGTH_SAMPLE_READS: usize = 4096;
+
+/// The lengths of the first `max_reads` records of a FASTA/FASTQ file
+///
+/// Empty for a file whose format can't be told, like [`input_format`]. A multi-line
+/// FASTA sequence counts as one record.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::sample_read_lengths;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_read_lengths");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let fastq = dir.join("reads.fq");
+/// std::fs::write(&fastq, "@r1\nACGTA\n+\nIIIII\n@r2\nAC\n+\nII\n@r3\nACG\n+\nIII\n").unwrap();
+/// assert_eq!(sample_read_lengths(&fastq, 2).unwrap(), [5, 2]);
+///
+/// let fasta = dir.join("contigs.fa");
+/// std::fs::write(&fasta, ">c1\nACGT\nACG\n>c2\nA\n").unwrap();
+/// assert_eq!(sample_read_lengths(&fasta, 10).unwrap(), [7, 1]);
+/// ```
+pub fn sample_read_lengths<P: AsRef<Path>>(path: P, max_reads: usize) -> Result<Vec<u64>> {
+    let mut lengths = Vec::new();
+    sample_sequences(path.as_ref(), max_reads, |seq| {
+        lengths.push(seq.len() as u64)
+    })?;
+    Ok(lengths)
+}
+
+/// Calls `f` with the sequences of the first `max_reads` records of a FASTA/FASTQ file
+fn sample_sequences<F: FnMut(&[u8])>(path: &Path, max_reads: usize, mut f: F) -> Result<()> {
+    for_each_record(path, max_reads, |_, seq| f(seq))
+}
+
+/// Calls `f` with the ID and the sequence of the first `max_reads` records of a
+/// FASTA/FASTQ file, plain or gzipped
+///
+/// The ID is the one of [`record_id`]. Nothing is read from a file whose format can't be
+/// told, like [`input_format`].
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::for_each_record;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_for_each_record");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let fasta = dir.join("markers.fa");
+/// std::fs::write(&fasta, ">adk_1 adenylate kinase\nACGT\nACG\n>gyrB_3\nA\n").unwrap();
+/// let mut records = Vec::new();
+/// for_each_record(&fasta, usize::MAX, |id, seq| {
+///     records.push((id.to_string(), String::from_utf8(seq.to_vec()).unwrap()))
+/// })
+/// .unwrap();
+/// assert_eq!(records, [("adk_1".into(), "ACGTACG".into()), ("gyrB_3".into(), "A".into())]);
+/// ```
+pub fn for_each_record<P, F>(path: P, max_reads: usize, mut f: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str, &[u8]),
+{
+    let path = path.as_ref();
+    let format = input_format(path)?;
+    let (Some(format), Some(reader)) = (format, open_text(path)?) else {
+        return Ok(());
+    };
+    let mut lines = reader.lines();
+    let mut records = 0;
+    match format {
+        SeqFormat::Fastq => {
+            while records < max_reads {
+                let (Some(header), Some(seq)) = (lines.next(), lines.next()) else {
+                    break;
+                };
+                f(record_id(&header?), seq?.trim_end().as_bytes());
+                records += 1;
+                lines.next();
+                lines.next();
+            }
+        }
+        _ => {
+            let mut current: Option<(String, Vec<u8>)> = None;
+            for line in lines {
+                let line = line?;
+                if line.starts_with('>') {
+                    if let Some((id, seq)) = current.take() {
+                        f(&id, &seq);
+                        records += 1;
+                    }
+                    if records == max_reads {
+                        return Ok(());
+                    }
+                    current = Some((record_id(&line).to_string(), Vec::new()));
+                } else if let Some((_, seq)) = current.as_mut() {
+                    seq.extend_from_slice(line.trim_end().as_bytes());
+                }
+            }
+            if let Some((id, seq)) = current {
+                f(&id, &seq);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Number of records of a FASTA/FASTQ file, plain or gzipped, counted from its lines
+///
+/// A FASTQ record is four lines, a file ending inside a record, like a truncated copy, is
+/// an error naming that record. `None` for a file whose format can't be told, like
+/// [`input_format`].
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::count_records;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_count_records");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let fastq = dir.join("reads.fq");
+/// std::fs::write(&fastq, "@r1\nACGT\n+\nIIII\n@r2\nAC\n+\nII\n\n").unwrap();
+/// assert_eq!(count_records(&fastq).unwrap(), Some(2));
+///
+/// std::fs::write(&fastq, "@r1\nACGT\n+\nIIII\n@r2\nAC\n").unwrap();
+/// let err = count_records(&fastq).unwrap_err().to_string();
+/// assert!(err.ends_with("ends inside read 2"), "{}", err);
+///
+/// let fasta = dir.join("contigs.fa");
+/// std::fs::write(&fasta, ">c1\nACGT\nACG\n>c2\nA\n").unwrap();
+/// assert_eq!(count_records(&fasta).unwrap(), Some(2));
+/// ```
+pub fn count_records<P: AsRef<Path>>(path: P) -> Result<Option<u64>> {
+    let path = path.as_ref();
+    let format = input_format(path)?;
+    let (Some(format), Some(mut reader)) = (format, open_text(path)?) else {
+        return Ok(None);
+    };
+    let mut line = Vec::new();
+    let (mut lines, mut headers, mut blank_tail) = (0u64, 0u64, 0u64);
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        lines += 1;
+        if is_blank_line(&line) {
+            blank_tail += 1;
+        } else {
+            blank_tail = 0;
+        }
+        if line.starts_with(b">") {
+            headers += 1;
+        }
+        line.clear();
+    }
+    match format {
+        SeqFormat::Fastq => {
+            let lines = lines - blank_tail;
+            if lines % 4 != 0 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} ends inside read {}",
+                        path.to_string_lossy(),
+                        lines / 4 + 1
+                    ),
+                ));
+            }
+            Ok(Some(lines / 4))
+        }
+        _ => Ok(Some(headers)),
+    }
+}
+
+fn is_blank_line(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Checks that the files of one mate of a sample, its parts in order, hold `reads`
+/// records in all
+///
+/// This is the second pass of `--verify-counts`, it catches inputs the reader took as
+/// shorter than they are. Files whose records can't be counted, see [`count_records`],
+/// are not checked.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::verify_record_count;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_verify_record_count");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let (part1, part2) = (dir.join("part_001.fq"), dir.join("part_002.fq"));
+/// std::fs::write(&part1, "@r1\nACGT\n+\nIIII\n@r2\nAC\n+\nII\n").unwrap();
+/// std::fs::write(&part2, "@r3\nACGT\n+\nIIII\n").unwrap();
+/// assert!(verify_record_count(&[&part1, &part2], 3).is_ok());
+/// let err = verify_record_count(&[&part1, &part2], 2).unwrap_err().to_string();
+/// assert!(err.ends_with("hold 3 records, 2 reads were read"), "{}", err);
+/// ```
+pub fn verify_record_count<P: AsRef<Path>>(files: &[P], reads: u64) -> Result<()> {
+    let mut records = 0;
+    for file in files {
+        match count_records(file)? {
+            Some(count) => records += count,
+            None => return Ok(()),
+        }
+    }
+    if records != reads {
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.as_ref().to_string_lossy())
+            .collect();
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} hold {} records, {} reads were read",
+                names.join(","),
+                records,
+                reads
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Number of reads sampled by [`sample_alphabet`] for the alphabet check
+pub const ALPHABET_SAMPLE_READS: usize = 4096;
+
+/// The molecule type of sequences, told by [`AlphabetCounts::molecule`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Molecule {
+    /// DNA or RNA, IUPAC ambiguity codes included
+    Nucleotide,
+    Protein,
+    /// No sequence to tell from
+    Unknown,
+}
+
+/// Character counts of sampled sequences
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::{AlphabetCounts, Molecule};
+///
+/// let molecule = |seqs: &[&[u8]]| {
+///     let mut counts = AlphabetCounts::default();
+///     seqs.iter().for_each(|seq| counts.add(seq));
+///     counts.molecule()
+/// };
+///
+/// // DNA, with some Ns and lower case
+/// assert_eq!(molecule(&[b"ACGTTGCANNACGTacgtagct", b"GATTACA"]), Molecule::Nucleotide);
+/// // RNA, U instead of T
+/// assert_eq!(molecule(&[b"ACGUUGCAACGUAGCUAGCUAGCAUCG"]), Molecule::Nucleotide);
+/// // Heavily ambiguous DNA, outside ACGTN but without amino acid only letters
+/// assert_eq!(molecule(&[b"ACRYSWKMBDHVNNRYACGTRYKM"]), Molecule::Nucleotide);
+/// // Protein
+/// assert_eq!(
+///     molecule(&[b"MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKALPDAQ"]),
+///     Molecule::Protein
+/// );
+/// // Every amino acid once
+/// assert_eq!(molecule(&[b"GAVLIPFMWSTCYNQDEKRH"]), Molecule::Protein);
+/// assert_eq!(molecule(&[]), Molecule::Unknown);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AlphabetCounts {
+    /// Number of characters
+    pub total: u64,
+    /// Characters outside ACGTN
+    pub non_acgtn: u64,
+    /// Characters of amino acids that are not nucleotide codes (E, F, I, L, P, Q, ...)
+    pub amino_only: u64,
+}
+
+impl AlphabetCounts {
+    pub fn add(&mut self, seq: &[u8]) {
+        for &ch in seq {
+            self.total += 1;
+            match ch.to_ascii_uppercase() {
+                b'A' | b'C' | b'G' | b'T' | b'N' => {}
+                b'E' | b'F' | b'I' | b'J' | b'L' | b'O' | b'P' | b'Q' | b'Z' | b'*' => {
+                    self.non_acgtn += 1;
+                    self.amino_only += 1;
+                }
+                _ => self.non_acgtn += 1,
+            }
+        }
+    }
+
+    /// Nucleotides when at most 10% of the characters are outside ACGTN, or when the others
+    /// are nucleotide codes (U, IUPAC) rather than amino acid only letters
+    pub fn molecule(&self) -> Molecule {
+        if self.total == 0 {
+            Molecule::Unknown
+        } else if self.non_acgtn * 10 <= self.total || self.amino_only * 20 <= self.total {
+            Molecule::Nucleotide
+        } else {
+            Molecule::Protein
+        }
+    }
+}
+
+/// The character counts of the first `max_reads` records of a FASTA/FASTQ file
+pub fn sample_alphabet<P: AsRef<Path>>(path: P, max_reads: usize) -> Result<AlphabetCounts> {
+    let mut counts = AlphabetCounts::default();
+    sample_sequences(path.as_ref(), max_reads, |seq| counts.add(seq))?;
+    Ok(counts)
+}
+
+/// The format of a FASTA/FASTQ file, told from its first line
+///
+/// `None` for an empty file or a compression other than gzip.
+pub fn input_format<P: AsRef<Path>>(path: P) -> Result<Option<SeqFormat>> {
+    Ok(
+        match first_line(path.as_ref())?.as_deref().map(str::as_bytes) {
+            Some([b'>', ..]) => Some(SeqFormat::Fasta),
+            Some([b'@', ..]) => Some(SeqFormat::Fastq),
+            _ => None,
+        },
+    )
+}
+
+/// The formats of the files of a sample, e.g. `FASTQ` or `FASTA,FASTQ` for a sample mixing
+/// lanes and contigs, `-` for the files whose format can't be told
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::{format_label, quality_score_note};
+///
+/// let dir = std::env::temp_dir().join("kun_peng_format_label");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let lane = dir.join("lane.fq");
+/// std::fs::write(&lane, "@r1\nACGT\n+\nIIII\n").unwrap();
+/// let contigs = dir.join("contigs.fa");
+/// std::fs::write(&contigs, ">c1\nACGTACGT\n").unwrap();
+///
+/// assert_eq!(format_label(&[&lane, &lane]), "FASTQ");
+/// assert_eq!(format_label(&[&contigs, &lane]), "FASTA,FASTQ");
+/// assert_eq!(format_label(&[dir.join("missing.fq")]), "-");
+///
+/// assert!(quality_score_note(&[&lane, &contigs], 0).is_none());
+/// assert!(quality_score_note(&[&lane], 20).is_none());
+/// let note = quality_score_note(&[&lane, &contigs], 20).unwrap();
+/// assert!(note.contains("contigs.fa") && !note.contains("lane.fq"));
+/// ```
+pub fn format_label<P: AsRef<Path>>(paths: &[P]) -> String {
+    let mut labels: Vec<&str> = Vec::new();
+    for path in paths {
+        let label = match input_format(path) {
+            Ok(Some(SeqFormat::Fasta)) => "FASTA",
+            Ok(Some(SeqFormat::Fastq)) => "FASTQ",
+            _ => "-",
+        };
+        if !labels.contains(&label) {
+            labels.push(label);
+        }
+    }
+    labels.join(",")
+}
+
+/// The note printed once when a minimum quality score is set and some inputs are FASTA
+///
+/// FASTA records have no qualities, their bases are never masked. Unreadable files are
+/// left to the readers to report.
+pub fn quality_score_note<P: AsRef<Path>>(paths: &[P], quality_score: i32) -> Option<String> {
+    if quality_score <= 0 {
+        return None;
+    }
+    let fasta: Vec<_> = paths
+        .iter()
+        .filter(|path| matches!(input_format(path), Ok(Some(SeqFormat::Fasta))))
+        .map(|path| path.as_ref().to_string_lossy())
+        .collect();
+    (!fasta.is_empty()).then(|| {
+        format!(
+            "note: --minimum-quality-score {} only masks FASTQ bases, the FASTA inputs ({}) are read as they are",
+            quality_score,
+            fasta.join(",")
+        )
+    })
+}
+
+/// Puts the two files of every pair back in R1, R2 order
+///
+/// The mates of a pair are told from the first read header of each file (see
+/// [`header_mate`]). A pair whose first file holds the second mates and whose second file
+/// holds the first mates is swapped, other pairs are left alone. Returns the indexes of
+/// the swapped pairs, for the caller to warn about them.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::order_mate_files;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_mate_order");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let fastq = |name: &str, header: &str| {
+///     let path = dir.join(name);
+///     std::fs::write(&path, format!("@{}\nACGT\n+\nIIII\n", header)).unwrap();
+///     path
+/// };
+/// let mut files = vec![
+///     fastq("a_R1.fq", "a.1/1"),
+///     fastq("a_R2.fq", "a.1/2"),
+///     fastq("b_R2.fq", "b.1 2:N:0:ATCACG"),
+///     fastq("b_R1.fq", "b.1 1:N:0:ATCACG"),
+///     fastq("c_x.fq", "c.1"),
+///     fastq("c_y.fq", "c.1"),
+/// ];
+/// assert_eq!(order_mate_files(&mut files).unwrap(), vec![1]);
+/// assert_eq!(files[2], dir.join("b_R1.fq"));
+/// assert_eq!(files[3], dir.join("b_R2.fq"));
+/// assert_eq!(files[0], dir.join("a_R1.fq"));
+/// assert_eq!(files[4], dir.join("c_x.fq"));
+/// ```
+pub fn order_mate_files<P: AsRef<Path>>(files: &mut [P]) -> Result<Vec<usize>> {
+    let mut swapped = Vec::new();
+    for (i, pair) in files.chunks_exact_mut(2).enumerate() {
+        let mut mates = [None, None];
+        for (mate, path) in mates.iter_mut().zip(pair.iter()) {
+            *mate = first_line(path.as_ref())?.as_deref().and_then(header_mate);
+        }
+        if mates == [Some(2), Some(1)] {
+            pair.swap(0, 1);
+            swapped.push(i);
+        }
+    }
+    Ok(swapped)
+}
+
+/// [`order_mate_files`], warning about every swapped pair on stderr
+pub fn order_mate_files_warn<P: AsRef<Path>>(files: &mut [P]) -> Result<()> {
+    for i in order_mate_files(files)? {
+        eprintln!(
+            "WARNING: paired files given in R2, R1 order, reading {} as R1 and {} as R2",
+            files[2 * i].as_ref().to_string_lossy(),
+            files[2 * i + 1].as_ref().to_string_lossy()
+        );
+    }
+    Ok(())
+}
@@ -1,8 +1,9 @@
 use crate::readcounts::{ReadCounter, TaxonCounters};
-use crate::taxonomy::Taxonomy;
+use crate::taxonomy::{rank_code, rank_name, Taxonomy};
+use serde::Serialize;
 use std::collections::HashMap;
 
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Write};
 use std::path::Path;
 
@@ -97,9 +98,12 @@ fn print_mpa_style_report_line(
 
 /// Performs a depth-first search to generate an MPA-style report
 ///
+/// The taxa are visited with an explicit stack, chains of custom taxa can be deeper than
+/// the call stack allows.
+///
 /// # Arguments
 ///
-/// * `taxid` - The current taxon ID
+/// * `taxid` - The taxon ID the search starts from
 /// * `file` - The file to write the report to
 /// * `report_zeros` - Whether to report zero counts
 /// * `taxonomy` - The taxonomy structure
@@ -117,42 +121,46 @@ fn mpa_report_dfs(
     clade_counts: &HashMap<u64, u64>,
     taxonomy_names: &mut Vec<String>,
 ) -> io::Result<()> {
-    if !report_zeros && *clade_counts.get(&taxid).unwrap_or(&0) == 0 {
-        return Ok(());
-    }
+    // Each taxon with the number of names of its parent's lineage
+    let base_len = taxonomy_names.len();
+    let mut stack = vec![(taxid, base_len)];
+    while let Some((taxid, lineage_len)) = stack.pop() {
+        if !report_zeros && *clade_counts.get(&taxid).unwrap_or(&0) == 0 {
+            continue;
+        }
+        taxonomy_names.truncate(lineage_len);
 
-    let node = &taxonomy.nodes[taxid as usize];
-    let rank = extract_string_from_offset(&taxonomy.rank_data, node.rank_offset as usize);
-
-    let rank_code = match rank {
-        "superkingdom" => 'd',
-        "kingdom" => 'k',
-        "phylum" => 'p',
-        "class" => 'c',
-        "order" => 'o',
-        "family" => 'f',
-        "genus" => 'g',
-        "species" => 's',
-        _ => '\0',
-    };
+        let node = &taxonomy.nodes[taxid as usize];
+        let rank = extract_string_from_offset(&taxonomy.rank_data, node.rank_offset as usize);
 
-    if rank_code != '\0' {
-        let name_str = extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+        let rank_code = match rank {
+            "superkingdom" => 'd',
+            "kingdom" => 'k',
+            "phylum" => 'p',
+            "class" => 'c',
+            "order" => 'o',
+            "family" => 'f',
+            "genus" => 'g',
+            "species" => 's',
+            _ => '\0',
+        };
 
-        let name = format!("{}__{}", rank_code, name_str);
-        taxonomy_names.push(name);
-        let taxonomy_line = taxonomy_names.join("|");
-        print_mpa_style_report_line(
-            file,
-            *clade_counts.get(&taxid).unwrap_or(&0),
-            &taxonomy_line,
-        )?;
-    }
+        if rank_code != '\0' {
+            let name_str =
+                extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+
+            let name = format!("{}__{}", rank_code, name_str);
+            taxonomy_names.push(name);
+            let taxonomy_line = taxonomy_names.join("|");
+            print_mpa_style_report_line(
+                file,
+                *clade_counts.get(&taxid).unwrap_or(&0),
+                &taxonomy_line,
+            )?;
+        }
 
-    let child_count = node.child_count as usize;
-    if child_count != 0 {
-        let mut children: Vec<u64> = (0..child_count)
-            .map(|i| node.first_child + i as u64)
+        let mut children: Vec<u64> = (0..node.child_count)
+            .map(|i| node.first_child + i)
             .collect();
 
         children.sort_by(|&a, &b| {
@@ -162,21 +170,11 @@ fn mpa_report_dfs(
                 .cmp(&clade_counts.get(&a).unwrap_or(&0))
         });
 
-        for child in children {
-            mpa_report_dfs(
-                child,
-                file,
-                report_zeros,
-                taxonomy,
-                clade_counts,
-                taxonomy_names,
-            )?;
-        }
-    }
-
-    if rank_code != '\0' {
-        taxonomy_names.pop();
+        // Pushed in reverse, the children are visited by decreasing count
+        let lineage_len = taxonomy_names.len();
+        stack.extend(children.into_iter().rev().map(|child| (child, lineage_len)));
     }
+    taxonomy_names.truncate(base_len);
 
     Ok(())
 }
@@ -243,7 +241,7 @@ pub fn print_kraken_style_report_line(
     clade_counter: &mut ReadCounter,
     taxon_counter: &ReadCounter,
     rank_str: &str,
-    taxid: u32,
+    taxid: u64,
     sci_name: &str,
     depth: usize,
 ) -> io::Result<()> {
@@ -267,20 +265,25 @@ pub fn print_kraken_style_report_line(
         )?;
     }
 
-    write!(file, "\t{}\t{}\t", rank_str, taxid)?;
-
-    for _ in 0..depth {
-        write!(file, "  ")?;
-    }
-
-    writeln!(file, "{}", sci_name)
+    writeln!(
+        file,
+        "\t{}\t{}\t{:indent$}{}",
+        rank_str,
+        taxid,
+        "",
+        sci_name,
+        indent = depth * 2
+    )
 }
 
 /// Performs a depth-first search to generate a Kraken-style report
 ///
+/// The taxa are visited with an explicit stack, chains of custom taxa can be deeper than
+/// the call stack allows.
+///
 /// # Arguments
 ///
-/// * `taxid` - The current taxon ID
+/// * `taxid` - The taxon ID the search starts from
 /// * `file` - The file to write the report to
 /// * `report_zeros` - Whether to report zero counts
 /// * `report_kmer_data` - Whether to report k-mer data
@@ -288,13 +291,15 @@ pub fn print_kraken_style_report_line(
 /// * `clade_counters` - A mutable reference to TaxonCounters for clade counts
 /// * `call_counters` - A reference to TaxonCounters for call counts
 /// * `total_seqs` - The total number of sequences
-/// * `rank_code` - The current rank code
-/// * `rank_depth` - The current rank depth
-/// * `depth` - The current depth in the taxonomy tree
+/// * `rank_code` - The rank code above `taxid`
+/// * `rank_depth` - The rank depth above `taxid`
+/// * `depth` - The depth of `taxid` in the taxonomy tree
+/// * `max_indent` - The deepest level indented, deeper taxa are indented like it
 ///
 /// # Returns
 ///
 /// An io::Result indicating success or failure of the operation
+#[allow(clippy::too_many_arguments)]
 pub fn kraken_report_dfs(
     taxid: u64,
     file: &mut File,
@@ -307,83 +312,70 @@ pub fn kraken_report_dfs(
     rank_code: char,
     rank_depth: i32,
     depth: usize,
+    max_indent: usize,
 ) -> io::Result<()> {
-    if !report_zeros && clade_counters.get(&taxid).map_or(0, |c| c.read_count()) == 0 {
-        return Ok(());
-    }
-
-    let node = &taxonomy.nodes[taxid as usize];
-    let rank = std::str::from_utf8(&taxonomy.rank_data[node.rank_offset as usize..])
-        .unwrap_or_default()
-        .split('\0')
-        .next()
-        .unwrap_or("");
-
-    let (new_rank_code, new_rank_depth) = match rank {
-        "superkingdom" => ('D', 0),
-        "kingdom" => ('K', 0),
-        "phylum" => ('P', 0),
-        "class" => ('C', 0),
-        "order" => ('O', 0),
-        "family" => ('F', 0),
-        "genus" => ('G', 0),
-        "species" => ('S', 0),
-        _ => (rank_code, rank_depth + 1),
-    };
-
-    let rank_str = if new_rank_depth == 0 {
-        new_rank_code.to_string()
-    } else {
-        format!("{}{}", new_rank_code, new_rank_depth)
-    };
+    let mut stack = vec![(taxid, rank_code, rank_depth, depth)];
+    while let Some((taxid, rank_code, rank_depth, depth)) = stack.pop() {
+        if !report_zeros && clade_counters.get(&taxid).map_or(0, |c| c.read_count()) == 0 {
+            continue;
+        }
 
-    let name = std::str::from_utf8(&taxonomy.name_data[node.name_offset as usize..])
-        .unwrap_or_default()
-        .split('\0')
-        .next()
-        .unwrap_or("");
+        let node = &taxonomy.nodes[taxid as usize];
+        let rank = std::str::from_utf8(&taxonomy.rank_data[node.rank_offset as usize..])
+            .unwrap_or_default()
+            .split('\0')
+            .next()
+            .unwrap_or("");
 
-    let mut clade_counter = clade_counters
-        .entry(taxid)
-        .or_insert_with(ReadCounter::default);
+        let (new_rank_code, new_rank_depth) = match crate::taxonomy::rank_code(rank) {
+            Some(code) => (code, 0),
+            None => (rank_code, rank_depth + 1),
+        };
 
-    print_kraken_style_report_line(
-        file,
-        report_kmer_data,
-        total_seqs,
-        &mut clade_counter,
-        call_counters.get(&taxid).unwrap_or(&ReadCounter::default()),
-        &rank_str,
-        node.external_id as u32,
-        name,
-        depth,
-    )?;
+        let rank_str = if new_rank_depth == 0 {
+            new_rank_code.to_string()
+        } else {
+            format!("{}{}", new_rank_code, new_rank_depth)
+        };
 
-    let mut children: Vec<u64> = (0..node.child_count)
-        .map(|i| node.first_child + i)
-        .collect();
+        let name = std::str::from_utf8(&taxonomy.name_data[node.name_offset as usize..])
+            .unwrap_or_default()
+            .split('\0')
+            .next()
+            .unwrap_or("");
 
-    children.sort_by_key(|&child_taxid| {
-        clade_counters
-            .get(&child_taxid)
-            .map_or(0, |c| c.read_count())
-    });
-    children.reverse();
+        let clade_counter = clade_counters
+            .entry(taxid)
+            .or_insert_with(ReadCounter::default);
 
-    for child_taxid in children {
-        kraken_report_dfs(
-            child_taxid,
+        print_kraken_style_report_line(
             file,
-            report_zeros,
             report_kmer_data,
-            taxonomy,
-            clade_counters,
-            call_counters,
             total_seqs,
-            new_rank_code,
-            new_rank_depth,
-            depth + 1,
+            clade_counter,
+            call_counters.get(&taxid).unwrap_or(&ReadCounter::default()),
+            &rank_str,
+            node.external_id,
+            name,
+            depth.min(max_indent),
         )?;
+
+        let mut children: Vec<u64> = (0..node.child_count)
+            .map(|i| node.first_child + i)
+            .collect();
+
+        // Sorted by increasing count and popped from the end, the children are visited
+        // by decreasing count
+        children.sort_by_key(|&child_taxid| {
+            clade_counters
+                .get(&child_taxid)
+                .map_or(0, |c| c.read_count())
+        });
+        stack.extend(
+            children
+                .into_iter()
+                .map(|child| (child, new_rank_code, new_rank_depth, depth + 1)),
+        );
     }
 
     Ok(())
@@ -399,11 +391,49 @@ pub fn kraken_report_dfs(
 /// * `taxonomy` - The taxonomy structure
 /// * `call_counters` - A HashMap of taxon IDs to their ReadCounters
 /// * `total_seqs` - The total number of sequences
-/// * `total_unclassified` - The total number of unclassified sequences
+/// * `total_unclassified` - The total number of unclassified sequences, reported as the
+///   top-level `U` row. `None` leaves the row out.
+/// * `max_indent` - The deepest level indented, deeper taxa are indented like it
+/// * `header` - Lines written first, each after a `# ` (the read group and the provenance
+///   of the run). Empty writes no header, as in the Kraken 2 report.
 ///
 /// # Returns
 ///
 /// An io::Result indicating success or failure of the operation
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::report_kraken_style;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 1, ..Default::default() });
+///
+/// let (processed, classified) = (10, 7);
+/// let mut call_counters = TaxonCounters::new();
+/// call_counters.insert(1, ReadCounter::new(classified, 0));
+///
+/// let filename = std::env::temp_dir().join("kun_peng_report_unclassified.kreport2");
+/// report_kraken_style(&filename, false, false, &taxonomy, &call_counters, processed, Some(processed - classified), 100, &[]).unwrap();
+/// let report = std::fs::read_to_string(&filename).unwrap();
+/// let u_row: Vec<&str> = report.lines().next().unwrap().split('\t').collect();
+/// assert_eq!(u_row[1], (processed - classified).to_string());
+/// assert_eq!(&u_row[3..], ["U", "0", "unclassified"]);
+///
+/// report_kraken_style(&filename, false, false, &taxonomy, &call_counters, processed, None, 100, &[]).unwrap();
+/// let report = std::fs::read_to_string(&filename).unwrap();
+/// assert!(!report.contains("unclassified"));
+///
+/// let header = ["read_group: run7".to_string()];
+/// report_kraken_style(&filename, false, false, &taxonomy, &call_counters, processed, None, 100, &header).unwrap();
+/// let with_group = std::fs::read_to_string(&filename).unwrap();
+/// assert_eq!(with_group.lines().next(), Some("# read_group: run7"));
+/// assert_eq!(with_group.lines().skip(1).collect::<Vec<_>>(), report.lines().collect::<Vec<_>>());
+/// ```
+#[allow(clippy::too_many_arguments)]
 pub fn report_kraken_style<P: AsRef<Path>>(
     filename: P,
     report_zeros: bool,
@@ -411,14 +441,19 @@ pub fn report_kraken_style<P: AsRef<Path>>(
     taxonomy: &Taxonomy,
     call_counters: &HashMap<u64, ReadCounter>,
     total_seqs: u64,
-    total_unclassified: u64,
+    total_unclassified: Option<u64>,
+    max_indent: usize,
+    header: &[String],
 ) -> io::Result<()> {
     let mut clade_counters = get_clade_counters(taxonomy, call_counters);
 
     let mut file = File::create(filename)?;
+    for line in header {
+        writeln!(file, "# {}", line)?;
+    }
 
     // Handle the special case for unclassified sequences
-    if total_unclassified != 0 || report_zeros {
+    if let Some(total_unclassified) = total_unclassified {
         let mut rc = ReadCounter::new(total_unclassified, 0);
         let trc = ReadCounter::new(total_unclassified, 0);
         print_kraken_style_report_line(
@@ -447,5 +482,894 @@ pub fn report_kraken_style<P: AsRef<Path>>(
         'R',
         -1,
         0,
+        max_indent,
     )
 }
+
+/// Estimates the fraction of the index minimizers of each taxon observed in a run
+///
+/// `index_counts` holds the number of minimizers stored in the index for each taxon, see
+/// [`crate::compact_hash::CHTable::taxon_minimizer_counts`]. The distinct observed
+/// minimizers come from the counters of the run. Returns `(taxid, observed, in index,
+/// fraction)` for every observed taxon of the index, by decreasing fraction. A real
+/// presence covers much of the genome, cross-mapping reads only a few minimizers.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::genome_coverage;
+/// use std::collections::HashMap;
+///
+/// let index_counts = HashMap::from([(1, 200), (2, 200), (3, 50)]);
+/// let mut counters = TaxonCounters::new();
+/// // Taxon 1 has most of its minimizers observed, several times, taxon 2 only a few.
+/// for _ in 0..3 {
+///     for kmer in 0..180u64 {
+///         counters.entry(1).or_insert_with(ReadCounter::default).add_kmer(kmer << 20);
+///     }
+/// }
+/// for kmer in 0..6u64 {
+///     counters.entry(2).or_insert_with(ReadCounter::default).add_kmer(kmer << 20);
+/// }
+///
+/// let coverage = genome_coverage(&counters, &index_counts);
+/// assert_eq!(coverage.len(), 2);
+/// let (taxid, observed, total, fraction) = coverage[0];
+/// assert_eq!((taxid, total), (1, 200));
+/// assert!((175..=185).contains(&observed));
+/// assert!(fraction > 0.85);
+/// let (taxid, _, _, fraction) = coverage[1];
+/// assert_eq!(taxid, 2);
+/// assert!(fraction < 0.05);
+/// ```
+pub fn genome_coverage(
+    call_counters: &TaxonCounters,
+    index_counts: &HashMap<u32, u64>,
+) -> Vec<(u64, u64, u64, f64)> {
+    let mut coverage: Vec<(u64, u64, u64, f64)> = call_counters
+        .iter()
+        .filter_map(|(&taxid, counter)| {
+            let total = *index_counts
+                .get(&(taxid as u32))
+                .filter(|&&total| total > 0)?;
+            let mut observed = ReadCounter::default();
+            let _ = observed.merge(counter);
+            let observed = observed.distinct_kmer_count() as u64;
+            if observed == 0 {
+                return None;
+            }
+            let fraction = (observed as f64 / total as f64).min(1.0);
+            Some((taxid, observed, total, fraction))
+        })
+        .collect();
+    coverage.sort_by(|a, b| b.3.total_cmp(&a.3).then(a.0.cmp(&b.0)));
+    coverage
+}
+
+/// Writes the [`genome_coverage`] of the observed taxa as a tab separated table
+///
+/// The columns are the taxid, the name, the distinct observed minimizers, the minimizers
+/// of the taxon in the index and the covered fraction.
+pub fn report_genome_coverage<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    index_counts: &HashMap<u32, u64>,
+) -> io::Result<()> {
+    let mut file = File::create(filename)?;
+    writeln!(
+        file,
+        "taxid\tname\tobserved_minimizers\tindex_minimizers\tcoverage"
+    )?;
+    for (taxid, observed, total, fraction) in genome_coverage(call_counters, index_counts) {
+        let node = &taxonomy.nodes[taxid as usize];
+        let name = extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{:.4}",
+            node.external_id, name, observed, total, fraction
+        )?;
+    }
+    Ok(())
+}
+
+/// A named group of taxa of the custom reporting groups
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportGroup {
+    pub name: String,
+    /// Internal taxids, the whole subtree of each taxon belongs to the group
+    pub taxids: Vec<u32>,
+}
+
+/// Reads the reporting groups, one `group_name<TAB>taxid,taxid,...` line per group
+///
+/// The taxids are external ones, they must be in the taxonomy. Empty lines and lines
+/// starting with `#` are skipped.
+pub fn read_report_groups<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+) -> io::Result<Vec<ReportGroup>> {
+    let invalid = |line_no: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("groups line {}: {}", line_no + 1, msg),
+        )
+    };
+    let mut groups = Vec::new();
+    for (line_no, line) in std::fs::read_to_string(filename)?.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, taxids) = line
+            .split_once('\t')
+            .ok_or_else(|| invalid(line_no, "expected group_name<TAB>taxid,...".to_string()))?;
+        let taxids = taxids
+            .split(',')
+            .map(|taxid| {
+                let external_id: u64 = taxid
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(line_no, format!("invalid taxid '{}'", taxid)))?;
+                match taxonomy.get_internal_id(external_id) {
+                    0 => Err(invalid(
+                        line_no,
+                        format!("taxid {} is not in the taxonomy", external_id),
+                    )),
+                    internal_id => Ok(internal_id),
+                }
+            })
+            .collect::<io::Result<Vec<u32>>>()?;
+        groups.push(ReportGroup {
+            name: name.trim().to_string(),
+            taxids,
+        });
+    }
+    Ok(groups)
+}
+
+/// Sums the reads called in the subtrees of the taxa of every group
+///
+/// A read is counted once per group, also when the group holds a taxon and one of its
+/// ancestors. Returns the read count of every group, in order.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::{group_read_counts, read_report_groups};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// // root(1) -> 10 -> 11, root(1) -> 20
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 1, first_child: 2, child_count: 2, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 10, parent_id: 1, first_child: 4, child_count: 1, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 20, parent_id: 1, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 11, parent_id: 2, ..Default::default() });
+/// taxonomy.generate_external_to_internal_id_map();
+/// taxonomy.build_path_cache();
+///
+/// let filename = std::env::temp_dir().join("kun_peng_groups.tsv");
+/// std::fs::write(&filename, "# name\ttaxids\ncommensals\t11,20\nclade_10\t10,11\n").unwrap();
+/// let groups = read_report_groups(&filename, &taxonomy).unwrap();
+/// assert_eq!(groups[0].name, "commensals");
+///
+/// let mut call_counters = TaxonCounters::new();
+/// call_counters.insert(2, ReadCounter::new(2, 0));
+/// call_counters.insert(3, ReadCounter::new(3, 0));
+/// call_counters.insert(4, ReadCounter::new(5, 0));
+/// assert_eq!(group_read_counts(&taxonomy, &call_counters, &groups), [8, 7]);
+///
+/// std::fs::write(&filename, "unknown\t11,99\n").unwrap();
+/// assert!(read_report_groups(&filename, &taxonomy).is_err());
+/// ```
+pub fn group_read_counts(
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    groups: &[ReportGroup],
+) -> Vec<u64> {
+    groups
+        .iter()
+        .map(|group| {
+            call_counters
+                .iter()
+                .filter(|(&taxid, _)| {
+                    group
+                        .taxids
+                        .iter()
+                        .any(|&member| taxonomy.is_a_ancestor_of_b(member, taxid as u32))
+                })
+                .map(|(_, counter)| counter.read_count())
+                .sum()
+        })
+        .collect()
+}
+
+/// Writes the [`group_read_counts`] as a tab separated table
+///
+/// The columns are the group name, the percentage of all reads, the reads of the group
+/// and the external taxids of the group.
+pub fn report_groups<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    groups: &[ReportGroup],
+    total_seqs: u64,
+) -> io::Result<()> {
+    let mut file = File::create(filename)?;
+    writeln!(file, "group\tpercentage\treads\ttaxids")?;
+    let counts = group_read_counts(taxonomy, call_counters, groups);
+    for (group, reads) in groups.iter().zip(counts) {
+        let pct = 100.0 * reads as f64 / total_seqs.max(1) as f64;
+        let taxids: Vec<String> = group
+            .taxids
+            .iter()
+            .map(|&taxid| taxonomy.nodes[taxid as usize].external_id.to_string())
+            .collect();
+        writeln!(
+            file,
+            "{}\t{:.2}\t{}\t{}",
+            group.name,
+            pct,
+            reads,
+            taxids.join(",")
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads the genome sizes, one `taxid<TAB>bp` line per taxon
+///
+/// The taxids are external ones, they must be in the taxonomy. Returns the size of every
+/// internal taxid. Empty lines and lines starting with `#` are skipped.
+pub fn read_genome_sizes<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+) -> io::Result<HashMap<u32, u64>> {
+    let invalid = |line_no: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("genome sizes line {}: {}", line_no + 1, msg),
+        )
+    };
+    let mut sizes = HashMap::new();
+    for (line_no, line) in std::fs::read_to_string(filename)?.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (taxid, size) = line
+            .split_once('\t')
+            .ok_or_else(|| invalid(line_no, "expected taxid<TAB>bp".to_string()))?;
+        let external_id: u64 = taxid
+            .trim()
+            .parse()
+            .map_err(|_| invalid(line_no, format!("invalid taxid '{}'", taxid)))?;
+        let size: u64 = size
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&size| size > 0)
+            .ok_or_else(|| invalid(line_no, format!("invalid genome size '{}'", size)))?;
+        match taxonomy.get_internal_id(external_id) {
+            0 => {
+                return Err(invalid(
+                    line_no,
+                    format!("taxid {} is not in the taxonomy", external_id),
+                ))
+            }
+            internal_id => sizes.insert(internal_id, size),
+        };
+    }
+    Ok(sizes)
+}
+
+/// Normalizes the read counts of the taxa with a genome size by that size
+///
+/// A read called at a taxon counts for the closest taxon of its lineage with a size, a
+/// taxon itself or an ancestor, and is left out when there is none. The abundance of a
+/// taxon is its reads divided by its genome size, renormalized to sum to 1 over the
+/// sized taxa. Returns `(taxid, reads, genome size, abundance)` for every sized taxon
+/// with reads, by decreasing abundance.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::{normalized_abundance, read_genome_sizes};
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// // root(1) -> 10 -> 11, root(1) -> 20
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 1, first_child: 2, child_count: 2, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 10, parent_id: 1, first_child: 4, child_count: 1, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 20, parent_id: 1, ..Default::default() });
+/// taxonomy.nodes.push(TaxonomyNode { external_id: 11, parent_id: 2, ..Default::default() });
+/// taxonomy.generate_external_to_internal_id_map();
+///
+/// // Taxon 10 has a genome 4 times as large as taxon 20
+/// let filename = std::env::temp_dir().join("kun_peng_genome_sizes.tsv");
+/// std::fs::write(&filename, "# taxid\tbp\n10\t4000000\n20\t1000000\n").unwrap();
+/// let sizes = read_genome_sizes(&filename, &taxonomy).unwrap();
+///
+/// // 60 reads on taxon 10 (20 of them on its child 11), 30 on taxon 20, 10 on the root
+/// let mut call_counters = TaxonCounters::new();
+/// call_counters.insert(1, ReadCounter::new(10, 0));
+/// call_counters.insert(2, ReadCounter::new(40, 0));
+/// call_counters.insert(3, ReadCounter::new(30, 0));
+/// call_counters.insert(4, ReadCounter::new(20, 0));
+///
+/// let abundance = normalized_abundance(&taxonomy, &call_counters, &sizes);
+/// let taxa: Vec<(u64, u64, u64)> = abundance.iter().map(|&(t, r, s, _)| (t, r, s)).collect();
+/// assert_eq!(taxa, [(3, 30, 1000000), (2, 60, 4000000)]);
+/// // Twice the reads, but a third of the cells of taxon 20
+/// assert!((abundance[0].3 - 2.0 / 3.0).abs() < 1e-9);
+/// assert!((abundance[1].3 - 1.0 / 3.0).abs() < 1e-9);
+///
+/// std::fs::write(&filename, "10\t0\n").unwrap();
+/// assert!(read_genome_sizes(&filename, &taxonomy).is_err());
+/// std::fs::write(&filename, "99\t1000\n").unwrap();
+/// assert!(read_genome_sizes(&filename, &taxonomy).is_err());
+/// ```
+pub fn normalized_abundance(
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    genome_sizes: &HashMap<u32, u64>,
+) -> Vec<(u64, u64, u64, f64)> {
+    let mut reads: HashMap<u64, u64> = HashMap::new();
+    for (&taxid, counter) in call_counters {
+        let mut sized = taxid;
+        while sized != 0 && !genome_sizes.contains_key(&(sized as u32)) {
+            sized = taxonomy.nodes[sized as usize].parent_id;
+        }
+        if sized != 0 && counter.read_count() > 0 {
+            *reads.entry(sized).or_default() += counter.read_count();
+        }
+    }
+    let total: f64 = reads
+        .iter()
+        .map(|(&taxid, &count)| count as f64 / genome_sizes[&(taxid as u32)] as f64)
+        .sum();
+    let mut abundance: Vec<(u64, u64, u64, f64)> = reads
+        .into_iter()
+        .map(|(taxid, count)| {
+            let size = genome_sizes[&(taxid as u32)];
+            (taxid, count, size, count as f64 / size as f64 / total)
+        })
+        .collect();
+    abundance.sort_by(|a, b| b.3.total_cmp(&a.3).then(a.0.cmp(&b.0)));
+    abundance
+}
+
+/// Writes the [`normalized_abundance`] of the sized taxa as a tab separated table
+///
+/// The columns are the taxid, the name, the reads, the genome size, the fraction of the
+/// reads of the sized taxa and the normalized abundance.
+pub fn report_abundance<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    genome_sizes: &HashMap<u32, u64>,
+) -> io::Result<()> {
+    let mut file = File::create(filename)?;
+    writeln!(
+        file,
+        "taxid\tname\treads\tgenome_size\tread_fraction\tabundance"
+    )?;
+    let abundance = normalized_abundance(taxonomy, call_counters, genome_sizes);
+    let total_reads: u64 = abundance.iter().map(|&(_, reads, _, _)| reads).sum();
+    for (taxid, reads, size, fraction) in abundance {
+        let node = &taxonomy.nodes[taxid as usize];
+        let name = extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{}\t{:.6}\t{:.6}",
+            node.external_id,
+            name,
+            reads,
+            size,
+            reads as f64 / total_reads as f64,
+            fraction
+        )?;
+    }
+    Ok(())
+}
+
+/// The taxa with reads in their clade, with their counts and lineages
+///
+/// Returns `(taxid, clade reads, taxon reads, lineage)` by decreasing clade reads. The
+/// lineage holds the names from the top of the taxonomy down to the taxon, separated by
+/// `;`, without the root.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::sparse_report;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// // root(1) -> Bacteria(2) -> {Escherichia(561) -> E. coli(562), Salmonella(590)}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.name_data = b"root\0Bacteria\0Escherichia\0Escherichia coli\0Salmonella\0".to_vec();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id, name_offset) in [(1, 0, 0), (2, 1, 5), (561, 2, 14), (562, 3, 26), (590, 2, 43)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, name_offset, ..Default::default() });
+/// }
+///
+/// // 5 reads on E. coli, 2 on its genus, 1 on Bacteria, none on Salmonella
+/// let mut call_counters = TaxonCounters::new();
+/// call_counters.insert(4, ReadCounter::new(5, 0));
+/// call_counters.insert(3, ReadCounter::new(2, 0));
+/// call_counters.insert(2, ReadCounter::new(1, 0));
+/// call_counters.insert(5, ReadCounter::new(0, 0));
+///
+/// let rows = sparse_report(&taxonomy, &call_counters);
+/// let rows: Vec<(u64, u64, u64, &str)> =
+///     rows.iter().map(|(t, c, r, l)| (*t, *c, *r, l.as_str())).collect();
+/// assert_eq!(
+///     rows,
+///     [
+///         (1, 8, 0, "root"),
+///         (2, 8, 1, "Bacteria"),
+///         (3, 7, 2, "Bacteria;Escherichia"),
+///         (4, 5, 5, "Bacteria;Escherichia;Escherichia coli"),
+///     ]
+/// );
+/// ```
+pub fn sparse_report(
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+) -> Vec<(u64, u64, u64, String)> {
+    let call_counts: HashMap<u64, u64> = call_counters
+        .iter()
+        .map(|(&taxid, counter)| (taxid, counter.read_count()))
+        .collect();
+    let name = |taxid: u64| {
+        let node = &taxonomy.nodes[taxid as usize];
+        extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize)
+    };
+    let lineage = |taxid: u64| {
+        let mut names = Vec::new();
+        let mut current = taxid;
+        while current > 1 {
+            names.push(name(current));
+            current = taxonomy.nodes[current as usize].parent_id;
+        }
+        if names.is_empty() {
+            return name(taxid).to_string();
+        }
+        names.reverse();
+        names.join(";")
+    };
+    let mut rows: Vec<(u64, u64, u64, String)> = get_clade_counts(taxonomy, &call_counts)
+        .into_iter()
+        .filter(|&(_, clade)| clade > 0)
+        .map(|(taxid, clade)| {
+            let reads = call_counts.get(&taxid).copied().unwrap_or(0);
+            (taxid, clade, reads, lineage(taxid))
+        })
+        .collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    rows
+}
+
+/// Writes the [`sparse_report`] as a flat tab separated table
+///
+/// The columns are the taxid, the rank, the name, the percentage of all reads in the
+/// clade, the reads of the clade, the reads called at the taxon and the lineage.
+pub fn report_sparse<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    total_seqs: u64,
+) -> io::Result<()> {
+    let mut file = File::create(filename)?;
+    writeln!(
+        file,
+        "taxid\trank\tname\tpercentage\tclade_reads\ttaxon_reads\tlineage"
+    )?;
+    for (taxid, clade, reads, lineage) in sparse_report(taxonomy, call_counters) {
+        let node = &taxonomy.nodes[taxid as usize];
+        let name = extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+        writeln!(
+            file,
+            "{}\t{}\t{}\t{:.2}\t{}\t{}\t{}",
+            node.external_id,
+            taxonomy.rank_of(taxid as u32),
+            name,
+            100.0 * clade as f64 / total_seqs.max(1) as f64,
+            clade,
+            reads,
+            lineage
+        )?;
+    }
+    Ok(())
+}
+
+/// The reads of a report collapsed at one rank
+///
+/// Returns `(taxid, reads)` for every taxon of the `rank` code with reads in its clade, by
+/// decreasing reads, then the reads called above the rank or in lineages without it, as
+/// a last row of taxid 0 when there are any. The rows sum to the reads of `call_counters`.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::rank_report;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// // root(1) -> Bacteria(2) -> {Escherichia(561) -> E. coli(562), Salmonella(590)}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.name_data = b"root\0Bacteria\0Escherichia\0Escherichia coli\0Salmonella\0".to_vec();
+/// taxonomy.rank_data = b"no rank\0superkingdom\0genus\0species\0".to_vec();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// let nodes = [(1, 0, 0, 0), (2, 1, 5, 8), (561, 2, 14, 21), (562, 3, 26, 27), (590, 2, 43, 21)];
+/// for (external_id, parent_id, name_offset, rank_offset) in nodes {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, name_offset, rank_offset, ..Default::default() });
+/// }
+///
+/// let mut call_counters = TaxonCounters::new();
+/// for (taxid, reads) in [(4, 5), (3, 2), (2, 1), (5, 3)] {
+///     call_counters.insert(taxid, ReadCounter::new(reads, 0));
+/// }
+/// assert_eq!(rank_report(&taxonomy, &call_counters, 'G'), [(3, 7), (5, 3), (0, 1)]);
+/// assert_eq!(rank_report(&taxonomy, &call_counters, 'D'), [(2, 11)]);
+/// assert_eq!(rank_report(&taxonomy, &call_counters, 'S'), [(4, 5), (0, 6)]);
+/// ```
+pub fn rank_report(
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    rank: char,
+) -> Vec<(u64, u64)> {
+    let mut counts: HashMap<u64, u64> = HashMap::new();
+    let mut unassigned = 0;
+    for (&taxid, counter) in call_counters {
+        let reads = counter.read_count();
+        if reads == 0 {
+            continue;
+        }
+        let mut current = taxid;
+        while current != 0 && rank_code(taxonomy.rank_of(current as u32)) != Some(rank) {
+            current = taxonomy.nodes[current as usize].parent_id;
+        }
+        match current {
+            0 => unassigned += reads,
+            _ => *counts.entry(current).or_default() += reads,
+        }
+    }
+    let mut rows: Vec<(u64, u64)> = counts.into_iter().collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    if unassigned > 0 {
+        rows.push((0, unassigned));
+    }
+    rows
+}
+
+/// Writes the [`rank_report`] as a tab separated table
+///
+/// The columns are the taxid, the name, the percentage of all reads and the reads of the
+/// clade. The reads above the rank are on an `unassigned at <rank>` row of taxid 0.
+pub fn report_rank<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+    rank: char,
+    total_seqs: u64,
+) -> io::Result<()> {
+    let mut file = File::create(filename)?;
+    writeln!(file, "taxid\tname\tpercentage\treads")?;
+    for (taxid, reads) in rank_report(taxonomy, call_counters, rank) {
+        let (external_id, name) = match taxid {
+            0 => (0, format!("unassigned at {}", rank_name(rank))),
+            _ => {
+                let node = &taxonomy.nodes[taxid as usize];
+                let name =
+                    extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+                (node.external_id, name.to_string())
+            }
+        };
+        writeln!(
+            file,
+            "{}\t{}\t{:.2}\t{}",
+            external_id,
+            name,
+            100.0 * reads as f64 / total_seqs.max(1) as f64,
+            reads
+        )?;
+    }
+    Ok(())
+}
+
+/// A taxon of the [`report_tree`], with the taxa below it that have reads
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TreeNode {
+    pub taxid: u64,
+    pub name: String,
+    pub rank: String,
+    /// Reads called at the taxon
+    pub count: u64,
+    /// Reads called at the taxon and below it
+    pub clade_count: u64,
+    /// By decreasing clade count
+    pub children: Vec<TreeNode>,
+}
+
+/// The taxa with reads in their clade, as a tree from the root, `None` without reads
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::report_tree;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// // root(1) -> Bacteria(2) -> {Escherichia(561) -> E. coli(562), Salmonella(590)}
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.name_data = b"root\0Bacteria\0Escherichia\0Escherichia coli\0Salmonella\0".to_vec();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id, name_offset) in [(1, 0, 0), (2, 1, 5), (561, 2, 14), (562, 3, 26), (590, 2, 43)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, name_offset, ..Default::default() });
+/// }
+///
+/// let mut call_counters = TaxonCounters::new();
+/// for (taxid, reads) in [(4, 5), (3, 2), (2, 1), (5, 0)] {
+///     call_counters.insert(taxid, ReadCounter::new(reads, 0));
+/// }
+/// let root = report_tree(&taxonomy, &call_counters).unwrap();
+/// let bacteria = &root.children[0];
+/// assert_eq!((bacteria.taxid, bacteria.count, bacteria.clade_count), (2, 1, 8));
+/// // Salmonella has no reads
+/// assert_eq!(bacteria.children.len(), 1);
+/// assert_eq!(bacteria.children[0].children[0].name, "Escherichia coli");
+/// assert!(report_tree(&taxonomy, &TaxonCounters::new()).is_none());
+/// ```
+pub fn report_tree(taxonomy: &Taxonomy, call_counters: &TaxonCounters) -> Option<TreeNode> {
+    let call_counts: HashMap<u64, u64> = call_counters
+        .iter()
+        .map(|(&taxid, counter)| (taxid, counter.read_count()))
+        .collect();
+    let clade_counts = get_clade_counts(taxonomy, &call_counts);
+    let mut children: HashMap<u64, Vec<u64>> = HashMap::new();
+    for (&taxid, &clade) in &clade_counts {
+        if clade > 0 {
+            let parent = taxonomy.nodes[taxid as usize].parent_id;
+            children.entry(parent).or_default().push(taxid);
+        }
+    }
+
+    fn build(
+        taxid: u64,
+        taxonomy: &Taxonomy,
+        call_counts: &HashMap<u64, u64>,
+        clade_counts: &HashMap<u64, u64>,
+        children: &HashMap<u64, Vec<u64>>,
+    ) -> TreeNode {
+        let node = &taxonomy.nodes[taxid as usize];
+        let mut below: Vec<u64> = children.get(&taxid).cloned().unwrap_or_default();
+        below.sort_by(|a, b| clade_counts[b].cmp(&clade_counts[a]).then(a.cmp(b)));
+        TreeNode {
+            taxid: node.external_id,
+            name: extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize)
+                .to_string(),
+            rank: taxonomy.rank_of(taxid as u32).to_string(),
+            count: call_counts.get(&taxid).copied().unwrap_or(0),
+            clade_count: clade_counts[&taxid],
+            children: below
+                .into_iter()
+                .map(|child| build(child, taxonomy, call_counts, clade_counts, children))
+                .collect(),
+        }
+    }
+
+    // The taxa hanging from the placeholder node 0, the root of the taxonomy
+    let top = children.get(&0)?.first().copied()?;
+    Some(build(top, taxonomy, &call_counts, &clade_counts, &children))
+}
+
+/// Writes the [`report_tree`] as JSON, `null` without reads
+pub fn report_tree_json<P: AsRef<Path>>(
+    filename: P,
+    taxonomy: &Taxonomy,
+    call_counters: &TaxonCounters,
+) -> io::Result<()> {
+    let file = File::create(filename)?;
+    serde_json::to_writer_pretty(file, &report_tree(taxonomy, call_counters))?;
+    Ok(())
+}
+
+/// Writes a line whenever the reads called at a taxon first reach one of a set of
+/// thresholds, for surveillance while a run is going
+///
+/// The counts are the running totals of the batches passed to [`AbundanceEvents::add`],
+/// in read order. Every line holds the reads processed so far, the taxid, the name, the
+/// threshold and the reads of the taxon. The crossings of a batch are written by increasing
+/// threshold, then taxid, and a taxon crossing several thresholds at once gets a line each.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+/// use kun_peng::report::AbundanceEvents;
+/// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+///
+/// let mut taxonomy = Taxonomy::default();
+/// taxonomy.name_data = b"root\0Escherichia coli\0Salmonella\0".to_vec();
+/// taxonomy.nodes.push(TaxonomyNode::default());
+/// for (external_id, parent_id, name_offset) in [(1, 0, 0), (562, 1, 5), (590, 1, 22)] {
+///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, name_offset, ..Default::default() });
+/// }
+/// let batch = |counts: &[(u64, u64)]| -> TaxonCounters {
+///     counts.iter().map(|&(taxid, reads)| (taxid, ReadCounter::new(reads, 0))).collect()
+/// };
+///
+/// let path = std::env::temp_dir().join("kun_peng_abundance_events.tsv");
+/// let mut events = AbundanceEvents::create(&path, &[10, 2, 5]).unwrap();
+/// events.add(&taxonomy, 4, &batch(&[(2, 1), (3, 2)])).unwrap();
+/// events.add(&taxonomy, 20, &batch(&[(2, 11), (3, 1)])).unwrap();
+/// events.add(&taxonomy, 10, &batch(&[(2, 5), (3, 3)])).unwrap();
+/// drop(events);
+///
+/// let content = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(
+///     content.lines().collect::<Vec<_>>(),
+///     [
+///         "#processed\ttaxid\tname\tthreshold\treads",
+///         "4\t590\tSalmonella\t2\t2",
+///         "24\t562\tEscherichia coli\t2\t12",
+///         "24\t562\tEscherichia coli\t5\t12",
+///         "24\t562\tEscherichia coli\t10\t12",
+///         "34\t590\tSalmonella\t5\t6",
+///     ]
+/// );
+/// ```
+pub struct AbundanceEvents {
+    writer: Box<dyn Write + Send>,
+    thresholds: Vec<u64>,
+    counts: HashMap<u64, u64>,
+    processed: u64,
+}
+
+impl AbundanceEvents {
+    /// Writes the events to `writer`, zero thresholds are ignored
+    pub fn new(writer: Box<dyn Write + Send>, thresholds: &[u64]) -> io::Result<Self> {
+        Self::start(writer, thresholds, true)
+    }
+
+    fn start(
+        mut writer: Box<dyn Write + Send>,
+        thresholds: &[u64],
+        header: bool,
+    ) -> io::Result<Self> {
+        let mut thresholds: Vec<u64> = thresholds.iter().copied().filter(|&t| t > 0).collect();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        if header {
+            writeln!(writer, "#processed\ttaxid\tname\tthreshold\treads")?;
+            writer.flush()?;
+        }
+        Ok(Self {
+            writer,
+            thresholds,
+            counts: HashMap::new(),
+            processed: 0,
+        })
+    }
+
+    /// Writes the events to a file, or to stderr if the path is `-`
+    pub fn create<P: AsRef<Path>>(path: P, thresholds: &[u64]) -> io::Result<Self> {
+        let path = path.as_ref();
+        let writer: Box<dyn Write + Send> = if path == Path::new("-") {
+            Box::new(io::stderr())
+        } else {
+            Box::new(io::BufWriter::new(File::create(path)?))
+        };
+        Self::new(writer, thresholds)
+    }
+
+    /// Continues the events of the reads counted so far, appending to the file
+    ///
+    /// `processed` reads were classified before with the calls of `taxon_counters`, the
+    /// thresholds they reached are not written again. A `--watch` run continues its events
+    /// over its batches.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+    /// use kun_peng::report::AbundanceEvents;
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    ///
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.name_data = b"root\0Escherichia coli\0".to_vec();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// for (external_id, parent_id, name_offset) in [(1, 0, 0), (562, 1, 5)] {
+    ///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, name_offset, ..Default::default() });
+    /// }
+    /// let batch = |reads: u64| -> TaxonCounters {
+    ///     [(2, ReadCounter::new(reads, 0))].into_iter().collect()
+    /// };
+    ///
+    /// let path = std::env::temp_dir().join("kun_peng_abundance_events_resume.tsv");
+    /// let mut events = AbundanceEvents::create(&path, &[2, 5]).unwrap();
+    /// events.add(&taxonomy, 10, &batch(3)).unwrap();
+    /// drop(events);
+    ///
+    /// let mut events = AbundanceEvents::resume(&path, &[2, 5], 10, &batch(3)).unwrap();
+    /// events.add(&taxonomy, 10, &batch(3)).unwrap();
+    /// drop(events);
+    ///
+    /// let content = std::fs::read_to_string(&path).unwrap();
+    /// assert_eq!(
+    ///     content.lines().collect::<Vec<_>>(),
+    ///     [
+    ///         "#processed\ttaxid\tname\tthreshold\treads",
+    ///         "10\t562\tEscherichia coli\t2\t3",
+    ///         "20\t562\tEscherichia coli\t5\t6",
+    ///     ]
+    /// );
+    /// ```
+    pub fn resume<P: AsRef<Path>>(
+        path: P,
+        thresholds: &[u64],
+        processed: u64,
+        taxon_counters: &TaxonCounters,
+    ) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (writer, header): (Box<dyn Write + Send>, bool) = if path == Path::new("-") {
+            (Box::new(io::stderr()), false)
+        } else {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let header = file.metadata()?.len() == 0;
+            (Box::new(io::BufWriter::new(file)), header)
+        };
+        let mut events = Self::start(writer, thresholds, header)?;
+        events.processed = processed;
+        events.counts = taxon_counters
+            .iter()
+            .map(|(&taxid, counter)| (taxid, counter.read_count()))
+            .filter(|&(_, reads)| reads > 0)
+            .collect();
+        Ok(events)
+    }
+
+    /// Adds the reads of a batch and the counters of the taxa called in it
+    pub fn add(
+        &mut self,
+        taxonomy: &Taxonomy,
+        processed: u64,
+        taxon_counters: &TaxonCounters,
+    ) -> io::Result<()> {
+        self.processed += processed;
+        let mut crossings = Vec::new();
+        for (&taxid, counter) in taxon_counters {
+            let reads = counter.read_count();
+            if reads == 0 {
+                continue;
+            }
+            let count = self.counts.entry(taxid).or_insert(0);
+            let before = *count;
+            *count += reads;
+            for &threshold in &self.thresholds {
+                if before < threshold && threshold <= *count {
+                    crossings.push((threshold, taxid, *count));
+                }
+            }
+        }
+        if crossings.is_empty() {
+            return Ok(());
+        }
+        crossings.sort_unstable();
+        for (threshold, taxid, reads) in crossings {
+            let node = &taxonomy.nodes[taxid as usize];
+            let name = extract_string_from_offset(&taxonomy.name_data, node.name_offset as usize);
+            writeln!(
+                self.writer,
+                "{}\t{}\t{}\t{}\t{}",
+                self.processed, node.external_id, name, threshold, reads
+            )?;
+        }
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,438 @@
+//! Compact encoding of the hit rows passed from `annotate` to `resolve`
+//!
+//! A spill file (`sample_file_<N>_<M>.bin`) is a [`crate::fmt`] header followed by blocks,
+//! all integers little endian:
+//!
+//! ```text
+//! header  streamed header of kind spill, version 2, flags = value_bits << 8 | deflate
+//! block   payload_len u32 | payload (deflate compressed if flags & 1)
+//! payload n_reads varint
+//!         n_reads x (seq_id_delta varint | n_rows varint
+//!                    | n_rows x (kmer_id_delta varint | taxid varint | key))
+//! ```
+//!
+//! Varints are LEB128: 7 bits per byte, low bits first, the high bit set on every byte
+//! but the last. The reads of a block are sorted by seq ID, the first delta being from 0,
+//! and the rows of a read by k-mer position, the first delta again from 0. A row value
+//! is split into its taxid, the low `value_bits` bits, and its compacted key, the high
+//! bits written in `key = ceil((32 - value_bits) / 8)` bytes. A read may show up in
+//! several blocks, its rows are the union of them. A file ends after its last complete
+//! block, a partial block is an error.
+//!
+//! Version 1 files, with the 8 byte header `"KPSP" | version u8 (1) | flags u8 |
+//! value_bits u8 | reserved u8`, have the same blocks and are still read.
+//!
+//! A row takes 12 bytes in memory. The k-mer deltas of a read mostly fit in one byte and
+//! the taxids in one or two, a row with 16 taxid bits then takes 4 to 5 bytes. The keys
+//! are hash bits and don't compress, deflate mostly saves on the repeated taxids.
+use crate::compact_hash::Row;
+use crate::fmt::{self, Kind, STREAMED};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Magic of the version 1 files, written before the [`crate::fmt`] header
+pub const SPILL_MAGIC: [u8; 4] = *b"KPSP";
+pub const SPILL_VERSION: u8 = 2;
+const FLAG_DEFLATE: u16 = 1;
+
+/// The reads of a block, by seq ID
+pub type SpillBlock = Vec<(u32, Vec<Row>)>;
+
+/// The header of a spill file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpillHeader {
+    pub version: u8,
+    /// Number of taxid bits of the row values
+    pub value_bits: u8,
+    /// The block payloads are deflate compressed
+    pub compressed: bool,
+}
+
+impl SpillHeader {
+    pub fn new(value_bits: usize, compressed: bool) -> Self {
+        Self {
+            version: SPILL_VERSION,
+            value_bits: value_bits as u8,
+            compressed,
+        }
+    }
+
+    fn to_bytes(self) -> [u8; fmt::HEADER_SIZE] {
+        let flags = if self.compressed { FLAG_DEFLATE } else { 0 };
+        fmt::Header {
+            kind: Kind::Spill,
+            version: self.version,
+            flags: flags | (self.value_bits as u16) << 8,
+            payload_len: STREAMED,
+            checksum: 0,
+        }
+        .to_bytes()
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = [0u8; fmt::HEADER_SIZE];
+        reader.read_exact(&mut bytes[..4])?;
+        let (version, flags, value_bits) = if bytes[..4] == SPILL_MAGIC {
+            reader.read_exact(&mut bytes[4..8])?;
+            if bytes[4] != 1 {
+                return Err(invalid(&format!(
+                    "spill file version {}, expected {}",
+                    bytes[4], SPILL_VERSION
+                )));
+            }
+            (1, bytes[5] as u16, bytes[6])
+        } else {
+            reader.read_exact(&mut bytes[4..])?;
+            let header = fmt::Header::from_bytes(&bytes, Kind::Spill, SPILL_VERSION)?;
+            if header.payload_len != STREAMED {
+                return Err(invalid("spill file header without blocks"));
+            }
+            (
+                header.version,
+                header.flags & 0xff,
+                (header.flags >> 8) as u8,
+            )
+        };
+        if value_bits as usize >= 32 {
+            return Err(invalid("spill file value bits out of range"));
+        }
+        Ok(Self {
+            version,
+            value_bits,
+            compressed: flags & FLAG_DEFLATE != 0,
+        })
+    }
+
+    /// Whether blocks written with `other` may be appended to a file with this header,
+    /// the blocks of every version are the same
+    fn same_blocks(&self, other: &Self) -> bool {
+        (self.value_bits, self.compressed) == (other.value_bits, other.compressed)
+    }
+
+    /// Number of bytes of the compacted key of a row
+    fn key_bytes(&self) -> usize {
+        (32 - self.value_bits as usize).div_ceil(8)
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf
+            .get(*pos)
+            .ok_or_else(|| invalid("truncated spill block"))?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("spill varint too long"))
+}
+
+fn read_u32_varint(buf: &[u8], pos: &mut usize) -> Result<u32> {
+    u32::try_from(read_varint(buf, pos)?).map_err(|_| invalid("spill value out of range"))
+}
+
+/// Writes hit rows to a spill file, one block per call to [`SpillWriter::write_block`]
+pub struct SpillWriter<W: Write> {
+    inner: W,
+    header: SpillHeader,
+    payload: Vec<u8>,
+}
+
+impl<W: Write> SpillWriter<W> {
+    /// Starts a new spill file, writing its header
+    pub fn new(mut inner: W, header: SpillHeader) -> Result<Self> {
+        inner.write_all(&header.to_bytes())?;
+        Ok(Self::with_header(inner, header))
+    }
+
+    /// Continues a spill file whose header is already written
+    pub fn with_header(inner: W, header: SpillHeader) -> Self {
+        Self {
+            inner,
+            header,
+            payload: Vec::new(),
+        }
+    }
+
+    /// Writes the rows as one block, sorting them by read and position
+    pub fn write_block(&mut self, rows: &mut [Row]) -> Result<()> {
+        rows.sort_unstable_by_key(|row| (row.seq_id, row.kmer_id));
+        let reads: Vec<(u32, &[Row])> = rows
+            .chunk_by(|a, b| a.seq_id == b.seq_id)
+            .map(|read_rows| (read_rows[0].seq_id, read_rows))
+            .collect();
+        self.write_reads(&reads)
+    }
+
+    /// Writes reads as one block, their seq IDs strictly increasing and the rows of each
+    /// read sorted by position. A read may have no rows.
+    pub fn write_reads(&mut self, reads: &[(u32, &[Row])]) -> Result<()> {
+        let value_bits = self.header.value_bits as usize;
+        let value_mask = (1u64 << value_bits) - 1;
+        let key_bytes = self.header.key_bytes();
+        let payload = &mut self.payload;
+        payload.clear();
+        write_varint(payload, reads.len() as u64);
+        let mut last_seq_id = 0;
+        for (i, &(seq_id, rows)) in reads.iter().enumerate() {
+            if i > 0 && seq_id <= last_seq_id {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "spill block reads must be sorted by seq id",
+                ));
+            }
+            write_varint(payload, (seq_id - last_seq_id) as u64);
+            last_seq_id = seq_id;
+            write_varint(payload, rows.len() as u64);
+            let mut last_kmer_id = 0;
+            for row in rows {
+                if row.kmer_id < last_kmer_id {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "spill block rows must be sorted by position",
+                    ));
+                }
+                write_varint(payload, (row.kmer_id - last_kmer_id) as u64);
+                last_kmer_id = row.kmer_id;
+                let value = row.value as u64;
+                write_varint(payload, value & value_mask);
+                payload.extend_from_slice(&(value >> value_bits).to_le_bytes()[..key_bytes]);
+            }
+        }
+
+        if self.header.compressed {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(payload)?;
+            *payload = encoder.finish()?;
+        }
+        let len = u32::try_from(payload.len())
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "spill block too large"))?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(payload)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl SpillWriter<BufWriter<std::fs::File>> {
+    /// Opens a spill file for appending blocks, writing its header if the file is new
+    ///
+    /// The header of an existing file must match `header`.
+    pub fn append<P: AsRef<Path>>(path: P, header: SpillHeader) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            return Self::new(BufWriter::new(file), header);
+        }
+        file.seek(SeekFrom::Start(0))?;
+        let existing = SpillHeader::read_from(&mut file)?;
+        if !existing.same_blocks(&header) {
+            return Err(invalid(
+                "spill file header differs from the appended blocks",
+            ));
+        }
+        Ok(Self::with_header(BufWriter::new(file), header))
+    }
+}
+
+/// Reads the blocks of a spill file
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::spill::{SpillHeader, SpillReader, SpillWriter};
+///
+/// let mut state = 0x9e3779b97f4a7c15u64;
+/// let mut next = || {
+///     state ^= state << 13;
+///     state ^= state >> 7;
+///     state ^= state << 17;
+///     state
+/// };
+/// // A read of 100k hits, reads with few hits and a read without any
+/// let mut rows: Vec<Row> = (1..=100_000)
+///     .map(|i| Row::new(u32::combined(next() as u32, next() as u32 % 5000 + 1, 16), 7, i * 3))
+///     .collect();
+/// for seq_id in 8..1000 {
+///     let mut kmer_id = 0;
+///     for _ in 0..next() % 8 {
+///         kmer_id += 1 + next() as u32 % 40;
+///         rows.push(Row::new(next() as u32, seq_id, kmer_id));
+///     }
+/// }
+/// rows.push(Row::new(u32::MAX, u32::MAX, u32::MAX));
+///
+/// for compressed in [false, true] {
+///     let header = SpillHeader::new(16, compressed);
+///     let mut writer = SpillWriter::new(Vec::new(), header).unwrap();
+///     let (first, second) = rows.split_at(rows.len() / 2);
+///     writer.write_block(&mut first.to_vec()).unwrap();
+///     writer.write_block(&mut second.to_vec()).unwrap();
+///     writer.write_reads(&[(3, &[][..])]).unwrap();
+///     writer.write_block(&mut []).unwrap();
+///     let bytes = writer.into_inner();
+///     // Less than half of the 12 bytes of a row, even with random keys and taxids
+///     assert!(bytes.len() * 2 < rows.len() * 12);
+///
+///     let mut reader = SpillReader::new(&bytes[..]).unwrap();
+///     assert_eq!(reader.header(), header);
+///     let mut read_rows = reader.read_rows().unwrap();
+///     assert_eq!(read_rows.remove(&3), Some(vec![]));
+///     let mut all: Vec<Row> = read_rows.into_values().flatten().collect();
+///     all.sort_unstable_by_key(|row| (row.seq_id, row.kmer_id));
+///     let mut expected = rows.clone();
+///     expected.sort_unstable_by_key(|row| (row.seq_id, row.kmer_id));
+///     assert_eq!(all, expected);
+///
+///     // A cut block is an error, not the end of the file
+///     let cut = SpillReader::new(&bytes[..bytes.len() - 1]).unwrap().read_rows();
+///     assert!(cut.is_err());
+/// }
+///
+/// assert!(SpillReader::new(&b"KPSP\x02\0\x10\0"[..]).is_err());
+/// assert!(SpillReader::new(&b"not a spill"[..]).is_err());
+/// ```
+///
+/// Version 1 files only differ by their header:
+///
+/// ```
+/// use kun_peng::compact_hash::{Compact, Row};
+/// use kun_peng::fmt::HEADER_SIZE;
+/// use kun_peng::spill::{SpillHeader, SpillReader, SpillWriter};
+///
+/// let rows = vec![Row::new(u32::combined(0xabcd, 562, 16), 3, 1), Row::new(5, 3, 9)];
+/// let mut writer = SpillWriter::new(Vec::new(), SpillHeader::new(16, true)).unwrap();
+/// writer.write_block(&mut rows.clone()).unwrap();
+/// let bytes = writer.into_inner();
+///
+/// let mut legacy = b"KPSP\x01\x01\x10\0".to_vec();
+/// legacy.extend_from_slice(&bytes[HEADER_SIZE..]);
+/// let reader = SpillReader::new(&legacy[..]).unwrap();
+/// assert_eq!((reader.header().version, reader.header().value_bits), (1, 16));
+/// assert_eq!(reader.read_rows().unwrap()[&3], rows);
+///
+/// // Another kind of file
+/// let mut checkpoint = Vec::new();
+/// kun_peng::fmt::write_payload(&mut checkpoint, kun_peng::fmt::Kind::Checkpoint, 1, 0, b"{}").unwrap();
+/// assert!(SpillReader::new(&checkpoint[..]).is_err());
+/// ```
+pub struct SpillReader<R: Read> {
+    inner: R,
+    header: SpillHeader,
+    payload: Vec<u8>,
+}
+
+impl<R: Read> SpillReader<R> {
+    /// Reads and checks the header of a spill file
+    pub fn new(mut inner: R) -> Result<Self> {
+        let header = SpillHeader::read_from(&mut inner)?;
+        Ok(Self {
+            inner,
+            header,
+            payload: Vec::new(),
+        })
+    }
+
+    pub fn header(&self) -> SpillHeader {
+        self.header
+    }
+
+    /// Reads the next block, `None` at the end of the file
+    pub fn next_block(&mut self) -> Result<Option<SpillBlock>> {
+        let mut len = [0u8; 4];
+        match self.inner.read(&mut len[..1])? {
+            0 => return Ok(None),
+            _ => self.inner.read_exact(&mut len[1..])?,
+        }
+        let len = u32::from_le_bytes(len) as usize;
+        self.payload.resize(len, 0);
+        self.inner.read_exact(&mut self.payload)?;
+        if self.header.compressed {
+            let mut decoded = Vec::new();
+            DeflateDecoder::new(&self.payload[..]).read_to_end(&mut decoded)?;
+            self.payload = decoded;
+        }
+
+        let value_bits = self.header.value_bits as u32;
+        let key_bytes = self.header.key_bytes();
+        let buf = &self.payload;
+        let mut pos = 0;
+        let n_reads = read_varint(buf, &mut pos)? as usize;
+        let mut reads = Vec::with_capacity(n_reads.min(buf.len()));
+        let mut seq_id = 0u32;
+        for _ in 0..n_reads {
+            seq_id = seq_id
+                .checked_add(read_u32_varint(buf, &mut pos)?)
+                .ok_or_else(|| invalid("spill seq id out of range"))?;
+            let n_rows = read_varint(buf, &mut pos)? as usize;
+            let mut rows = Vec::with_capacity(n_rows.min(buf.len()));
+            let mut kmer_id = 0u32;
+            for _ in 0..n_rows {
+                kmer_id = kmer_id
+                    .checked_add(read_u32_varint(buf, &mut pos)?)
+                    .ok_or_else(|| invalid("spill position out of range"))?;
+                let taxid = read_u32_varint(buf, &mut pos)?;
+                let key = buf
+                    .get(pos..pos + key_bytes)
+                    .ok_or_else(|| invalid("truncated spill block"))?;
+                pos += key_bytes;
+                let mut le = [0u8; 8];
+                le[..key_bytes].copy_from_slice(key);
+                let value = (u64::from_le_bytes(le) << value_bits) as u32 | taxid;
+                rows.push(Row::new(value, seq_id, kmer_id));
+            }
+            reads.push((seq_id, rows));
+        }
+        if pos != buf.len() {
+            return Err(invalid("trailing bytes in spill block"));
+        }
+        Ok(Some(reads))
+    }
+
+    /// Reads all the blocks, merging the rows of every read
+    pub fn read_rows(mut self) -> Result<HashMap<u32, Vec<Row>>> {
+        let mut map: HashMap<u32, Vec<Row>> = HashMap::new();
+        while let Some(reads) = self.next_block()? {
+            for (seq_id, rows) in reads {
+                map.entry(seq_id).or_default().extend(rows);
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Reads all the rows of a spill file, see [`SpillReader::read_rows`]
+pub fn read_spill_file<P: AsRef<Path>>(path: P) -> Result<HashMap<u32, Vec<Row>>> {
+    let file = std::fs::File::open(path)?;
+    SpillReader::new(io::BufReader::new(file))?.read_rows()
+}
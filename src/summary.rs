@@ -0,0 +1,633 @@
+use crate::reader::{AlphabetCounts, Molecule};
+use seqkmer::Meros;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Read statistics collected over a classification run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReadStats {
+    /// Number of processed sequences (pairs count once)
+    pub total_seqs: u64,
+    /// Number of classified sequences
+    pub classified: u64,
+    /// Number of sequences without a single valid k-mer (all N, masked or shorter than k)
+    pub no_kmers: u64,
+    /// Number of sequences with hits in fewer hit groups than required, left out of the
+    /// outputs by `--filter-hit-groups`
+    #[serde(default)]
+    pub filtered: u64,
+    /// Number of sequences longer than `--max-read-length`, truncated or skipped
+    #[serde(default)]
+    pub too_long: u64,
+    /// Number of observed mates, used for the read length statistics
+    pub total_mates: u64,
+    /// Sum of all mate lengths
+    pub total_bases: u64,
+    /// Shortest observed mate
+    pub min_len: u64,
+    /// Longest observed mate
+    pub max_len: u64,
+    /// Number of minimizer hits on index cells holding taxid 0, skipped as misses
+    #[serde(default)]
+    pub zero_taxon_hits: u64,
+}
+
+impl ReadStats {
+    /// Records the length of one mate
+    pub fn add_mate_len(&mut self, len: u64) {
+        self.min_len = if self.total_mates == 0 {
+            len
+        } else {
+            self.min_len.min(len)
+        };
+        self.max_len = self.max_len.max(len);
+        self.total_mates += 1;
+        self.total_bases += len;
+    }
+
+    /// Records a sequence without any valid k-mer
+    pub fn add_no_kmers(&mut self) {
+        self.no_kmers += 1;
+    }
+
+    /// Records a sequence left out of the outputs for too few hit groups
+    pub fn add_filtered(&mut self) {
+        self.filtered += 1;
+    }
+
+    /// Merges the statistics of another run part into this one
+    pub fn merge(&mut self, other: &ReadStats) {
+        if other.total_mates > 0 {
+            self.min_len = if self.total_mates == 0 {
+                other.min_len
+            } else {
+                self.min_len.min(other.min_len)
+            };
+            self.max_len = self.max_len.max(other.max_len);
+        }
+        self.total_seqs += other.total_seqs;
+        self.classified += other.classified;
+        self.no_kmers += other.no_kmers;
+        self.filtered += other.filtered;
+        self.too_long += other.too_long;
+        self.zero_taxon_hits += other.zero_taxon_hits;
+        self.total_mates += other.total_mates;
+        self.total_bases += other.total_bases;
+    }
+
+    /// Average mate length
+    pub fn mean_len(&self) -> f64 {
+        if self.total_mates == 0 {
+            0.0
+        } else {
+            self.total_bases as f64 / self.total_mates as f64
+        }
+    }
+
+    /// Fraction of classified sequences
+    pub fn classified_fraction(&self) -> f64 {
+        if self.total_seqs == 0 {
+            0.0
+        } else {
+            self.classified as f64 / self.total_seqs as f64
+        }
+    }
+
+    /// Number of sequences neither classified nor filtered
+    pub fn unclassified(&self) -> u64 {
+        self.total_seqs
+            .saturating_sub(self.classified + self.filtered)
+    }
+
+    /// Fraction of sequences without a single valid k-mer
+    pub fn no_kmers_fraction(&self) -> f64 {
+        if self.total_seqs == 0 {
+            0.0
+        } else {
+            self.no_kmers as f64 / self.total_seqs as f64
+        }
+    }
+
+    /// The warning of a run whose index gave hits on taxid 0, `None` without any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::summary::ReadStats;
+    ///
+    /// let mut stats = ReadStats::default();
+    /// assert_eq!(stats.zero_taxon_message(), None);
+    /// let mut batch = ReadStats::default();
+    /// batch.zero_taxon_hits = 3;
+    /// stats.merge(&batch);
+    /// stats.merge(&batch);
+    /// assert!(stats.zero_taxon_message().unwrap().starts_with("WARNING: 6 minimizer hits"));
+    /// ```
+    pub fn zero_taxon_message(&self) -> Option<String> {
+        (self.zero_taxon_hits > 0).then(|| {
+            format!(
+                "WARNING: {} minimizer hits on index cells holding taxid 0 were skipped, rebuild the database",
+                self.zero_taxon_hits
+            )
+        })
+    }
+}
+
+/// Formats the per input file statistics of a run as a table
+///
+/// Every row is labelled with the file index, the detected input format and the input
+/// paths, a bad lane or a swapped sample shows up as an outlier in the classified column.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::summary::{file_summary_table, ReadStats};
+///
+/// let mut good = ReadStats::default();
+/// good.total_seqs = 1000;
+/// good.classified = 950;
+/// let mut bad = ReadStats::default();
+/// bad.total_seqs = 800;
+/// bad.classified = 20;
+/// bad.no_kmers = 400;
+/// bad.filtered = 30;
+///
+/// let table = file_summary_table(&[
+///     (1, "lane1_R1.fq,lane1_R2.fq".to_string(), "FASTQ".to_string(), good),
+///     (2, "lane2_R1.fq,lane2_R2.fq".to_string(), "FASTQ".to_string(), bad),
+/// ]);
+/// let lines: Vec<&str> = table.lines().collect();
+/// assert_eq!(lines[0], "file\treads\tclassified\t%\ttoo-short\tfiltered\tformat\tinput");
+/// assert_eq!(lines[1], "1\t1000\t950\t95.00\t0\t0\tFASTQ\tlane1_R1.fq,lane1_R2.fq");
+/// assert_eq!(lines[2], "2\t800\t20\t2.50\t400\t30\tFASTQ\tlane2_R1.fq,lane2_R2.fq");
+/// ```
+pub fn file_summary_table(files: &[(usize, String, String, ReadStats)]) -> String {
+    let mut table =
+        String::from("file\treads\tclassified\t%\ttoo-short\tfiltered\tformat\tinput\n");
+    for (file_index, input, format, stats) in files {
+        table.push_str(&format!(
+            "{}\t{}\t{}\t{:.2}\t{}\t{}\t{}\t{}\n",
+            file_index,
+            stats.total_seqs,
+            stats.classified,
+            stats.classified_fraction() * 100.0,
+            stats.no_kmers,
+            stats.filtered,
+            format,
+            input
+        ));
+    }
+    table
+}
+
+/// Builds the QC warning for a run whose classification rate is below `threshold`
+///
+/// Returns `None` if the run classified enough reads or did not process any read.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::summary::{low_classification_message, ReadStats};
+/// use seqkmer::Meros;
+///
+/// let mut stats = ReadStats::default();
+/// stats.total_seqs = 1000;
+/// stats.classified = 5;
+/// stats.add_mate_len(150);
+///
+/// let meros = Meros::new(35, 31, None, None, None);
+/// let msg = low_classification_message(&stats, &meros, 0.05).unwrap();
+/// assert!(msg.contains("0.50%"));
+/// assert!(msg.contains("k = 35, l = 31"));
+///
+/// stats.classified = 500;
+/// assert!(low_classification_message(&stats, &meros, 0.05).is_none());
+/// ```
+pub fn low_classification_message(
+    stats: &ReadStats,
+    meros: &Meros,
+    threshold: f64,
+) -> Option<String> {
+    if stats.total_seqs == 0 || stats.classified_fraction() >= threshold {
+        return None;
+    }
+
+    Some(format!(
+        "WARNING: only {:.2}% of {} sequences were classified (threshold {:.2}%)\n  \
+         database: k = {}, l = {}\n  \
+         observed read length: min {}, mean {:.1}, max {}\n  \
+         sequences without a valid k-mer: {:.2}%\n\
+         Likely causes: the database does not match the sample type \
+         (e.g. a 16S database for shotgun data), reads shorter than k, \
+         or a low quality run.",
+        stats.classified_fraction() * 100.0,
+        stats.total_seqs,
+        threshold * 100.0,
+        meros.k_mer,
+        meros.l_mer,
+        stats.min_len,
+        stats.mean_len(),
+        stats.max_len,
+        stats.no_kmers_fraction() * 100.0,
+    ))
+}
+
+/// Reports a low classification rate on stderr, or as an error if `as_error` is set
+pub fn check_classified_rate(
+    stats: &ReadStats,
+    meros: &Meros,
+    threshold: f64,
+    as_error: bool,
+) -> io::Result<()> {
+    if let Some(msg) = low_classification_message(stats, meros, threshold) {
+        if as_error {
+            return Err(io::Error::other(msg));
+        }
+        eprintln!("{}", msg);
+    }
+    Ok(())
+}
+
+/// The warning printed when the sampled reads are short for the k-mer length
+///
+/// Below a median length of 2 x k a read holds fewer k-mers than bases, too few for a
+/// confident call. `None` for longer reads or an empty sample.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::summary::short_read_message;
+///
+/// let lengths = [75, 76, 74, 150, 75];
+/// let msg = short_read_message(&lengths, 51).unwrap();
+/// assert!(msg.contains("median read length 75"));
+/// assert!(msg.contains("k = 51"));
+/// assert!(msg.contains("25 k-mers"));
+///
+/// assert!(short_read_message(&lengths, 35).is_none());
+/// assert!(short_read_message(&[], 35).is_none());
+/// ```
+pub fn short_read_message(lengths: &[u64], k_mer: usize) -> Option<String> {
+    let mut sorted = lengths.to_vec();
+    sorted.sort_unstable();
+    let median = *sorted.get(sorted.len() / 2)?;
+    if median >= 2 * k_mer as u64 {
+        return None;
+    }
+    Some(format!(
+        "WARNING: median read length {} of {} sampled reads is below 2 x k (k = {})\n  \
+         a read of that length holds only {} k-mers, expect a low sensitivity. \
+         Use a database with a smaller k, or --no-length-check to silence this warning.",
+        median,
+        sorted.len(),
+        k_mer,
+        (median + 1).saturating_sub(k_mer as u64),
+    ))
+}
+
+/// The error raised when the sampled reads look like the other molecule type than the
+/// database, protein reads for a DNA database or nucleotides for a protein one
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::reader::AlphabetCounts;
+/// use kun_peng::summary::alphabet_mismatch_message;
+///
+/// let mut protein = AlphabetCounts::default();
+/// protein.add(b"MKTAYIAKQRQISFVKSHFSRQLEERLGLIEVQAPILSRVGDGTQDNLSGAEKAVQVKVKALPDAQ");
+/// let msg = alphabet_mismatch_message(&protein, true).unwrap();
+/// assert!(msg.contains("look like protein"));
+/// assert!(msg.contains("--ignore-alphabet-check"));
+/// assert!(alphabet_mismatch_message(&protein, false).is_none());
+///
+/// let mut dna = AlphabetCounts::default();
+/// dna.add(b"ACGTTGCAACGTAGCTAGCTAGCATCG");
+/// assert!(alphabet_mismatch_message(&dna, true).is_none());
+/// assert!(alphabet_mismatch_message(&dna, false).is_some());
+/// assert!(alphabet_mismatch_message(&AlphabetCounts::default(), true).is_none());
+/// ```
+pub fn alphabet_mismatch_message(counts: &AlphabetCounts, dna_db: bool) -> Option<String> {
+    let (reads, database) = match (counts.molecule(), dna_db) {
+        (Molecule::Protein, true) => ("protein", "DNA"),
+        (Molecule::Nucleotide, false) => ("nucleotides", "protein"),
+        _ => return None,
+    };
+    Some(format!(
+        "the reads look like {} ({:.1}% of {} sampled characters outside ACGTN) \
+         but the database is a {} database. \
+         Use --ignore-alphabet-check to classify them anyway.",
+        reads,
+        counts.non_acgtn as f64 * 100.0 / counts.total as f64,
+        counts.total,
+        database,
+    ))
+}
+
+/// Minimum time between two writes of a [`StatusFile`]
+pub const STATUS_FILE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Running counts of a classification run, as written to a [`StatusFile`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunStatus {
+    /// Number of processed sequences
+    pub processed: u64,
+    /// Number of classified sequences
+    pub classified: u64,
+    /// Fraction of the processed sequences that were classified
+    pub rate: f64,
+}
+
+impl RunStatus {
+    /// The status matching the counts of the run summary
+    pub fn from_stats(stats: &ReadStats) -> Self {
+        Self {
+            processed: stats.total_seqs,
+            classified: stats.classified,
+            rate: stats.classified_fraction(),
+        }
+    }
+}
+
+/// A JSON file overwritten with the running counts of a run, for monitoring
+///
+/// [`StatusFile::add`] writes at most once per interval, [`StatusFile::finish`] always
+/// writes. The file is replaced by a rename, readers never see a partial status.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::summary::{ReadStats, RunStatus, StatusFile};
+/// use std::time::Duration;
+///
+/// let dir = std::env::temp_dir().join("kun_peng_status_file");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let path = dir.join("status.json");
+///
+/// let mut status = StatusFile::new(&path, Duration::ZERO);
+/// status.add(100, 40).unwrap();
+/// let running = StatusFile::read(&path).unwrap();
+/// assert_eq!((running.processed, running.classified, running.rate), (100, 40, 0.4));
+///
+/// status.add(50, 35).unwrap();
+/// assert_eq!(StatusFile::read(&path).unwrap().processed, 150);
+///
+/// // A long interval holds back the running updates, but not the final status.
+/// let mut status = StatusFile::new(&path, Duration::from_secs(3600));
+/// status.add(150, 75).unwrap();
+/// status.add(50, 25).unwrap();
+/// assert_eq!(StatusFile::read(&path).unwrap().processed, 150);
+/// status.finish().unwrap();
+///
+/// let mut stats = ReadStats::default();
+/// stats.total_seqs = 200;
+/// stats.classified = 100;
+/// assert_eq!(StatusFile::read(&path).unwrap(), RunStatus::from_stats(&stats));
+/// ```
+pub struct StatusFile {
+    path: PathBuf,
+    interval: Duration,
+    last_write: Option<Instant>,
+    processed: u64,
+    classified: u64,
+}
+
+impl StatusFile {
+    pub fn new<P: AsRef<Path>>(path: P, interval: Duration) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            interval,
+            last_write: None,
+            processed: 0,
+            classified: 0,
+        }
+    }
+
+    /// The current counts
+    pub fn status(&self) -> RunStatus {
+        let rate = if self.processed == 0 {
+            0.0
+        } else {
+            self.classified as f64 / self.processed as f64
+        };
+        RunStatus {
+            processed: self.processed,
+            classified: self.classified,
+            rate,
+        }
+    }
+
+    /// Adds the counts of a batch, and writes the file if the interval has passed
+    pub fn add(&mut self, processed: u64, classified: u64) -> io::Result<()> {
+        self.processed += processed;
+        self.classified += classified;
+        let recent = self
+            .last_write
+            .is_some_and(|last| last.elapsed() < self.interval);
+        if !recent {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the final counts
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.write()
+    }
+
+    fn write(&mut self) -> io::Result<()> {
+        let json = serde_json::to_string(&self.status())?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, &self.path)?;
+        self.last_write = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Reads a status file
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<RunStatus> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Version of the [`RunSummary`] schema
+///
+/// Fields may be added without a new version, a version change means a field was renamed,
+/// removed or changed meaning.
+pub const RUN_SUMMARY_VERSION: u32 = 1;
+
+/// Counts of one input file of a [`RunSummary`], the rows of [`file_summary_table`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileSummary {
+    /// Index of the file, as in `sample_file.map`
+    pub file: usize,
+    /// Comma separated input paths
+    pub input: String,
+    /// Detected input format, e.g. `FASTQ`
+    pub format: String,
+    pub reads: u64,
+    pub classified: u64,
+    pub unclassified: u64,
+    /// Sequences without a single valid k-mer
+    pub too_short: u64,
+    /// Sequences left out by `--filter-hit-groups`
+    pub filtered: u64,
+    /// Sequences truncated or skipped by `--max-read-length`
+    #[serde(default)]
+    pub too_long: u64,
+}
+
+/// Time spent in one stage of a run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub seconds: f64,
+}
+
+/// Machine readable summary of a classification run, written by `--summary-output`
+///
+/// The counts come from the [`ReadStats`] the printed summary is made of. Schema, version 1:
+///
+/// ```text
+/// {
+///   "version": 1,
+///   "command": "direct" | "resolve" | "classify",
+///   "reads": N, "classified": N, "unclassified": N, "too_short": N, "filtered": N,
+///   "too_long": N,
+///   "files": [{"file": N, "input": "a.fq,b.fq", "format": "FASTQ", "reads": N,
+///              "classified": N, "unclassified": N, "too_short": N, "filtered": N,
+///              "too_long": N}],
+///   "wall_time_seconds": F,
+///   "stages": [{"stage": "classify", "seconds": F}],
+///   "warnings": ["..."]
+/// }
+/// ```
+///
+/// `reads` = `classified` + `unclassified` + `filtered`, `too_short` reads are also
+/// counted as unclassified. The `too_long` reads are classified on their first bases or,
+/// skipped, counted as unclassified and too short.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::summary::{ReadStats, RunSummary, RUN_SUMMARY_VERSION};
+/// use std::time::Duration;
+///
+/// let mut stats = ReadStats::default();
+/// stats.total_seqs = 100;
+/// stats.classified = 70;
+/// stats.no_kmers = 5;
+/// stats.filtered = 10;
+/// stats.too_long = 2;
+///
+/// let mut summary = RunSummary::new("direct");
+/// summary.set_counts(&stats, &[(1, "r.fq".to_string(), "FASTQ".to_string(), stats.clone())]);
+/// summary.add_stage("classify", Duration::from_millis(1500));
+/// summary.warnings.push("WARNING: short reads".to_string());
+///
+/// let path = std::env::temp_dir().join("kun_peng_run_summary.json");
+/// summary.write(&path).unwrap();
+/// let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+/// assert_eq!(json["version"], RUN_SUMMARY_VERSION);
+/// assert_eq!(json["unclassified"], 20);
+/// assert_eq!(json["files"][0]["too_short"], 5);
+/// assert_eq!(json["too_long"], 2);
+/// assert_eq!(json["stages"][0]["seconds"], 1.5);
+///
+/// let read = RunSummary::read(&path).unwrap();
+/// assert_eq!(read.files, summary.files);
+/// assert_eq!(read.warnings, ["WARNING: short reads"]);
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunSummary {
+    pub version: u32,
+    /// The subcommand of the run
+    pub command: String,
+    pub reads: u64,
+    pub classified: u64,
+    pub unclassified: u64,
+    pub too_short: u64,
+    pub filtered: u64,
+    #[serde(default)]
+    pub too_long: u64,
+    pub files: Vec<FileSummary>,
+    pub wall_time_seconds: f64,
+    pub stages: Vec<StageTiming>,
+    /// The warnings printed during the run
+    pub warnings: Vec<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+impl RunSummary {
+    /// An empty summary, the wall time counts from now
+    pub fn new(command: &str) -> Self {
+        Self {
+            version: RUN_SUMMARY_VERSION,
+            command: command.to_string(),
+            reads: 0,
+            classified: 0,
+            unclassified: 0,
+            too_short: 0,
+            filtered: 0,
+            too_long: 0,
+            files: Vec::new(),
+            wall_time_seconds: 0.0,
+            stages: Vec::new(),
+            warnings: Vec::new(),
+            started: Some(Instant::now()),
+        }
+    }
+
+    /// Takes the counts of the run and of its files, as given to [`file_summary_table`]
+    pub fn set_counts(&mut self, stats: &ReadStats, files: &[(usize, String, String, ReadStats)]) {
+        self.reads = stats.total_seqs;
+        self.classified = stats.classified;
+        self.unclassified = stats.unclassified();
+        self.too_short = stats.no_kmers;
+        self.filtered = stats.filtered;
+        self.too_long = stats.too_long;
+        self.files = files
+            .iter()
+            .map(|(file, input, format, stats)| FileSummary {
+                file: *file,
+                input: input.clone(),
+                format: format.clone(),
+                reads: stats.total_seqs,
+                classified: stats.classified,
+                unclassified: stats.unclassified(),
+                too_short: stats.no_kmers,
+                filtered: stats.filtered,
+                too_long: stats.too_long,
+            })
+            .collect();
+    }
+
+    pub fn add_stage(&mut self, stage: &str, duration: Duration) {
+        self.stages.push(StageTiming {
+            stage: stage.to_string(),
+            seconds: duration.as_secs_f64(),
+        });
+    }
+
+    /// Writes the summary as JSON, with the wall time up to now for a summary made by
+    /// [`RunSummary::new`]
+    pub fn write<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        if let Some(started) = self.started {
+            self.wall_time_seconds = started.elapsed().as_secs_f64();
+        }
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+    }
+
+    /// Reads a summary file
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
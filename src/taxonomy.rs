@@ -1,10 +1,14 @@
-use crate::utils::open_file;
+use crate::utils::{open_file, write_atomically};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
 use std::path::Path;
 
+pub use kun_peng_core::rank::{
+    parse_rank, rank_code, rank_name, EXTRA_TAXID_MAX, EXTRA_TAXID_MIN, MAX_RANK_WEIGHT, RANK_CODES,
+};
+
 /// Parse the NCBI taxonomy nodes file
 ///
 /// # Arguments
@@ -194,6 +198,131 @@ impl NCBITaxonomy {
         }
     }
 
+    /// Adds the custom taxa of a `taxid<TAB>parent_taxid<TAB>rank<TAB>name` file
+    ///
+    /// Custom taxa, such as synthetic spike-in controls, take taxids from
+    /// [`EXTRA_TAXID_MIN`] on, above the NCBI ones. A parent is an NCBI taxon or another
+    /// custom taxon. Empty lines and lines starting with `#` are skipped. Returns the
+    /// number of added taxa.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::taxonomy::NCBITaxonomy;
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_extra_taxa");
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let (nodes, names, extra) = (dir.join("nodes.dmp"), dir.join("names.dmp"), dir.join("extra.tsv"));
+    /// std::fs::write(&nodes, "1\t|\t1\t|\tno rank\t|\n10239\t|\t1\t|\tsuperkingdom\t|\n").unwrap();
+    /// std::fs::write(&names, "1\t|\troot\t|\t\t|\tscientific name\t|\n10239\t|\tViruses\t|\t\t|\tscientific name\t|\n").unwrap();
+    /// std::fs::write(
+    ///     &extra,
+    ///     "# taxid\tparent\trank\tname\n\
+    ///      2000000000\t1\tno rank\tspike-in controls\n\
+    ///      2000000001\t2000000000\tspecies\tERCC-00002\n",
+    /// )
+    /// .unwrap();
+    ///
+    /// let mut ncbi = NCBITaxonomy::from_ncbi(&nodes, &names).unwrap();
+    /// assert_eq!(ncbi.add_extra_taxa(&extra).unwrap(), 2);
+    /// ncbi.mark_node(2000000001);
+    /// ncbi.mark_node(10239);
+    /// let mut taxonomy = ncbi.convert_to_kraken_taxonomy();
+    /// taxonomy.generate_external_to_internal_id_map();
+    /// taxonomy.build_path_cache();
+    ///
+    /// let ercc = taxonomy.get_internal_id(2000000001);
+    /// let controls = taxonomy.get_internal_id(2000000000);
+    /// assert_eq!(taxonomy.rank_of(ercc), "species");
+    /// assert!(taxonomy.is_a_ancestor_of_b(controls, ercc));
+    /// assert_eq!(taxonomy.lca(ercc, taxonomy.get_internal_id(10239)), 1);
+    ///
+    /// let reject = |content: &str| {
+    ///     std::fs::write(&extra, content).unwrap();
+    ///     let mut ncbi = NCBITaxonomy::from_ncbi(&nodes, &names).unwrap();
+    ///     ncbi.add_extra_taxa(&extra).unwrap_err().to_string()
+    /// };
+    /// assert!(reject("10239\t1\tspecies\tclash\n").contains("reserved range"));
+    /// assert!(reject("2000000001\t1\tspecies\ta\n2000000001\t1\tspecies\tb\n").contains("already"));
+    /// assert!(reject("2000000001\t2000000005\tspecies\ta\n").contains("parent"));
+    /// assert!(reject("2000000001\t2000000002\tspecies\ta\n2000000002\t2000000001\tgenus\tb\n")
+    ///     .contains("cycle"));
+    /// ```
+    pub fn add_extra_taxa<P: AsRef<Path>>(&mut self, filename: P) -> Result<usize> {
+        let invalid = |line_no: usize, msg: String| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("extra taxa line {}: {}", line_no + 1, msg),
+            )
+        };
+        let mut added = Vec::new();
+        let reader = BufReader::new(open_file(filename)?);
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+            let [taxid, parent_id, rank, name] = fields[..] else {
+                return Err(invalid(
+                    line_no,
+                    "expected taxid<TAB>parent_taxid<TAB>rank<TAB>name".to_string(),
+                ));
+            };
+            let parse = |field: &str| {
+                field
+                    .parse::<u64>()
+                    .map_err(|_| invalid(line_no, format!("invalid taxid '{}'", field)))
+            };
+            let (taxid, parent_id) = (parse(taxid)?, parse(parent_id)?);
+            if !(EXTRA_TAXID_MIN..=EXTRA_TAXID_MAX).contains(&taxid) {
+                return Err(invalid(
+                    line_no,
+                    format!(
+                        "taxid {} is outside the reserved range {}..={}",
+                        taxid, EXTRA_TAXID_MIN, EXTRA_TAXID_MAX
+                    ),
+                ));
+            }
+            if self.parent_map.contains_key(&taxid) {
+                return Err(invalid(
+                    line_no,
+                    format!("taxid {} is already in the taxonomy", taxid),
+                ));
+            }
+            self.parent_map.insert(taxid, parent_id);
+            self.child_map.entry(parent_id).or_default().insert(taxid);
+            self.rank_map.insert(taxid, rank.to_string());
+            self.known_ranks.insert(rank.to_string());
+            self.name_map.insert(taxid, name.to_string());
+            added.push((line_no, taxid));
+        }
+
+        // Every custom taxon must reach the root, through taxa that exist after the merge.
+        for &(line_no, taxid) in &added {
+            let mut current = taxid;
+            for _ in 0..=added.len() {
+                if current < EXTRA_TAXID_MIN {
+                    break;
+                }
+                current = self.parent_map[&current];
+                if !self.parent_map.contains_key(&current) {
+                    return Err(invalid(
+                        line_no,
+                        format!("parent {} of taxid {} does not exist", current, taxid),
+                    ));
+                }
+            }
+            if current >= EXTRA_TAXID_MIN {
+                return Err(invalid(
+                    line_no,
+                    format!("taxid {} is in a cycle of custom taxa", taxid),
+                ));
+            }
+        }
+        Ok(added.len())
+    }
+
     /// Get rank offset data for the taxonomy
     ///
     /// # Returns
@@ -313,7 +442,7 @@ impl Taxonomy {
     ///
     /// A Result containing the new Taxonomy or an error
     pub fn from_file<P: AsRef<Path> + Debug>(filename: P) -> Result<Taxonomy> {
-        let mut file = open_file(&filename)?;
+        let mut file = BufReader::new(open_file(&filename)?);
 
         let mut magic = vec![0; Self::MAGIC.len()];
         file.read_exact(&mut magic)?;
@@ -417,38 +546,32 @@ impl Taxonomy {
     }
 
     /// Build the path cache for efficient ancestor lookups
+    ///
+    /// The tree is walked with an explicit stack, chains of custom taxa can be deeper than
+    /// the call stack allows. Every node keeps its full path from the root, the cache takes
+    /// about 80 bytes per node plus 4 bytes per ancestor of each node.
     pub fn build_path_cache(&mut self) {
-        let mut cache: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut cache: HashMap<u32, Vec<u32>> = HashMap::with_capacity(self.nodes.len());
         let root_external_id = 1u64;
         if let Some(&root_internal_id) = self.external_to_internal_id_map.get(&root_external_id) {
-            // Start traversing from the root node
-            self.build_path_for_node(root_internal_id, &mut cache, Vec::new());
+            // Each node with its parent, whose path is already cached
+            let mut stack = vec![(root_internal_id, 0u32)];
+            while let Some((node_id, parent_id)) = stack.pop() {
+                let mut path = Vec::with_capacity(cache.get(&parent_id).map_or(0, Vec::len) + 1);
+                path.extend_from_slice(cache.get(&parent_id).map_or(&[][..], Vec::as_slice));
+                path.push(node_id);
+                cache.insert(node_id, path);
+
+                // Assume child IDs are consecutive
+                let node = &self.nodes[node_id as usize];
+                let first_child_id = node.first_child as u32;
+                let children = first_child_id..first_child_id + node.child_count as u32;
+                stack.extend(children.map(|child_id| (child_id, node_id)));
+            }
         }
         self.path_cache = cache;
     }
 
-    fn build_path_for_node(
-        &self,
-        node_id: u32,
-        path_cache: &mut HashMap<u32, Vec<u32>>,
-        mut current_path: Vec<u32>,
-    ) {
-        current_path.push(node_id); // Add the current node to the path
-                                    // Store the current node's path
-        path_cache.insert(node_id, current_path.clone());
-
-        // Get the current node's information
-        let node = &self.nodes[node_id as usize];
-        let first_child_id = node.first_child as u32;
-        let child_count = node.child_count as u32;
-
-        // Traverse all children
-        for i in 0..child_count {
-            let child_internal_id = first_child_id + i; // Assume child IDs are consecutive
-            self.build_path_for_node(child_internal_id, path_cache, current_path.clone());
-        }
-    }
-
     /// Get the number of nodes in the taxonomy
     ///
     /// # Returns
@@ -458,6 +581,34 @@ impl Taxonomy {
         self.nodes.len()
     }
 
+    /// Get a node by internal ID, `None` past the end of the nodes
+    ///
+    /// The internal IDs of hits come from the hash tables, those of a corrupt index, or of
+    /// an index paired with another taxonomy, may point outside the nodes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    ///
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// taxonomy.nodes.push(TaxonomyNode { external_id: 562, ..Default::default() });
+    /// assert_eq!(taxonomy.node(1).map(|node| node.external_id), Some(562));
+    /// assert!(taxonomy.node(2).is_none());
+    /// assert_eq!(taxonomy.external_id(1), 562);
+    /// assert_eq!(taxonomy.external_id(u32::MAX), 0);
+    /// assert_eq!(taxonomy.rank_of(2), "");
+    /// ```
+    pub fn node(&self, id: u32) -> Option<&TaxonomyNode> {
+        self.nodes.get(id as usize)
+    }
+
+    /// Get the external ID of a node, 0 (unclassified) for an ID outside the nodes
+    pub fn external_id(&self, id: u32) -> u64 {
+        self.node(id).map_or(0, |node| node.external_id)
+    }
+
     /// Get the internal ID for a given external ID
     ///
     /// # Arguments
@@ -474,6 +625,91 @@ impl Taxonomy {
             .unwrap_or(&0)
     }
 
+    /// Get the rank name of a node, e.g. `genus` or `no rank`
+    ///
+    /// # Arguments
+    ///
+    /// * `taxid` - The internal node ID
+    ///
+    /// # Returns
+    ///
+    /// The rank name, or an empty string if the node has no rank data
+    pub fn rank_of(&self, taxid: u32) -> &str {
+        let Some(node) = self.node(taxid) else {
+            return "";
+        };
+        let offset = node.rank_offset as usize;
+        let data = self.rank_data.get(offset..).unwrap_or_default();
+        let end = data.iter().position(|&c| c == b'\0').unwrap_or(data.len());
+        std::str::from_utf8(&data[..end]).unwrap_or("")
+    }
+
+    /// Get the code of the closest standard rank at or above a node
+    ///
+    /// A strain below a species gives `S`, the root and nodes above the domains give `None`.
+    pub fn standard_rank_of(&self, mut taxid: u32) -> Option<char> {
+        while taxid != 0 {
+            if let Some(code) = rank_code(self.rank_of(taxid)) {
+                return Some(code);
+            }
+            let parent = self.node(taxid).map_or(0, |node| node.parent_id as u32);
+            if parent == taxid {
+                break;
+            }
+            taxid = parent;
+        }
+        None
+    }
+
+    /// Get the weight of the hits of a node, the more general the node the lower
+    ///
+    /// Nodes at or below species level weigh [`MAX_RANK_WEIGHT`], every standard rank
+    /// above one less, down to 1 for domains and the nodes above them.
+    pub fn rank_weight(&self, taxid: u32) -> u64 {
+        self.standard_rank_of(taxid)
+            .and_then(|code| RANK_CODES.iter().position(|&c| c == code))
+            .map_or(1, |level| level as u64 + 1)
+    }
+
+    /// Check if a node is at or below the rank `max_rank`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+    ///
+    /// // root -> Bacteria -> Proteobacteria -> ... -> Escherichia -> E. coli -> strain
+    /// let mut taxonomy = Taxonomy::default();
+    /// taxonomy.rank_data = b"no rank\0superkingdom\0phylum\0genus\0species\0".to_vec();
+    /// taxonomy.nodes.push(TaxonomyNode::default());
+    /// for (external_id, parent_id, rank_offset) in
+    ///     [(1, 1, 0), (2, 1, 8), (1224, 2, 21), (561, 3, 28), (562, 4, 34), (83333, 5, 0)]
+    /// {
+    ///     taxonomy.nodes.push(TaxonomyNode { external_id, parent_id, rank_offset, ..Default::default() });
+    /// }
+    ///
+    /// assert_eq!(taxonomy.rank_of(4), "genus");
+    /// assert_eq!(taxonomy.standard_rank_of(6), Some('S'));
+    /// assert_eq!(taxonomy.standard_rank_of(1), None);
+    ///
+    /// // A genus level call passes `--max-rank G`, a phylum level call does not
+    /// assert!(taxonomy.is_within_rank(4, 'G'));
+    /// assert!(taxonomy.is_within_rank(6, 'G'));
+    /// assert!(!taxonomy.is_within_rank(3, 'G'));
+    /// assert!(!taxonomy.is_within_rank(1, 'G'));
+    /// assert!(taxonomy.is_within_rank(3, 'P'));
+    /// ```
+    pub fn is_within_rank(&self, taxid: u32, max_rank: char) -> bool {
+        let level = |code: char| RANK_CODES.iter().position(|&c| c == code);
+        match (
+            self.standard_rank_of(taxid).and_then(level),
+            level(max_rank),
+        ) {
+            (Some(rank), Some(max_rank)) => rank >= max_rank,
+            _ => false,
+        }
+    }
+
     /// Generate the mapping from external to internal IDs
     pub fn generate_external_to_internal_id_map(&mut self) {
         self.external_to_internal_id_map.clear();
@@ -495,34 +731,35 @@ impl Taxonomy {
     ///
     /// A Result indicating success or failure
     pub fn write_to_disk<P: AsRef<Path>>(&self, filename: P) -> Result<()> {
-        let mut file = File::create(filename)?;
-
-        // Write file magic
-        file.write_all(Taxonomy::MAGIC)?;
-
-        // Write node count, name data length, and rank data length
-        let node_count = self.nodes.len() as u64;
-        let name_data_len = self.name_data.len() as u64;
-        let rank_data_len = self.rank_data.len() as u64;
-        file.write_all(&node_count.to_le_bytes())?;
-        file.write_all(&name_data_len.to_le_bytes())?;
-        file.write_all(&rank_data_len.to_le_bytes())?;
-
-        // Write nodes as binary data
-        for node in &self.nodes {
-            file.write_all(&node.parent_id.to_le_bytes())?;
-            file.write_all(&node.first_child.to_le_bytes())?;
-            file.write_all(&node.child_count.to_le_bytes())?;
-            file.write_all(&node.name_offset.to_le_bytes())?;
-            file.write_all(&node.rank_offset.to_le_bytes())?;
-            file.write_all(&node.external_id.to_le_bytes())?;
-            file.write_all(&node.godparent_id.to_le_bytes())?;
-        }
-
-        // Write name data and rank data
-        file.write_all(&self.name_data)?;
-        file.write_all(&self.rank_data)?;
+        write_atomically(filename, |tmp| {
+            let mut file = BufWriter::new(File::create(tmp)?);
+
+            // Write file magic
+            file.write_all(Taxonomy::MAGIC)?;
+
+            // Write node count, name data length, and rank data length
+            let node_count = self.nodes.len() as u64;
+            let name_data_len = self.name_data.len() as u64;
+            let rank_data_len = self.rank_data.len() as u64;
+            file.write_all(&node_count.to_le_bytes())?;
+            file.write_all(&name_data_len.to_le_bytes())?;
+            file.write_all(&rank_data_len.to_le_bytes())?;
+
+            // Write nodes as binary data
+            for node in &self.nodes {
+                file.write_all(&node.parent_id.to_le_bytes())?;
+                file.write_all(&node.first_child.to_le_bytes())?;
+                file.write_all(&node.child_count.to_le_bytes())?;
+                file.write_all(&node.name_offset.to_le_bytes())?;
+                file.write_all(&node.rank_offset.to_le_bytes())?;
+                file.write_all(&node.external_id.to_le_bytes())?;
+                file.write_all(&node.godparent_id.to_le_bytes())?;
+            }
 
-        Ok(())
+            // Write name data and rank data
+            file.write_all(&self.name_data)?;
+            file.write_all(&self.rank_data)?;
+            file.flush()
+        })
     }
 }
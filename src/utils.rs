@@ -1,3 +1,4 @@
+use seqkmer::{Meros, OptionPair, BITS_PER_CHAR};
 use std::collections::{BTreeMap as Map, HashMap};
 use std::fs::{self, create_dir_all, File, OpenOptions};
 use std::io::{self, BufRead, BufReader, BufWriter, Result};
@@ -81,6 +82,158 @@ pub fn expand_spaced_seed_mask(spaced_seed_mask: u64, bit_expansion_factor: u64)
     new_mask
 }
 
+/// Checks that the masks of `meros` fit its alphabet and minimizer length
+///
+/// The l-mer mask, the expanded spaced seed and the toggle mask are all derived from
+/// `BITS_PER_CHAR`, a mask built for another alphabet compiles but scans garbage.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::args::KLMTArgs;
+/// use kun_peng::utils::check_meros;
+/// use seqkmer::{Meros, BITS_PER_CHAR};
+///
+/// let max_l = 64 / BITS_PER_CHAR - 1;
+/// for l_mer in 1..=max_l {
+///     for minimizer_spaces in 0..=l_mer / 4 {
+///         let klmt = KLMTArgs {
+///             k_mer: l_mer as u64 + 4,
+///             l_mer: l_mer as u8,
+///             minimizer_spaces: minimizer_spaces as u8,
+///             toggle_mask: seqkmer::DEFAULT_TOGGLE_MASK,
+///             min_clear_hash_value: None,
+///         };
+///         check_meros(&klmt.as_meros()).unwrap();
+///     }
+/// }
+///
+/// // A spaced seed that was not expanded to whole characters
+/// assert!(check_meros(&Meros::new(35, 31, Some(0b1011), None, None)).is_err());
+/// // k shorter than l
+/// assert!(check_meros(&Meros::new(11, 15, None, None, None)).is_err());
+/// ```
+pub fn check_meros(meros: &Meros) -> Result<()> {
+    let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    let lmer_bits = meros.l_mer * BITS_PER_CHAR;
+    if meros.l_mer == 0 || lmer_bits >= 64 {
+        return invalid(format!(
+            "l = {} does not fit {} bits per character, use 1 <= l <= {}",
+            meros.l_mer,
+            BITS_PER_CHAR,
+            64 / BITS_PER_CHAR - 1
+        ));
+    }
+    if meros.k_mer < meros.l_mer {
+        return invalid(format!(
+            "k = {} is less than l = {}",
+            meros.k_mer, meros.l_mer
+        ));
+    }
+    let mask = (1u64 << lmer_bits) - 1;
+    if meros.mask != mask {
+        return invalid(format!("l-mer mask {:#x} is not {:#x}", meros.mask, mask));
+    }
+    if meros.toggle_mask & !mask != 0 {
+        return invalid(format!(
+            "toggle mask {:#x} exceeds the l-mer",
+            meros.toggle_mask
+        ));
+    }
+    if meros.spaced_seed_mask & !mask != 0 {
+        return invalid(format!(
+            "spaced seed mask {:#x} exceeds the l-mer",
+            meros.spaced_seed_mask
+        ));
+    }
+    let char_mask = (1u64 << BITS_PER_CHAR) - 1;
+    let partial = (0..meros.l_mer).any(|i| {
+        let bits = (meros.spaced_seed_mask >> (i * BITS_PER_CHAR)) & char_mask;
+        bits != 0 && bits != char_mask
+    });
+    if partial {
+        return invalid(format!(
+            "spaced seed mask {:#x} is not expanded to {} bits per character",
+            meros.spaced_seed_mask, BITS_PER_CHAR
+        ));
+    }
+    Ok(())
+}
+
+/// Number of l-mers a minimizer is picked from, the l-mers of one k-mer
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::utils::num_lmers_per_window;
+/// use seqkmer::Meros;
+///
+/// let meros = Meros::new(35, 31, None, None, None);
+/// assert_eq!(num_lmers_per_window(&meros), meros.window_size() + 1);
+/// assert_eq!(num_lmers_per_window(&meros), 5);
+/// ```
+pub fn num_lmers_per_window(meros: &Meros) -> usize {
+    meros.window_size() + 1
+}
+
+/// Summary of the minimizer parameters of `meros`, for logs and diagnostics
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::args::KLMTArgs;
+/// use kun_peng::utils::describe_meros;
+///
+/// let klmt = KLMTArgs {
+///     k_mer: 35,
+///     l_mer: 31,
+///     minimizer_spaces: 7,
+///     toggle_mask: seqkmer::DEFAULT_TOGGLE_MASK,
+///     min_clear_hash_value: Some(u64::MAX - u64::MAX / 4),
+/// };
+/// assert_eq!(
+///     describe_meros(&klmt.as_meros()),
+///     "k = 35, l = 31, 5 l-mers per window, l-mer mask 0x3fffffffffffffff, \
+///      spaced seed mask 0x3ffffffff3333333 (7 characters ignored), \
+///      toggle mask 0x237e28c4271b5a2d, \
+///      minimizers hashing below 0xc000000000000000 dropped (25.00% kept)"
+/// );
+///
+/// let klmt = KLMTArgs { minimizer_spaces: 0, min_clear_hash_value: None, ..klmt };
+/// let description = describe_meros(&klmt.as_meros());
+/// assert!(description.contains("(0 characters ignored)"));
+/// assert!(description.ends_with("all minimizers kept"));
+/// ```
+pub fn describe_meros(meros: &Meros) -> String {
+    let char_mask = (1u64 << BITS_PER_CHAR) - 1;
+    // A zero mask is no spaced seed at all
+    let ignored = match meros.spaced_seed_mask {
+        0 => 0,
+        seed => (0..meros.l_mer)
+            .filter(|i| (seed >> (i * BITS_PER_CHAR)) & char_mask == 0)
+            .count(),
+    };
+    let sampling = match meros.min_clear_hash_value {
+        Some(min_clear) => format!(
+            "minimizers hashing below {:#x} dropped ({:.2}% kept)",
+            min_clear,
+            (u64::MAX - min_clear) as f64 / u64::MAX as f64 * 100.0
+        ),
+        None => "all minimizers kept".to_string(),
+    };
+    format!(
+        "k = {}, l = {}, {} l-mers per window, l-mer mask {:#x}, spaced seed mask {:#x} ({} characters ignored), toggle mask {:#x}, {}",
+        meros.k_mer,
+        meros.l_mer,
+        num_lmers_per_window(meros),
+        meros.mask,
+        meros.spaced_seed_mask,
+        ignored,
+        meros.toggle_mask,
+        sampling
+    )
+}
+
 pub fn find_files<P: AsRef<Path>>(path: P, prefix: &str, suffix: &str) -> Vec<PathBuf> {
     let mut files: Vec<PathBuf> = WalkDir::new(path)
         .into_iter()
@@ -115,6 +268,44 @@ pub fn format_bytes(size: f64) -> String {
     format!("{:.2}{}", size, current_suffix)
 }
 
+/// Writes a file under a temporary name next to `path`, then renames it over `path`
+///
+/// Readers see the old file or the new one, never a partial one. The temporary file is
+/// removed if `write` fails.
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::utils::write_atomically;
+///
+/// let path = std::env::temp_dir().join("kun_peng_write_atomically.txt");
+/// write_atomically(&path, |tmp| std::fs::write(tmp, "new")).unwrap();
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+///
+/// let failed = write_atomically(&path, |tmp| {
+///     std::fs::write(tmp, "partial")?;
+///     Err(std::io::Error::new(std::io::ErrorKind::Other, "disk full"))
+/// });
+/// assert!(failed.is_err());
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+/// assert!(!path.with_extension("txt.tmp").exists());
+/// ```
+pub fn write_atomically<P, F>(path: P, write: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&Path) -> Result<()>,
+{
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    if let Err(e) = write(&tmp) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    fs::rename(&tmp, path)
+}
+
 #[cfg(unix)]
 extern crate libc;
 
@@ -387,6 +578,25 @@ pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<File> {
     })
 }
 
+/// Parses a sequence size column ("len", or "len1|len2" for pairs)
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::utils::parse_seq_size;
+/// use seqkmer::OptionPair;
+///
+/// assert_eq!(parse_seq_size("150"), OptionPair::Single(150));
+/// assert_eq!(parse_seq_size("150|148"), OptionPair::Pair(150, 148));
+/// ```
+pub fn parse_seq_size(seq_size: &str) -> OptionPair<usize> {
+    let parse = |len: &str| len.trim().parse::<usize>().unwrap_or(0);
+    match seq_size.split_once('|') {
+        Some((len1, len2)) => OptionPair::Pair(parse(len1), parse(len2)),
+        None => OptionPair::Single(parse(seq_size)),
+    }
+}
+
 /// Get the latest file index
 pub fn get_lastest_file_index(file_path: &PathBuf) -> Result<usize> {
     let file_content = fs::read_to_string(&file_path)?;
@@ -0,0 +1,125 @@
+//! Classification of a directory that keeps receiving reads
+//!
+//! A watch run wakes up at intervals, classifies the read files that appeared in the
+//! directory since the last wake-up and regenerates its cumulative reports. The files it
+//! classified and their summed counts are kept in a ledger, a [`SampleProgress`] snapshot
+//! saved after every batch, so that a restarted run skips the files already counted.
+use crate::checkpoint::SampleProgress;
+use crate::readcounts::TaxonCounters;
+use crate::summary::ReadStats;
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+/// File name of the ledger in the output directory
+pub const WATCH_LEDGER: &str = "watch.ledger";
+
+/// Extensions of the read files picked up in a watched directory, also gzipped
+const READ_EXTENSIONS: [&str; 6] = ["fq", "fastq", "fa", "fasta", "fna", "fas"];
+
+/// Whether a file name is the one of a FASTA/FASTQ file, plain or gzipped
+///
+/// # Examples
+///
+/// ```
+/// use kun_peng::watch::is_read_file;
+///
+/// assert!(is_read_file("FAL12345_pass_0.fastq.gz".as_ref()));
+/// assert!(is_read_file("batch_7.fa".as_ref()));
+/// assert!(!is_read_file("stop.txt".as_ref()));
+/// assert!(!is_read_file("batch_8.fastq.tmp".as_ref()));
+/// ```
+pub fn is_read_file(path: &Path) -> bool {
+    let path = match path.extension() {
+        Some(ext) if ext == "gz" => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| READ_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// The files classified by a watch run and their cumulative counts
+///
+/// The ledger is a [`SampleProgress`] whose `inputs` are the classified files, in the
+/// order they were classified, and whose counts are the sums over them.
+pub struct WatchLedger {
+    path: PathBuf,
+    pub progress: SampleProgress,
+}
+
+impl WatchLedger {
+    /// Loads the ledger of an earlier run, or starts an empty one
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let progress = if path.exists() {
+            SampleProgress::load(&path)?
+        } else {
+            SampleProgress::new(0, Vec::new())
+        };
+        Ok(Self { path, progress })
+    }
+
+    /// The read files of `dir` that are not in the ledger, by name
+    ///
+    /// Files are taken as soon as they appear, they should be moved into the directory
+    /// once complete rather than written in place.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use kun_peng::readcounts::TaxonCounters;
+    /// use kun_peng::summary::ReadStats;
+    /// use kun_peng::watch::WatchLedger;
+    ///
+    /// let dir = std::env::temp_dir().join("kun_peng_watch_ledger");
+    /// let _ = std::fs::remove_dir_all(&dir);
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// for name in ["b.fq", "a.fq", "notes.txt"] {
+    ///     std::fs::write(dir.join(name), "").unwrap();
+    /// }
+    ///
+    /// let ledger_path = dir.join("watch.ledger");
+    /// let mut ledger = WatchLedger::open(&ledger_path).unwrap();
+    /// let files = ledger.new_files(&dir).unwrap();
+    /// assert_eq!(files, [dir.join("a.fq"), dir.join("b.fq")]);
+    /// ledger.add(&files, 10, 8, &ReadStats::default(), &TaxonCounters::new());
+    /// ledger.save().unwrap();
+    ///
+    /// // A restarted run only sees the files that came since
+    /// std::fs::write(dir.join("c.fq"), "").unwrap();
+    /// let ledger = WatchLedger::open(&ledger_path).unwrap();
+    /// assert_eq!(ledger.new_files(&dir).unwrap(), [dir.join("c.fq")]);
+    /// assert_eq!((ledger.progress.reads_index, ledger.progress.classified), (10, 8));
+    /// ```
+    pub fn new_files<P: AsRef<Path>>(&self, dir: P) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && is_read_file(&path) && !self.progress.inputs.contains(&path) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Records a batch of classified files and adds their counts
+    pub fn add(
+        &mut self,
+        files: &[PathBuf],
+        reads: usize,
+        classified: usize,
+        read_stats: &ReadStats,
+        taxon_counts: &TaxonCounters,
+    ) {
+        self.progress.inputs.extend_from_slice(files);
+        self.progress
+            .add_batch(reads, classified, read_stats, taxon_counts);
+    }
+
+    /// Saves the ledger, replacing the previous one only once the new one is on disk
+    pub fn save(&self) -> Result<()> {
+        self.progress.save(&self.path)
+    }
+}
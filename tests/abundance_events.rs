@@ -0,0 +1,68 @@
+//! `--abundance-events` writes a line whenever a taxon reaches one of the thresholds.
+//!
+//! Builds the database of `data/` and classifies 25 reads cut from the SARS-CoV-2 genome
+//! and 5 random reads. The virus crosses the 1, 10 and 20 read thresholds in that order,
+//! never the 100 read one, and the random reads fire nothing.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, kun_peng, random_bases, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 25 reads of 150 bp of the genome and 5 random ones, as FASTA
+fn write_reads(reads: &Path) {
+    let starts = (0..25).map(|i| 1000 + i * 1000);
+    let mut records = cut_reads(&genome("COVID_19.fa"), "virus", starts, 150);
+    let mut state = 12345u64;
+    for i in 0..5 {
+        records.push((format!("random_{}", i), random_bases(150, &mut state)));
+    }
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+#[test]
+fn threshold_crossings_are_reported_in_order() {
+    let work = work_dir("events");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+    let events = work.join("events.tsv");
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        work.join("out"),
+        "--abundance-events".into(),
+        events.clone(),
+        "--event-thresholds".into(),
+        "20,1,100,10".into(),
+        reads,
+    ];
+    fs::create_dir_all(work.join("out")).unwrap();
+    kun_peng(&direct);
+
+    let content = fs::read_to_string(&events).unwrap();
+    let mut lines = content.lines();
+    assert_eq!(
+        lines.next(),
+        Some("#processed\ttaxid\tname\tthreshold\treads")
+    );
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split('\t').collect()).collect();
+    let crossings: Vec<(&str, &str)> = rows.iter().map(|row| (row[1], row[3])).collect();
+    assert_eq!(
+        crossings,
+        [("2697049", "1"), ("2697049", "10"), ("2697049", "20")]
+    );
+    let mut last_processed = 0;
+    for row in &rows {
+        let processed: u64 = row[0].parse().unwrap();
+        let (threshold, reads): (u64, u64) = (row[3].parse().unwrap(), row[4].parse().unwrap());
+        assert!(processed >= last_processed && processed <= 30, "{:?}", row);
+        assert!(reads >= threshold && reads <= 25, "{:?}", row);
+        last_processed = processed;
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,72 @@
+//! The batch sizing changes how the reads are handed to the threads, not the output.
+//!
+//! Builds the database of `data/` and classifies 50 reads cut from the SARS-CoV-2 genome
+//! with the default plan, in batches of 3 reads one at a time, and with a budget
+//! of 64 KiB. The Kraken outputs are the same, in input order, and the chosen plan is
+//! printed to stderr, away from a Kraken output written to stdout.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn batch_sizes_keep_the_output() {
+    let work = work_dir("batching");
+    let db = database();
+
+    let reads = cut_reads(
+        &genome("COVID_19.fa"),
+        "read",
+        (0..50).map(|i| i * 500),
+        150,
+    );
+    let reads_fa = work.join("reads.fa");
+    fs::write(&reads_fa, fasta(reads)).unwrap();
+
+    let direct = |name: &str, extra: &[&str]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.push(reads_fa.clone());
+        let run = kun_peng(&args);
+        assert!(!String::from_utf8_lossy(&run.stdout).contains("batches:"));
+        let stderr = String::from_utf8_lossy(&run.stderr).into_owned();
+        let output = fs::read_to_string(output_dir.join("output_1.txt")).unwrap();
+        (output, stderr)
+    };
+
+    let (expected, stderr) = direct("default", &[]);
+    assert_eq!(expected.lines().count(), 50);
+    assert!(
+        stderr.contains("batches: 4096 reads of 150 bases"),
+        "{}",
+        stderr
+    );
+
+    let (output, stderr) = direct("small", &["--batch-reads", "3", "--queue-len", "1"]);
+    assert_eq!(output, expected);
+    assert!(
+        stderr.contains("batches: 3 reads of 150 bases on average, 1 in flight"),
+        "{}",
+        stderr
+    );
+
+    // 64 KiB hold 109 reads of 150 bases, in batches of fewer reads than a thread gets
+    let (output, stderr) = direct("budget", &["--max-batch-memory", "64K", "-p", "4"]);
+    assert_eq!(output, expected);
+    assert!(
+        stderr.contains("batches: 13 reads of 150 bases on average, 8 in flight"),
+        "{}",
+        stderr
+    );
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,94 @@
+//! `--bin-dir` copies every read into the file of the taxon it is classified to.
+//!
+//! Builds the database of `data/` and classifies FASTQ reads of SARS-CoV-2 and HIV-1 and a
+//! random read, then checks every read against its line of the Kraken output: it is in
+//! `<taxid>.fastq`, or `unclassified.fastq`, with its header and qualities, and nowhere else.
+mod common;
+
+use common::{database, genome, kun_peng, random_bases, work_dir};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 5 reads of 150 bp of each genome and a random read, with varied qualities, as FASTQ
+/// records by read ID
+fn write_reads(reads: &Path) -> HashMap<String, String> {
+    let mut records = Vec::new();
+    for (name, file) in [("COVID_19", "COVID_19.fa"), ("HIV_1", "HIV_1.fna")] {
+        let seq = genome(file);
+        for i in 0..5 {
+            let start = 1000 + i * 1500;
+            let quality: String = (0..150).map(|j| (b'#' + (j % 40) as u8) as char).collect();
+            let id = format!("{}_{}", name, i);
+            let record = format!(
+                "@{} sample=1\n{}\n+\n{}\n",
+                id,
+                &seq[start..start + 150],
+                quality
+            );
+            records.push((id, record));
+        }
+    }
+    let random = random_bases(150, &mut 12345);
+    let quality = "I".repeat(150);
+    let record = format!("@random\n{}\n+\n{}\n", random, quality);
+    records.push(("random".to_string(), record));
+
+    let text: String = records.iter().map(|(_, record)| record.as_str()).collect();
+    fs::write(reads, text).unwrap();
+    records.into_iter().collect()
+}
+
+#[test]
+fn reads_land_in_the_file_of_their_taxon() {
+    let work = work_dir("bin_dir");
+    let db = database();
+
+    let reads = work.join("reads.fq");
+    let records = write_reads(&reads);
+    let output_dir = work.join("out");
+    let bin_dir = work.join("bins");
+    fs::create_dir_all(&output_dir).unwrap();
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        output_dir.clone(),
+        "--bin-dir".into(),
+        bin_dir.clone(),
+        reads,
+    ];
+    kun_peng(&direct);
+
+    let mut expected: HashMap<String, String> = HashMap::new();
+    let output = fs::read_to_string(output_dir.join("output_1.txt")).unwrap();
+    for line in output.lines() {
+        let columns: Vec<&str> = line.split('\t').collect();
+        let bin = match columns[0] {
+            "C" => format!("{}.fastq", columns[2]),
+            _ => "unclassified.fastq".to_string(),
+        };
+        expected
+            .entry(bin)
+            .or_default()
+            .push_str(&records[columns[1].split_whitespace().next().unwrap()]);
+    }
+    assert_eq!(output.lines().count(), records.len());
+    assert!(expected.contains_key("unclassified.fastq"), "{}", output);
+    assert!(expected.len() >= 3, "{}", output);
+
+    let mut bins: Vec<String> = fs::read_dir(&bin_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    bins.sort();
+    let mut names: Vec<&String> = expected.keys().collect();
+    names.sort();
+    assert_eq!(bins.iter().collect::<Vec<_>>(), names);
+    for (bin, content) in &expected {
+        assert_eq!(&fs::read_to_string(bin_dir.join(bin)).unwrap(), content);
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,161 @@
+//! Helpers shared by the integration tests: running `kun_peng`, the database of `data/`
+//! and reads cut from its genomes.
+//!
+//! Every file under `tests/` is its own binary and uses only some of these.
+#![allow(dead_code)]
+
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::OnceLock;
+
+/// Runs `kun_peng` with `args`, whatever its exit status
+pub fn run<P: AsRef<Path>>(args: &[P]) -> Output {
+    let args: Vec<&std::ffi::OsStr> = args.iter().map(|arg| arg.as_ref().as_os_str()).collect();
+    Command::new(env!("CARGO_BIN_EXE_kun_peng"))
+        .args(&args)
+        .output()
+        .expect("failed to run kun_peng")
+}
+
+/// Runs `kun_peng` with `args`, which must succeed
+pub fn kun_peng<P: AsRef<Path>>(args: &[P]) -> Output {
+    let output = run(args);
+    assert!(
+        output.status.success(),
+        "kun_peng {:?} failed: {}",
+        args.iter().map(|arg| arg.as_ref()).collect::<Vec<_>>(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Runs `kun_peng` with `args`, which must fail, and returns its stdout then its stderr
+pub fn kun_peng_err<P: AsRef<Path>>(args: &[P]) -> String {
+    let output = run(args);
+    assert!(
+        !output.status.success(),
+        "kun_peng {:?} succeeded",
+        args.iter().map(|arg| arg.as_ref()).collect::<Vec<_>>()
+    );
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+/// The `data/` directory with the sample genomes and taxonomy
+pub fn data_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("data")
+}
+
+/// An empty directory `kun_peng_<name>_<pid>` in the temporary directory
+pub fn work_dir(name: &str) -> PathBuf {
+    let work = std::env::temp_dir().join(format!("kun_peng_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&work);
+    fs::create_dir_all(&work).unwrap();
+    work
+}
+
+/// Builds the database of `data/` on 4 threads with `extra` arguments of `build` into `db`
+pub fn build_database(db: &Path, extra: &[&str]) {
+    let mut args: Vec<PathBuf> = vec![
+        "build".into(),
+        "--download-dir".into(),
+        data_dir(),
+        "--db".into(),
+        db.to_path_buf(),
+        "-p".into(),
+        "4".into(),
+    ];
+    args.extend(extra.iter().map(PathBuf::from));
+    kun_peng(&args);
+}
+
+/// The database of `data/`, built on first use and shared by the tests of the binary.
+///
+/// It lives under the target directory, named after the test binary, and is rebuilt by
+/// every run. Tests only read it: one that changes its database builds its own.
+pub fn database() -> &'static Path {
+    static DATABASE: OnceLock<PathBuf> = OnceLock::new();
+    DATABASE.get_or_init(|| {
+        let exe = std::env::current_exe().unwrap();
+        let name = exe.file_stem().unwrap().to_string_lossy();
+        let db = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}_db", name));
+        let _ = fs::remove_dir_all(&db);
+        build_database(&db, &[]);
+        db
+    })
+}
+
+/// The sequence of the first record of a genome of `data/`
+pub fn genome(name: &str) -> String {
+    let content = fs::read_to_string(data_dir().join(name)).unwrap();
+    content
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.starts_with('>'))
+        .collect()
+}
+
+/// `len` random bases, drawn from a linear congruential generator seeded by `state`
+pub fn random_bases(len: usize, state: &mut u64) -> String {
+    (0..len)
+        .map(|_| {
+            *state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            b"ACGT"[(*state >> 62) as usize] as char
+        })
+        .collect()
+}
+
+/// `len` bp reads of `seq` at `starts`, named `<name>_<i>`
+pub fn cut_reads(
+    seq: &str,
+    name: &str,
+    starts: impl IntoIterator<Item = usize>,
+    len: usize,
+) -> Vec<(String, String)> {
+    starts
+        .into_iter()
+        .enumerate()
+        .map(|(i, start)| {
+            (
+                format!("{}_{}", name, i),
+                seq[start..start + len].to_string(),
+            )
+        })
+        .collect()
+}
+
+/// The records as FASTA
+pub fn fasta<I: Display, S: Display>(records: impl IntoIterator<Item = (I, S)>) -> String {
+    records
+        .into_iter()
+        .map(|(id, seq)| format!(">{}\n{}\n", id, seq))
+        .collect()
+}
+
+/// The records as FASTQ, every base with quality 40
+pub fn fastq<I: Display, S: AsRef<str>>(records: impl IntoIterator<Item = (I, S)>) -> String {
+    records
+        .into_iter()
+        .map(|(id, seq)| {
+            let seq = seq.as_ref();
+            format!("@{}\n{}\n+\n{}\n", id, seq, "I".repeat(seq.len()))
+        })
+        .collect()
+}
+
+/// 10 reads of 150 bp of each of SARS-CoV-2 and HIV-1, two genomes of the database, as
+/// FASTA
+pub fn write_virus_reads(path: &Path) {
+    let records = ["COVID_19.fa", "HIV_1.fna"].iter().flat_map(|name| {
+        let starts = (0..10).map(|i| 500 + i * 800);
+        cut_reads(&genome(name), name, starts, 150)
+    });
+    fs::write(path, fasta(records)).unwrap();
+}
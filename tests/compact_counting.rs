@@ -0,0 +1,67 @@
+//! The compact distinct k-mer counters keep a fixed size, however many k-mers they see.
+//!
+//! Counts the heap of this test binary through its allocator while 200 taxa get 1000,
+//! then 10000 distinct k-mers each, with the compact counters and with the HyperLogLog++
+//! of the default build. The compact counters take the same memory at both sizes, a few
+//! KiB per taxon, the default ones grow past them.
+use hyperloglogplus::HyperLogLogPlus;
+use kun_peng::readcounts::{compact_kmers, ReadCounts};
+use kun_peng::KBuildHasher;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const TAXA: u64 = 200;
+
+/// Heap bytes taken by the counters of `TAXA` taxa after `distinct` k-mers each
+fn heap_per_taxon<T, F>(new: F, distinct: u64) -> usize
+where
+    F: Fn() -> ReadCounts<T>,
+    T: kun_peng::readcounts::Unionable,
+{
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    let mut counters: Vec<ReadCounts<T>> = (0..TAXA).map(|_| new()).collect();
+    for (taxon, counter) in counters.iter_mut().enumerate() {
+        for kmer in 0..distinct {
+            counter.add_kmer((taxon as u64 * distinct + kmer) << 20);
+        }
+    }
+    let used = ALLOCATED.load(Ordering::SeqCst) - before;
+    drop(counters);
+    used / TAXA as usize
+}
+
+#[test]
+fn compact_counters_keep_a_fixed_size() {
+    let compact = || ReadCounts::with_capacity(compact_kmers(), 0, 0);
+    let small = heap_per_taxon(compact, 1000);
+    let large = heap_per_taxon(compact, 10000);
+    assert_eq!(small, large);
+    assert!(large < 4096, "{} bytes per taxon", large);
+
+    let default = || {
+        let kmers: HyperLogLogPlus<u64, KBuildHasher> =
+            HyperLogLogPlus::new(16, KBuildHasher).unwrap();
+        ReadCounts::with_capacity(kmers, 0, 0)
+    };
+    let grown = heap_per_taxon(default, 10000);
+    assert!(grown > 4 * large, "{} against {} bytes", grown, large);
+}
@@ -0,0 +1,75 @@
+//! `compact-db` shrinks the index of a database to a target load factor.
+//!
+//! Builds the database of `data/` and compacts it. With a genome left out of its library,
+//! as one added by `add-to-db` would be, the database is left unchanged. The full library
+//! accounts for every cell of the fresh index, so the index is replaced without `--force`:
+//! it has fewer cells at the requested load factor, and reads of the genomes are
+//! classified as before.
+mod common;
+
+use common::{build_database, kun_peng, kun_peng_err, work_dir, write_virus_reads};
+use kun_peng::compact_hash::HashConfig;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The Kraken output of `reads`
+fn classify(db: &Path, reads: &Path, output_dir: &Path) -> String {
+    fs::create_dir_all(output_dir).unwrap();
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        output_dir.to_path_buf(),
+        reads.to_path_buf(),
+    ];
+    kun_peng(&direct);
+    fs::read_to_string(output_dir.join("output_1.txt")).unwrap()
+}
+
+#[test]
+fn fresh_database_compacts_without_force() {
+    // Its own database, which `compact-db` rewrites
+    let work = work_dir("compact_db");
+    let db = work.join("db");
+    build_database(&db, &[]);
+
+    let reads = work.join("reads.fa");
+    write_virus_reads(&reads);
+    let before = classify(&db, &reads, &work.join("before"));
+    let old = HashConfig::from_hash_header(db.join("hash_config.k2d")).unwrap();
+
+    let compact: Vec<PathBuf> = vec![
+        "compact-db".into(),
+        "--db".into(),
+        db.clone(),
+        "--load-factor".into(),
+        "0.9".into(),
+    ];
+
+    // Without the records of HIV-1, the library misses cells of the index
+    let library = db.join("library").join("library_0_0.fna");
+    let content = fs::read_to_string(&library).unwrap();
+    let without_hiv: String = content
+        .split('>')
+        .filter(|record| !record.is_empty() && !record.contains("NC_001802"))
+        .map(|record| format!(">{}", record))
+        .collect();
+    fs::write(&library, without_hiv).unwrap();
+    let err = kun_peng_err(&compact);
+    assert!(err.contains("not accounted for by library/"), "{}", err);
+    let unchanged = HashConfig::from_hash_header(db.join("hash_config.k2d")).unwrap();
+    assert_eq!(unchanged.capacity, old.capacity);
+    fs::write(&library, content).unwrap();
+
+    let output = kun_peng(&compact);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("smaller"), "{}", stdout);
+
+    let new = HashConfig::from_hash_header(db.join("hash_config.k2d")).unwrap();
+    assert!(new.capacity < old.capacity, "{:?} {:?}", old, new);
+    assert!(new.size as f64 <= 0.9 * new.capacity as f64 + 1.0);
+    assert_eq!(classify(&db, &reads, &work.join("after")), before);
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,151 @@
+//! `compare-output` sorts the calls of two Kraken outputs into agreement categories.
+//!
+//! Builds the database of `data/`, classifies reads of SARS-CoV-2 and HIV-1, and edits a
+//! copy of the output the way another classifier could differ: a call moved to the root,
+//! a read left unclassified, a missing read and a taxid written with its name, as
+//! `kraken2 --use-names` does. Outputs whose reads are all of one taxon have no
+//! correlation.
+mod common;
+
+use common::{database, kun_peng, work_dir, write_virus_reads};
+use std::fs;
+use std::path::PathBuf;
+
+/// The `(category, reads)` rows of the summary table
+fn summary(stdout: &str) -> Vec<(String, String)> {
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("category\treads\tpercentage"));
+    lines
+        .map(|line| {
+            let columns: Vec<&str> = line.split('\t').collect();
+            (columns[0].to_string(), columns[1].to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn edited_calls_fall_in_their_categories() {
+    let work = work_dir("compare");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_virus_reads(&reads);
+    let output_dir = work.join("out");
+    fs::create_dir_all(&output_dir).unwrap();
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        output_dir.clone(),
+        reads,
+    ];
+    kun_peng(&direct);
+
+    let first = output_dir.join("output_1.txt");
+    let lines: Vec<String> = fs::read_to_string(&first)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    let edited: Vec<usize> = (0..lines.len())
+        .filter(|&i| lines[i].starts_with("C\t") && lines[i].split('\t').nth(2) != Some("1"))
+        .take(4)
+        .collect();
+    assert_eq!(edited.len(), 4, "{:?}", lines);
+
+    let mut second = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let columns: Vec<&str> = line.split('\t').collect();
+        let rest = columns[3..].join("\t");
+        if i == edited[0] {
+            second.push_str(&format!("C\t{}\t1\t{}\n", columns[1], rest));
+        } else if i == edited[1] {
+            second.push_str(&format!("U\t{}\t0\t{}\n", columns[1], rest));
+        } else if i == edited[2] {
+            continue;
+        } else if i == edited[3] {
+            let named = format!("some virus (taxid {})", columns[2]);
+            second.push_str(&format!("C\t{}\t{}\t{}\n", columns[1], named, rest));
+        } else {
+            second.push_str(line);
+            second.push('\n');
+        }
+    }
+    let second_file = work.join("kraken2.txt");
+    fs::write(&second_file, second).unwrap();
+
+    let disagreements = work.join("disagreements.tsv");
+    let compare: Vec<PathBuf> = vec![
+        "compare-output".into(),
+        "--taxonomy".into(),
+        db.join("taxo.k2d"),
+        "--disagreements".into(),
+        disagreements.clone(),
+        first,
+        second_file,
+    ];
+    let output = kun_peng(&compare);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows = summary(&stdout);
+    let reads = |category: &str| -> String {
+        let row = rows.iter().find(|(name, _)| name == category);
+        row.unwrap_or_else(|| panic!("no {} in {}", category, stdout))
+            .1
+            .clone()
+    };
+    assert_eq!(reads("same"), (lines.len() - 3).to_string());
+    assert_eq!(reads("ancestor"), "0");
+    assert_eq!(reads("descendant"), "1");
+    assert_eq!(reads("disagree"), "0");
+    assert_eq!(reads("one-unclassified"), "1");
+    assert_eq!(reads("only_first"), "1");
+    assert_eq!(reads("only_second"), "0");
+    let correlation: f64 = reads("clade_count_correlation").parse().unwrap();
+    assert!(correlation > 0.9 && correlation <= 1.0, "{}", correlation);
+
+    let tsv = fs::read_to_string(&disagreements).unwrap();
+    let tsv: Vec<&str> = tsv.lines().collect();
+    assert_eq!(tsv[0], "read_id\tcategory\tfirst_taxid\tsecond_taxid");
+    assert_eq!(tsv.len(), 3);
+    assert!(tsv[1].ends_with("\t1") && tsv[1].contains("\tdescendant\t"));
+    assert!(tsv[2].ends_with("\t0") && tsv[2].contains("\tone-unclassified\t"));
+
+    let _ = fs::remove_dir_all(&work);
+}
+
+#[test]
+fn one_taxon_has_no_correlation() {
+    let work = work_dir("compare_one_taxon");
+    let db = database();
+
+    let output: String = (0..3)
+        .map(|i| format!("C\tread_{}\t2697049\t150\t2697049:116\n", i))
+        .collect();
+    let (first, second) = (work.join("first.txt"), work.join("second.txt"));
+    fs::write(&first, &output).unwrap();
+    fs::write(&second, &output).unwrap();
+
+    let compare: Vec<PathBuf> = vec![
+        "compare-output".into(),
+        "--taxonomy".into(),
+        db.join("taxo.k2d"),
+        first,
+        second,
+    ];
+    let output = kun_peng(&compare);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows = summary(&stdout);
+    assert!(
+        rows.contains(&("same".to_string(), "3".to_string())),
+        "{}",
+        stdout
+    );
+    assert!(
+        rows.contains(&("clade_count_correlation".to_string(), "NA".to_string())),
+        "{}",
+        stdout
+    );
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,71 @@
+//! `build`, `add-to-db` and `compact-db` lock the database directory while they write it.
+//!
+//! Builds the database of `data/`, then runs `compact-db` on it under a stale lock, left
+//! by a process that is gone, and under the lock of a running process, standing for a
+//! second builder. The stale lock is only removed with `--force-unlock`, the live one
+//! never is.
+mod common;
+
+use common::{data_dir, kun_peng, kun_peng_err, work_dir};
+use kun_peng::lock::{LockOwner, LOCK_FILE};
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn locked_databases_refuse_a_second_writer() {
+    // Its own database, which `compact-db` rewrites
+    let work = work_dir("lock");
+    let db = work.join("db");
+    let lock_file = db.join(LOCK_FILE);
+
+    let build: Vec<PathBuf> = vec![
+        "build".into(),
+        "--download-dir".into(),
+        data_dir(),
+        "--db".into(),
+        db.clone(),
+        "-p".into(),
+        "4".into(),
+    ];
+    kun_peng(&build);
+    assert!(!lock_file.exists());
+    let tmp_files: Vec<_> = fs::read_dir(&db)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().ends_with(".tmp"))
+        .collect();
+    assert!(tmp_files.is_empty(), "{:?}", tmp_files);
+
+    let compact: Vec<PathBuf> = vec!["compact-db".into(), "--db".into(), db.clone()];
+    let force: Vec<PathBuf> = compact
+        .iter()
+        .cloned()
+        .chain(["--force-unlock".into()])
+        .collect();
+
+    // A builder killed before it could remove its lock
+    let stale = LockOwner {
+        pid: i32::MAX as u32,
+        ..LockOwner::current("build")
+    };
+    stale.create(&lock_file).unwrap();
+    let err = kun_peng_err(&compact);
+    assert!(err.contains("no longer running"), "{}", err);
+    assert!(err.contains("--force-unlock"), "{}", err);
+    assert!(lock_file.exists());
+    kun_peng(&force);
+    assert!(!lock_file.exists());
+
+    // A builder still running, this test process
+    let live = LockOwner::current("build");
+    live.create(&lock_file).unwrap();
+    let err = kun_peng_err(&force);
+    assert!(err.contains("is locked by `build`"), "{}", err);
+    let err = kun_peng_err(&build);
+    assert!(err.contains("is locked by `build`"), "{}", err);
+    assert_eq!(LockOwner::read(&lock_file).unwrap(), Some(live));
+    fs::remove_file(&lock_file).unwrap();
+    kun_peng(&compact);
+
+    let _ = fs::remove_dir_all(&work);
+}
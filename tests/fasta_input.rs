@@ -0,0 +1,91 @@
+//! `classify` reads FASTA as well as FASTQ, single reads and mates alike.
+//!
+//! Builds the database of `data/` and classifies 20 contigs of 1000 bp and 10 pairs of
+//! 150 bp cut from the SARS-CoV-2 genome, written once as FASTA and once as FASTQ. The
+//! format is told from the first byte of every file, and the Kraken outputs are the same.
+mod common;
+
+use common::{database, fasta, fastq, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Writes the records as FASTA and as FASTQ
+fn write_records(records: &[(String, &str)], fasta_path: &Path, fastq_path: &Path) {
+    fs::write(fasta_path, fasta(records.iter().cloned())).unwrap();
+    fs::write(fastq_path, fastq(records.iter().cloned())).unwrap();
+}
+
+#[test]
+fn fasta_and_fastq_give_the_same_calls() {
+    let work = work_dir("fasta_input");
+    let db = database();
+
+    let seq = genome("COVID_19.fa");
+    let contigs: Vec<(String, &str)> = (0..20)
+        .map(|i| (format!("contig{}", i), &seq[i * 1000..i * 1000 + 1000]))
+        .collect();
+    let mates = |mate: usize| -> Vec<(String, &str)> {
+        (0..10)
+            .map(|i| {
+                let start = i * 2000 + (mate - 1) * 300;
+                (format!("pair{}/{}", i, mate), &seq[start..start + 150])
+            })
+            .collect()
+    };
+    let file = |name: &str| {
+        (
+            work.join(format!("{}.fa", name)),
+            work.join(format!("{}.fq", name)),
+        )
+    };
+    let (contigs_fa, contigs_fq) = file("contigs");
+    let (r1_fa, r1_fq) = file("r1");
+    let (r2_fa, r2_fq) = file("r2");
+    write_records(&contigs, &contigs_fa, &contigs_fq);
+    write_records(&mates(1), &r1_fa, &r1_fq);
+    write_records(&mates(2), &r2_fa, &r2_fq);
+
+    let classify = |name: &str, extra: &[&str], inputs: &[&PathBuf]| {
+        let chunk_dir = work.join(format!("{}_chunk", name));
+        let output_dir = work.join(name);
+        fs::create_dir_all(&chunk_dir).unwrap();
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "classify".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--chunk-dir".into(),
+            chunk_dir,
+            "--output-dir".into(),
+            output_dir.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.extend(inputs.iter().map(|&input| input.clone()));
+        kun_peng(&args);
+        let output = fs::read_to_string(output_dir.join("output_1.txt")).unwrap();
+        let mut lines: Vec<String> = output.lines().map(String::from).collect();
+        lines.sort();
+        lines
+    };
+
+    let fasta = classify("contigs_fa", &[], &[&contigs_fa]);
+    assert_eq!(fasta.len(), 20);
+    assert!(
+        fasta.iter().all(|line| line.starts_with("C\t")),
+        "{:?}",
+        fasta
+    );
+    assert!(fasta
+        .iter()
+        .all(|line| line.split('\t').nth(3) == Some("1000")));
+    assert_eq!(classify("contigs_fq", &[], &[&contigs_fq]), fasta);
+
+    let fasta = classify("pairs_fa", &["-P"], &[&r1_fa, &r2_fa]);
+    assert_eq!(fasta.len(), 10);
+    assert!(fasta
+        .iter()
+        .all(|line| line.split('\t').nth(3) == Some("150|150")));
+    assert_eq!(classify("pairs_fq", &["-P"], &[&r1_fq, &r2_fq]), fasta);
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,77 @@
+//! `--filter-hit-groups` leaves the reads with too few hit groups out of the outputs.
+//!
+//! Builds the database of `data/` and classifies reads cut from the SARS-CoV-2 genome,
+//! which have hits, and a random read, which has none. With a `-g` no read reaches, the
+//! genome reads are filtered and counted in the summary, the random read stays `U`.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, kun_peng, random_bases, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Three 150 bp reads of the genome and one random read, as FASTA
+fn write_reads(reads: &Path) {
+    let mut records = cut_reads(&genome("COVID_19.fa"), "genome", [1000, 10000, 20000], 150);
+    records.push(("random".to_string(), random_bases(150, &mut 12345)));
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+/// The classification and read ID of every line of the Kraken output
+fn calls(dir: &Path) -> Vec<(String, String)> {
+    let content = fs::read_to_string(dir.join("output_1.txt")).unwrap();
+    content
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            (fields[0].to_string(), fields[1].to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn reads_below_hit_groups_are_filtered() {
+    let work = work_dir("filter");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+
+    let run = |name: &str, extra: &[&str]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.push(reads.clone());
+        let output = kun_peng(&args);
+        (output, calls(&output_dir), output_dir)
+    };
+
+    let (_, default, _) = run("default", &[]);
+    let status: Vec<&str> = default.iter().map(|(status, _)| status.as_str()).collect();
+    assert_eq!(status, ["C", "C", "C", "U"]);
+
+    // Without the filter, the reads below -g are unclassified
+    let (_, unfiltered, _) = run("unfiltered", &["-g", "1000"]);
+    assert!(unfiltered.iter().all(|(status, _)| status == "U"));
+    assert_eq!(unfiltered.len(), 4);
+
+    let (output, filtered, output_dir) = run("filtered", &["-g", "1000", "--filter-hit-groups"]);
+    assert_eq!(filtered, [("U".to_string(), "random".to_string())]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("3 sequences filtered below 1000 hit groups"));
+    // The U row of the report only counts the random read
+    let report = fs::read_to_string(output_dir.join("output_1.kreport2")).unwrap();
+    let u_row = report
+        .lines()
+        .find(|line| line.contains("\tunclassified"))
+        .unwrap();
+    assert_eq!(u_row.split('\t').nth(1), Some("1"));
+
+    let _ = fs::remove_dir_all(&work);
+}
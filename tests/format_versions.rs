@@ -0,0 +1,106 @@
+//! Every file written with a `kun_peng::fmt` header is still read in its older versions.
+//!
+//! `tests/fixtures/formats` holds one file per format and version, `<format>_v<N>`: the
+//! hash config with its provenance trailer, a spill file of one read and a checkpoint.
+//! The versions of a format hold the same content and load to the same values, but for
+//! the layout that version 2 of the hash config adds.
+use kun_peng::checkpoint::SampleProgress;
+use kun_peng::compact_hash::{Compact, HashConfig, Row, TableLayout};
+use kun_peng::fmt::{Kind, HEADER_SIZE};
+use kun_peng::spill::{read_spill_file, SpillReader};
+use std::fs;
+use std::path::PathBuf;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/formats")
+        .join(name)
+}
+
+#[test]
+fn hash_config_versions() {
+    for (name, layout) in [
+        ("hash_config_v0.k2d", TableLayout::Linear),
+        ("hash_config_v1.k2d", TableLayout::Linear),
+        ("hash_config_v2.k2d", TableLayout::Bucketed),
+    ] {
+        let config = HashConfig::from_hash_header(fixture(name)).unwrap();
+        assert_eq!(config.layout, layout, "{}", name);
+        let fields = (
+            config.version,
+            config.partition,
+            config.hash_capacity,
+            config.capacity,
+            config.size,
+            config.value_bits,
+        );
+        assert_eq!(fields, (1, 4, 1000, 4000, 1234, 16), "{}", name);
+        assert_eq!(config.value_mask, 0xFFFF);
+    }
+    // The current version is the one written
+    let config = HashConfig::from_hash_header(fixture("hash_config_v1.k2d")).unwrap();
+    let path = std::env::temp_dir().join(format!("kun_peng_fmt_{}.k2d", std::process::id()));
+    config.write_to_file(&path).unwrap();
+    let written = fs::read(&path).unwrap();
+    let fixture_bytes = fs::read(fixture("hash_config_v1.k2d")).unwrap();
+    assert_eq!(written[..], fixture_bytes[..HEADER_SIZE + 48]);
+    // Only a bucketed table needs version 2
+    let config = HashConfig::from_hash_header(fixture("hash_config_v2.k2d")).unwrap();
+    config.write_to_file(&path).unwrap();
+    let written = fs::read(&path).unwrap();
+    let fixture_bytes = fs::read(fixture("hash_config_v2.k2d")).unwrap();
+    assert_eq!(written[..], fixture_bytes[..HEADER_SIZE + 56]);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn spill_versions() {
+    let expected = vec![
+        Row::new(u32::combined(0xabcd, 562, 16), 3, 1),
+        Row::new(5, 3, 9),
+    ];
+    for (name, version) in [("spill_v1.bin", 1), ("spill_v2.bin", 2)] {
+        let bytes = fs::read(fixture(name)).unwrap();
+        let reader = SpillReader::new(&bytes[..]).unwrap();
+        let header = reader.header();
+        assert_eq!((header.version, header.value_bits), (version, 16));
+        assert!(!header.compressed);
+        let rows = read_spill_file(fixture(name)).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[&3], expected, "{}", name);
+    }
+}
+
+#[test]
+fn checkpoint_versions() {
+    for name in ["checkpoint_v0.json", "checkpoint_v1.bin"] {
+        let progress = SampleProgress::load(fixture(name)).unwrap();
+        assert_eq!(progress.file_index, 1);
+        assert_eq!(progress.offsets, [8192, 8190]);
+        assert_eq!((progress.reads_index, progress.classified), (42, 40));
+        assert_eq!(progress.outputs, [("output_1.txt".into(), 5000)]);
+        assert_eq!(progress.read_stats.total_bases, 12600);
+        assert!(progress.taxon_counts.is_empty());
+    }
+}
+
+#[test]
+fn formats_are_not_mistaken_for_each_other() {
+    let err = HashConfig::from_hash_header(fixture("checkpoint_v1.bin")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "found a checkpoint file, expected a hash config file"
+    );
+    let bytes = fs::read(fixture("hash_config_v1.k2d")).unwrap();
+    assert!(SpillReader::new(&bytes[..]).is_err());
+    assert!(SampleProgress::load(fixture("spill_v2.bin")).is_err());
+
+    // A flipped bit of the payload, a cut file
+    let mut corrupt = fs::read(fixture("checkpoint_v1.bin")).unwrap();
+    corrupt[HEADER_SIZE + 10] ^= 0x20;
+    let err = kun_peng::fmt::read_payload(&corrupt[..], Kind::Checkpoint, 1).unwrap_err();
+    assert!(err.to_string().contains("checksum"), "{}", err);
+    let cut = &corrupt[..corrupt.len() - 5];
+    let err = kun_peng::fmt::read_payload(cut, Kind::Checkpoint, 1).unwrap_err();
+    assert!(err.to_string().contains("truncated"), "{}", err);
+}
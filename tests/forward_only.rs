@@ -0,0 +1,165 @@
+//! Strand-specific indices hold the minimizers of the forward strand only.
+//!
+//! Indexes the forward-only minimizers of a random genome and classifies reads of it from
+//! their minimizers. Reads scanned without canonicalization match the index, the same
+//! reads scanned canonically miss most of it, and the opposite strand misses it entirely.
+//! `build --forward-only` records the flag in `opts.k2d`, and `direct` refuses the index.
+mod common;
+
+use common::{build_database, kun_peng_err, work_dir, write_virus_reads};
+use kun_peng::classifier::{ClassifierOptions, Database, MinimizerRecord};
+use kun_peng::compact_hash::{CHTable, HashConfig, Page};
+use kun_peng::mmscanner::{scan_minimizers_with, ScanOptions};
+use kun_peng::taxonomy::{Taxonomy, TaxonomyNode};
+use kun_peng::IndexOptions;
+use seqkmer::{Meros, OptionPair, DEFAULT_TOGGLE_MASK};
+use std::sync::atomic::AtomicUsize;
+
+const READ_LENGTH: usize = 300;
+
+fn random_genome(len: usize) -> Vec<u8> {
+    let mut state = 0x9e3779b97f4a7c15u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            b"ACGT"[(state % 4) as usize]
+        })
+        .collect()
+}
+
+fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            _ => b'A',
+        })
+        .collect()
+}
+
+fn hashes(seq: &[u8], meros: &Meros, options: ScanOptions) -> Vec<u64> {
+    let mut minimizers = Vec::new();
+    scan_minimizers_with(seq, meros, options, &mut minimizers);
+    minimizers.into_iter().map(|(_, hash)| hash).collect()
+}
+
+/// A single-partition database of the minimizers of `genome` under taxon 562
+fn database(genome: &[u8], meros: Meros, options: ScanOptions) -> Database {
+    let hash_config = HashConfig::new(1, 1 << 14, 16, 0, 1, 1 << 14);
+    let mut page = Page::with_capacity(0, 1 << 14);
+    for hash_key in hashes(genome, &meros, options) {
+        let slot = hash_config.slot(hash_key, 1);
+        let idx = (slot.idx..)
+            .find(|&i| page.data[i] == 0 || page.data[i] == slot.value)
+            .unwrap();
+        page.data[idx] = slot.value;
+    }
+    let chtable = CHTable {
+        config: hash_config,
+        pages: vec![page],
+    };
+
+    let mut taxonomy = Taxonomy::default();
+    taxonomy.nodes.push(TaxonomyNode::default());
+    taxonomy.nodes.push(TaxonomyNode {
+        external_id: 562,
+        ..Default::default()
+    });
+    taxonomy.path_cache.insert(1, vec![1]);
+    Database::new(meros, hash_config, chtable, taxonomy)
+}
+
+#[test]
+fn forward_only_index_matches_forward_scans() {
+    let meros = Meros::new(35, 31, Some(0), Some(DEFAULT_TOGGLE_MASK), None);
+    let forward_only = ScanOptions {
+        forward_only: true,
+        ..Default::default()
+    };
+    let canonical = ScanOptions::default();
+    let genome = random_genome(5000);
+    let db = database(&genome, meros, forward_only);
+
+    // Most of the minimizers of a read have to hit, about 80 for 300 bp
+    let options = ClassifierOptions {
+        min_supporting_minimizers: 60,
+        ..Default::default()
+    };
+    let counter = AtomicUsize::new(0);
+    let classify = |id: &str, seq: &[u8], scan: ScanOptions| {
+        let record = MinimizerRecord {
+            id: id.to_string(),
+            seq_size: seq.len().to_string(),
+            minimizers: OptionPair::Single(hashes(seq, &meros, scan)),
+        };
+        db.classify_minimizers(&record, &options, &counter)
+    };
+
+    for start in (0..genome.len() - READ_LENGTH).step_by(450) {
+        let read = &genome[start..start + READ_LENGTH];
+        let id = format!("read_{}", start);
+        let matched = classify(&id, read, forward_only);
+        assert!(matched.classified, "{}", matched);
+        assert_eq!(matched.taxid, 562);
+
+        let mismatched = classify(&id, read, canonical);
+        assert!(!mismatched.classified, "{}", mismatched);
+
+        let antisense = classify(&id, &reverse_complement(read), forward_only);
+        assert!(!antisense.classified, "{}", antisense);
+    }
+}
+
+#[test]
+fn forward_only_flag_round_trips() {
+    let dir = std::env::temp_dir().join(format!("kun_peng_forward_only_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let opts_file = dir.join("opts.k2d");
+
+    let canonical = IndexOptions::new(35, 31, 0, DEFAULT_TOGGLE_MASK, true, 0);
+    let forward_only = IndexOptions {
+        forward_only: true,
+        ..canonical.clone()
+    };
+    assert_eq!(canonical.to_bytes().len(), forward_only.to_bytes().len());
+    for opts in [canonical, forward_only] {
+        opts.write_to_file(&opts_file).unwrap();
+        let reloaded = IndexOptions::read_index_options(&opts_file).unwrap();
+        assert_eq!(reloaded, opts);
+        assert_eq!(reloaded.require_canonical().is_err(), opts.forward_only);
+        assert_eq!(
+            IndexOptions::from_json(&opts.to_json().unwrap()).unwrap(),
+            opts
+        );
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn build_flag_is_recorded_and_refused_by_direct() {
+    let work = work_dir("forward_only_build");
+    let db = work.join("db");
+    build_database(&db, &["--forward-only"]);
+    let opts = IndexOptions::read_index_options(db.join("opts.k2d")).unwrap();
+    assert!(opts.forward_only);
+
+    let reads = work.join("reads.fa");
+    write_virus_reads(&reads);
+    let direct = [
+        "direct".into(),
+        "--db".into(),
+        db.clone(),
+        "--output-dir".into(),
+        work.join("output"),
+        reads,
+    ];
+    let err = kun_peng_err(&direct);
+    assert!(err.contains("forward-only minimizers"), "{}", err);
+
+    let _ = std::fs::remove_dir_all(&work);
+}
@@ -0,0 +1,231 @@
+//! Taxonomies with millions of nodes, like pan-genome ones with every assembly as a leaf.
+//!
+//! Generates the `nodes.dmp` and `names.dmp` of a taxonomy, builds the Kraken taxonomy
+//! of its leaves as `build` does, then classifies hits on the leaves with the value bits
+//! derived from the node count and renders the reports. The 5 million leaf taxonomy takes
+//! a few GB and about a minute, run it with `cargo test --release -- --ignored`.
+use kun_peng::classify::{process_hitgroup, HitGroupOptions};
+use kun_peng::compact_hash::{Compact, Row};
+use kun_peng::db::{generate_taxonomy, get_bits_for_taxid};
+use kun_peng::readcounts::{ReadCounter, TaxonCounters};
+use kun_peng::report::{report_kraken_style, report_mpa_style, sparse_report};
+use kun_peng::taxonomy::Taxonomy;
+use kun_peng::HitGroup;
+use seqkmer::OptionPair;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+
+/// First external id of the leaves, past `u32::MAX`
+const LEAF_ID: u64 = 5_000_000_000;
+/// First external id of a chain of custom taxa
+const CHAIN_ID: u64 = 2_000_000_000;
+
+/// Writes a root with `genera` genera of `species` leaves each under Bacteria and a chain
+/// of `chain` taxa under Custom, returns the external ids of the leaves and the chain tip
+fn write_taxonomy(dir: &Path, genera: u64, species: u64, chain: u64) -> Vec<u64> {
+    let (mut nodes, mut names) = (String::new(), String::new());
+    let mut add = |taxid: u64, parent: u64, rank: &str, name: &str| {
+        writeln!(nodes, "{}\t|\t{}\t|\t{}\t|\t\t|", taxid, parent, rank).unwrap();
+        writeln!(names, "{}\t|\t{}\t|\t\t|\tscientific name\t|", taxid, name).unwrap();
+    };
+    add(1, 1, "no rank", "root");
+    add(2, 1, "superkingdom", "Bacteria");
+    add(3, 1, "superkingdom", "Custom");
+    let mut leaves = Vec::with_capacity((genera * species) as usize + 1);
+    for g in 0..genera {
+        let genus = 1000 + g;
+        add(genus, 2, "genus", &format!("genus_{}", g));
+        for s in 0..species {
+            let leaf = LEAF_ID + g * species + s;
+            add(leaf, genus, "species", &format!("genus_{} sp{}", g, s));
+            leaves.push(leaf);
+        }
+    }
+    for i in 0..chain {
+        let parent = if i == 0 { 3 } else { CHAIN_ID + i - 1 };
+        add(CHAIN_ID + i, parent, "no rank", &format!("chain_{}", i));
+    }
+    if chain > 0 {
+        leaves.push(CHAIN_ID + chain - 1);
+    }
+    fs::write(dir.join("nodes.dmp"), nodes).unwrap();
+    fs::write(dir.join("names.dmp"), names).unwrap();
+    leaves
+}
+
+/// The Kraken taxonomy of `leaves`, written to and read back from `taxo.k2d`
+fn build_taxonomy(dir: &Path, leaves: &[u64]) -> Taxonomy {
+    let id_map: HashMap<String, u64> = leaves
+        .iter()
+        .map(|&taxid| (format!("seq_{}", taxid), taxid))
+        .collect();
+    let taxo_file = dir.join("taxo.k2d");
+    generate_taxonomy(&dir.to_path_buf(), &taxo_file, &id_map, None).unwrap();
+    Taxonomy::from_file(&taxo_file).unwrap()
+}
+
+/// The external taxid `process_hitgroup` calls for `hits` minimizers hitting `taxid`
+fn classify(taxonomy: &Taxonomy, taxid: u32, hits: u32) -> u64 {
+    let value_bits = get_bits_for_taxid(0, taxonomy.node_count() as f64).unwrap();
+    let value_mask = (1 << value_bits) - 1;
+    let rows = (1..=hits)
+        .map(|i| Row::new(u32::combined(1, taxid, value_bits), 0, i))
+        .collect();
+    let hits = HitGroup::new(rows, OptionPair::Single((0, hits as usize)));
+    let counter = AtomicUsize::new(0);
+    let options = HitGroupOptions {
+        minimum_hit_groups: 0,
+        ..HitGroupOptions::new(value_mask)
+    };
+    process_hitgroup(&hits, taxonomy, &counter, 0, &options).taxid
+}
+
+fn work_dir(name: &str) -> PathBuf {
+    let work = std::env::temp_dir().join(format!("kun_peng_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&work);
+    fs::create_dir_all(&work).unwrap();
+    work
+}
+
+#[test]
+#[ignore = "generates a 5 million node taxonomy"]
+fn five_million_leaves() {
+    let work = work_dir("large_taxonomy");
+    let leaves = write_taxonomy(&work, 1000, 5000, 0);
+    let taxonomy = build_taxonomy(&work, &leaves);
+    assert_eq!(taxonomy.node_count(), 5_001_003);
+    assert_eq!(get_bits_for_taxid(0, taxonomy.node_count() as f64), Ok(23));
+
+    let (first, last) = (leaves[0], *leaves.last().unwrap());
+    let (first_id, last_id) = (
+        taxonomy.get_internal_id(first),
+        taxonomy.get_internal_id(last),
+    );
+    assert!(last_id > 1 << 22);
+    assert_eq!(taxonomy.nodes[last_id as usize].external_id, last);
+    let bacteria = taxonomy.get_internal_id(2);
+    assert_eq!(taxonomy.lca(first_id, last_id), bacteria);
+    assert_eq!(classify(&taxonomy, last_id, 10), last);
+
+    let mut call_counters = TaxonCounters::new();
+    call_counters.insert(first_id as u64, ReadCounter::new(3, 0));
+    call_counters.insert(last_id as u64, ReadCounter::new(1, 0));
+
+    let kraken = work.join("report.txt");
+    report_kraken_style(
+        &kraken,
+        false,
+        false,
+        &taxonomy,
+        &call_counters,
+        4,
+        None,
+        100,
+        &[],
+    )
+    .unwrap();
+    let report = fs::read_to_string(&kraken).unwrap();
+    let last_line = report.lines().last().unwrap();
+    let fields: Vec<&str> = last_line.split('\t').collect();
+    assert_eq!(fields[..5], [" 25.00", "1", "1", "S", &last.to_string()]);
+    assert_eq!(fields[5].trim(), "genus_999 sp4999");
+
+    let mpa = work.join("report.mpa");
+    report_mpa_style(&mpa, false, &taxonomy, &call_counters).unwrap();
+    let mpa = fs::read_to_string(&mpa).unwrap();
+    assert!(mpa.contains("d__Bacteria|g__genus_999|s__genus_999 sp4999\t1"));
+
+    let rows = sparse_report(&taxonomy, &call_counters);
+    assert_eq!(rows.len(), 6);
+    assert_eq!(
+        rows.last().unwrap().3,
+        "Bacteria;genus_999;genus_999 sp4999"
+    );
+
+    let _ = fs::remove_dir_all(&work);
+}
+
+/// A chain of 10 000 custom taxa, deeper than a small call stack allows
+#[test]
+fn deep_chain() {
+    let work = work_dir("deep_taxonomy");
+    let leaves = write_taxonomy(&work, 1, 1, 10_000);
+    let tip = *leaves.last().unwrap();
+    let report_file = work.join("report.txt");
+    let mpa_file = work.join("report.mpa");
+
+    let (report, mpa) = std::thread::Builder::new()
+        .stack_size(256 * 1024)
+        .spawn(move || {
+            let taxonomy = build_taxonomy(&work, &leaves);
+            let tip_id = taxonomy.get_internal_id(tip);
+            assert_eq!(taxonomy.path_cache[&tip_id].len(), 10_002);
+            let custom = taxonomy.get_internal_id(3);
+            let leaf_id = taxonomy.get_internal_id(leaves[0]);
+            assert_eq!(taxonomy.lca(tip_id, leaf_id), 1);
+            assert!(taxonomy.is_a_ancestor_of_b(custom, tip_id));
+            assert_eq!(classify(&taxonomy, tip_id, 10), tip);
+
+            let mut call_counters = TaxonCounters::new();
+            call_counters.insert(tip_id as u64, ReadCounter::new(2, 0));
+            call_counters.insert(leaf_id as u64, ReadCounter::new(3, 0));
+            report_kraken_style(
+                &report_file,
+                false,
+                false,
+                &taxonomy,
+                &call_counters,
+                5,
+                None,
+                100,
+                &[],
+            )
+            .unwrap();
+            report_mpa_style(&mpa_file, false, &taxonomy, &call_counters).unwrap();
+            let report = fs::read_to_string(&report_file).unwrap();
+            let mpa = fs::read_to_string(&mpa_file).unwrap();
+            let _ = fs::remove_dir_all(&work);
+            (report, mpa)
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    // root, Bacteria, genus, species, Custom and the chain
+    let lines: Vec<Vec<&str>> = report
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    assert_eq!(lines.len(), 10_005);
+    let line = |name: &str| {
+        lines
+            .iter()
+            .find(|fields| fields[5].trim_start() == name)
+            .unwrap()
+    };
+    let indent = |fields: &[&str]| fields[5].len() - fields[5].trim_start().len();
+    assert_eq!(line("root")[1], "5");
+    assert_eq!(line("Bacteria")[1], "3");
+    assert_eq!(line("Custom")[1], "2");
+    // Every taxon of the chain holds the reads of the tip, deep ones are indented like
+    // the 100th level
+    for (i, depth) in [(0, 2), (97, 99), (98, 100), (9999, 10_001)] {
+        let fields = line(&format!("chain_{}", i));
+        assert_eq!(fields[1], "2");
+        assert_eq!(indent(fields), 2 * depth.min(100));
+    }
+    let tip_line = line("chain_9999");
+    assert_eq!((tip_line[2], tip_line[4]), ("2", tip.to_string().as_str()));
+    assert_eq!(
+        mpa.lines().collect::<Vec<_>>(),
+        [
+            "d__Bacteria\t3",
+            "d__Bacteria|g__genus_0\t3",
+            "d__Bacteria|g__genus_0|s__genus_0 sp0\t3",
+            "d__Custom\t2",
+        ]
+    );
+}
@@ -0,0 +1,67 @@
+//! `markers` reports the marker genes hit by each read, by marker ID.
+//!
+//! Takes three 600 bp stretches of the SARS-CoV-2 genome as a panel of markers and
+//! classifies a read joining parts of the first two, a read within the third and a
+//! random read. The first read lists both of its markers and no other.
+mod common;
+
+use common::{fasta, fastq, genome, kun_peng, random_bases, work_dir};
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn reads_list_the_markers_they_hit() {
+    let genome = genome("COVID_19.fa");
+    let work = work_dir("markers");
+
+    let markers = work.join("markers.fa");
+    let panel = [
+        ("gene_a first marker", &genome[1000..1600]),
+        ("gene_b", &genome[5000..5600]),
+        ("gene_c", &genome[9000..9600]),
+    ];
+    fs::write(&markers, fasta(panel)).unwrap();
+    let reads = work.join("reads.fq");
+    let records = [
+        (
+            "both",
+            format!("{}{}", &genome[1300..1450], &genome[5200..5350]),
+        ),
+        ("third", genome[9100..9400].to_string()),
+        ("none", random_bases(300, &mut 12345)),
+    ];
+    fs::write(&reads, fastq(records)).unwrap();
+
+    let output = work.join("markers.tsv");
+    let args: Vec<PathBuf> = vec![
+        "markers".into(),
+        "--markers".into(),
+        markers,
+        "-o".into(),
+        output.clone(),
+        reads,
+    ];
+    kun_peng(&args);
+
+    let content = fs::read_to_string(&output).unwrap();
+    let lines: Vec<(&str, &str)> = content
+        .lines()
+        .map(|line| line.split_once('\t').unwrap())
+        .collect();
+    let ids: Vec<&str> = lines.iter().map(|&(id, _)| id).collect();
+    assert_eq!(ids, ["both", "third", "none"]);
+    let hit_markers = |hits: &str| -> Vec<String> {
+        hits.split(',')
+            .map(|hit| {
+                let (name, count) = hit.split_once(':').unwrap();
+                assert!(count.parse::<u64>().unwrap() > 0, "{}", hit);
+                name.to_string()
+            })
+            .collect()
+    };
+    assert_eq!(hit_markers(lines[0].1), ["gene_a", "gene_b"]);
+    assert_eq!(hit_markers(lines[1].1), ["gene_c"]);
+    assert_eq!(lines[2].1, "-");
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,91 @@
+//! `--report-mate-calls` appends the calls of the mates of a pair classified on their own.
+//!
+//! Builds the database of `data/` and classifies a chimeric pair, a first mate of SARS-CoV-2
+//! and a second mate of influenza A H1N1. The pair gets a single combined call, the column gives the
+//! two mates their own taxa, the same as each mate classified as a single read.
+mod common;
+
+use common::{database, fasta, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 150 bp of a genome as a FASTA record
+fn write_mate(name: &str, id: &str, path: &Path) {
+    fs::write(path, fasta([(id, &genome(name)[1000..1150])])).unwrap();
+}
+
+/// The columns of the only line of a Kraken output
+fn columns(output_dir: &Path) -> Vec<String> {
+    let content = fs::read_to_string(output_dir.join("output_1.txt")).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", content);
+    lines[0].split('\t').map(String::from).collect()
+}
+
+#[test]
+fn chimeric_pair_reports_both_mate_calls() {
+    let work = work_dir("mate_calls");
+    let db = database();
+
+    let (r1, r2) = (work.join("r1.fa"), work.join("r2.fa"));
+    write_mate("COVID_19.fa", "pair/1", &r1);
+    write_mate("FluA_H1N1.fa", "pair/2", &r2);
+
+    let direct = |name: &str, extra: &[&str], inputs: &[&PathBuf]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            "--report-mate-calls".into(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.extend(inputs.iter().map(|&input| input.clone()));
+        kun_peng(&args);
+        columns(&output_dir)
+    };
+
+    // Each mate on its own is a single read, without mate calls
+    let alone1 = direct("r1", &[], &[&r1]);
+    let alone2 = direct("r2", &[], &[&r2]);
+    for alone in [&alone1, &alone2] {
+        assert_eq!(alone[0], "C", "{:?}", alone);
+        assert_eq!(alone[5], "-");
+    }
+    assert_ne!(alone1[2], alone2[2]);
+
+    let pair = direct("pair", &["-P"], &[&r1, &r2]);
+    assert_eq!(pair[0], "C", "{:?}", pair);
+    assert_eq!(pair[3], "150|150");
+    assert_eq!(pair.len(), 6, "{:?}", pair);
+    assert_eq!(pair[5], format!("{}|{}", alone1[2], alone2[2]));
+
+    // The classify pipeline reports the same calls
+    let chunk_dir = work.join("chunk");
+    let output_dir = work.join("classify");
+    fs::create_dir_all(&chunk_dir).unwrap();
+    fs::create_dir_all(&output_dir).unwrap();
+    let classify: Vec<PathBuf> = vec![
+        "classify".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--chunk-dir".into(),
+        chunk_dir,
+        "--output-dir".into(),
+        output_dir.clone(),
+        "--report-mate-calls".into(),
+        "-P".into(),
+        r1.clone(),
+        r2.clone(),
+    ];
+    kun_peng(&classify);
+    let classified = columns(&output_dir);
+    for i in [0, 2, 3, 5] {
+        assert_eq!(classified[i], pair[i], "{:?} {:?}", classified, pair);
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,91 @@
+//! `--max-read-length` truncates or skips the reads longer than a maximum length.
+//!
+//! Builds the database of `data/` and classifies a 150 bp and a 1000 bp read cut from the
+//! SARS-CoV-2 genome with a maximum of 300 bases. Truncated, the long read is classified
+//! on its first 300 bases, skipped it is left unclassified. Both count in the summary.
+mod common;
+
+use common::{database, fasta, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A 150 bp and a 1000 bp read of the genome, as FASTA
+fn write_reads(reads: &Path) {
+    let seq = genome("COVID_19.fa");
+    let records = [("short", &seq[1000..1150]), ("long", &seq[5000..6000])];
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+/// The classification, read ID and length of every line of the Kraken output
+fn calls(dir: &Path) -> Vec<(String, String, String)> {
+    let content = fs::read_to_string(dir.join("output_1.txt")).unwrap();
+    content
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            (
+                fields[0].to_string(),
+                fields[1].to_string(),
+                fields[3].to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn long_reads_are_truncated_or_skipped() {
+    let work = work_dir("max_length");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+
+    let direct = |name: &str, extra: &[&str]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let summary = output_dir.join("summary.json");
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            "--summary-output".into(),
+            summary.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.push(reads.clone());
+        kun_peng(&args);
+        let summary: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(summary).unwrap()).unwrap();
+        (calls(&output_dir), summary)
+    };
+    let call =
+        |status: &str, id: &str, len: &str| (status.to_string(), id.to_string(), len.to_string());
+
+    let (default, summary) = direct("default", &[]);
+    assert_eq!(
+        default,
+        [call("C", "short", "150"), call("C", "long", "1000")]
+    );
+    assert_eq!(summary["too_long"], 0);
+
+    let (truncated, summary) = direct("truncate", &["--max-read-length", "300"]);
+    assert_eq!(
+        truncated,
+        [call("C", "short", "150"), call("C", "long", "300")]
+    );
+    assert_eq!(summary["too_long"], 1);
+    assert_eq!(summary["classified"], 2);
+
+    let (skipped, summary) = direct(
+        "skip",
+        &["--max-read-length", "300", "--long-reads", "skip"],
+    );
+    assert_eq!(skipped, [call("C", "short", "150"), call("U", "long", "0")]);
+    assert_eq!(summary["too_long"], 1);
+    assert_eq!(summary["files"][0]["too_long"], 1);
+    assert_eq!(summary["classified"], 1);
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,65 @@
+//! A run may mix FASTQ lanes with a FASTA of contigs.
+//!
+//! Builds the database of `data/` and classifies one FASTQ and one FASTA file in a single
+//! `direct` run with a minimum quality score. The score only applies to the FASTQ file,
+//! both files get the calls of a run without it, and the summary notes their formats.
+mod common;
+
+use common::{data_dir, database, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The Kraken output lines of the first two samples
+fn output_lines(dir: &Path) -> Vec<Vec<String>> {
+    (1..=2)
+        .map(|i| {
+            let content = fs::read_to_string(dir.join(format!("output_{}.txt", i))).unwrap();
+            content.lines().map(String::from).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn fastq_and_fasta_in_one_run() {
+    let work = work_dir("mixed");
+    let db = database();
+
+    let fastq = work.join("COVID_19.fq");
+    fs::write(&fastq, common::fastq([("COVID_19", genome("COVID_19.fa"))])).unwrap();
+    let fasta = data_dir().join("HIV_1.fna");
+
+    let run = |name: &str, score: &str| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "-Q".into(),
+            score.into(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            fastq.clone(),
+            fasta.clone(),
+        ];
+        let output = kun_peng(&args);
+        (output, output_lines(&output_dir))
+    };
+
+    let (_, expected) = run("no_score", "0");
+    let (output, lines) = run("score", "20");
+    assert_eq!(lines, expected);
+    for sample in &lines {
+        assert!(!sample.is_empty());
+        assert!(sample.iter().all(|line| line.starts_with("C\t")));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stdout.contains(&format!("\tFASTQ\t{}", fastq.display())));
+    assert!(stdout.contains(&format!("\tFASTA\t{}", fasta.display())));
+    assert_eq!(stderr.matches("only masks FASTQ bases").count(), 1);
+    assert!(stderr.contains("HIV_1.fna"));
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,47 @@
+//! `direct` without input files fails instead of reading standard input.
+//!
+//! Runs `direct` with no read files, once with standard input closed and once with reads
+//! piped to it. Both runs exit with an error before the database is opened, the message
+//! says that standard input is not read and that piped reads belong in a file.
+mod common;
+
+use common::{run, work_dir};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const NO_INPUT: &str =
+    "No input files provided, direct reads FASTA/FASTQ files and not standard input.";
+
+#[test]
+fn direct_without_input_files_fails() {
+    let work = work_dir("no_input");
+    // Never opened, the missing inputs are reported first
+    let direct: Vec<PathBuf> = vec!["direct".into(), "--db".into(), work.join("db")];
+
+    let output = run(&direct);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(NO_INPUT), "{}", stderr);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kun_peng"))
+        .args(&direct)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run kun_peng");
+    // The run may be over before the reads are written
+    let _ = child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b">read\nACGTACGTACGT\n");
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(NO_INPUT), "{}", stderr);
+    assert!(stderr.contains("Reads piped in"), "{}", stderr);
+
+    let _ = std::fs::remove_dir_all(&work);
+}
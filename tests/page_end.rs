@@ -0,0 +1,50 @@
+//! Building a hash page probes up to the end of the page, never around it.
+//!
+//! Puts three keys homed on the second to last cell of a 16-cell page, and one key homed
+//! on its first cell. Two of the three fill the last two cells, the third is dropped
+//! rather than wrapped around to the start of the page, where no lookup would find it.
+//! The key homed on the first cell keeps that cell whatever the order of the insertions.
+mod common;
+
+use common::work_dir;
+use kun_peng::compact_hash::{Compact, HashConfig, Page, Slot};
+use kun_peng::db::process_k2file;
+use kun_peng::taxonomy::Taxonomy;
+use std::fs;
+
+#[test]
+fn keys_past_the_page_end_are_dropped() {
+    let work = work_dir("page_end");
+    let config = HashConfig::new(1, 16, 16, 0, 1, 16);
+    let chunk_file = work.join("chunk_1.k2");
+    let slots = [(14, 0xA), (14, 0xB), (14, 0xC), (0, 0xD)]
+        .map(|(idx, key)| Slot::new(idx, u32::combined(key, 3, 16)));
+    let slot_size = std::mem::size_of::<Slot<u32>>();
+    let bytes: Vec<u8> = slots
+        .iter()
+        .flat_map(|slot| slot.as_slice(slot_size).to_vec())
+        .collect();
+    fs::write(&chunk_file, bytes).unwrap();
+
+    let taxonomy = Taxonomy::default();
+    let counts = process_k2file(config, &work, &chunk_file, &taxonomy, 16, 1, None).unwrap();
+    assert_eq!(counts, (3, 0));
+    let data = fs::read(work.join("hash_1.k2d")).unwrap();
+    let cells: Vec<u32> = data[16..]
+        .chunks(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    assert_eq!(cells.len(), 16);
+    assert_eq!(cells[0], u32::combined(0xD, 3, 16));
+    assert!(cells[1..14].iter().all(|&cell| cell == 0), "{:?}", cells);
+
+    // Which of the three keys is dropped depends on the order of the insertions
+    let page = Page::new(0, 16, cells);
+    let found: Vec<u32> = [0xA, 0xB, 0xC]
+        .into_iter()
+        .filter(|&key| page.find_index(14, key, 16, 0xFFFF) == 3)
+        .collect();
+    assert_eq!(found.len(), 2, "{:?}", page.data);
+
+    let _ = fs::remove_dir_all(&work);
+}
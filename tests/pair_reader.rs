@@ -0,0 +1,91 @@
+//! The mates of a pair are read one file at a time, and a pair without its mate is an error.
+//!
+//! Builds the database of `data/` and classifies 10 pairs cut from the SARS-CoV-2 genome,
+//! with R1 and R2 plain, then with one of them gzipped. The outputs are the same. An R2
+//! holding 7 reads, or ending inside its last record with `--verify-counts`, fails the run
+//! with the index of the first pair that lost its mate.
+mod common;
+
+use common::{database, fastq, genome, kun_peng, kun_peng_err, work_dir};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The FASTQ records of 10 pairs, 150 bp mates 300 bp apart on the genome
+fn mates() -> (Vec<String>, Vec<String>) {
+    let seq = genome("COVID_19.fa");
+    let record = |id: usize, mate: usize, start: usize| {
+        fastq([(format!("pair{}/{}", id, mate), &seq[start..start + 150])])
+    };
+    (0..10)
+        .map(|i| (record(i, 1, 1000 + i * 2000), record(i, 2, 1300 + i * 2000)))
+        .unzip()
+}
+
+fn gzip(path: &Path, content: &str) {
+    let mut encoder = GzEncoder::new(fs::File::create(path).unwrap(), Compression::default());
+    encoder.write_all(content.as_bytes()).unwrap();
+    encoder.finish().unwrap();
+}
+
+#[test]
+fn mixed_compression_and_truncated_mates() {
+    let work = work_dir("pair_reader");
+    let db = database();
+
+    let (r1_reads, r2_reads) = mates();
+    let (r1, r2) = (r1_reads.concat(), r2_reads.concat());
+    let (r1_fq, r2_fq) = (work.join("r1.fq"), work.join("r2.fq"));
+    fs::write(&r1_fq, &r1).unwrap();
+    fs::write(&r2_fq, &r2).unwrap();
+    let (r1_gz, r2_gz) = (work.join("r1.fq.gz"), work.join("r2.fq.gz"));
+    gzip(&r1_gz, &r1);
+    gzip(&r2_gz, &r2);
+
+    let direct = |name: &str, inputs: [&PathBuf; 2]| -> Vec<PathBuf> {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir,
+            "--verify-counts".into(),
+            "-P".into(),
+        ];
+        args.extend(inputs.map(PathBuf::clone));
+        args
+    };
+    let output = |name: &str| fs::read_to_string(work.join(name).join("output_1.txt")).unwrap();
+
+    kun_peng(&direct("plain", [&r1_fq, &r2_fq]));
+    let plain = output("plain");
+    assert_eq!(plain.lines().count(), 10);
+    assert!(
+        plain.lines().all(|line| line.starts_with("C\t")),
+        "{}",
+        plain
+    );
+    kun_peng(&direct("gzip_r2", [&r1_fq, &r2_gz]));
+    assert_eq!(output("gzip_r2"), plain);
+    kun_peng(&direct("gzip_r1", [&r1_gz, &r2_fq]));
+    assert_eq!(output("gzip_r1"), plain);
+
+    // R2 lost its last 3 reads, pair 8 is the first without a mate
+    let short_r2 = work.join("short_r2.fq.gz");
+    gzip(&short_r2, &r2_reads[..7].concat());
+    let err = kun_peng_err(&direct("short", [&r1_fq, &short_r2]));
+    assert!(err.contains("short_r2.fq.gz ends before read 8"), "{}", err);
+
+    // R2 cut after the sequence of its last record, without its quality
+    let cut_r2 = work.join("cut_r2.fq");
+    fs::write(&cut_r2, &r2[..r2.len() - "+\n\n".len() - 150]).unwrap();
+    let err = kun_peng_err(&direct("cut", [&r1_fq, &cut_r2]));
+    assert!(err.contains("cut_r2.fq ends"), "{}", err);
+    assert!(err.contains("read 10"), "{}", err);
+
+    let _ = fs::remove_dir_all(&work);
+}
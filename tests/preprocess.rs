@@ -0,0 +1,107 @@
+//! The per-read steps run together, in order, on every read and mate.
+//!
+//! Builds the database of `data/` and classifies reads cut from the SARS-CoV-2 genome, then
+//! the same reads with spaces inside, a few extra bases at both ends, an IUPAC code in place
+//! of a base, and a long read above the maximum length. With `--skip-whitespace`, the
+//! trimming, `--iupac first-base` and `--max-read-length` all enabled, the noisy reads get
+//! the calls and lengths of the clean ones.
+mod common;
+
+use common::{database, fasta, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `seq` with 3 extra bases at both ends, an `R` (A or G) in place of its 40th base when it
+/// is an A, and a space every 50 bases
+fn noisy(seq: &str) -> String {
+    let mut bases: Vec<char> = seq.chars().collect();
+    if bases[40] == 'A' {
+        bases[40] = 'R';
+    }
+    let mut read = String::from("TTT");
+    for (i, base) in bases.into_iter().enumerate() {
+        if i > 0 && i % 50 == 0 {
+            read.push(' ');
+        }
+        read.push(base);
+    }
+    read.push_str("GGG");
+    read
+}
+
+/// The classification, read ID, taxid and length of every line of the Kraken output
+fn calls(dir: &Path) -> Vec<(String, String, String, String)> {
+    let content = fs::read_to_string(dir.join("output_1.txt")).unwrap();
+    content
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            (
+                fields[0].to_string(),
+                fields[1].to_string(),
+                fields[2].to_string(),
+                fields[3].to_string(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn noisy_reads_are_prepared_like_clean_ones() {
+    let work = work_dir("preprocess");
+    let db = database();
+
+    let seq = genome("COVID_19.fa");
+    let (short, long) = (&seq[1000..1150], &seq[5000..6000]);
+    let clean = work.join("clean.fa");
+    fs::write(&clean, fasta([("short", short), ("long", &long[..300])])).unwrap();
+    let dirty = work.join("noisy.fa");
+    let records = [("short", noisy(short)), ("long", format!("TTT{}GGG", long))];
+    fs::write(&dirty, fasta(records)).unwrap();
+
+    let direct = |name: &str, extra: &[&str], reads: &Path| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let summary = output_dir.join("summary.json");
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            "--summary-output".into(),
+            summary.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.push(reads.to_path_buf());
+        kun_peng(&args);
+        let summary: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(summary).unwrap()).unwrap();
+        (calls(&output_dir), summary)
+    };
+
+    let (expected, summary) = direct("clean", &[], &clean);
+    assert!(expected.iter().all(|call| call.0 == "C"), "{:?}", expected);
+    assert_eq!(summary["too_long"], 0);
+
+    let steps = [
+        "--skip-whitespace",
+        "--trim-head",
+        "3",
+        "--trim-tail",
+        "3",
+        "--iupac",
+        "first-base",
+        "--max-read-length",
+        "300",
+    ];
+    let (prepared, summary) = direct("noisy", &steps, &dirty);
+    assert_eq!(prepared, expected);
+    assert_eq!(summary["too_long"], 1);
+
+    // Without the steps the noisy reads keep their length, spaces and extra bases included
+    let (raw, _) = direct("raw", &[], &dirty);
+    assert_eq!(raw[1].3, "1006");
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,98 @@
+//! `--report-read-coverage` and `--min-read-coverage` tell spread hits from clustered ones.
+//!
+//! Builds the database of `data/` and classifies a read cut from the SARS-CoV-2 genome,
+//! hit along its whole length, and a read with 60 bp of the genome followed by random
+//! sequence, hit in its first segments only. Both are classified, only the first one
+//! passes a minimum coverage of 0.5.
+mod common;
+
+use common::{database, fasta, genome, kun_peng, random_bases, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A 300 bp read of the genome and a read of 60 bp of the genome and 240 random bases
+fn write_reads(reads: &Path) {
+    let seq = genome("COVID_19.fa");
+    let clustered = format!("{}{}", &seq[10000..10060], random_bases(240, &mut 12345));
+    let records = [("spread", &seq[5000..5300]), ("clustered", &clustered)];
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+/// The classification, read ID and last column of every line of a Kraken output
+fn calls(output: &Path) -> Vec<(String, String, f64)> {
+    let content = fs::read_to_string(output).unwrap();
+    content
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let coverage = fields.last().unwrap().parse().unwrap();
+            (fields[0].to_string(), fields[1].to_string(), coverage)
+        })
+        .collect()
+}
+
+#[test]
+fn clustered_hits_have_a_low_read_coverage() {
+    let work = work_dir("coverage");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+
+    let direct = |name: &str, extra: &[&str]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            "--report-read-coverage".into(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.push(reads.clone());
+        kun_peng(&args);
+        calls(&output_dir.join("output_1.txt"))
+    };
+
+    let default = direct("default", &[]);
+    assert_eq!(default.len(), 2);
+    let (spread, clustered) = (&default[0], &default[1]);
+    assert_eq!((spread.0.as_str(), spread.1.as_str()), ("C", "spread"));
+    assert_eq!(
+        (clustered.0.as_str(), clustered.1.as_str()),
+        ("C", "clustered")
+    );
+    assert!(spread.2 >= 0.9, "spread coverage {}", spread.2);
+    assert!(
+        clustered.2 > 0.0 && clustered.2 <= 0.3,
+        "clustered coverage {}",
+        clustered.2
+    );
+
+    let gated = direct("gated", &["--min-read-coverage", "0.5"]);
+    let status: Vec<(&str, f64)> = gated.iter().map(|c| (c.0.as_str(), c.2)).collect();
+    assert_eq!(status, [("C", spread.2), ("U", 0.0)]);
+
+    // The classify pipeline reports the same coverage
+    let chunk_dir = work.join("chunk");
+    fs::create_dir_all(&chunk_dir).unwrap();
+    let classify: Vec<PathBuf> = vec![
+        "classify".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--chunk-dir".into(),
+        chunk_dir,
+        "--output-dir".into(),
+        work.join("classify"),
+        "--report-read-coverage".into(),
+        reads.clone(),
+    ];
+    kun_peng(&classify);
+    let mut resolved = calls(&work.join("classify").join("output_1.txt"));
+    resolved.sort_by(|a, b| b.1.cmp(&a.1));
+    assert_eq!(resolved, default);
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,69 @@
+//! Read IDs keep everything but the `/1` `/2` mate markers.
+//!
+//! Writes paired FASTQ files with SRA spot names, which differ only by their numeric
+//! suffix, and with Illumina names, whose mate is in the comment. Every read keeps its
+//! own ID and both mates of a pair get the same one.
+use kun_peng::reader::{for_each_record, header_mate};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Builds the headers of the reads of one mate
+type HeaderStyle<'a> = &'a dyn Fn(u8) -> Vec<String>;
+
+/// Writes the reads of `headers` to `path`
+fn write_fastq(path: &Path, headers: &[String]) {
+    let records: String = headers
+        .iter()
+        .map(|header| format!("@{}\nACGTACGT\n+\nIIIIIIII\n", header))
+        .collect();
+    fs::write(path, records).unwrap();
+}
+
+/// The IDs of the reads of `path`
+fn ids(path: &Path) -> Vec<String> {
+    let mut ids = Vec::new();
+    for_each_record(path, usize::MAX, |id, _| ids.push(id.to_string())).unwrap();
+    ids
+}
+
+#[test]
+fn mates_pair_without_collapsing() {
+    let dir = std::env::temp_dir().join(format!("kun_peng_record_ids_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    let spots = 1000;
+    let sra = |mate: u8| -> Vec<String> {
+        (1..=spots)
+            .map(|spot| format!("SRR1.{}/{} {} length=8", spot, mate, spot))
+            .collect()
+    };
+    let illumina = |mate: u8| -> Vec<String> {
+        (1..=spots)
+            .map(|spot| {
+                format!(
+                    "M00123:8:000000000-A1B2C:1:1101:{}:1331 {}:N:0:ATCACG",
+                    spot, mate
+                )
+            })
+            .collect()
+    };
+    let styles: [(&str, HeaderStyle); 2] = [("sra", &sra), ("illumina", &illumina)];
+    for (style, headers) in styles {
+        let r1 = dir.join(format!("{}_R1.fq", style));
+        let r2 = dir.join(format!("{}_R2.fq", style));
+        write_fastq(&r1, &headers(1));
+        write_fastq(&r2, &headers(2));
+        assert_eq!(header_mate(&format!("@{}", headers(2)[0])), Some(2));
+
+        let (ids1, ids2) = (ids(&r1), ids(&r2));
+        assert_eq!(ids1.len(), spots, "{}", style);
+        let unique: HashSet<&String> = ids1.iter().collect();
+        assert_eq!(unique.len(), spots, "{} reads share IDs", style);
+        assert_eq!(ids1, ids2, "{} mates get different IDs", style);
+    }
+    assert_eq!(ids(&dir.join("sra_R1.fq"))[..2], ["SRR1.1", "SRR1.2"]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,89 @@
+//! `--report-rank` collapses the report to the taxa of one rank.
+//!
+//! Builds the database of `data/` and classifies reads of three viruses and random reads.
+//! At every rank the rows, the unassigned one included, sum to the classified reads; at
+//! the genus each virus has the row of its genus.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, kun_peng, random_bases, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 5 reads of 150 bp of each genome and 3 random ones, as FASTA
+fn write_reads(reads: &Path) {
+    let mut records = Vec::new();
+    for name in ["COVID_19.fa", "FluA_H1N1.fa", "HIV_1.fna"] {
+        let starts = (0..5).map(|i| 100 + i * 300);
+        records.extend(cut_reads(&genome(name), name, starts, 150));
+    }
+    let mut state = 12345u64;
+    for i in 0..3 {
+        records.push((format!("random_{}", i), random_bases(150, &mut state)));
+    }
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+/// The `(name, reads)` rows of a rank report
+fn rows(path: &Path) -> Vec<(String, u64)> {
+    let content = fs::read_to_string(path).unwrap();
+    let mut lines = content.lines();
+    assert_eq!(lines.next(), Some("taxid\tname\tpercentage\treads"));
+    lines
+        .map(|line| {
+            let columns: Vec<&str> = line.split('\t').collect();
+            (columns[1].to_string(), columns[3].parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn collapsed_counts_sum_to_the_classified_reads() {
+    let work = work_dir("rank");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+
+    for (rank, name) in [("G", "genus"), ("family", "family"), ("phylum", "phylum")] {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let direct: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            "--report-rank".into(),
+            rank.into(),
+            reads.clone(),
+        ];
+        kun_peng(&direct);
+
+        let output = fs::read_to_string(output_dir.join("output_1.txt")).unwrap();
+        let classified = output
+            .lines()
+            .filter(|line| line.starts_with("C\t"))
+            .count() as u64;
+        assert!(classified >= 12, "{} reads classified", classified);
+        for report in [
+            format!("output_1.{}.tsv", name),
+            format!("output.{}.tsv", name),
+        ] {
+            let rows = rows(&output_dir.join(&report));
+            let total: u64 = rows.iter().map(|(_, reads)| reads).sum();
+            assert_eq!(total, classified, "{}", report);
+            let unassigned = format!("unassigned at {}", name);
+            assert!(rows.iter().all(|(_, reads)| *reads > 0));
+            assert!(rows[..rows.len() - 1]
+                .iter()
+                .all(|(row, _)| *row != unassigned));
+            if name == "genus" {
+                for genus in ["Betacoronavirus", "Alphainfluenzavirus", "Lentivirus"] {
+                    assert!(rows.iter().any(|(row, _)| row == genus), "{:?}", rows);
+                }
+            }
+        }
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
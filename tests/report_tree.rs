@@ -0,0 +1,94 @@
+//! `--report-tree-json` writes the taxa with reads as a nested tree.
+//!
+//! Builds the database of `data/` and classifies reads of SARS-CoV-2 and HIV-1. Every node
+//! of the tree holds its own reads plus the ones of its children, the root all the
+//! classified reads, and both viruses sit below their genus.
+mod common;
+
+use common::{database, kun_peng, work_dir, write_virus_reads};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+
+/// Checks the counts of a node and its descendants, returns the names on the path to `name`
+fn check(node: &Value, name: &str) -> Option<Vec<String>> {
+    for key in ["taxid", "name", "rank", "count", "clade_count", "children"] {
+        assert!(node.get(key).is_some(), "no {} in {}", key, node);
+    }
+    let children = node["children"].as_array().unwrap();
+    let below: u64 = children
+        .iter()
+        .map(|child| child["clade_count"].as_u64().unwrap())
+        .sum();
+    let clade_count = node["clade_count"].as_u64().unwrap();
+    assert_eq!(
+        clade_count,
+        node["count"].as_u64().unwrap() + below,
+        "{}",
+        node["name"]
+    );
+    assert!(clade_count > 0);
+
+    let mut path = None;
+    for child in children {
+        if let Some(found) = check(child, name) {
+            path = Some(found);
+        }
+    }
+    let node_name = node["name"].as_str().unwrap().to_string();
+    match path {
+        Some(mut path) => {
+            path.insert(0, node_name);
+            Some(path)
+        }
+        None => (node_name == name).then(|| vec![node_name]),
+    }
+}
+
+#[test]
+fn clade_counts_add_up_along_the_tree() {
+    let work = work_dir("tree");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_virus_reads(&reads);
+    let output_dir = work.join("out");
+    fs::create_dir_all(&output_dir).unwrap();
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        output_dir.clone(),
+        "--report-tree-json".into(),
+        reads,
+    ];
+    kun_peng(&direct);
+
+    let output = fs::read_to_string(output_dir.join("output_1.txt")).unwrap();
+    let classified = output
+        .lines()
+        .filter(|line| line.starts_with("C\t"))
+        .count() as u64;
+    let content = fs::read_to_string(output_dir.join("output_1.tree.json")).unwrap();
+    let tree: Value = serde_json::from_str(&content).unwrap();
+    assert_eq!(tree["taxid"], 1);
+    assert_eq!(tree["clade_count"].as_u64(), Some(classified));
+
+    for (virus, genus) in [
+        (
+            "Severe acute respiratory syndrome-related coronavirus",
+            "Betacoronavirus",
+        ),
+        ("Human immunodeficiency virus 1", "Lentivirus"),
+    ] {
+        let path = check(&tree, virus).unwrap_or_else(|| panic!("no {} in {}", virus, tree));
+        assert!(path.iter().any(|name| name == genus), "{:?}", path);
+    }
+    assert_eq!(
+        fs::read_to_string(output_dir.join("output.tree.json")).unwrap(),
+        content
+    );
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,105 @@
+//! `--score-mode distinct` counts a minimizer once per read, however often it repeats.
+//!
+//! Builds the database of `data/` and classifies a read of three tandem copies of 150 bp of
+//! the SARS-CoV-2 genome, and a read of 450 bp without repeats. Counting positions, every
+//! copy supports the call and the repeat passes a confidence threshold of 0.5. Counting
+//! distinct minimizers, the copies support it once, about a third of the read. The read
+//! without repeats is called the same way in both modes.
+mod common;
+
+use common::{database, fasta, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The tandem repeat and the read without repeats, as FASTA
+fn write_reads(reads: &Path) {
+    let seq = genome("COVID_19.fa");
+    let records = [
+        ("repeat", seq[2000..2150].repeat(3)),
+        ("unique", seq[10000..10450].to_string()),
+    ];
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+/// The Kraken output lines, by read id
+fn calls(dir: &Path) -> Vec<(String, String)> {
+    let content = fs::read_to_string(dir.join("output_1.txt")).unwrap();
+    let mut calls: Vec<(String, String)> = content
+        .lines()
+        .map(|line| {
+            let id = line.split('\t').nth(1).unwrap().to_string();
+            (id, line.to_string())
+        })
+        .collect();
+    calls.sort();
+    calls
+}
+
+/// Whether the read `id` is classified
+fn classified(calls: &[(String, String)], id: &str) -> bool {
+    let (_, line) = calls.iter().find(|(read, _)| read == id).unwrap();
+    line.starts_with("C\t")
+}
+
+#[test]
+fn distinct_minimizers_discount_tandem_repeats() {
+    let work = work_dir("score");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+
+    let direct = |name: &str, extra: &[&str]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.push(reads.clone());
+        kun_peng(&args);
+        calls(&output_dir)
+    };
+
+    // Without a threshold, the mode only changes the scores
+    let positions = direct("positions", &[]);
+    let distinct = direct("distinct", &["--score-mode", "distinct"]);
+    assert!(classified(&positions, "repeat") && classified(&distinct, "repeat"));
+    assert_eq!(positions, direct("default", &["--score-mode", "positions"]));
+    let unique = |calls: &[(String, String)]| calls.iter().find(|(id, _)| id == "unique").cloned();
+    assert_eq!(unique(&positions), unique(&distinct));
+
+    let positions = direct("positions_t", &["-T", "0.5"]);
+    let distinct = direct("distinct_t", &["-T", "0.5", "--score-mode", "distinct"]);
+    assert!(classified(&positions, "repeat"));
+    assert!(!classified(&distinct, "repeat"));
+    assert!(classified(&positions, "unique") && classified(&distinct, "unique"));
+
+    // The classify pipeline scores the same way
+    let chunk_dir = work.join("chunk");
+    fs::create_dir_all(&chunk_dir).unwrap();
+    let classify: Vec<PathBuf> = vec![
+        "classify".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--chunk-dir".into(),
+        chunk_dir,
+        "--output-dir".into(),
+        work.join("classify"),
+        "-T".into(),
+        "0.5".into(),
+        "--score-mode".into(),
+        "distinct".into(),
+        reads.clone(),
+    ];
+    kun_peng(&classify);
+    let pipeline = calls(&work.join("classify"));
+    assert!(!classified(&pipeline, "repeat"));
+    assert!(classified(&pipeline, "unique"));
+
+    let _ = fs::remove_dir_all(&work);
+}
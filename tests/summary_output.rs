@@ -0,0 +1,95 @@
+//! `--summary-output` writes the counts of the printed summary as JSON.
+//!
+//! Builds the database of `data/` and classifies reads cut from the SARS-CoV-2 genome with
+//! `direct` and `classify`, then parses the summaries as a pipeline would.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, kun_peng, work_dir};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Four 150 bp reads of the genome and one read of Ns, as FASTA
+fn write_reads(reads: &Path) {
+    let starts = [1000, 5000, 10000, 20000];
+    let mut records = cut_reads(&genome("COVID_19.fa"), "genome", starts, 150);
+    records.push(("masked".to_string(), "N".repeat(150)));
+    fs::write(reads, fasta(records)).unwrap();
+}
+
+fn read_summary(path: &Path) -> Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+fn stages(summary: &Value) -> Vec<&str> {
+    summary["stages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|stage| stage["stage"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn summary_output_matches_the_counts() {
+    let work = work_dir("summary");
+    let db = database();
+
+    let reads = work.join("reads.fa");
+    write_reads(&reads);
+
+    let direct_summary = work.join("direct.json");
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        work.join("direct"),
+        "--summary-output".into(),
+        direct_summary.clone(),
+        reads.clone(),
+    ];
+    fs::create_dir_all(work.join("direct")).unwrap();
+    kun_peng(&direct);
+
+    let summary = read_summary(&direct_summary);
+    assert_eq!(summary["version"], 1);
+    assert_eq!(summary["command"], "direct");
+    assert_eq!(summary["reads"], 5);
+    assert_eq!(summary["classified"], 4);
+    assert_eq!(summary["unclassified"], 1);
+    assert_eq!(summary["too_short"], 1);
+    assert_eq!(summary["filtered"], 0);
+    assert_eq!(summary["files"].as_array().unwrap().len(), 1);
+    assert_eq!(summary["files"][0]["format"], "FASTA");
+    assert_eq!(summary["files"][0]["reads"], 5);
+    assert_eq!(stages(&summary), ["load database", "classify"]);
+    assert!(summary["wall_time_seconds"].as_f64().unwrap() > 0.0);
+    assert!(summary["warnings"].is_array());
+
+    let classify_summary = work.join("classify.json");
+    let chunk_dir = work.join("chunk");
+    fs::create_dir_all(&chunk_dir).unwrap();
+    let classify: Vec<PathBuf> = vec![
+        "classify".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--chunk-dir".into(),
+        chunk_dir,
+        "--output-dir".into(),
+        work.join("classify"),
+        "--summary-output".into(),
+        classify_summary.clone(),
+        reads.clone(),
+    ];
+    kun_peng(&classify);
+
+    let summary = read_summary(&classify_summary);
+    assert_eq!(summary["command"], "classify");
+    assert_eq!(summary["reads"], 5);
+    assert_eq!(summary["classified"], 4);
+    assert_eq!(summary["unclassified"], 1);
+    assert_eq!(stages(&summary), ["splitr", "annotate", "resolve"]);
+
+    let _ = fs::remove_dir_all(&work);
+}
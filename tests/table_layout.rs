@@ -0,0 +1,113 @@
+//! The bucketed layout of the hash table finds the same taxa as the linear one.
+//!
+//! Fills a table of each layout with the same keys at load factors 0.7 and 0.9 and looks
+//! up the stored keys and absent ones. The keys have distinct fingerprints, the bits a
+//! cell keeps of them, so no probe can stop at the cell of another key. Then builds the
+//! database of `data/` with both layouts and classifies the same reads with each.
+mod common;
+
+use common::{build_database, cut_reads, fasta, genome, kun_peng, work_dir};
+use kun_peng::compact_hash::{CHTable, HashConfig, Page, TableLayout};
+use std::fs;
+use std::path::PathBuf;
+
+const CAPACITY: usize = 1 << 16;
+const VALUE_BITS: usize = 16;
+
+/// Hash keys whose fingerprints, the bits above `32 + VALUE_BITS`, are all different
+fn keys(count: usize) -> Vec<u64> {
+    let mut state = 0x9e3779b97f4a7c15u64;
+    (0..count as u64)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let fingerprint = i.wrapping_mul(40503) & 0xffff;
+            fingerprint << 48 | state >> 16
+        })
+        .collect()
+}
+
+fn table(layout: TableLayout, entries: &[(u64, u32)]) -> CHTable {
+    let config = HashConfig {
+        layout,
+        ..HashConfig::new(1, CAPACITY, VALUE_BITS, entries.len(), 1, CAPACITY)
+    };
+    let mut page = Page::with_capacity(0, CAPACITY);
+    for &(hash_key, taxid) in entries {
+        let slot = config.slot(hash_key, taxid);
+        let idx = (slot.idx..).find(|&i| page.data[i] == 0).unwrap();
+        page.data[idx] = slot.value;
+    }
+    page.size = page.data.len();
+    CHTable {
+        config,
+        pages: vec![page],
+    }
+}
+
+#[test]
+fn layouts_find_the_same_taxa() {
+    for load_factor in [0.7, 0.9] {
+        let stored = (CAPACITY as f64 * load_factor) as usize;
+        let keys = keys(1 << 16);
+        let entries: Vec<(u64, u32)> = keys[..stored]
+            .iter()
+            .enumerate()
+            .map(|(i, &hash_key)| (hash_key, i as u32 % 60000 + 1))
+            .collect();
+        let linear = table(TableLayout::Linear, &entries);
+        let bucketed = table(TableLayout::Bucketed, &entries);
+
+        for &(hash_key, taxid) in &entries {
+            assert_eq!(linear.get(hash_key), Some(taxid));
+            assert_eq!(bucketed.get(hash_key), Some(taxid));
+            assert!(bucketed.contains(hash_key));
+        }
+        for &hash_key in &keys[stored..] {
+            assert_eq!(linear.get(hash_key), None);
+            assert_eq!(bucketed.get(hash_key), None);
+            assert!(!bucketed.contains(hash_key));
+        }
+    }
+}
+
+#[test]
+fn bucketed_database_classifies_like_a_linear_one() {
+    let work = work_dir("table_layout");
+
+    let reads = work.join("reads.fa");
+    let records = cut_reads(
+        &genome("COVID_19.fa"),
+        "read",
+        (0..20).map(|i| i * 1000),
+        150,
+    );
+    fs::write(&reads, fasta(records)).unwrap();
+
+    let mut outputs = Vec::new();
+    for layout in ["linear", "bucketed"] {
+        let db = work.join(layout);
+        build_database(&db, &["--table-layout", layout]);
+        let config = HashConfig::from_hash_header(db.join("hash_config.k2d")).unwrap();
+        assert_eq!(format!("{:?}", config.layout).to_lowercase(), layout);
+
+        let output_dir = work.join(format!("out_{}", layout));
+        fs::create_dir_all(&output_dir).unwrap();
+        let direct: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db,
+            "--output-dir".into(),
+            output_dir.clone(),
+            reads.clone(),
+        ];
+        kun_peng(&direct);
+        outputs.push(fs::read_to_string(output_dir.join("output_1.txt")).unwrap());
+    }
+    let classified = outputs[0].lines().filter(|l| l.starts_with("C\t")).count();
+    assert!(classified >= 15, "{}", outputs[0]);
+    assert_eq!(outputs[0], outputs[1]);
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,74 @@
+//! `--tag-source` appends the input file of every read to its output line.
+//!
+//! Builds the database of `data/` and classifies 10 SARS-CoV-2 reads and 10 HIV-1 reads,
+//! in two files merged into one output with `--merge-parts`. Every line ends with the file
+//! of its read and is otherwise the line of a run without the tag. A pair is tagged with
+//! both of its files.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, kun_peng, work_dir};
+use std::fs;
+use std::path::PathBuf;
+
+#[test]
+fn merged_files_tag_their_reads() {
+    let work = work_dir("tag_source");
+    let db = database();
+
+    let (covid, hiv) = (work.join("covid.fa"), work.join("hiv.fa"));
+    let starts = (0..10).map(|i| i * 1000);
+    let covid_reads = cut_reads(&genome("COVID_19.fa"), "covid", starts.clone(), 150);
+    fs::write(&covid, fasta(covid_reads)).unwrap();
+    fs::write(
+        &hiv,
+        fasta(cut_reads(&genome("HIV_1.fna"), "hiv", starts, 150)),
+    )
+    .unwrap();
+
+    let direct = |name: &str, extra: &[&str], inputs: &[&PathBuf]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.extend(inputs.iter().map(|&input| input.clone()));
+        kun_peng(&args);
+        fs::read_to_string(output_dir.join("output_1.txt")).unwrap()
+    };
+
+    let untagged = direct("untagged", &["--merge-parts"], &[&covid, &hiv]);
+    let tagged = direct(
+        "tagged",
+        &["--merge-parts", "--tag-source"],
+        &[&covid, &hiv],
+    );
+    assert_eq!(tagged.lines().count(), 20);
+    for (line, expected) in tagged.lines().zip(untagged.lines()) {
+        let (line, source) = line.rsplit_once('\t').unwrap();
+        assert_eq!(line, expected);
+        let id = line.split('\t').nth(1).unwrap();
+        let file = if id.starts_with("covid") {
+            &covid
+        } else {
+            &hiv
+        };
+        assert_eq!(source, file.to_str().unwrap(), "{}", id);
+    }
+
+    // The mates of a pair are both named
+    let pair = direct("pair", &["-P", "--tag-source"], &[&covid, &hiv]);
+    let sources = format!("{},{}", covid.display(), hiv.display());
+    assert_eq!(pair.lines().count(), 10);
+    assert!(
+        pair.lines().all(|line| line.ends_with(&sources)),
+        "{}",
+        pair
+    );
+
+    let _ = fs::remove_dir_all(&work);
+}
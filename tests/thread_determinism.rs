@@ -0,0 +1,79 @@
+//! The classification of a read must not depend on the number of threads.
+//!
+//! Builds the database of `data/` and classifies the sample sequences with `-p 1` and
+//! `-p 8`, with `direct` and with the `classify` pipeline. The Kraken output lines, sorted
+//! by read, must be identical byte for byte.
+mod common;
+
+use common::{data_dir, database, kun_peng, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The lines of all `output_<N>.txt` files of a directory, sorted
+fn sorted_output_lines(dir: &Path) -> Vec<String> {
+    let mut lines = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().to_string();
+        if name.starts_with("output_") && name.ends_with(".txt") {
+            let content = fs::read_to_string(&path).unwrap();
+            lines.extend(content.lines().map(|line| format!("{}\t{}", name, line)));
+        }
+    }
+    lines.sort();
+    lines
+}
+
+#[test]
+fn one_and_eight_threads_agree() {
+    let work = work_dir("threads");
+    let db = database();
+
+    let mut inputs: Vec<PathBuf> = fs::read_dir(data_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "fa" || ext == "fna")
+        })
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+
+    for command in ["direct", "classify"] {
+        let outputs: Vec<Vec<String>> = ["1", "8"]
+            .into_iter()
+            .map(|threads| {
+                let run_dir = work.join(format!("{}_p{}", command, threads));
+                let output_dir = run_dir.join("output");
+                let mut args: Vec<PathBuf> = vec![
+                    command.into(),
+                    "--db".into(),
+                    db.to_path_buf(),
+                    "-p".into(),
+                    threads.into(),
+                    "--output-dir".into(),
+                    output_dir.clone(),
+                ];
+                fs::create_dir_all(&output_dir).unwrap();
+                if command == "classify" {
+                    let chunk_dir = run_dir.join("chunk");
+                    fs::create_dir_all(&chunk_dir).unwrap();
+                    args.push("--chunk-dir".into());
+                    args.push(chunk_dir);
+                }
+                args.extend(inputs.iter().cloned());
+                kun_peng(&args);
+                sorted_output_lines(&output_dir)
+            })
+            .collect();
+        assert!(!outputs[0].is_empty(), "{} wrote no output", command);
+        assert_eq!(
+            outputs[0], outputs[1],
+            "{} differs between 1 and 8 threads",
+            command
+        );
+    }
+
+    let _ = fs::remove_dir_all(&work);
+}
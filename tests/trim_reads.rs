@@ -0,0 +1,95 @@
+//! `--trim-head` and `--trim-tail` cut bases from both ends of every mate before scanning.
+//!
+//! Builds the database of `data/` and classifies a pair whose first mate holds 150 bp of
+//! the SARS-CoV-2 genome followed by 150 random bases and whose second mate is random.
+//! With a confidence threshold of 0.5 the random bases outweigh the hits, trimming them
+//! off the tail leaves the genome only and the pair is classified. Trimming the genome off
+//! the head leaves it unclassified, trimming everything counts it as too short.
+mod common;
+
+use common::{database, fasta, genome, kun_peng, random_bases, work_dir};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A 300 bp first mate, 150 bp of the genome then random bases, and a random 150 bp mate
+fn write_pair(r1: &Path, r2: &Path) {
+    let seq = genome("COVID_19.fa");
+    let mut state = 12345u64;
+    let mate1 = format!("{}{}", &seq[5000..5150], random_bases(150, &mut state));
+    let mate2 = random_bases(150, &mut state);
+    fs::write(r1, fasta([("pair/1", mate1)])).unwrap();
+    fs::write(r2, fasta([("pair/2", mate2)])).unwrap();
+}
+
+/// The classification and length of the pair in the Kraken output
+fn call(dir: &Path) -> (String, String) {
+    let content = fs::read_to_string(dir.join("output_1.txt")).unwrap();
+    let fields: Vec<&str> = content.lines().next().unwrap().split('\t').collect();
+    (fields[0].to_string(), fields[3].to_string())
+}
+
+#[test]
+fn trimming_changes_the_call_of_a_pair() {
+    let work = work_dir("trim");
+    let db = database();
+
+    let (r1, r2) = (work.join("r1.fa"), work.join("r2.fa"));
+    write_pair(&r1, &r2);
+
+    let direct = |name: &str, extra: &[&str]| {
+        let output_dir = work.join(name);
+        fs::create_dir_all(&output_dir).unwrap();
+        let summary = output_dir.join("summary.json");
+        let mut args: Vec<PathBuf> = vec![
+            "direct".into(),
+            "--db".into(),
+            db.to_path_buf(),
+            "--output-dir".into(),
+            output_dir.clone(),
+            "--summary-output".into(),
+            summary.clone(),
+            "-P".into(),
+        ];
+        args.extend(extra.iter().map(PathBuf::from));
+        args.extend([r1.clone(), r2.clone()]);
+        kun_peng(&args);
+        let summary: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(summary).unwrap()).unwrap();
+        (call(&output_dir), summary["too_short"].as_u64().unwrap())
+    };
+    let expect = |status: &str, len: &str| (status.to_string(), len.to_string());
+
+    assert_eq!(direct("default", &[]).0, expect("C", "300|150"));
+    assert_eq!(
+        direct("untrimmed", &["-T", "0.5"]).0,
+        expect("U", "300|150")
+    );
+    // Only the genome is left, it supports the call on its own
+    let tail = ["-T", "0.5", "--trim-tail", "150"];
+    assert_eq!(direct("tail", &tail), (expect("C", "150|0"), 0));
+    // Only random bases are left
+    let head = ["--trim-head", "150"];
+    assert_eq!(direct("head", &head), (expect("U", "150|0"), 0));
+    let all = ["--trim-head", "200", "--trim-tail", "200"];
+    assert_eq!(direct("all", &all), (expect("U", "0|0"), 1));
+
+    // The classify pipeline trims the same way
+    let chunk_dir = work.join("chunk");
+    fs::create_dir_all(&chunk_dir).unwrap();
+    let mut classify: Vec<PathBuf> = vec![
+        "classify".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--chunk-dir".into(),
+        chunk_dir,
+        "--output-dir".into(),
+        work.join("classify"),
+        "-P".into(),
+    ];
+    classify.extend(tail.iter().map(PathBuf::from));
+    classify.extend([r1.clone(), r2.clone()]);
+    kun_peng(&classify);
+    assert_eq!(call(&work.join("classify")), expect("C", "150|0"));
+
+    let _ = fs::remove_dir_all(&work);
+}
@@ -0,0 +1,248 @@
+//! `--watch` classifies the read files appearing in a directory.
+//!
+//! Builds the database of `data/`, drops a read file in a watched directory and stops the
+//! run with a stop file already there. A second run started after a new file came only
+//! classifies that file, and the cumulative report counts the reads of both once. A run
+//! getting two batches, stopped by `--stop-after-files`, keeps the split and ID outputs
+//! of the first, and sums the summary. Every run is killed if it outlives `TIMEOUT`.
+mod common;
+
+use common::{cut_reads, database, fasta, genome, work_dir};
+use kun_peng::watch::WatchLedger;
+use serde_json::Value;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::time::{Duration, Instant};
+
+/// The longest a watch run or the wait for one of its batches may take
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// 10 reads of 150 bp of a genome, as FASTA, moved into place once written
+fn write_reads(genome_name: &str, name: &str, reads: &Path) {
+    let starts = (0..10).map(|i| 500 + i * 800);
+    let tmp = reads.with_extension("tmp");
+    fs::write(
+        &tmp,
+        fasta(cut_reads(&genome(genome_name), name, starts, 150)),
+    )
+    .unwrap();
+    fs::rename(&tmp, reads).unwrap();
+}
+
+/// Starts `kun_peng` with `args`
+fn spawn(args: &[PathBuf]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_kun_peng"))
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run kun_peng")
+}
+
+/// Waits for a run, which must succeed within `TIMEOUT`
+fn finish(mut child: Child) -> Output {
+    let start = Instant::now();
+    while child.try_wait().unwrap().is_none() {
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let output = child.wait_with_output().unwrap();
+            panic!(
+                "kun_peng still running after {:?}: {}",
+                TIMEOUT,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "kun_peng failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// The classified reads of all the per-sample outputs
+fn classified(output_dir: &Path) -> usize {
+    fs::read_dir(output_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+            name.starts_with("output_") && name.ends_with(".txt")
+        })
+        .map(|path| {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .filter(|line| line.starts_with("C\t"))
+                .count()
+        })
+        .sum()
+}
+
+/// The clade count of the root in a Kraken-style report
+fn root_count(report: &Path) -> usize {
+    let content = fs::read_to_string(report).unwrap();
+    let row = content
+        .lines()
+        .find(|line| line.ends_with("\troot"))
+        .unwrap_or_else(|| panic!("no root in {}", content));
+    row.split('\t').nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn restarted_watch_counts_every_file_once() {
+    let work = work_dir("watch");
+    let db = database();
+
+    let incoming = work.join("incoming");
+    let output_dir = work.join("out");
+    fs::create_dir_all(&incoming).unwrap();
+    fs::create_dir_all(&output_dir).unwrap();
+    write_reads("COVID_19.fa", "covid", &incoming.join("batch_1.fa"));
+    fs::write(incoming.join("notes.txt"), "not reads").unwrap();
+    fs::write(incoming.join("stop.txt"), "").unwrap();
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        output_dir.clone(),
+        "--watch".into(),
+        incoming.clone(),
+        "--watch-interval".into(),
+        "1".into(),
+        "--stop-file".into(),
+        "stop.txt".into(),
+    ];
+    finish(spawn(&direct));
+
+    let first = classified(&output_dir);
+    assert!(first >= 5, "{} reads classified", first);
+    assert_eq!(root_count(&output_dir.join("output.kreport2")), first);
+    assert!(output_dir.join("watch.ledger").exists());
+
+    // The restarted run skips batch_1.fa and adds the reads of batch_2.fa
+    write_reads("HIV_1.fna", "hiv", &incoming.join("batch_2.fa"));
+    let output = finish(spawn(&direct));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("1 files already classified"), "{}", stdout);
+    assert!(stdout.contains("classifying 1 new files"), "{}", stdout);
+
+    let both = classified(&output_dir);
+    assert!(both > first, "{} then {} reads classified", first, both);
+    assert_eq!(root_count(&output_dir.join("output.kreport2")), both);
+
+    let _ = fs::remove_dir_all(&work);
+}
+
+/// Waits until the ledger of a watch run lists a file, at most `TIMEOUT`
+fn wait_for_ledger(ledger: &Path, file: &str, child: &mut Child) {
+    let start = Instant::now();
+    let listed = || {
+        ledger.exists()
+            && WatchLedger::open(ledger).is_ok_and(|ledger| {
+                ledger
+                    .progress
+                    .inputs
+                    .iter()
+                    .any(|path| path.ends_with(file))
+            })
+    };
+    while !listed() {
+        if start.elapsed() > TIMEOUT || child.try_wait().unwrap().is_some() {
+            let _ = child.kill();
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            panic!("{} never classified: {}", file, stderr);
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[test]
+fn batches_of_one_run_append_to_the_outputs() {
+    let work = work_dir("watch_batches");
+    let db = database();
+
+    let incoming = work.join("incoming");
+    let output_dir = work.join("out");
+    fs::create_dir_all(&incoming).unwrap();
+    fs::create_dir_all(&output_dir).unwrap();
+    let (split, ids, summary) = (
+        work.join("split"),
+        work.join("classified.txt"),
+        work.join("summary.json"),
+    );
+    write_reads("COVID_19.fa", "covid", &incoming.join("batch_1.fa"));
+    let direct: Vec<PathBuf> = vec![
+        "direct".into(),
+        "--db".into(),
+        db.to_path_buf(),
+        "--output-dir".into(),
+        output_dir.clone(),
+        "--watch".into(),
+        incoming.clone(),
+        "--watch-interval".into(),
+        "1".into(),
+        "--stop-after-files".into(),
+        "2".into(),
+        "--split-output".into(),
+        split.clone(),
+        "--classified-ids-output".into(),
+        ids.clone(),
+        "--summary-output".into(),
+        summary.clone(),
+    ];
+    let mut child = spawn(&direct);
+
+    let ledger = output_dir.join("watch.ledger");
+    wait_for_ledger(&ledger, "batch_1.fa", &mut child);
+    write_reads("HIV_1.fna", "hiv", &incoming.join("batch_2.fa"));
+    let output = finish(child);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.matches("classifying 1 new files").count(),
+        2,
+        "{}",
+        stdout
+    );
+
+    // The outputs of the first batch are still there
+    let kraken_lines: Vec<String> = [".classified.kraken", ".unclassified.kraken"]
+        .iter()
+        .flat_map(|suffix| {
+            let path = format!("{}{}", split.display(), suffix);
+            let content = fs::read_to_string(path).unwrap();
+            content.lines().map(String::from).collect::<Vec<_>>()
+        })
+        .collect();
+    assert_eq!(kraken_lines.len(), 20, "{:?}", kraken_lines);
+    for name in ["covid", "hiv"] {
+        let prefix = format!("\t{}_", name);
+        assert_eq!(
+            kraken_lines
+                .iter()
+                .filter(|line| line.contains(&prefix))
+                .count(),
+            10
+        );
+    }
+    let ids = fs::read_to_string(&ids).unwrap();
+    assert!(ids.lines().any(|id| id.starts_with("covid_")), "{}", ids);
+    assert!(ids.lines().any(|id| id.starts_with("hiv_")), "{}", ids);
+    assert_eq!(ids.lines().count(), classified(&output_dir));
+
+    // The summary counts both batches
+    let summary: Value = serde_json::from_str(&fs::read_to_string(&summary).unwrap()).unwrap();
+    assert_eq!(summary["reads"], 20);
+    assert_eq!(summary["classified"], classified(&output_dir) as u64);
+
+    let _ = fs::remove_dir_all(&work);
+}